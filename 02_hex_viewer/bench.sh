@@ -0,0 +1,36 @@
+#!/usr/bin/env bash
+# Throughput benchmark for hex_viewer.
+#
+# Generates a large file of random bytes, dumps it to /dev/null and prints
+# how many megabytes of input per second we get through, once on a single
+# thread and once with the default (one thread per core).
+#
+# Usage: ./bench.sh [SIZE_IN_MIB]     (default: 2048, i.e. 2 GiB)
+
+set -euo pipefail
+
+SIZE_MIB="${1:-2048}"
+DIR="$(cd "$(dirname "$0")" && pwd)"
+INPUT="${TMPDIR:-/tmp}/hex_viewer_bench.bin"
+
+cargo build --release --quiet --manifest-path "$DIR/Cargo.toml"
+BIN="$(cd "$DIR/.." && pwd)/target/release/hex_viewer"
+
+if [ ! -f "$INPUT" ] || [ "$(stat -c %s "$INPUT")" -ne $((SIZE_MIB * 1024 * 1024)) ]; then
+    echo "Generating ${SIZE_MIB} MiB of random data in $INPUT ..."
+    head -c "$((SIZE_MIB * 1024 * 1024))" /dev/urandom > "$INPUT"
+fi
+
+run() {
+    local label="$1"
+    shift
+    local start end
+    start=$(date +%s.%N)
+    "$BIN" "$@" "$INPUT" > /dev/null
+    end=$(date +%s.%N)
+    awk -v l="$label" -v s="$start" -v e="$end" -v m="$SIZE_MIB" \
+        'BEGIN { t = e - s; printf "%-18s %8.2f s  %8.1f MiB/s\n", l, t, m / t }'
+}
+
+run "1 thread" --threads 1
+run "all cores"
@@ -0,0 +1,435 @@
+// Annotations: named, colored byte ranges drawn on top of the dump.
+//
+// An annotation is the smallest useful piece of "structure": it says
+// "bytes START..START+LEN are called LABEL". Anything that understands a
+// file format can produce them, and every renderer knows how to draw them.
+//
+// Annotations can also live in a plain-text "sidecar" file next to the
+// binary, one per line:
+//
+//     # start..end     color   label ; comment
+//     0x0000..0x0040   cyan    ELF header ; 64 bytes for ELFCLASS64
+//     0x0040+0x38      yellow  program header
+//
+// The file is meant to be edited by hand and kept in version control, so a
+// team can grow a shared understanding of a format one line at a time.
+
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+
+use crate::error::Error;
+
+/// The small, fixed palette annotations can be drawn in.
+///
+/// We keep this list short on purpose: it has to look right both as an
+/// ANSI terminal color and as an HTML background.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Color {
+    Red,
+    Green,
+    Yellow,
+    Blue,
+    Magenta,
+    Cyan,
+}
+
+impl Color {
+    pub const ALL: [Color; 6] = [
+        Color::Red,
+        Color::Green,
+        Color::Yellow,
+        Color::Blue,
+        Color::Magenta,
+        Color::Cyan,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Color::Red => "red",
+            Color::Green => "green",
+            Color::Yellow => "yellow",
+            Color::Blue => "blue",
+            Color::Magenta => "magenta",
+            Color::Cyan => "cyan",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Color> {
+        Color::ALL
+            .into_iter()
+            .find(|c| c.name().eq_ignore_ascii_case(name))
+    }
+
+    /// A pale background that keeps black text readable in a browser.
+    pub fn html(self) -> &'static str {
+        match self {
+            Color::Red => "#ffb3b3",
+            Color::Green => "#b3f0b3",
+            Color::Yellow => "#fff0a0",
+            Color::Blue => "#b3d1ff",
+            Color::Magenta => "#f0b3f0",
+            Color::Cyan => "#a8f0f0",
+        }
+    }
+}
+
+/// How the palette is drawn in a terminal. Which one reads best depends on
+/// the terminal's own colors, so it is a user setting (see `config.rs`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Theme {
+    /// Colored text.
+    #[default]
+    Default,
+    /// Bold, bright text: easier to see on dark backgrounds.
+    Bright,
+    /// Black text on a colored background, like the HTML page.
+    Background,
+    /// No colors at all.
+    None,
+}
+
+impl Theme {
+    pub const ALL: [Theme; 4] = [
+        Theme::Default,
+        Theme::Bright,
+        Theme::Background,
+        Theme::None,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Theme::Default => "default",
+            Theme::Bright => "bright",
+            Theme::Background => "background",
+            Theme::None => "none",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Theme> {
+        Theme::ALL
+            .into_iter()
+            .find(|t| t.name().eq_ignore_ascii_case(name))
+    }
+
+    /// The ANSI escape sequence that switches the terminal to `color`, or
+    /// `None` if this theme draws no colors.
+    pub fn ansi(self, color: Color) -> Option<&'static str> {
+        // The palette is in ANSI order, so the index picks the code.
+        let index = Color::ALL.iter().position(|&c| c == color).unwrap_or(0);
+        let codes = match self {
+            Theme::Default => [
+                "\x1b[31m", "\x1b[32m", "\x1b[33m", "\x1b[34m", "\x1b[35m", "\x1b[36m",
+            ],
+            Theme::Bright => [
+                "\x1b[1;91m",
+                "\x1b[1;92m",
+                "\x1b[1;93m",
+                "\x1b[1;94m",
+                "\x1b[1;95m",
+                "\x1b[1;96m",
+            ],
+            Theme::Background => [
+                "\x1b[30;41m",
+                "\x1b[30;42m",
+                "\x1b[30;43m",
+                "\x1b[30;44m",
+                "\x1b[30;45m",
+                "\x1b[30;46m",
+            ],
+            Theme::None => return None,
+        };
+        Some(codes[index])
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Annotation {
+    pub start: usize,
+    pub len: usize,
+    pub label: String,
+    pub color: Color,
+    /// Free-form notes. Empty when there is nothing to add.
+    pub comment: String,
+}
+
+impl Annotation {
+    /// One past the last byte. The parsers reject ranges that would go
+    /// past `usize::MAX`; saturating keeps a hand-built one from
+    /// panicking anyway.
+    pub fn end(&self) -> usize {
+        self.start.saturating_add(self.len)
+    }
+
+    pub fn contains(&self, offset: usize) -> bool {
+        offset >= self.start && offset < self.end()
+    }
+}
+
+/// Parses a number written either in decimal or with a `0x` prefix.
+/// Underscores are ignored, as in Rust: `0x0800_0000`.
+pub fn parse_number(text: &str) -> Option<usize> {
+    let text = &text.replace('_', "");
+    match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => usize::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
+/// Parses a `--mark START:LEN:LABEL[:COLOR]` argument.
+///
+/// When no color is given we cycle through the palette so that
+/// neighbouring marks are still easy to tell apart.
+///
+/// The label has to survive `--save-annotations`, so it follows the
+/// sidecar rules: it can't be empty or hold a `;` (that starts the
+/// comment), and runs of whitespace become one space.
+pub fn parse_mark(spec: &str, index: usize) -> Result<Annotation, String> {
+    let fields: Vec<&str> = spec.splitn(4, ':').collect();
+    if fields.len() < 3 {
+        return Err(format!(
+            "invalid mark '{}': expected START:LEN:LABEL[:COLOR]",
+            spec
+        ));
+    }
+
+    let start =
+        parse_number(fields[0]).ok_or_else(|| format!("invalid mark start '{}'", fields[0]))?;
+    let len =
+        parse_number(fields[1]).ok_or_else(|| format!("invalid mark length '{}'", fields[1]))?;
+    if start.checked_add(len).is_none() {
+        return Err(format!(
+            "invalid mark '{}': the range ends past the largest offset",
+            spec
+        ));
+    }
+    let label = fields[2].split_whitespace().collect::<Vec<_>>().join(" ");
+    if label.is_empty() {
+        return Err(format!("invalid mark '{}': missing label", spec));
+    }
+    if label.contains(';') {
+        return Err(format!(
+            "invalid mark '{}': labels cannot contain ';'",
+            spec
+        ));
+    }
+    let color = match fields.get(3) {
+        Some(name) => Color::from_name(name).ok_or_else(|| format!("unknown color '{}'", name))?,
+        None => Color::ALL[index % Color::ALL.len()],
+    };
+
+    Ok(Annotation {
+        start,
+        len,
+        label,
+        color,
+        comment: String::new(),
+    })
+}
+
+/// Parses the text of a sidecar file. Errors mention the line number,
+/// because that is what you need to go and fix a hand-edited file.
+pub fn parse_sidecar(text: &str) -> Result<Vec<Annotation>, String> {
+    let mut annotations = Vec::new();
+
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let annotation =
+            parse_sidecar_line(line).map_err(|e| format!("line {}: {}", number + 1, e))?;
+        annotations.push(annotation);
+    }
+
+    Ok(annotations)
+}
+
+fn parse_sidecar_line(line: &str) -> Result<Annotation, String> {
+    // Everything after the first ';' is the comment.
+    let (main, comment) = match line.split_once(';') {
+        Some((main, comment)) => (main, comment.trim()),
+        None => (line, ""),
+    };
+
+    let mut words = main.split_whitespace();
+    let range = words.next().ok_or("missing range")?;
+    let color = words.next().ok_or("missing color")?;
+    let label = words.collect::<Vec<_>>().join(" ");
+    if label.is_empty() {
+        return Err("missing label".to_string());
+    }
+
+    let (start, len) = parse_range(range)?;
+    let color = Color::from_name(color).ok_or_else(|| format!("unknown color '{}'", color))?;
+
+    Ok(Annotation {
+        start,
+        len,
+        label,
+        color,
+        comment: comment.to_string(),
+    })
+}
+
+/// Accepts `START..END` (end is exclusive) or `START+LEN`.
+fn parse_range(range: &str) -> Result<(usize, usize), String> {
+    let bad = || {
+        format!(
+            "invalid range '{}': expected START..END or START+LEN",
+            range
+        )
+    };
+
+    if let Some((start, end)) = range.split_once("..") {
+        let start = parse_number(start).ok_or_else(bad)?;
+        let end = parse_number(end).ok_or_else(bad)?;
+        if end < start {
+            return Err(format!("range '{}' ends before it starts", range));
+        }
+        Ok((start, end - start))
+    } else if let Some((start, len)) = range.split_once('+') {
+        let start = parse_number(start).ok_or_else(bad)?;
+        let len = parse_number(len).ok_or_else(bad)?;
+        if start.checked_add(len).is_none() {
+            return Err(format!("range '{}' ends past the largest offset", range));
+        }
+        Ok((start, len))
+    } else {
+        Err(bad())
+    }
+}
+
+pub fn load_sidecar(path: &Path) -> Result<Vec<Annotation>, Error> {
+    let name = path.display().to_string();
+    let text = fs::read_to_string(path).map_err(|e| Error::opening(&name, e))?;
+    parse_sidecar(&text).map_err(|e| Error::Parse(format!("{}: {}", name, e)))
+}
+
+/// Writes annotations back out in the same format `parse_sidecar` reads.
+pub fn write_sidecar<W: Write>(out: &mut W, annotations: &[Annotation]) -> io::Result<()> {
+    writeln!(out, "# hex_viewer annotations")?;
+    writeln!(out, "# start..end             color    label ; comment")?;
+    for a in annotations {
+        let range = format!("0x{:08x}..0x{:08x}", a.start, a.end());
+        write!(out, "{:<24} {:<8} {}", range, a.color.name(), a.label)?;
+        if !a.comment.is_empty() {
+            write!(out, " ; {}", a.comment)?;
+        }
+        writeln!(out)?;
+    }
+    Ok(())
+}
+
+/// Combines annotations from several sources into one sorted list.
+///
+/// Two annotations describing the same range with the same label are the
+/// same note, even if they came from different files. We keep the first
+/// one, but borrow the other's comment if the first had none.
+pub fn merge(sources: Vec<Annotation>) -> Vec<Annotation> {
+    let mut merged: Vec<Annotation> = Vec::new();
+
+    for annotation in sources {
+        let existing = merged.iter_mut().find(|a| {
+            a.start == annotation.start && a.len == annotation.len && a.label == annotation.label
+        });
+        match existing {
+            Some(a) if a.comment.is_empty() => a.comment = annotation.comment,
+            Some(_) => {}
+            None => merged.push(annotation),
+        }
+    }
+
+    // A stable sort keeps the original order for annotations that start at
+    // the same offset, so "later wins" still holds when they overlap.
+    merged.sort_by_key(|a| a.start);
+    merged
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_marks_with_and_without_color() {
+        let mark = parse_mark("0x10:4:magic:red", 0).unwrap();
+        assert_eq!(mark.start, 16);
+        assert_eq!(mark.len, 4);
+        assert_eq!(mark.label, "magic");
+        assert_eq!(mark.color, Color::Red);
+
+        let mark = parse_mark("8:2:version", 1).unwrap();
+        assert_eq!(mark.color, Color::Green);
+        assert!(mark.contains(9));
+        assert!(!mark.contains(10));
+    }
+
+    #[test]
+    fn sidecar_round_trips() {
+        let text = "# notes\n\
+                    0x0..0x40 cyan ELF header ; 64 bytes\n\
+                    \n\
+                    0x40+0x38 yellow program header\n";
+        let annotations = parse_sidecar(text).unwrap();
+        assert_eq!(annotations.len(), 2);
+        assert_eq!(annotations[0].len, 0x40);
+        assert_eq!(annotations[0].label, "ELF header");
+        assert_eq!(annotations[0].comment, "64 bytes");
+        assert_eq!(annotations[1].start, 0x40);
+        assert_eq!(annotations[1].comment, "");
+
+        let mut out = Vec::new();
+        write_sidecar(&mut out, &annotations).unwrap();
+        let reparsed = parse_sidecar(&String::from_utf8(out).unwrap()).unwrap();
+        assert_eq!(reparsed, annotations);
+    }
+
+    #[test]
+    fn sidecar_errors_name_the_line() {
+        let err = parse_sidecar("0..4 red ok\n4..2 red backwards\n").unwrap_err();
+        assert!(err.starts_with("line 2:"), "{}", err);
+        assert!(parse_sidecar("0..4 pink label").is_err());
+        assert!(parse_sidecar("0..4 red").is_err());
+
+        let err = parse_sidecar("0+4 red ok\n\n1+0xffffffffffffffff red huge\n").unwrap_err();
+        assert_eq!(
+            err,
+            "line 3: range '1+0xffffffffffffffff' ends past the largest offset"
+        );
+    }
+
+    #[test]
+    fn merge_deduplicates_and_sorts() {
+        let a = parse_sidecar("0x10+4 red magic\n0+4 blue header").unwrap();
+        let b = parse_sidecar("0x10+4 green magic ; seen in v2\n0x20+1 red flag").unwrap();
+        let merged = merge(a.into_iter().chain(b).collect());
+
+        let labels: Vec<&str> = merged.iter().map(|a| a.label.as_str()).collect();
+        assert_eq!(labels, ["header", "magic", "flag"]);
+        assert_eq!(merged[1].color, Color::Red);
+        assert_eq!(merged[1].comment, "seen in v2");
+    }
+
+    #[test]
+    fn rejects_bad_marks() {
+        assert!(parse_mark("10:4", 0).is_err());
+        assert!(parse_mark("zz:4:x", 0).is_err());
+        assert!(parse_mark("0:4:x:purple", 0).is_err());
+        assert!(parse_mark("1:0xffffffffffffffff:x", 0).is_err());
+        assert!(parse_mark("0:4: ", 0).is_err());
+        assert!(parse_mark("0:4:a;b", 0).is_err());
+    }
+
+    #[test]
+    fn saved_marks_load_back_the_same() {
+        let marks = vec![
+            parse_mark("0:4:magic", 0).unwrap(),
+            parse_mark("4:2: two \t words :blue", 1).unwrap(),
+        ];
+        assert_eq!(marks[1].label, "two words");
+        let mut out = Vec::new();
+        write_sidecar(&mut out, &marks).unwrap();
+        let loaded = parse_sidecar(&String::from_utf8(out).unwrap()).unwrap();
+        assert_eq!(loaded, marks);
+    }
+}
@@ -0,0 +1,418 @@
+// Command-line parsing.
+//
+// We parse arguments by hand instead of pulling in a crate like `clap`.
+// It keeps the project dependency-free, and it shows that "parsing
+// arguments" is really just walking over a list of strings.
+
+use crate::annotate::{self, Annotation};
+use crate::config::Settings;
+use crate::disasm::Arch;
+use crate::transform::{self, Transform};
+
+/// What the program does with the input file.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    /// Show the bytes (as a dump, HTML, JSON or disassembly).
+    #[default]
+    View,
+    /// Copy a byte range to `--output`.
+    Extract,
+    /// Write a copy of the file with `blob` spliced in to `--output`.
+    Splice,
+}
+
+#[derive(Debug, Default)]
+pub struct Options {
+    pub command: Command,
+    /// Files and directories to show. `extract` and `splice` take one.
+    pub paths: Vec<String>,
+    /// Only show files in directories whose names match one of these.
+    pub include: Vec<String>,
+    /// The file whose bytes `splice` puts into the target.
+    pub blob: String,
+    /// Where `extract` and `splice` write their result.
+    pub output: Option<String>,
+    /// `splice` inserts instead of overwriting.
+    pub insert: bool,
+    /// `extract` and `splice` pad to a multiple of this many bytes.
+    pub align: Option<usize>,
+    /// Emit a self-contained HTML page instead of a text dump.
+    pub html: bool,
+    /// Emit NDJSON records instead of human-readable text.
+    pub json: bool,
+    /// Byte ranges given with `--mark`, drawn on top of the dump.
+    pub marks: Vec<Annotation>,
+    /// Sidecar files to load annotations from, merged in order.
+    pub annotation_files: Vec<String>,
+    /// Where to write the merged annotations, if anywhere.
+    pub save_annotations: Option<String>,
+    /// Transforms applied to every byte before display, in order.
+    pub transforms: Vec<Transform>,
+    /// Rank single-byte XOR keys instead of dumping.
+    pub xor_brute: bool,
+    /// Rendering threads; `None` means one per CPU core.
+    pub threads: Option<usize>,
+    /// Start this many bytes into the file.
+    pub skip: usize,
+    /// Stop after this many bytes.
+    pub length: Option<usize>,
+    /// Show the selected range as machine code instead of a hex dump.
+    pub disasm: Option<Arch>,
+    /// Added to the offsets shown, so they read as addresses in memory.
+    pub base_addr: usize,
+    /// Show offsets from the start of the last annotation instead.
+    pub relative: bool,
+    /// Settings given as flags, as `(key, value)` pairs in `config.rs`
+    /// terms. They override the config file and the environment.
+    pub settings: Vec<(String, String)>,
+    /// Show the effective settings and where they came from, then exit.
+    pub print_config: bool,
+}
+
+pub fn usage(program: &str) -> String {
+    format!(
+        "Usage: {program} [options] <path>...\n\
+         \x20      {program} extract -s OFFSET [-n LEN] [--align N] -o OUT <file_path>\n\
+         \x20      {program} splice -s OFFSET [-n LEN] [--insert] [--align N] -o OUT <file_path> <blob>\n\
+         \n\
+         Options:\n\
+         \x20 --html                        write a browsable HTML page to stdout (one file only)\n\
+         \x20 --json                        write NDJSON records (schema in src/json.rs)\n\
+         \x20 --mark START:LEN:LABEL[:COLOR] annotate a byte range (repeatable)\n\
+         \x20 --annotations FILE            load a sidecar annotations file (repeatable)\n\
+         \x20 --save-annotations FILE       write the merged annotations to FILE\n\
+         \x20 --include GLOB                in directories, only show matching file names\n\
+         \x20                               (repeatable, e.g. '*.bin')\n\
+         \x20 --threads N                   render on N threads (default: one per core)\n\
+         \x20 -s, --skip N                  start N bytes into the file\n\
+         \x20 -n, --length N                dump at most N bytes\n\
+         \x20 --disasm ARCH                 disassemble as x86-64 or aarch64\n\
+         \x20 --base-addr ADDR              show offsets as addresses from ADDR, e.g. 0x0800_0000\n\
+         \x20 --relative                    show offsets from the start of the last annotation\n\
+         \x20                               (blank before the first one)\n\
+         \n\
+         Settings (defaults come from ~/.config/hex_viewer/config and HEX_VIEWER_*):\n\
+         \x20 --width N                     bytes per line (1-64, default 16)\n\
+         \x20 --group N                     extra space every N bytes (default 8)\n\
+         \x20 --theme NAME                  default, bright, background or none\n\
+         \x20 --offset-base BASE            hex (default) or dec\n\
+         \x20 -d                            same as --offset-base dec\n\
+         \x20 --squeeze | --no-squeeze      collapse repeated lines into '*'\n\
+         \x20 -e, --encoding NAME           text column: ascii (default), utf8, latin1,\n\
+         \x20                               utf16le, utf16be, ebcdic, cp437\n\
+         \x20 --print-config                show the effective settings and their sources\n\
+         \n\
+         Transforms (applied before display, in the order given):\n\
+         \x20 --xor KEY                     XOR with a hex key, e.g. 41 or deadbeef\n\
+         \x20 --add N | --sub N             add or subtract N from every byte\n\
+         \x20 --rol N | --ror N             rotate the bits of every byte by N\n\
+         \x20 --bitrev                      reverse the bit order of every byte\n\
+         \x20 --xor-brute                   rank single-byte XOR keys by English-likeness\n\
+         \n\
+         Extract and splice (the input file is never modified):\n\
+         \x20 -o, --output FILE             where to write the range or the spliced copy\n\
+         \x20 -n, --length N                extract N bytes / require a blob of exactly N bytes\n\
+         \x20 --insert                      insert the blob instead of overwriting\n\
+         \x20 --align N                     pad with zero bytes to a multiple of N\n\
+         \n\
+         Exit status:\n\
+         \x20 0                             success, also when the reader quits early (| head)\n\
+         \x20 2                             bad arguments\n\
+         \x20 3                             a file could not be opened\n\
+         \x20 4                             a file could not be read\n\
+         \x20 5                             a config or annotations file is malformed\n\
+         \x20 6                             the output could not be written",
+    )
+}
+
+/// Turns `argv` (without the program name) into `Options`.
+pub fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut options = Options::default();
+    let mut paths = Vec::new();
+    let mut iter = args.iter().peekable();
+
+    // A subcommand, if any, comes first.
+    match iter.peek().map(|arg| arg.as_str()) {
+        Some("extract") => options.command = Command::Extract,
+        Some("splice") => options.command = Command::Splice,
+        _ => {}
+    }
+    if options.command != Command::View {
+        iter.next();
+    }
+
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--html" => options.html = true,
+            "--json" => options.json = true,
+            "--mark" => {
+                let spec = iter.next().ok_or("--mark needs a value")?;
+                let mark = annotate::parse_mark(spec, options.marks.len())?;
+                options.marks.push(mark);
+            }
+            "--annotations" => {
+                let file = iter.next().ok_or("--annotations needs a file")?;
+                options.annotation_files.push(file.clone());
+            }
+            "--save-annotations" => {
+                let file = iter.next().ok_or("--save-annotations needs a file")?;
+                options.save_annotations = Some(file.clone());
+            }
+            "--xor" => {
+                let key = iter.next().ok_or("--xor needs a key")?;
+                options
+                    .transforms
+                    .push(Transform::Xor(transform::parse_key(key)?));
+            }
+            "--add" | "--sub" => {
+                let value = iter
+                    .next()
+                    .ok_or_else(|| format!("{} needs a value", arg))?;
+                let n = annotate::parse_number(value)
+                    .and_then(|n| u8::try_from(n).ok())
+                    .ok_or_else(|| format!("invalid {} value '{}': expected 0-255", arg, value))?;
+                options.transforms.push(if arg == "--add" {
+                    Transform::Add(n)
+                } else {
+                    Transform::Sub(n)
+                });
+            }
+            "--rol" | "--ror" => {
+                let value = iter
+                    .next()
+                    .ok_or_else(|| format!("{} needs a value", arg))?;
+                let n = value
+                    .parse::<u32>()
+                    .ok()
+                    .filter(|n| *n < 8)
+                    .ok_or_else(|| format!("invalid {} value '{}': expected 0-7", arg, value))?;
+                options.transforms.push(if arg == "--rol" {
+                    Transform::RotateLeft(n)
+                } else {
+                    Transform::RotateRight(n)
+                });
+            }
+            "--threads" => {
+                let value = iter.next().ok_or("--threads needs a value")?;
+                let n = value
+                    .parse::<usize>()
+                    .ok()
+                    .filter(|n| *n > 0)
+                    .ok_or_else(|| format!("invalid --threads value '{}'", value))?;
+                options.threads = Some(n);
+            }
+            "-s" | "--skip" | "-n" | "--length" => {
+                let value = iter
+                    .next()
+                    .ok_or_else(|| format!("{} needs a value", arg))?;
+                let n = annotate::parse_number(value)
+                    .ok_or_else(|| format!("invalid {} value '{}'", arg, value))?;
+                if arg == "-s" || arg == "--skip" {
+                    options.skip = n;
+                } else {
+                    options.length = Some(n);
+                }
+            }
+            "--disasm" => {
+                let name = iter.next().ok_or("--disasm needs an architecture")?;
+                let arch = Arch::from_name(name).ok_or_else(|| {
+                    format!(
+                        "unknown architecture '{}': expected x86-64 or aarch64",
+                        name
+                    )
+                })?;
+                options.disasm = Some(arch);
+            }
+            "--width" | "--group" | "--theme" | "--offset-base" | "-e" | "--encoding" => {
+                let value = iter
+                    .next()
+                    .ok_or_else(|| format!("{} needs a value", arg))?;
+                let key = match arg.as_str() {
+                    "-e" => "encoding".to_string(),
+                    flag => flag.trim_start_matches('-').replace('-', "_"),
+                };
+                // Check the value now, so a typo is reported with the usage.
+                Settings::default().set(&key, value)?;
+                options.settings.push((key, value.clone()));
+            }
+            "-d" => options
+                .settings
+                .push(("offset_base".to_string(), "dec".to_string())),
+            "--base-addr" => {
+                let value = iter.next().ok_or("--base-addr needs an address")?;
+                options.base_addr = annotate::parse_number(value)
+                    .ok_or_else(|| format!("invalid --base-addr value '{}'", value))?;
+            }
+            "--relative" => options.relative = true,
+            "--squeeze" | "--no-squeeze" => {
+                let value = (arg == "--squeeze").to_string();
+                options.settings.push(("squeeze".to_string(), value));
+            }
+            "--print-config" => options.print_config = true,
+            "-o" | "--output" => {
+                let file = iter.next().ok_or_else(|| format!("{} needs a file", arg))?;
+                options.output = Some(file.clone());
+            }
+            "--insert" => options.insert = true,
+            "--align" => {
+                let value = iter.next().ok_or("--align needs a value")?;
+                let n = annotate::parse_number(value)
+                    .filter(|n| *n > 0)
+                    .ok_or_else(|| format!("invalid --align value '{}'", value))?;
+                options.align = Some(n);
+            }
+            "--include" => {
+                let pattern = iter.next().ok_or("--include needs a pattern")?;
+                options.include.push(pattern.clone());
+            }
+            "--bitrev" => options.transforms.push(Transform::BitReverse),
+            "--xor-brute" => options.xor_brute = true,
+            flag if flag.starts_with('-') && flag.len() > 1 => {
+                return Err(format!("unknown option '{}'", flag));
+            }
+            _ => paths.push(arg.clone()),
+        }
+    }
+
+    if options.html && options.json {
+        return Err("--html and --json cannot be combined".to_string());
+    }
+
+    // `splice` takes the target and the blob, `extract` one file, and
+    // viewing as many files and directories as you like.
+    match (options.command, paths.len()) {
+        // `--print-config` is the one thing that needs no file at all.
+        (Command::View, 0) if options.print_config => {}
+        (_, 0) => return Err("missing file path".to_string()),
+        (Command::Splice, n) if n != 2 => {
+            return Err("splice needs a target file and a blob file".to_string());
+        }
+        (Command::Splice, _) => options.blob = paths.pop().unwrap_or_default(),
+        (Command::Extract, n) if n != 1 => {
+            return Err("extract takes exactly one file".to_string());
+        }
+        _ => {}
+    }
+    options.paths = paths;
+
+    if options.command == Command::View {
+        if options.output.is_some() || options.insert || options.align.is_some() {
+            return Err("--output, --insert and --align need extract or splice".to_string());
+        }
+    } else if options.output.is_none() {
+        return Err("extract and splice need --output FILE".to_string());
+    } else if options.insert && options.length.is_some() {
+        return Err("--insert replaces nothing, so it cannot take --length".to_string());
+    }
+    Ok(options)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(list: &[&str]) -> Vec<String> {
+        list.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn parses_html_and_marks() {
+        let options = parse_args(&args(&["--html", "--mark", "0:4:magic", "a.bin"])).unwrap();
+        assert!(options.html);
+        assert_eq!(options.paths, ["a.bin"]);
+        assert_eq!(options.marks.len(), 1);
+    }
+
+    #[test]
+    fn keeps_transforms_in_order() {
+        let options = parse_args(&args(&["--rol", "3", "--xor", "41", "--bitrev", "f"])).unwrap();
+        assert_eq!(
+            options.transforms,
+            vec![
+                Transform::RotateLeft(3),
+                Transform::Xor(vec![0x41]),
+                Transform::BitReverse
+            ]
+        );
+        assert!(parse_args(&args(&["--rol", "8", "f"])).is_err());
+        assert!(parse_args(&args(&["--add", "256", "f"])).is_err());
+    }
+
+    #[test]
+    fn parses_range_and_disassembly() {
+        let options = parse_args(&args(&[
+            "-s", "0x1000", "--length", "64", "--disasm", "x86-64", "a.out",
+        ]))
+        .unwrap();
+        assert_eq!(options.skip, 0x1000);
+        assert_eq!(options.length, Some(64));
+        assert_eq!(options.disasm, Some(Arch::X86_64));
+        assert!(parse_args(&args(&["--disasm", "mips", "a.out"])).is_err());
+    }
+
+    #[test]
+    fn collects_settings_in_config_terms() {
+        let options = parse_args(&args(&[
+            "-e",
+            "UTF-16LE",
+            "--offset-base",
+            "dec",
+            "--squeeze",
+            "-d",
+            "--base-addr",
+            "0x0800_0000",
+            "a.bin",
+        ]))
+        .unwrap();
+        assert_eq!(
+            options.settings,
+            [
+                ("encoding".to_string(), "UTF-16LE".to_string()),
+                ("offset_base".to_string(), "dec".to_string()),
+                ("squeeze".to_string(), "true".to_string()),
+                ("offset_base".to_string(), "dec".to_string()),
+            ]
+        );
+        assert_eq!(options.base_addr, 0x0800_0000);
+        assert!(parse_args(&args(&["--encoding", "klingon", "a.bin"])).is_err());
+        assert!(parse_args(&args(&["--width", "65", "a.bin"])).is_err());
+        assert!(parse_args(&args(&["--print-config"])).unwrap().print_config);
+    }
+
+    #[test]
+    fn parses_extract_and_splice() {
+        let options = parse_args(&args(&["extract", "-s", "16", "-o", "out", "a.img"])).unwrap();
+        assert_eq!(options.command, Command::Extract);
+        assert_eq!(options.output.as_deref(), Some("out"));
+
+        let options = parse_args(&args(&[
+            "splice", "--insert", "--align", "512", "-o", "out", "a.img", "blob",
+        ]))
+        .unwrap();
+        assert_eq!(options.command, Command::Splice);
+        assert_eq!(
+            (options.paths[0].as_str(), options.blob.as_str()),
+            ("a.img", "blob")
+        );
+        assert_eq!(options.align, Some(512));
+
+        assert!(parse_args(&args(&["splice", "-o", "out", "a.img"])).is_err());
+        assert!(parse_args(&args(&["extract", "a.img"])).is_err());
+        assert!(parse_args(&args(&["--align", "4", "a.img"])).is_err());
+    }
+
+    #[test]
+    fn accepts_many_paths_with_filters() {
+        let options = parse_args(&args(&["--include", "*.bin", "dir", "a.img"])).unwrap();
+        assert_eq!(options.paths, ["dir", "a.img"]);
+        assert_eq!(options.include, ["*.bin"]);
+        assert!(parse_args(&args(&["extract", "-o", "out", "a", "b"])).is_err());
+    }
+
+    #[test]
+    fn rejects_bad_arguments() {
+        assert!(parse_args(&args(&[])).is_err());
+        assert!(parse_args(&args(&["--bogus", "a"])).is_err());
+        assert!(parse_args(&args(&["--html", "--json", "a"])).is_err());
+    }
+}
@@ -0,0 +1,334 @@
+// User settings: built-in defaults, a config file, environment variables
+// and command-line flags, in that order, each overriding the one before.
+//
+// The config file lives at `$XDG_CONFIG_HOME/hex_viewer/config` (usually
+// `~/.config/hex_viewer/config`) and holds one `key = value` per line:
+//
+//     # Our team's defaults
+//     width = 32
+//     group = 4
+//     theme = bright
+//     offset_base = hex
+//     squeeze = true
+//     encoding = utf8
+//
+// Every key can also be set with an environment variable named after it,
+// like `HEX_VIEWER_WIDTH=32`, and with a flag, like `--width 32`.
+//
+// All three sources go through the same `Settings::set`, so a value means
+// exactly the same thing wherever it comes from. `--print-config` shows
+// the result, and which source each value was taken from.
+
+use std::fmt;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use crate::annotate::{self, Theme};
+use crate::error::Error;
+use crate::text::Encoding;
+
+/// The widest line we draw. Wider lines would not fit on any screen, and a
+/// fixed limit lets the renderer use fixed-size arrays per line.
+pub const MAX_WIDTH: usize = 64;
+
+/// The setting names, in the order `--print-config` shows them.
+pub const KEYS: [&str; 6] = [
+    "width",
+    "group",
+    "theme",
+    "offset_base",
+    "squeeze",
+    "encoding",
+];
+
+/// How offsets are written at the start of each line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OffsetBase {
+    #[default]
+    Hex,
+    Dec,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Settings {
+    /// Bytes per line.
+    pub width: usize,
+    /// Bytes per group: an extra space separates the groups of a line.
+    pub group: usize,
+    /// How annotation colors are drawn in a terminal.
+    pub theme: Theme,
+    pub offset_base: OffsetBase,
+    /// Replace runs of identical lines with a single `*`, like `hexdump`.
+    pub squeeze: bool,
+    /// How the text column decodes bytes.
+    pub encoding: Encoding,
+}
+
+impl Default for Settings {
+    fn default() -> Settings {
+        Settings {
+            // 16 bytes is a standard hex view width.
+            width: 16,
+            group: 8,
+            theme: Theme::Default,
+            offset_base: OffsetBase::Hex,
+            squeeze: false,
+            encoding: Encoding::Ascii,
+        }
+    }
+}
+
+impl Settings {
+    /// Sets `key` from its text form, as found in any of the sources.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        let invalid =
+            |expected: &str| format!("invalid {} '{}': expected {}", key, value, expected);
+        match key {
+            "width" => {
+                self.width = annotate::parse_number(value)
+                    .filter(|n| (1..=MAX_WIDTH).contains(n))
+                    .ok_or_else(|| invalid(&format!("1-{}", MAX_WIDTH)))?;
+            }
+            "group" => {
+                self.group = annotate::parse_number(value)
+                    .filter(|n| *n > 0)
+                    .ok_or_else(|| invalid("a number above 0"))?;
+            }
+            "theme" => {
+                self.theme = Theme::from_name(value)
+                    .ok_or_else(|| invalid("default, bright, background or none"))?;
+            }
+            "offset_base" => {
+                self.offset_base = match value {
+                    "hex" | "16" => OffsetBase::Hex,
+                    "dec" | "10" => OffsetBase::Dec,
+                    _ => return Err(invalid("hex or dec")),
+                };
+            }
+            "squeeze" => {
+                self.squeeze = match value {
+                    "true" | "yes" | "on" | "1" => true,
+                    "false" | "no" | "off" | "0" => false,
+                    _ => return Err(invalid("true or false")),
+                };
+            }
+            "encoding" => {
+                self.encoding = Encoding::from_name(value).ok_or_else(|| {
+                    invalid("ascii, utf8, latin1, utf16le, utf16be, ebcdic or cp437")
+                })?;
+            }
+            _ => return Err(format!("unknown setting '{}'", key)),
+        }
+        Ok(())
+    }
+
+    /// True if byte `i` of a line ends a group (but not the line), so an
+    /// extra space follows it.
+    pub fn ends_group(&self, i: usize) -> bool {
+        (i + 1).is_multiple_of(self.group) && i + 1 < self.width
+    }
+
+    /// The text form of `key`, as `set` would accept it.
+    pub fn get(&self, key: &str) -> String {
+        match key {
+            "width" => self.width.to_string(),
+            "group" => self.group.to_string(),
+            "theme" => self.theme.name().to_string(),
+            "offset_base" => match self.offset_base {
+                OffsetBase::Hex => "hex".to_string(),
+                OffsetBase::Dec => "dec".to_string(),
+            },
+            "squeeze" => self.squeeze.to_string(),
+            "encoding" => self.encoding.name().to_string(),
+            _ => String::new(),
+        }
+    }
+}
+
+/// Where a setting's value came from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Source {
+    Default,
+    File(PathBuf, usize),
+    Env(String),
+    Flag,
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Source::Default => write!(f, "default"),
+            Source::File(path, line) => write!(f, "{}:{}", path.display(), line),
+            Source::Env(name) => write!(f, "environment {}", name),
+            Source::Flag => write!(f, "command line"),
+        }
+    }
+}
+
+/// The effective settings, and where each one came from.
+#[derive(Debug)]
+pub struct Config {
+    pub settings: Settings,
+    /// One entry per key in `KEYS`.
+    pub sources: Vec<Source>,
+    /// The config file we looked for, and whether it was there.
+    pub file: Option<(PathBuf, bool)>,
+}
+
+impl Config {
+    fn set(&mut self, key: &str, value: &str, source: Source) -> Result<(), String> {
+        self.settings.set(key, value)?;
+        if let Some(i) = KEYS.iter().position(|k| *k == key) {
+            self.sources[i] = source;
+        }
+        Ok(())
+    }
+}
+
+/// Where the config file is: `$XDG_CONFIG_HOME/hex_viewer/config`, falling
+/// back to `~/.config` as the XDG spec says.
+pub fn config_path() -> Option<PathBuf> {
+    let base = match std::env::var_os("XDG_CONFIG_HOME") {
+        // The spec says relative paths are invalid and must be ignored.
+        Some(dir) if Path::new(&dir).is_absolute() => PathBuf::from(dir),
+        _ => PathBuf::from(std::env::var_os("HOME")?).join(".config"),
+    };
+    Some(base.join("hex_viewer").join("config"))
+}
+
+/// The environment variable for `key`, e.g. `HEX_VIEWER_OFFSET_BASE`.
+pub fn env_name(key: &str) -> String {
+    format!("HEX_VIEWER_{}", key.to_ascii_uppercase())
+}
+
+/// Loads the settings from the real config file and environment, then
+/// applies the `(key, value)` pairs given as flags.
+pub fn load(flags: &[(String, String)]) -> Result<Config, Error> {
+    let file = match config_path() {
+        Some(path) => match fs::read_to_string(&path) {
+            Ok(text) => Some((path, Some(text))),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Some((path, None)),
+            Err(e) => return Err(Error::reading(&path.display().to_string(), e)),
+        },
+        None => None,
+    };
+    let file = file
+        .as_ref()
+        .map(|(path, text)| (path.as_path(), text.as_deref()));
+    resolve(file, |name| std::env::var(name).ok(), flags).map_err(Error::Parse)
+}
+
+/// Applies the config file (path and contents, if it exists), the
+/// environment (looked up with `env`) and the flags, in that order.
+fn resolve(
+    file: Option<(&Path, Option<&str>)>,
+    env: impl Fn(&str) -> Option<String>,
+    flags: &[(String, String)],
+) -> Result<Config, String> {
+    let mut config = Config {
+        settings: Settings::default(),
+        sources: vec![Source::Default; KEYS.len()],
+        file: file.map(|(path, text)| (path.to_path_buf(), text.is_some())),
+    };
+
+    // 1. THE CONFIG FILE
+    if let Some((path, Some(text))) = file {
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let at = |message: String| format!("{}:{}: {}", path.display(), number + 1, message);
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| at("expected 'key = value'".to_string()))?;
+            let source = Source::File(path.to_path_buf(), number + 1);
+            config.set(key.trim(), value.trim(), source).map_err(at)?;
+        }
+    }
+
+    // 2. THE ENVIRONMENT
+    for key in KEYS {
+        let name = env_name(key);
+        if let Some(value) = env(&name) {
+            config
+                .set(key, value.trim(), Source::Env(name.clone()))
+                .map_err(|e| format!("{}: {}", name, e))?;
+        }
+    }
+
+    // 3. THE COMMAND LINE
+    for (key, value) in flags {
+        config.set(key, value, Source::Flag)?;
+    }
+
+    Ok(config)
+}
+
+/// Prints the effective settings for `--print-config`:
+///
+/// # config file: /home/me/.config/hex_viewer/config
+/// width        32        /home/me/.config/hex_viewer/config:2
+/// theme        bright    environment HEX_VIEWER_THEME
+pub fn write_report<W: Write>(out: &mut W, config: &Config) -> io::Result<()> {
+    match &config.file {
+        Some((path, true)) => writeln!(out, "# config file: {}", path.display())?,
+        Some((path, false)) => writeln!(out, "# config file: {} (not found)", path.display())?,
+        None => writeln!(out, "# config file: none ($HOME is not set)")?,
+    }
+    for (key, source) in KEYS.iter().zip(&config.sources) {
+        writeln!(
+            out,
+            "{:<12} {:<9} {}",
+            key,
+            config.settings.get(key),
+            source
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn later_sources_override_earlier_ones() {
+        let file = "# team defaults\nwidth = 32\ntheme = bright\nsqueeze = yes\n";
+        let env = |name: &str| (name == "HEX_VIEWER_THEME").then(|| "none".to_string());
+        let flags = vec![("width".to_string(), "8".to_string())];
+        let config = resolve(Some((Path::new("cfg"), Some(file))), env, &flags).unwrap();
+
+        assert_eq!(config.settings.width, 8);
+        assert_eq!(config.settings.theme, Theme::None);
+        assert!(config.settings.squeeze);
+        assert_eq!(config.settings.group, 8);
+        assert_eq!(config.sources[0], Source::Flag);
+        assert_eq!(config.sources[1], Source::Default);
+        assert_eq!(
+            config.sources[2],
+            Source::Env("HEX_VIEWER_THEME".to_string())
+        );
+        assert_eq!(config.sources[4], Source::File(PathBuf::from("cfg"), 4));
+
+        let mut report = Vec::new();
+        write_report(&mut report, &config).unwrap();
+        let report = String::from_utf8(report).unwrap();
+        assert!(
+            report.contains("squeeze      true      cfg:4\n"),
+            "{}",
+            report
+        );
+    }
+
+    #[test]
+    fn bad_values_name_their_source() {
+        let err = resolve(Some((Path::new("cfg"), Some("width = 0"))), |_| None, &[]).unwrap_err();
+        assert!(err.starts_with("cfg:1: invalid width '0'"), "{}", err);
+
+        let env = |_: &str| Some("sideways".to_string());
+        let err = resolve(None, env, &[]).unwrap_err();
+        assert!(err.starts_with("HEX_VIEWER_WIDTH:"), "{}", err);
+    }
+}
@@ -0,0 +1,358 @@
+// AArch64 decoding.
+//
+// ARM is much friendlier than x86: every instruction is exactly one 32-bit
+// little-endian word, and fields sit at fixed bit positions. Decoding is a
+// matter of masking the word to find its "class", then slicing out fields.
+
+use super::Instruction;
+
+const CONDITIONS: [&str; 16] = [
+    "eq", "ne", "hs", "lo", "mi", "pl", "vs", "vc", "hi", "ls", "ge", "lt", "gt", "le", "al", "nv",
+];
+
+/// Extracts `len` bits starting at bit `lo`.
+fn bits(word: u32, lo: u32, len: u32) -> u32 {
+    (word >> lo) & ((1 << len) - 1)
+}
+
+/// Sign-extends the low `len` bits of `value`.
+fn signed(value: u32, len: u32) -> i64 {
+    let shift = 64 - len;
+    ((value as i64) << shift) >> shift
+}
+
+/// Register 31 means either the stack pointer or the zero register,
+/// depending on the instruction. `sp` says which one applies here.
+fn reg(n: u32, wide: bool, sp: bool) -> String {
+    match (n, wide, sp) {
+        (31, true, true) => "sp".to_string(),
+        (31, false, true) => "wsp".to_string(),
+        (31, true, false) => "xzr".to_string(),
+        (31, false, false) => "wzr".to_string(),
+        (n, true, _) => format!("x{}", n),
+        (n, false, _) => format!("w{}", n),
+    }
+}
+
+fn target(address: u64, offset: i64) -> String {
+    format!("0x{:x}", address.wrapping_add(offset as u64))
+}
+
+pub fn decode(bytes: &[u8], address: u64) -> Option<Instruction> {
+    let word = u32::from_le_bytes(bytes.get(..4)?.try_into().ok()?);
+    let text = decode_word(word, address)?;
+    Some(Instruction { len: 4, text })
+}
+
+fn decode_word(w: u32, address: u64) -> Option<String> {
+    let rd = bits(w, 0, 5);
+    let rn = bits(w, 5, 5);
+    let wide = bits(w, 31, 1) == 1;
+
+    let text = if w == 0xd503201f {
+        "nop".to_string()
+    } else if w & 0xfffffc1f == 0xd65f0000 {
+        if rn == 30 {
+            "ret".to_string()
+        } else {
+            format!("ret x{}", rn)
+        }
+    } else if w & 0xfffffc1f == 0xd61f0000 {
+        format!("br x{}", rn)
+    } else if w & 0xfffffc1f == 0xd63f0000 {
+        format!("blr x{}", rn)
+    } else if w & 0x7c000000 == 0x14000000 {
+        // b / bl: a 26-bit word offset, so +-128 MB around the instruction.
+        let name = if w & 0x80000000 != 0 { "bl" } else { "b" };
+        format!(
+            "{} {}",
+            name,
+            target(address, signed(bits(w, 0, 26), 26) * 4)
+        )
+    } else if w & 0xff000010 == 0x54000000 {
+        let offset = signed(bits(w, 5, 19), 19) * 4;
+        format!(
+            "b.{} {}",
+            CONDITIONS[bits(w, 0, 4) as usize],
+            target(address, offset)
+        )
+    } else if w & 0x7e000000 == 0x34000000 {
+        let name = if bits(w, 24, 1) == 1 { "cbnz" } else { "cbz" };
+        let offset = signed(bits(w, 5, 19), 19) * 4;
+        format!(
+            "{} {}, {}",
+            name,
+            reg(rd, wide, false),
+            target(address, offset)
+        )
+    } else if w & 0x1f000000 == 0x10000000 {
+        // adr / adrp: the offset is split into 2 low bits and 19 high bits.
+        let imm = signed(bits(w, 29, 2) | (bits(w, 5, 19) << 2), 21);
+        if wide {
+            let page = address & !0xfff;
+            format!("adrp x{}, {}", rd, target(page, imm << 12))
+        } else {
+            format!("adr x{}, {}", rd, target(address, imm))
+        }
+    } else if w & 0xffe0001f == 0xd4000001 {
+        format!("svc #0x{:x}", bits(w, 5, 16))
+    } else if w & 0xffe0001f == 0xd4200000 {
+        format!("brk #0x{:x}", bits(w, 5, 16))
+    } else if w & 0x1f800000 == 0x11000000 {
+        decode_add_sub_immediate(w, wide, rd, rn)
+    } else if w & 0x1f800000 == 0x12800000 {
+        decode_move_wide(w, wide, rd)?
+    } else if w & 0x1f000000 == 0x0a000000 {
+        // Bit 21 (N) is part of these: it picks `bic` over `and` and so on.
+        decode_logical_register(w, wide, rd, rn)
+    } else if w & 0x1f200000 == 0x0b000000 {
+        decode_add_sub_register(w, wide, rd, rn)
+    } else if w & 0x3b000000 == 0x39000000 {
+        decode_load_store(w, rd, rn)?
+    } else if w & 0x3a000000 == 0x28000000 {
+        decode_pair(w, rd, rn)?
+    } else {
+        return None;
+    };
+
+    Some(text)
+}
+
+fn decode_add_sub_immediate(w: u32, wide: bool, rd: u32, rn: u32) -> String {
+    let sub = bits(w, 30, 1) == 1;
+    let set_flags = bits(w, 29, 1) == 1;
+    let shift = if bits(w, 22, 1) == 1 { ", lsl #12" } else { "" };
+    let imm = bits(w, 10, 12);
+
+    // Aliases: `add x0, sp, #0` is `mov x0, sp`; `subs xzr, x1, #4` is `cmp x1, #4`.
+    if !sub && !set_flags && imm == 0 && (rd == 31 || rn == 31) {
+        return format!("mov {}, {}", reg(rd, wide, true), reg(rn, wide, true));
+    }
+    if set_flags && rd == 31 {
+        let name = if sub { "cmp" } else { "cmn" };
+        return format!("{} {}, #0x{:x}{}", name, reg(rn, wide, true), imm, shift);
+    }
+
+    let name = match (sub, set_flags) {
+        (false, false) => "add",
+        (false, true) => "adds",
+        (true, false) => "sub",
+        (true, true) => "subs",
+    };
+    format!(
+        "{} {}, {}, #0x{:x}{}",
+        name,
+        reg(rd, wide, !set_flags),
+        reg(rn, wide, true),
+        imm,
+        shift
+    )
+}
+
+fn decode_move_wide(w: u32, wide: bool, rd: u32) -> Option<String> {
+    let name = match bits(w, 29, 2) {
+        0 => "movn",
+        2 => "movz",
+        3 => "movk",
+        _ => return None,
+    };
+    let shift = bits(w, 21, 2) * 16;
+    let imm = bits(w, 5, 16);
+    Some(if name == "movz" && shift == 0 {
+        format!("mov {}, #0x{:x}", reg(rd, wide, false), imm)
+    } else if shift == 0 {
+        format!("{} {}, #0x{:x}", name, reg(rd, wide, false), imm)
+    } else {
+        format!(
+            "{} {}, #0x{:x}, lsl #{}",
+            name,
+            reg(rd, wide, false),
+            imm,
+            shift
+        )
+    })
+}
+
+fn shift_suffix(w: u32) -> String {
+    let amount = bits(w, 10, 6);
+    if amount == 0 {
+        return String::new();
+    }
+    let kind = ["lsl", "lsr", "asr", "ror"][bits(w, 22, 2) as usize];
+    format!(", {} #{}", kind, amount)
+}
+
+fn decode_logical_register(w: u32, wide: bool, rd: u32, rn: u32) -> String {
+    let rm = bits(w, 16, 5);
+    let invert = bits(w, 21, 1) == 1;
+    let opc = bits(w, 29, 2);
+
+    // `orr x0, xzr, x1` is how AArch64 spells `mov x0, x1`.
+    if opc == 1 && !invert && rn == 31 && bits(w, 10, 6) == 0 {
+        return format!("mov {}, {}", reg(rd, wide, false), reg(rm, wide, false));
+    }
+    if opc == 3 && !invert && rd == 31 {
+        return format!(
+            "tst {}, {}{}",
+            reg(rn, wide, false),
+            reg(rm, wide, false),
+            shift_suffix(w)
+        );
+    }
+
+    let names = if invert {
+        ["bic", "orn", "eon", "bics"]
+    } else {
+        ["and", "orr", "eor", "ands"]
+    };
+    format!(
+        "{} {}, {}, {}{}",
+        names[opc as usize],
+        reg(rd, wide, false),
+        reg(rn, wide, false),
+        reg(rm, wide, false),
+        shift_suffix(w)
+    )
+}
+
+fn decode_add_sub_register(w: u32, wide: bool, rd: u32, rn: u32) -> String {
+    let rm = bits(w, 16, 5);
+    let sub = bits(w, 30, 1) == 1;
+    let set_flags = bits(w, 29, 1) == 1;
+
+    if set_flags && sub && rd == 31 {
+        return format!(
+            "cmp {}, {}{}",
+            reg(rn, wide, false),
+            reg(rm, wide, false),
+            shift_suffix(w)
+        );
+    }
+
+    let name = match (sub, set_flags) {
+        (false, false) => "add",
+        (false, true) => "adds",
+        (true, false) => "sub",
+        (true, true) => "subs",
+    };
+    format!(
+        "{} {}, {}, {}{}",
+        name,
+        reg(rd, wide, false),
+        reg(rn, wide, false),
+        reg(rm, wide, false),
+        shift_suffix(w)
+    )
+}
+
+/// `ldr`/`str` (and byte/halfword forms) with an unsigned, scaled offset.
+fn decode_load_store(w: u32, rt: u32, rn: u32) -> Option<String> {
+    if bits(w, 26, 1) == 1 {
+        return None; // SIMD and floating point registers
+    }
+    let size = bits(w, 30, 2);
+    let load = match bits(w, 22, 2) {
+        0 => false,
+        1 => true,
+        _ => return None, // sign-extending loads and prefetch
+    };
+    let name = match (load, size) {
+        (false, 0) => "strb",
+        (false, 1) => "strh",
+        (false, _) => "str",
+        (true, 0) => "ldrb",
+        (true, 1) => "ldrh",
+        (true, _) => "ldr",
+    };
+    let offset = bits(w, 10, 12) << size;
+    let address = if offset == 0 {
+        format!("[{}]", reg(rn, true, true))
+    } else {
+        format!("[{}, #0x{:x}]", reg(rn, true, true), offset)
+    };
+    Some(format!(
+        "{} {}, {}",
+        name,
+        reg(rt, size == 3, false),
+        address
+    ))
+}
+
+/// `stp`/`ldp`, the workhorses of function prologues and epilogues.
+fn decode_pair(w: u32, rt: u32, rn: u32) -> Option<String> {
+    if bits(w, 26, 1) == 1 {
+        return None;
+    }
+    let wide = match bits(w, 30, 2) {
+        0 => false,
+        2 => true,
+        _ => return None,
+    };
+    let name = if bits(w, 22, 1) == 1 { "ldp" } else { "stp" };
+    let rt2 = bits(w, 10, 5);
+    let offset = signed(bits(w, 15, 7), 7) * if wide { 8 } else { 4 };
+    let base = reg(rn, true, true);
+    let offset_text = if offset < 0 {
+        format!("#-0x{:x}", offset.unsigned_abs())
+    } else {
+        format!("#0x{:x}", offset)
+    };
+
+    let address = match bits(w, 23, 2) {
+        1 => format!("[{}], {}", base, offset_text), // post-index
+        2 if offset == 0 => format!("[{}]", base),   // signed offset
+        2 => format!("[{}, {}]", base, offset_text),
+        3 => format!("[{}, {}]!", base, offset_text), // pre-index
+        _ => return None,
+    };
+    Some(format!(
+        "{} {}, {}, {}",
+        name,
+        reg(rt, wide, false),
+        reg(rt2, wide, false),
+        address
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(word: u32) -> String {
+        decode(&word.to_le_bytes(), 0x1000)
+            .expect("should decode")
+            .text
+    }
+
+    #[test]
+    fn decodes_a_typical_function() {
+        assert_eq!(text(0xa9bf7bfd), "stp x29, x30, [sp, #-0x10]!");
+        assert_eq!(text(0x910003fd), "mov x29, sp");
+        assert_eq!(text(0x52800540), "mov w0, #0x2a");
+        assert_eq!(text(0xf9400be1), "ldr x1, [sp, #0x10]");
+        assert_eq!(text(0xa8c17bfd), "ldp x29, x30, [sp], #0x10");
+        assert_eq!(text(0xd65f03c0), "ret");
+        assert_eq!(text(0xd503201f), "nop");
+    }
+
+    #[test]
+    fn decodes_branches_and_data_processing() {
+        assert_eq!(text(0x94000004), "bl 0x1010");
+        assert_eq!(text(0x17ffffff), "b 0xffc");
+        assert_eq!(text(0x54000040), "b.eq 0x1008");
+        assert_eq!(text(0xb4000041), "cbz x1, 0x1008");
+        assert_eq!(text(0xaa0103e0), "mov x0, x1");
+        assert_eq!(text(0x8a220020), "bic x0, x1, x2");
+        assert_eq!(text(0x2a620c20), "orn w0, w1, w2, lsr #3");
+        assert_eq!(text(0x8b020020), "add x0, x1, x2");
+        assert_eq!(text(0xf100043f), "cmp x1, #0x1");
+        assert_eq!(text(0xd4000001), "svc #0x0");
+        assert_eq!(text(0x90000000), "adrp x0, 0x1000");
+    }
+
+    #[test]
+    fn rejects_unknown_words() {
+        assert_eq!(decode(&0xffffffffu32.to_le_bytes(), 0), None);
+        assert_eq!(decode(&[0x1f, 0x20, 0x03], 0), None);
+    }
+}
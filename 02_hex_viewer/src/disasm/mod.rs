@@ -0,0 +1,141 @@
+// A small disassembler, for when the bytes are code.
+//
+// This is nowhere near a full decoder (x86-64 alone has thousands of
+// instructions). It covers the handful of instructions that make up most
+// compiler output: moves, arithmetic, compares, jumps, calls, pushes and
+// pops. Anything it does not understand is printed as a `db` line with the
+// raw bytes, and decoding carries on with the next byte (x86-64) or word
+// (AArch64).
+
+mod aarch64;
+mod x86_64;
+
+use std::fmt::Write as _;
+use std::io::{self, Write};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Arch {
+    X86_64,
+    Aarch64,
+}
+
+impl Arch {
+    pub fn from_name(name: &str) -> Option<Arch> {
+        match name {
+            "x86-64" | "x86_64" | "amd64" => Some(Arch::X86_64),
+            "aarch64" | "arm64" => Some(Arch::Aarch64),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Arch::X86_64 => "x86-64",
+            Arch::Aarch64 => "aarch64",
+        }
+    }
+}
+
+/// One decoded instruction: how many bytes it used, and its text.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Instruction {
+    pub len: usize,
+    pub text: String,
+}
+
+/// Decodes the instruction at the start of `bytes`, which lives at
+/// `address`. The address is needed to turn relative jumps into targets.
+pub fn decode(arch: Arch, bytes: &[u8], address: u64) -> Option<Instruction> {
+    match arch {
+        Arch::X86_64 => x86_64::decode(bytes, address),
+        Arch::Aarch64 => aarch64::decode(bytes, address),
+    }
+}
+
+/// Walks through `bytes` and returns `(address, length, text)` for every
+/// instruction, with undecodable bytes turned into `db` lines.
+pub fn disassemble(arch: Arch, bytes: &[u8], address: u64) -> Vec<(u64, usize, String)> {
+    // AArch64 instructions are always 4 bytes, so skipping a single byte
+    // would throw every following instruction out of alignment.
+    let skip = match arch {
+        Arch::X86_64 => 1,
+        Arch::Aarch64 => 4,
+    };
+
+    let mut lines = Vec::new();
+    let mut pos = 0;
+    while pos < bytes.len() {
+        let here = address + pos as u64;
+        let (len, text) = match decode(arch, &bytes[pos..], here) {
+            Some(instruction) => (instruction.len, instruction.text),
+            None => {
+                let len = skip.min(bytes.len() - pos);
+                (len, data_directive(&bytes[pos..pos + len]))
+            }
+        };
+        lines.push((here, len, text));
+        pos += len;
+    }
+    lines
+}
+
+fn data_directive(bytes: &[u8]) -> String {
+    let mut text = String::from("db ");
+    for (i, byte) in bytes.iter().enumerate() {
+        if i > 0 {
+            text.push_str(", ");
+        }
+        let _ = write!(text, "0x{:02x}", byte);
+    }
+    text
+}
+
+/// Prints a listing: address, raw bytes, then the instruction.
+///
+/// 00001000  55                          push rbp
+/// 00001001  48 89 e5                    mov rbp, rsp
+pub fn write_listing<W: Write>(
+    out: &mut W,
+    arch: Arch,
+    bytes: &[u8],
+    address: u64,
+) -> io::Result<()> {
+    writeln!(
+        out,
+        "# disassembly ({}) of 0x{:08x}..0x{:08x}",
+        arch.name(),
+        address,
+        address + bytes.len() as u64
+    )?;
+
+    let mut pos = 0;
+    for (here, len, text) in disassemble(arch, bytes, address) {
+        let mut hex = String::new();
+        for byte in &bytes[pos..pos + len] {
+            let _ = write!(hex, "{:02x} ", byte);
+        }
+        writeln!(out, "{:08x}  {:<30}{}", here, hex, text)?;
+        pos += len;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unknown_bytes_become_db_lines() {
+        // 0x06 is not a valid instruction in 64-bit mode.
+        let lines = disassemble(Arch::X86_64, &[0x06, 0xc3], 0x1000);
+        assert_eq!(lines[0], (0x1000, 1, "db 0x06".to_string()));
+        assert_eq!(lines[1], (0x1001, 1, "ret".to_string()));
+    }
+
+    #[test]
+    fn aarch64_skips_whole_words_and_keeps_the_tail() {
+        let lines = disassemble(Arch::Aarch64, &[0xff, 0xff, 0xff, 0xff, 0x1f, 0x20], 0);
+        assert_eq!(lines[0].2, "db 0xff, 0xff, 0xff, 0xff");
+        assert_eq!(lines[1], (4, 2, "db 0x1f, 0x20".to_string()));
+    }
+}
@@ -0,0 +1,622 @@
+// x86-64 decoding (Intel syntax).
+//
+// An x86 instruction is a byte soup:
+//
+//   [prefixes] [REX] opcode [ModRM] [SIB] [displacement] [immediate]
+//
+// * Prefixes change the operand size (0x66) or mean "repeat" (0xf3).
+// * REX (0x40-0x4f) unlocks 64-bit operands (W) and registers r8-r15
+//   (R, X and B each add a fourth bit to a register number).
+// * ModRM says which registers or memory the instruction works on, and
+//   SIB adds "base + index * scale" addressing.
+//
+// We decode just enough of this to read typical compiler output.
+
+use super::Instruction;
+
+const REG64: [&str; 16] = [
+    "rax", "rcx", "rdx", "rbx", "rsp", "rbp", "rsi", "rdi", "r8", "r9", "r10", "r11", "r12", "r13",
+    "r14", "r15",
+];
+const REG32: [&str; 16] = [
+    "eax", "ecx", "edx", "ebx", "esp", "ebp", "esi", "edi", "r8d", "r9d", "r10d", "r11d", "r12d",
+    "r13d", "r14d", "r15d",
+];
+const REG16: [&str; 16] = [
+    "ax", "cx", "dx", "bx", "sp", "bp", "si", "di", "r8w", "r9w", "r10w", "r11w", "r12w", "r13w",
+    "r14w", "r15w",
+];
+// With any REX prefix present, byte registers 4-7 are spl..dil; without
+// one they are the old 8086 high-byte registers ah..bh.
+const REG8_REX: [&str; 16] = [
+    "al", "cl", "dl", "bl", "spl", "bpl", "sil", "dil", "r8b", "r9b", "r10b", "r11b", "r12b",
+    "r13b", "r14b", "r15b",
+];
+const REG8_LEGACY: [&str; 8] = ["al", "cl", "dl", "bl", "ah", "ch", "dh", "bh"];
+
+const CONDITIONS: [&str; 16] = [
+    "o", "no", "b", "ae", "e", "ne", "be", "a", "s", "ns", "p", "np", "l", "ge", "le", "g",
+];
+const ALU: [&str; 8] = ["add", "or", "adc", "sbb", "and", "sub", "xor", "cmp"];
+const SHIFTS: [&str; 8] = ["rol", "ror", "rcl", "rcr", "shl", "shr", "sal", "sar"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Size {
+    Byte,
+    Word,
+    Dword,
+    Qword,
+}
+
+impl Size {
+    fn ptr(self) -> &'static str {
+        match self {
+            Size::Byte => "byte ptr",
+            Size::Word => "word ptr",
+            Size::Dword => "dword ptr",
+            Size::Qword => "qword ptr",
+        }
+    }
+}
+
+/// The r/m half of a ModRM byte: a register, or a memory operand such as
+/// `[rbp-0x8]` (without its size, which depends on the instruction).
+enum Operand {
+    Register(usize),
+    Memory(String),
+}
+
+/// The decoded fields of a ModRM byte (plus SIB and displacement).
+struct ModRm {
+    /// The `reg` field, already extended with REX.R.
+    reg: usize,
+    /// The `reg` field as written, 0-7. Group opcodes use it to pick the
+    /// operation instead of a register.
+    ext: usize,
+    rm: Operand,
+}
+
+struct Decoder<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+    rex: Option<u8>,
+    operand16: bool,
+}
+
+impl<'a> Decoder<'a> {
+    fn u8(&mut self) -> Option<u8> {
+        let byte = *self.bytes.get(self.pos)?;
+        self.pos += 1;
+        Some(byte)
+    }
+
+    fn take<const N: usize>(&mut self) -> Option<[u8; N]> {
+        let bytes = self.bytes.get(self.pos..self.pos + N)?.try_into().ok()?;
+        self.pos += N;
+        Some(bytes)
+    }
+
+    fn i8(&mut self) -> Option<i64> {
+        Some(self.u8()? as i8 as i64)
+    }
+
+    fn i16(&mut self) -> Option<i64> {
+        Some(i16::from_le_bytes(self.take()?) as i64)
+    }
+
+    fn i32(&mut self) -> Option<i64> {
+        Some(i32::from_le_bytes(self.take()?) as i64)
+    }
+
+    fn rex_bit(&self, mask: u8) -> usize {
+        match self.rex {
+            Some(rex) if rex & mask != 0 => 8,
+            _ => 0,
+        }
+    }
+
+    /// The size of a "v" operand: 64-bit with REX.W, 16-bit with 0x66,
+    /// otherwise 32-bit.
+    fn v(&self) -> Size {
+        if self.rex_bit(0x08) != 0 {
+            Size::Qword
+        } else if self.operand16 {
+            Size::Word
+        } else {
+            Size::Dword
+        }
+    }
+
+    fn reg(&self, n: usize, size: Size) -> &'static str {
+        match size {
+            Size::Qword => REG64[n],
+            Size::Dword => REG32[n],
+            Size::Word => REG16[n],
+            Size::Byte if self.rex.is_some() => REG8_REX[n],
+            Size::Byte => REG8_LEGACY.get(n).copied().unwrap_or(REG8_REX[n]),
+        }
+    }
+
+    /// Reads an immediate of the size used by "z" operands: 16 bits with
+    /// the 0x66 prefix, otherwise 32 bits (sign-extended for 64-bit ops).
+    fn imm_z(&mut self) -> Option<i64> {
+        if self.operand16 {
+            self.i16()
+        } else {
+            self.i32()
+        }
+    }
+
+    fn modrm(&mut self) -> Option<ModRm> {
+        let byte = self.u8()?;
+        let mode = byte >> 6;
+        let ext = ((byte >> 3) & 7) as usize;
+        let rm = (byte & 7) as usize;
+        let reg = ext + self.rex_bit(0x04);
+
+        if mode == 3 {
+            return Some(ModRm {
+                reg,
+                ext,
+                rm: Operand::Register(rm + self.rex_bit(0x01)),
+            });
+        }
+
+        let mut base = Some(rm + self.rex_bit(0x01));
+        let mut index = None;
+        let mut scale = 1;
+
+        if rm == 4 {
+            // A SIB byte follows: scale, index and base.
+            let sib = self.u8()?;
+            scale = 1 << (sib >> 6);
+            let idx = ((sib >> 3) & 7) as usize + self.rex_bit(0x02);
+            if idx != 4 {
+                index = Some(idx);
+            }
+            let sib_base = (sib & 7) as usize;
+            base = Some(sib_base + self.rex_bit(0x01));
+            if sib_base == 5 && mode == 0 {
+                base = None;
+            }
+        } else if rm == 5 && mode == 0 {
+            // No base register: the address is relative to the next instruction.
+            let disp = self.i32()?;
+            let memory = format!("[rip{}]", signed_hex(disp, true));
+            return Some(ModRm {
+                reg,
+                ext,
+                rm: Operand::Memory(memory),
+            });
+        }
+
+        let disp = match (mode, base) {
+            (1, _) => self.i8()?,
+            (2, _) | (0, None) => self.i32()?,
+            _ => 0,
+        };
+
+        let mut parts = Vec::new();
+        if let Some(base) = base {
+            parts.push(REG64[base].to_string());
+        }
+        if let Some(index) = index {
+            parts.push(if scale > 1 {
+                format!("{}*{}", REG64[index], scale)
+            } else {
+                REG64[index].to_string()
+            });
+        }
+        let mut text = format!("[{}", parts.join("+"));
+        if disp != 0 || parts.is_empty() {
+            text.push_str(&signed_hex(disp, !parts.is_empty()));
+        }
+        text.push(']');
+        Some(ModRm {
+            reg,
+            ext,
+            rm: Operand::Memory(text),
+        })
+    }
+
+    /// Formats the r/m half of a ModRM operand at the given size.
+    fn rm(&self, modrm: &ModRm, size: Size) -> String {
+        match &modrm.rm {
+            Operand::Memory(memory) => format!("{} {}", size.ptr(), memory),
+            Operand::Register(reg) => self.reg(*reg, size).to_string(),
+        }
+    }
+
+    fn target(&self, address: u64, rel: i64) -> String {
+        let next = address.wrapping_add(self.pos as u64);
+        format!("0x{:x}", next.wrapping_add(rel as u64))
+    }
+}
+
+/// `0x10`, `-0x10`, or with `plus` set `+0x10`, for displacements.
+fn signed_hex(value: i64, plus: bool) -> String {
+    if value < 0 {
+        format!("-0x{:x}", value.unsigned_abs())
+    } else if plus {
+        format!("+0x{:x}", value)
+    } else {
+        format!("0x{:x}", value)
+    }
+}
+
+pub fn decode(bytes: &[u8], address: u64) -> Option<Instruction> {
+    let mut d = Decoder {
+        bytes,
+        pos: 0,
+        rex: None,
+        operand16: false,
+    };
+    let mut rep = false;
+
+    // Legacy prefixes come first, then at most one REX byte right before
+    // the opcode.
+    let mut op = d.u8()?;
+    loop {
+        match op {
+            0x66 => d.operand16 = true,
+            0xf3 => rep = true,
+            _ => break,
+        }
+        op = d.u8()?;
+    }
+    if (0x40..=0x4f).contains(&op) {
+        d.rex = Some(op);
+        op = d.u8()?;
+    }
+
+    let text = match op {
+        // The eight classic ALU operations share one layout:
+        // op r/m8,r8 | op r/m,r | op r8,r/m8 | op r,r/m | op al,imm8 | op eax,imm
+        0x00..=0x3f if op & 7 < 6 => {
+            let name = ALU[(op >> 3) as usize];
+            let v = d.v();
+            match op & 7 {
+                0 => {
+                    let m = d.modrm()?;
+                    format!(
+                        "{} {}, {}",
+                        name,
+                        d.rm(&m, Size::Byte),
+                        d.reg(m.reg, Size::Byte)
+                    )
+                }
+                1 => {
+                    let m = d.modrm()?;
+                    format!("{} {}, {}", name, d.rm(&m, v), d.reg(m.reg, v))
+                }
+                2 => {
+                    let m = d.modrm()?;
+                    format!(
+                        "{} {}, {}",
+                        name,
+                        d.reg(m.reg, Size::Byte),
+                        d.rm(&m, Size::Byte)
+                    )
+                }
+                3 => {
+                    let m = d.modrm()?;
+                    format!("{} {}, {}", name, d.reg(m.reg, v), d.rm(&m, v))
+                }
+                4 => format!("{} al, {}", name, signed_hex(d.i8()?, false)),
+                _ => format!(
+                    "{} {}, {}",
+                    name,
+                    d.reg(0, v),
+                    signed_hex(d.imm_z()?, false)
+                ),
+            }
+        }
+        0x50..=0x57 => format!("push {}", REG64[(op - 0x50) as usize + d.rex_bit(0x01)]),
+        0x58..=0x5f => format!("pop {}", REG64[(op - 0x58) as usize + d.rex_bit(0x01)]),
+        0x63 => {
+            let m = d.modrm()?;
+            format!("movsxd {}, {}", d.reg(m.reg, d.v()), d.rm(&m, Size::Dword))
+        }
+        0x68 => format!("push {}", signed_hex(d.i32()?, false)),
+        0x6a => format!("push {}", signed_hex(d.i8()?, false)),
+        0x69 | 0x6b => {
+            let m = d.modrm()?;
+            let v = d.v();
+            let imm = if op == 0x69 { d.imm_z()? } else { d.i8()? };
+            format!(
+                "imul {}, {}, {}",
+                d.reg(m.reg, v),
+                d.rm(&m, v),
+                signed_hex(imm, false)
+            )
+        }
+        0x70..=0x7f => {
+            let rel = d.i8()?;
+            format!(
+                "j{} {}",
+                CONDITIONS[(op & 0xf) as usize],
+                d.target(address, rel)
+            )
+        }
+        0x80 | 0x81 | 0x83 => {
+            let m = d.modrm()?;
+            let size = if op == 0x80 { Size::Byte } else { d.v() };
+            let imm = if op == 0x81 { d.imm_z()? } else { d.i8()? };
+            format!(
+                "{} {}, {}",
+                ALU[m.ext],
+                d.rm(&m, size),
+                signed_hex(imm, false)
+            )
+        }
+        0x84..=0x89 => {
+            let m = d.modrm()?;
+            let size = if op & 1 == 0 { Size::Byte } else { d.v() };
+            let name = match op {
+                0x84 | 0x85 => "test",
+                0x86 | 0x87 => "xchg",
+                _ => "mov",
+            };
+            format!("{} {}, {}", name, d.rm(&m, size), d.reg(m.reg, size))
+        }
+        0x8a | 0x8b => {
+            let m = d.modrm()?;
+            let size = if op == 0x8a { Size::Byte } else { d.v() };
+            format!("mov {}, {}", d.reg(m.reg, size), d.rm(&m, size))
+        }
+        0x8d => {
+            let m = d.modrm()?;
+            // lea only makes sense with a memory operand.
+            match &m.rm {
+                Operand::Memory(memory) => format!("lea {}, {}", d.reg(m.reg, d.v()), memory),
+                Operand::Register(_) => return None,
+            }
+        }
+        0x90 if d.rex_bit(0x01) == 0 => if rep { "pause" } else { "nop" }.to_string(),
+        0x90..=0x97 => {
+            let v = d.v();
+            format!(
+                "xchg {}, {}",
+                d.reg(0, v),
+                d.reg((op - 0x90) as usize + d.rex_bit(0x01), v)
+            )
+        }
+        0x98 => match d.v() {
+            Size::Qword => "cdqe",
+            Size::Word => "cbw",
+            _ => "cwde",
+        }
+        .to_string(),
+        0x99 => match d.v() {
+            Size::Qword => "cqo",
+            Size::Word => "cwd",
+            _ => "cdq",
+        }
+        .to_string(),
+        0xa8 => format!("test al, {}", signed_hex(d.i8()?, false)),
+        0xa9 => {
+            let v = d.v();
+            format!("test {}, {}", d.reg(0, v), signed_hex(d.imm_z()?, false))
+        }
+        0xb0..=0xb7 => {
+            let reg = (op - 0xb0) as usize + d.rex_bit(0x01);
+            format!("mov {}, 0x{:x}", d.reg(reg, Size::Byte), d.u8()?)
+        }
+        0xb8..=0xbf => {
+            let reg = (op - 0xb8) as usize + d.rex_bit(0x01);
+            match d.v() {
+                // The one x86-64 instruction with a full 64-bit immediate.
+                Size::Qword => format!(
+                    "movabs {}, 0x{:x}",
+                    REG64[reg],
+                    u64::from_le_bytes(d.take()?)
+                ),
+                Size::Word => format!("mov {}, 0x{:x}", REG16[reg], u16::from_le_bytes(d.take()?)),
+                _ => format!("mov {}, 0x{:x}", REG32[reg], u32::from_le_bytes(d.take()?)),
+            }
+        }
+        0xc0 | 0xc1 | 0xd0 | 0xd1 | 0xd2 | 0xd3 => {
+            let m = d.modrm()?;
+            let size = if op & 1 == 0 { Size::Byte } else { d.v() };
+            let count = match op {
+                0xc0 | 0xc1 => format!("0x{:x}", d.u8()?),
+                0xd0 | 0xd1 => "1".to_string(),
+                _ => "cl".to_string(),
+            };
+            format!("{} {}, {}", SHIFTS[m.ext], d.rm(&m, size), count)
+        }
+        0xc2 => format!("ret 0x{:x}", u16::from_le_bytes(d.take()?)),
+        0xc3 => if rep { "repz ret" } else { "ret" }.to_string(),
+        0xc6 | 0xc7 => {
+            let m = d.modrm()?;
+            if m.ext != 0 {
+                return None;
+            }
+            let size = if op == 0xc6 { Size::Byte } else { d.v() };
+            let imm = if op == 0xc6 { d.i8()? } else { d.imm_z()? };
+            format!("mov {}, {}", d.rm(&m, size), signed_hex(imm, false))
+        }
+        0xc9 => "leave".to_string(),
+        0xcc => "int3".to_string(),
+        0xcd => format!("int 0x{:x}", d.u8()?),
+        0xe8 => {
+            let rel = d.i32()?;
+            format!("call {}", d.target(address, rel))
+        }
+        0xe9 => {
+            let rel = d.i32()?;
+            format!("jmp {}", d.target(address, rel))
+        }
+        0xeb => {
+            let rel = d.i8()?;
+            format!("jmp {}", d.target(address, rel))
+        }
+        0xf4 => "hlt".to_string(),
+        0xf6 | 0xf7 => {
+            let m = d.modrm()?;
+            let size = if op == 0xf6 { Size::Byte } else { d.v() };
+            match m.ext {
+                0 | 1 => {
+                    let imm = if op == 0xf6 { d.i8()? } else { d.imm_z()? };
+                    format!("test {}, {}", d.rm(&m, size), signed_hex(imm, false))
+                }
+                ext => {
+                    let name = ["", "", "not", "neg", "mul", "imul", "div", "idiv"][ext];
+                    format!("{} {}", name, d.rm(&m, size))
+                }
+            }
+        }
+        0xfe => {
+            let m = d.modrm()?;
+            let name = ["inc", "dec"].get(m.ext)?;
+            format!("{} {}", name, d.rm(&m, Size::Byte))
+        }
+        0xff => {
+            let m = d.modrm()?;
+            match m.ext {
+                0 => format!("inc {}", d.rm(&m, d.v())),
+                1 => format!("dec {}", d.rm(&m, d.v())),
+                // Indirect calls, jumps and pushes are always 64-bit.
+                2 => format!("call {}", d.rm(&m, Size::Qword)),
+                4 => format!("jmp {}", d.rm(&m, Size::Qword)),
+                6 => format!("push {}", d.rm(&m, Size::Qword)),
+                _ => return None,
+            }
+        }
+        0x0f => decode_two_byte(&mut d, address, rep)?,
+        _ => return None,
+    };
+
+    Some(Instruction { len: d.pos, text })
+}
+
+/// Opcodes that start with the 0x0f escape byte.
+fn decode_two_byte(d: &mut Decoder, address: u64, rep: bool) -> Option<String> {
+    let op = d.u8()?;
+    let text = match op {
+        0x05 => "syscall".to_string(),
+        0x0b => "ud2".to_string(),
+        0x1e if rep => {
+            // endbr64 is f3 0f 1e fa: a "landing pad" for indirect branches.
+            if d.u8()? != 0xfa {
+                return None;
+            }
+            "endbr64".to_string()
+        }
+        0x1f => {
+            // The recommended multi-byte nop: 0f 1f /0 with any operand.
+            let m = d.modrm()?;
+            format!("nop {}", d.rm(&m, d.v()))
+        }
+        0x40..=0x4f => {
+            let m = d.modrm()?;
+            let v = d.v();
+            format!(
+                "cmov{} {}, {}",
+                CONDITIONS[(op & 0xf) as usize],
+                d.reg(m.reg, v),
+                d.rm(&m, v)
+            )
+        }
+        0x80..=0x8f => {
+            let rel = d.i32()?;
+            format!(
+                "j{} {}",
+                CONDITIONS[(op & 0xf) as usize],
+                d.target(address, rel)
+            )
+        }
+        0x90..=0x9f => {
+            let m = d.modrm()?;
+            format!(
+                "set{} {}",
+                CONDITIONS[(op & 0xf) as usize],
+                d.rm(&m, Size::Byte)
+            )
+        }
+        0xa2 => "cpuid".to_string(),
+        0xaf => {
+            let m = d.modrm()?;
+            let v = d.v();
+            format!("imul {}, {}", d.reg(m.reg, v), d.rm(&m, v))
+        }
+        0xb6 | 0xb7 | 0xbe | 0xbf => {
+            let m = d.modrm()?;
+            let name = if op < 0xb8 { "movzx" } else { "movsx" };
+            let from = if op & 1 == 0 { Size::Byte } else { Size::Word };
+            format!("{} {}, {}", name, d.reg(m.reg, d.v()), d.rm(&m, from))
+        }
+        _ => return None,
+    };
+    Some(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(bytes: &[u8]) -> String {
+        let instruction = decode(bytes, 0x1000).expect("should decode");
+        assert_eq!(
+            instruction.len,
+            bytes.len(),
+            "length of {}",
+            instruction.text
+        );
+        instruction.text
+    }
+
+    #[test]
+    fn decodes_a_typical_function_prologue() {
+        assert_eq!(text(&[0xf3, 0x0f, 0x1e, 0xfa]), "endbr64");
+        assert_eq!(text(&[0x55]), "push rbp");
+        assert_eq!(text(&[0x48, 0x89, 0xe5]), "mov rbp, rsp");
+        assert_eq!(text(&[0x48, 0x83, 0xec, 0x10]), "sub rsp, 0x10");
+        assert_eq!(text(&[0x89, 0x7d, 0xfc]), "mov dword ptr [rbp-0x4], edi");
+        assert_eq!(text(&[0x41, 0x57]), "push r15");
+        assert_eq!(text(&[0xc9]), "leave");
+        assert_eq!(text(&[0xc3]), "ret");
+    }
+
+    #[test]
+    fn decodes_memory_operands() {
+        assert_eq!(
+            text(&[0x48, 0x8b, 0x04, 0xc8]),
+            "mov rax, qword ptr [rax+rcx*8]"
+        );
+        assert_eq!(
+            text(&[0x48, 0x8d, 0x05, 0x10, 0x00, 0x00, 0x00]),
+            "lea rax, [rip+0x10]"
+        );
+        assert_eq!(
+            text(&[0xc7, 0x45, 0xf8, 0x01, 0x00, 0x00, 0x00]),
+            "mov dword ptr [rbp-0x8], 0x1"
+        );
+        assert_eq!(text(&[0x0f, 0xb6, 0x07]), "movzx eax, byte ptr [rdi]");
+        assert_eq!(text(&[0x40, 0x88, 0xf0]), "mov al, sil");
+    }
+
+    #[test]
+    fn resolves_branch_targets() {
+        assert_eq!(text(&[0xe8, 0xfb, 0xff, 0xff, 0xff]), "call 0x1000");
+        assert_eq!(text(&[0x74, 0x02]), "je 0x1004");
+        assert_eq!(text(&[0x0f, 0x85, 0x00, 0x01, 0x00, 0x00]), "jne 0x1106");
+        assert_eq!(text(&[0xff, 0xe0]), "jmp rax");
+    }
+
+    #[test]
+    fn decodes_immediates_and_groups() {
+        assert_eq!(text(&[0x31, 0xc0]), "xor eax, eax");
+        assert_eq!(text(&[0xb8, 0x3c, 0x00, 0x00, 0x00]), "mov eax, 0x3c");
+        assert_eq!(text(&[0x48, 0xc1, 0xe0, 0x04]), "shl rax, 0x4");
+        assert_eq!(text(&[0x48, 0xf7, 0xd8]), "neg rax");
+        assert_eq!(text(&[0x0f, 0x05]), "syscall");
+    }
+
+    #[test]
+    fn truncated_or_unknown_input_does_not_decode() {
+        assert_eq!(decode(&[0xe8, 0x00], 0), None);
+        assert_eq!(decode(&[0x0f, 0xff], 0), None);
+        assert_eq!(decode(&[], 0), None);
+    }
+}
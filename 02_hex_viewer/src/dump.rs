@@ -0,0 +1,668 @@
+// The text dump, built for speed.
+//
+// The first version of this program called `print!` once per byte. That
+// is fine for a 1 KB file, but every `print!` locks stdout and goes through
+// the formatting machinery, so a 10 GB disk image took forever. This module
+// does three things differently:
+//
+// 1. BIG READS: we read the input in multi-megabyte blocks instead of one
+//    line at a time.
+// 2. NO FORMATTING IN THE HOT LOOP: lines are assembled into a reusable
+//    `Vec<u8>` with a hex lookup table, and written out with one
+//    `write_all` per block.
+// 3. PARALLEL RENDERING: each line only depends on its own 16 bytes and
+//    its offset, so a block is split into slices that are rendered on
+//    separate threads. We then write the slices out in order, so the
+//    output is byte-for-byte the same as with a single thread.
+//
+// Text encodings like UTF-8 complicate point 3 a little: a character can
+// start on one line and end on the next, even across blocks. So each block
+// sits in a "window" with a few bytes of context on both sides: the tail of
+// the previous block (already transformed) and the first bytes of the next
+// one, which we read early and keep for the next round.
+//
+// Why not memory-map the file? `mmap` would save one copy, but it needs
+// `unsafe` code or an extra crate, and does not work for pipes like
+// `cat image | hex_viewer /dev/stdin`. Large reads get us most of the way.
+
+use std::io::{self, Read, Write};
+use std::ops::Range;
+use std::thread;
+
+use crate::annotate::Annotation;
+use crate::config::{MAX_WIDTH, OffsetBase, Settings};
+use crate::json;
+use crate::printable;
+use crate::text::{self, CONTEXT, Encoding};
+use crate::transform::{self, Transform};
+
+/// How much we read from the input at a time, at most. The real block is
+/// rounded down to a whole number of lines, so that every block starts on
+/// a fresh line.
+pub const BLOCK_SIZE: usize = 4 * 1024 * 1024;
+
+/// How many bytes before a block we keep. The text decoder needs
+/// `CONTEXT` of them, and `--squeeze` compares a line with the two before
+/// it, which may both belong to the previous block.
+const BEHIND: usize = 2 * MAX_WIDTH;
+
+/// Blocks smaller than this are rendered on the calling thread: starting
+/// threads costs more than it saves for a handful of lines.
+const MIN_PARALLEL_SLICE: usize = 64 * 1024;
+
+const HEX_DIGITS: &[u8; 16] = b"0123456789abcdef";
+const RESET: &[u8] = b"\x1b[0m";
+const NO_COLORS: [Option<&str>; MAX_WIDTH] = [None; MAX_WIDTH];
+
+/// Everything that decides how a dump looks.
+pub struct DumpOptions<'a> {
+    /// File position of the first byte the reader produces.
+    pub start: usize,
+    pub annotations: &'a [Annotation],
+    pub transforms: &'a [Transform],
+    /// Wrap annotated bytes in ANSI colors (when the theme has any).
+    pub color: bool,
+    /// How many threads render lines. 1 disables parallel rendering.
+    pub threads: usize,
+    /// Width, grouping, theme, offset base, squeezing and text encoding.
+    pub settings: &'a Settings,
+    /// Write NDJSON `line` records instead of text (see `json.rs`).
+    pub json: bool,
+    /// Added to every offset shown (`--base-addr`). JSON records keep
+    /// plain file offsets.
+    pub base_addr: usize,
+    /// Show each offset from the start of the last annotation at or before
+    /// it, like distances from a bookmark (`--relative`). Lines before the
+    /// first annotation get a blank offset.
+    pub relative: bool,
+    /// The offset column is at least this wide, so that it does not grow
+    /// halfway through a big file. See `offset_width`.
+    pub offset_digits: usize,
+}
+
+/// Dumps everything `reader` produces to `out`.
+pub fn dump<R: Read, W: Write>(
+    mut reader: R,
+    out: &mut W,
+    options: &DumpOptions,
+) -> io::Result<()> {
+    let threads = options.threads.max(1);
+    let encoding = options.settings.encoding;
+    let block_len = BLOCK_SIZE - BLOCK_SIZE % options.settings.width;
+    // The window is laid out as [behind | block | ahead]. Single-byte
+    // encodings never need to look ahead, so `ahead` stays empty for them.
+    let mut window = vec![0u8; BEHIND + block_len + CONTEXT];
+    let mut behind = 0;
+    let mut carried = 0;
+    // One output buffer per thread. They are cleared, not freed, between
+    // blocks, so after the first block we stop allocating entirely.
+    let mut outputs: Vec<Vec<u8>> = vec![Vec::new(); threads];
+    let mut offset = options.start;
+
+    loop {
+        // The first `carried` bytes of the block were read last time round.
+        let body = BEHIND..BEHIND + block_len;
+        let n = carried + read_block(&mut reader, &mut window[body.start + carried..body.end])?;
+        if n == 0 {
+            break;
+        }
+
+        // Peek at the next block, keeping an untransformed copy for later.
+        let mut ahead = 0;
+        let mut raw_ahead = [0u8; CONTEXT];
+        if encoding.is_multi_byte() && n == block_len {
+            ahead = read_block(&mut reader, &mut window[body.end..])?;
+            raw_ahead[..ahead].copy_from_slice(&window[body.end..body.end + ahead]);
+        }
+
+        // Files too big for `--base-addr` are turned down before we get
+        // here, but some files (like those in /proc) don't say how long
+        // they are.
+        if (offset + n).checked_add(options.base_addr).is_none() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "--base-addr 0x{:x} is too large: the input goes past address 0x{:x}",
+                    options.base_addr,
+                    usize::MAX
+                ),
+            ));
+        }
+
+        let used = BEHIND - behind..BEHIND + n + ahead;
+        render_block(&mut window[used], behind, n, offset, &mut outputs, options);
+        for output in &outputs {
+            out.write_all(output)?;
+        }
+
+        // The end of this block becomes the context before the next one.
+        behind = n.min(BEHIND);
+        window.copy_within(BEHIND + n - behind..BEHIND + n, BEHIND - behind);
+        window[BEHIND..BEHIND + ahead].copy_from_slice(&raw_ahead[..ahead]);
+        carried = ahead;
+        offset += n;
+    }
+
+    // Squeezed output may end in a `*`, so say where the data ends.
+    if options.settings.squeeze && !options.json && offset > options.start {
+        let mut end = Vec::new();
+        write_offset(&mut end, offset, options);
+        end.push(b'\n');
+        out.write_all(&end)?;
+    }
+    out.flush()
+}
+
+/// Fills `block` as far as possible. A single `read` may legally return
+/// fewer bytes than asked for (pipes do this all the time), and a short
+/// block in the middle of a file would break our line alignment.
+fn read_block<R: Read>(reader: &mut R, block: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < block.len() {
+        match reader.read(&mut block[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
+
+/// Transforms and renders one block, leaving the text in `outputs` in
+/// display order. Unused output buffers are left empty.
+///
+/// `window` holds `behind` bytes of (already transformed) context, then the
+/// `len` bytes of the block, which start at file position `offset`, then
+/// whatever is left as context after it.
+fn render_block(
+    window: &mut [u8],
+    behind: usize,
+    len: usize,
+    offset: usize,
+    outputs: &mut [Vec<u8>],
+    options: &DumpOptions,
+) {
+    for output in outputs.iter_mut() {
+        output.clear();
+    }
+
+    let block = behind..behind + len;
+    let window_offset = offset - behind;
+    transform::apply_all(options.transforms, &mut window[block.end..], offset + len);
+
+    // Split the block into one slice per thread, each a whole number of lines.
+    let width = options.settings.width;
+    let lines = len.div_ceil(width);
+    let slice_len = lines.div_ceil(outputs.len()) * width;
+
+    if outputs.len() == 1 || slice_len < MIN_PARALLEL_SLICE {
+        transform::apply_all(options.transforms, &mut window[block.clone()], offset);
+        render_lines(&mut outputs[0], window, block, window_offset, options);
+        return;
+    }
+
+    // `thread::scope` lets the threads borrow `window` and `outputs`: the
+    // compiler knows they all finish before the scope ends. We transform
+    // everything before rendering anything, because a line may need to
+    // look at the first bytes of the next thread's slice.
+    thread::scope(|scope| {
+        for (i, slice) in window[block.clone()].chunks_mut(slice_len).enumerate() {
+            scope.spawn(move || {
+                transform::apply_all(options.transforms, slice, offset + i * slice_len);
+            });
+        }
+    });
+
+    let window = &*window;
+    thread::scope(|scope| {
+        for (i, output) in outputs.iter_mut().enumerate() {
+            let start = block.start + i * slice_len;
+            if start >= block.end {
+                break;
+            }
+            let lines = start..(start + slice_len).min(block.end);
+            scope.spawn(move || render_lines(output, window, lines, window_offset, options));
+        }
+    });
+}
+
+/// Renders the bytes `window[lines]` as dump lines. `window_offset` is
+/// the file position of `window[0]`; the bytes around `lines` are only
+/// looked at to decode characters that cross a line boundary, and to
+/// squeeze repeated lines.
+pub fn render_lines(
+    out: &mut Vec<u8>,
+    window: &[u8],
+    lines: Range<usize>,
+    window_offset: usize,
+    options: &DumpOptions,
+) {
+    let width = options.settings.width;
+    for line_start in lines.clone().step_by(width) {
+        let line = line_start..(line_start + width).min(lines.end);
+        let line_offset = window_offset + line_start;
+        if options.json {
+            let text = text_column(window, line.clone(), window_offset, options);
+            json::write_line(out, line_offset, &window[line], &text);
+            continue;
+        }
+
+        // A run of repeated lines becomes a single `*`, printed in place
+        // of the first repeat. This only needs the lines just before this
+        // one, so every thread can decide for its own lines.
+        if repeats(window, line.clone(), window_offset, options) {
+            let previous = line_start - width..line_start;
+            if !repeats(window, previous, window_offset, options) {
+                out.extend_from_slice(b"*\n");
+            }
+            continue;
+        }
+
+        write_line(out, window, line.clone(), window_offset, options);
+        write_annotation_notes(out, line.len(), line_offset, options);
+    }
+}
+
+/// True if `--squeeze` hides the line `window[line]`: it is a full line,
+/// equal to the line before it, and no annotation starts on it.
+fn repeats(window: &[u8], line: Range<usize>, window_offset: usize, options: &DumpOptions) -> bool {
+    let width = options.settings.width;
+    if !options.settings.squeeze || line.len() < width || line.start < width {
+        return false;
+    }
+    let offset = window_offset + line.start;
+    window[line.start - width..line.start] == window[line.clone()]
+        && !options
+            .annotations
+            .iter()
+            .any(|a| a.start >= offset && a.start < offset + width)
+}
+
+/// Formats a single line of the hex dump.
+///
+/// Format:
+/// OFFSET   HEX BYTES                  ASCII
+/// 00000000 48 65 6c 6c 6f 20 57 6f... Hello Wo...
+fn write_line(
+    out: &mut Vec<u8>,
+    window: &[u8],
+    line: Range<usize>,
+    window_offset: usize,
+    options: &DumpOptions,
+) {
+    let bytes = &window[line.clone()];
+    let offset = window_offset + line.start;
+    let settings = options.settings;
+    // Most dumps have no colors at all, so we skip building the table
+    // unless there is something to paint.
+    let painted;
+    let colors = if options.color {
+        painted = line_colors(bytes.len(), offset, options);
+        &painted
+    } else {
+        &NO_COLORS
+    };
+
+    // The offset (where we are in the file), padded to 8 digits.
+    write_offset(out, offset, options);
+    out.extend_from_slice(b"  ");
+
+    // The Hexadecimal representation
+    for (i, &color) in colors.iter().enumerate().take(settings.width) {
+        match bytes.get(i) {
+            Some(&byte) => {
+                let hex = [
+                    HEX_DIGITS[(byte >> 4) as usize],
+                    HEX_DIGITS[(byte & 0xf) as usize],
+                ];
+                paint(out, &hex, color);
+                out.push(b' ');
+            }
+            None => out.extend_from_slice(b"   "), // Padding for partial lines
+        }
+
+        // An extra space between groups for readability
+        if settings.ends_group(i) {
+            out.push(b' ');
+        }
+    }
+
+    out.extend_from_slice(b" |");
+
+    // The text representation. In ASCII, `printable` only ever returns
+    // ASCII, so every character is exactly one byte: the fast path.
+    if settings.encoding == Encoding::Ascii {
+        for (i, &byte) in bytes.iter().enumerate() {
+            paint(out, &[printable(byte) as u8], colors[i]);
+        }
+    } else {
+        let dump_offset = window_offset - options.start;
+        let cells = text::cells(settings.encoding, window, dump_offset, line);
+        let mut utf8 = [0u8; 4];
+        for (cell, &color) in cells.iter().zip(colors) {
+            // `None` is the second half of a wide character: draw nothing.
+            if let Some(c) = cell {
+                paint(out, c.encode_utf8(&mut utf8).as_bytes(), color);
+            }
+        }
+    }
+
+    out.extend_from_slice(b"|\n");
+}
+
+/// The text column of a line as a plain string, without colors.
+fn text_column(
+    window: &[u8],
+    line: Range<usize>,
+    window_offset: usize,
+    options: &DumpOptions,
+) -> String {
+    let dump_offset = window_offset - options.start;
+    text::cells(options.settings.encoding, window, dump_offset, line)
+        .into_iter()
+        .flatten()
+        .collect()
+}
+
+/// The offset shown for file position `offset`: counted from the last
+/// annotation in `--relative` mode, otherwise moved by `--base-addr`.
+/// `None` for lines before the first annotation in `--relative` mode:
+/// there is nothing to count from, and an absolute offset there would look
+/// just like a relative one.
+fn shown_offset(offset: usize, options: &DumpOptions) -> Option<usize> {
+    if options.relative {
+        // Annotations are sorted by start (see `annotate::merge`), so the
+        // last one at or before `offset` is just before the partition.
+        let before = options.annotations.partition_point(|a| a.start <= offset);
+        let bookmark = before.checked_sub(1)?;
+        return Some(offset - options.annotations[bookmark].start);
+    }
+    // `dump` checked that this fits.
+    Some(offset + options.base_addr)
+}
+
+/// Writes the offset shown for `offset`, as lowercase hex or as decimal,
+/// padded with zeros to the offset column width. Lines with no offset to
+/// show (see `shown_offset`) get a blank column.
+pub fn write_offset(out: &mut Vec<u8>, offset: usize, options: &DumpOptions) {
+    let base = options.settings.offset_base;
+    let Some(shown) = shown_offset(offset, options) else {
+        out.resize(out.len() + options.offset_digits.max(8), b' ');
+        return;
+    };
+    let digits = offset_width(shown, base).max(options.offset_digits);
+    match base {
+        OffsetBase::Hex => {
+            for shift in (0..digits).rev() {
+                out.push(HEX_DIGITS[(shown >> (shift * 4)) & 0xf]);
+            }
+        }
+        // Writing into a `Vec` cannot fail.
+        OffsetBase::Dec => {
+            let _ = write!(out, "{:0digits$}", shown);
+        }
+    }
+}
+
+/// Where annotation `a` starts and ends, as addresses from `--base-addr`.
+/// An annotation may go on past the end of the input, and so past the
+/// last address: those stop at `usize::MAX` instead of wrapping to 0.
+pub fn shown_range(a: &Annotation, options: &DumpOptions) -> (usize, usize) {
+    (
+        a.start.saturating_add(options.base_addr),
+        a.end().saturating_add(options.base_addr),
+    )
+}
+
+/// How many characters `offset` needs, but never fewer than 8, like
+/// `hexdump`. Past 4 GiB (or 100 MB in decimal) that is more than 8, so
+/// the caller works out the width for the last offset up front: that keeps
+/// every line of a big dump aligned.
+pub fn offset_width(offset: usize, base: OffsetBase) -> usize {
+    let digits = match base {
+        OffsetBase::Hex => (usize::BITS - offset.leading_zeros()).div_ceil(4) as usize,
+        OffsetBase::Dec => offset.checked_ilog10().map_or(1, |n| n as usize + 1),
+    };
+    digits.max(8)
+}
+
+/// Works out which color escape (if any) each byte of the line is drawn
+/// in. As in the HTML view, the annotation listed last wins on overlaps.
+fn line_colors(
+    len: usize,
+    offset: usize,
+    options: &DumpOptions,
+) -> [Option<&'static str>; MAX_WIDTH] {
+    let mut colors = NO_COLORS;
+    for a in options.annotations {
+        let start = a.start.max(offset);
+        let end = a.end().min(offset + len);
+        for color in colors
+            .iter_mut()
+            .take(end.saturating_sub(offset))
+            .skip(start - offset)
+        {
+            *color = options.settings.theme.ansi(a.color);
+        }
+    }
+    colors
+}
+
+fn paint(out: &mut Vec<u8>, text: &[u8], color: Option<&str>) {
+    match color {
+        Some(color) => {
+            out.extend_from_slice(color.as_bytes());
+            out.extend_from_slice(text);
+            out.extend_from_slice(RESET);
+        }
+        None => out.extend_from_slice(text),
+    }
+}
+
+/// Adds a note under the line for every annotation that starts on it,
+/// with a marker pointing at the first byte:
+///
+/// 00000000  7f 45 4c 46 02 01 01 00  ...
+///           └─ 0x00000000..0x00000040 ELF header ; 64 bytes
+fn write_annotation_notes(out: &mut Vec<u8>, len: usize, offset: usize, options: &DumpOptions) {
+    let settings = options.settings;
+    for a in options.annotations {
+        if a.start < offset || a.start >= offset + len {
+            continue;
+        }
+        // Same layout as the hex column: the offset and 2 spaces, 3 chars
+        // per byte and one extra space after every group.
+        let column = a.start - offset;
+        let gaps = (0..column).filter(|&i| settings.ends_group(i)).count();
+        let shown = shown_offset(offset, options).unwrap_or(0);
+        let digits = offset_width(shown, settings.offset_base);
+        let indent = digits.max(options.offset_digits) + 2 + column * 3 + gaps;
+        let (first, end) = shown_range(a, options);
+        // Writing into a `Vec` cannot fail, so the results are ignored.
+        let _ = write!(
+            out,
+            "{:indent$}└─ 0x{:08x}..0x{:08x} {}",
+            "", first, end, a.label
+        );
+        if !a.comment.is_empty() {
+            let _ = write!(out, " ; {}", a.comment);
+        }
+        out.push(b'\n');
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(threads: usize, settings: &Settings) -> DumpOptions<'_> {
+        DumpOptions {
+            start: 0,
+            annotations: &[],
+            transforms: &[],
+            color: false,
+            threads,
+            settings,
+            json: false,
+            base_addr: 0,
+            relative: false,
+            offset_digits: 8,
+        }
+    }
+
+    fn dump_to_string(data: &[u8], options: &DumpOptions) -> String {
+        let mut out = Vec::new();
+        dump(data, &mut out, options).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn formats_lines_like_the_classic_dump() {
+        let settings = Settings::default();
+        let text = dump_to_string(b"Hello, World!\n\x00\xffxyz", &options(1, &settings));
+        assert_eq!(
+            text,
+            "00000000  48 65 6c 6c 6f 2c 20 57  6f 72 6c 64 21 0a 00 ff  |Hello, World!...|\n\
+             00000010  78 79 7a                                          |xyz|\n"
+        );
+    }
+
+    #[test]
+    fn parallel_output_matches_single_threaded() {
+        // Big enough to be split across threads, with a ragged last line.
+        let data: Vec<u8> = (0..MIN_PARALLEL_SLICE * 5 + 7)
+            .map(|i| (i * 7) as u8)
+            .collect();
+        let settings = Settings::default();
+        assert_eq!(
+            dump_to_string(&data, &options(4, &settings)),
+            dump_to_string(&data, &options(1, &settings))
+        );
+    }
+
+    #[test]
+    fn characters_crossing_block_boundaries_are_decoded() {
+        // "é" starts on the last byte of the first block.
+        let mut data = vec![b'a'; BLOCK_SIZE - 1];
+        data.extend_from_slice("éz日本".as_bytes());
+        let settings = Settings {
+            encoding: Encoding::Utf8,
+            ..Settings::default()
+        };
+        let text = dump_to_string(&data, &options(1, &settings));
+        let lines: Vec<&str> = text.lines().collect();
+        let last_full = lines[BLOCK_SIZE / 16 - 1];
+        assert!(last_full.ends_with("|aaaaaaaaaaaaaaaé|"), "{}", last_full);
+        assert!(text.ends_with("|•z日•本•|\n"), "{}", text);
+
+        assert_eq!(dump_to_string(&data, &options(4, &settings)), text);
+    }
+
+    #[test]
+    fn follows_width_group_and_offset_base() {
+        let settings = Settings {
+            width: 6,
+            group: 2,
+            offset_base: OffsetBase::Dec,
+            ..Settings::default()
+        };
+        let annotations = [Annotation {
+            start: 9,
+            len: 1,
+            label: "x".to_string(),
+            color: crate::annotate::Color::Red,
+            comment: String::new(),
+        }];
+        let options = DumpOptions {
+            annotations: &annotations,
+            ..options(1, &settings)
+        };
+        assert_eq!(
+            dump_to_string(b"abcdefghij", &options),
+            "00000000  61 62  63 64  65 66  |abcdef|\n\
+             00000006  67 68  69 6a         |ghij|\n\
+             \x20                   └─ 0x00000009..0x0000000a x\n"
+        );
+    }
+
+    #[test]
+    fn squeezes_runs_of_repeated_lines() {
+        let settings = Settings {
+            width: 4,
+            squeeze: true,
+            ..Settings::default()
+        };
+        let text = dump_to_string(b"abcdabcdabcdabcdxyz", &options(1, &settings));
+        assert_eq!(
+            text,
+            "00000000  61 62 63 64  |abcd|\n\
+             *\n\
+             00000010  78 79 7a     |xyz|\n\
+             00000013\n"
+        );
+
+        // Runs that cross block and thread boundaries squeeze the same way.
+        let mut data = vec![0u8; BLOCK_SIZE + MIN_PARALLEL_SLICE * 8];
+        data[BLOCK_SIZE + 5] = 1;
+        let single = dump_to_string(&data, &options(1, &settings));
+        assert_eq!(single.lines().count(), 6);
+        assert_eq!(dump_to_string(&data, &options(4, &settings)), single);
+    }
+
+    #[test]
+    fn offsets_grow_past_eight_digits() {
+        let settings = Settings::default();
+        let mut out = Vec::new();
+        write_offset(&mut out, 0x1_2345_6789, &options(1, &settings));
+        assert_eq!(out, b"123456789");
+
+        // A 5 GiB file gets a 9-digit column from its very first line.
+        let wide = DumpOptions {
+            offset_digits: offset_width(5 << 30, OffsetBase::Hex),
+            ..options(1, &settings)
+        };
+        assert!(dump_to_string(b"x", &wide).starts_with("000000000  78"));
+    }
+
+    #[test]
+    fn offsets_count_from_a_base_address_or_a_bookmark() {
+        let settings = Settings {
+            width: 4,
+            ..Settings::default()
+        };
+        let based = DumpOptions {
+            base_addr: 0x0800_0000,
+            ..options(1, &settings)
+        };
+        assert_eq!(
+            dump_to_string(b"abcdef", &based),
+            "08000000  61 62 63 64  |abcd|\n\
+             08000004  65 66        |ef|\n"
+        );
+        // Addresses past the largest one are an error, not a wrap to 0.
+        let too_high = DumpOptions {
+            base_addr: usize::MAX - 4,
+            ..options(1, &settings)
+        };
+        assert_eq!(dump_to_string(b"abcd", &too_high).lines().count(), 1);
+        let error = dump(&b"abcdef"[..], &mut Vec::new(), &too_high).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+
+        let annotations = [Annotation {
+            start: 4,
+            len: 2,
+            label: "hdr".to_string(),
+            color: crate::annotate::Color::Red,
+            comment: String::new(),
+        }];
+        let relative = DumpOptions {
+            annotations: &annotations,
+            relative: true,
+            ..options(1, &settings)
+        };
+        let text = dump_to_string(b"abcdefghijkl", &relative);
+        let offsets: Vec<&str> = text.lines().map(|line| &line[..8]).collect();
+        assert_eq!(offsets, ["        ", "00000000", "        ", "00000004"]);
+    }
+}
@@ -0,0 +1,217 @@
+// Everything that can go wrong, and the exit status each one maps to.
+//
+// Returning `io::Result` from `main` prints Rust's debug form of the error
+// (`Error: Os { code: 2, kind: NotFound, ... }`) and exits with 1 whatever
+// happened. Scripts can't tell a typo in a flag from a missing file, and
+// people can't tell which file was missing. So we sort failures into a few
+// kinds, each with its own message and exit status:
+//
+//     0  success, also when the reader of our output went away early
+//        (`hex_viewer big.img | head`): that is not an error, it's done
+//     2  usage: unknown option, bad option value, missing file name
+//     3  an input file could not be opened
+//     4  an input file could not be read
+//     5  a config or annotations file is malformed
+//     6  the output could not be written
+//
+// When several files are shown and some of them fail, the exit status is
+// the one of the first failure.
+
+use std::fmt;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::process::ExitCode;
+
+#[derive(Debug)]
+pub enum Error {
+    Usage(String),
+    Open {
+        path: String,
+        source: io::Error,
+    },
+    Read {
+        path: String,
+        source: io::Error,
+    },
+    /// The message says which file and line.
+    Parse(String),
+    Write {
+        path: String,
+        source: io::Error,
+    },
+    /// Our output is a pipe and the other end was closed.
+    BrokenPipe,
+}
+
+impl Error {
+    /// An error from opening `path`, or from reading it in one go with
+    /// something like `fs::read`, which does both.
+    pub fn opening(path: &str, source: io::Error) -> Error {
+        match source.kind() {
+            io::ErrorKind::NotFound
+            | io::ErrorKind::PermissionDenied
+            | io::ErrorKind::IsADirectory => Error::Open {
+                path: path.to_string(),
+                source,
+            },
+            _ => Error::Read {
+                path: path.to_string(),
+                source,
+            },
+        }
+    }
+
+    pub fn reading(path: &str, source: io::Error) -> Error {
+        Error::Read {
+            path: path.to_string(),
+            source,
+        }
+    }
+
+    /// An error from writing to `path`. A closed pipe is not really a
+    /// failure, so it gets its own, quiet, variant.
+    pub fn writing(path: &str, source: io::Error) -> Error {
+        if source.kind() == io::ErrorKind::BrokenPipe {
+            return Error::BrokenPipe;
+        }
+        Error::Write {
+            path: path.to_string(),
+            source,
+        }
+    }
+
+    /// An error from writing to standard output.
+    pub fn output(source: io::Error) -> Error {
+        Error::writing("standard output", source)
+    }
+
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            Error::BrokenPipe => 0,
+            Error::Usage(_) => 2,
+            Error::Open { .. } => 3,
+            Error::Read { .. } => 4,
+            Error::Parse(_) => 5,
+            Error::Write { .. } => 6,
+        }
+    }
+
+    /// Prints the error (unless there is nothing worth saying) and returns
+    /// the exit status to finish with.
+    pub fn report(&self) -> ExitCode {
+        if !matches!(self, Error::BrokenPipe) {
+            eprintln!("Error: {}", self);
+        }
+        ExitCode::from(self.exit_code())
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Usage(message) | Error::Parse(message) => write!(f, "{}", message),
+            Error::Open { path, source } => {
+                write!(f, "cannot open '{}': {}", path, describe(source))
+            }
+            Error::Read { path, source } => {
+                write!(f, "cannot read '{}': {}", path, describe(source))
+            }
+            Error::Write { path, source } => {
+                write!(f, "cannot write {}: {}", quoted(path), describe(source))
+            }
+            Error::BrokenPipe => write!(f, "broken pipe"),
+        }
+    }
+}
+
+/// `io::Error` prints as "No such file or directory (os error 2)". The
+/// error number means nothing to most people, so we drop it for the
+/// common cases.
+fn describe(e: &io::Error) -> String {
+    match e.kind() {
+        io::ErrorKind::NotFound => "no such file or directory".to_string(),
+        io::ErrorKind::PermissionDenied => "permission denied".to_string(),
+        io::ErrorKind::IsADirectory => "it is a directory".to_string(),
+        io::ErrorKind::StorageFull => "no space left on the device".to_string(),
+        _ => e.to_string(),
+    }
+}
+
+/// Quotes file names, but not "standard output".
+fn quoted(path: &str) -> String {
+    if path == "standard output" {
+        path.to_string()
+    } else {
+        format!("'{}'", path)
+    }
+}
+
+/// A reader that remembers whether it ever failed.
+///
+/// Code like `io::copy` or `dump::dump` both reads and writes, and hands
+/// back a plain `io::Error` either way. Wrapping the input in `Tracked`
+/// lets us find out afterwards which side it came from.
+pub struct Tracked<R> {
+    inner: R,
+    pub failed: bool,
+}
+
+impl<R> Tracked<R> {
+    pub fn new(inner: R) -> Tracked<R> {
+        Tracked {
+            inner,
+            failed: false,
+        }
+    }
+}
+
+impl<R: Read> Read for Tracked<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf).inspect_err(|_| self.failed = true)
+    }
+}
+
+impl<R: Seek> Seek for Tracked<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.inner.seek(pos).inspect_err(|_| self.failed = true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn messages_name_the_file_and_codes_differ() {
+        let missing = Error::opening("fw.bin", io::ErrorKind::NotFound.into());
+        assert_eq!(
+            missing.to_string(),
+            "cannot open 'fw.bin': no such file or directory"
+        );
+        assert_eq!(missing.exit_code(), 3);
+
+        let closed = Error::output(io::ErrorKind::BrokenPipe.into());
+        assert!(matches!(closed, Error::BrokenPipe));
+        assert_eq!(closed.exit_code(), 0);
+
+        let full = Error::output(io::ErrorKind::StorageFull.into());
+        assert_eq!(
+            full.to_string(),
+            "cannot write standard output: no space left on the device"
+        );
+        assert_eq!(full.exit_code(), 6);
+    }
+
+    #[test]
+    fn tracked_readers_remember_failures() {
+        struct Broken;
+        impl Read for Broken {
+            fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+                Err(io::ErrorKind::Other.into())
+            }
+        }
+
+        let mut input = Tracked::new(Broken);
+        assert!(io::copy(&mut input, &mut io::sink()).is_err());
+        assert!(input.failed);
+    }
+}
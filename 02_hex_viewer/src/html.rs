@@ -0,0 +1,345 @@
+// HTML export.
+//
+// The page is completely self-contained (inline CSS and JavaScript, no
+// external files) so it can be attached to a bug report and opened anywhere.
+// Every byte becomes two cells, one in the hex column and one in the ASCII
+// column. Both cells carry the same index, which is all the script needs to
+// link them together on hover.
+
+use std::io::{self, Write};
+
+use crate::annotate::Annotation;
+use crate::dump::{self, DumpOptions};
+use crate::text;
+use crate::transform;
+
+const STYLE: &str = "\
+body { font-family: sans-serif; margin: 1.5em; }
+pre { font-family: monospace; font-size: 14px; line-height: 1.4; }
+.off { color: #888; }
+.h, .a { cursor: default; }
+.hl { outline: 2px solid #d33; background: #ffe066 !important; }
+#tip { position: fixed; display: none; background: #222; color: #eee; padding: 6px 9px;
+       font: 12px monospace; white-space: pre; border-radius: 4px; pointer-events: none; }
+#legend span.sw { display: inline-block; width: 1em; height: 1em; vertical-align: middle; margin-right: 0.4em; }
+#legend li { list-style: none; margin: 0.2em 0; font-family: monospace; }
+";
+
+// The script reads the raw bytes back out of `DATA` so the tooltip can show
+// multi-byte integer interpretations without asking the server anything.
+// `START` is the file offset of the first byte, for `--skip`, and `BASE`
+// is `--base-addr` (a BigInt, since addresses can go past 2^53).
+const SCRIPT: &str = "\
+const bytes = DATA.match(/../g) ? DATA.match(/../g).map(h => parseInt(h, 16)) : [];
+const hex = document.querySelectorAll('.h');
+const asc = document.querySelectorAll('.a');
+const tip = document.getElementById('tip');
+function word(o, n, le) {
+  if (o + n > bytes.length) return null;
+  let v = 0n;
+  for (let i = 0; i < n; i++) v = (v << 8n) | BigInt(bytes[le ? o + n - 1 - i : o + i]);
+  return v;
+}
+function signed(v, bits) { return v >= (1n << BigInt(bits - 1)) ? v - (1n << BigInt(bits)) : v; }
+function describe(o) {
+  const b = bytes[o], at = START + o;
+  const lines = ['offset  0x' + at.toString(16).padStart(8, '0') + ' (' + at + ')'];
+  if (BASE) lines.push('address 0x' + (BASE + BigInt(at)).toString(16).padStart(8, '0'));
+  lines.push('u8 ' + b + '  i8 ' + (b > 127 ? b - 256 : b));
+  for (const n of [2, 4, 8]) {
+    const le = word(o, n, true), be = word(o, n, false);
+    if (le === null) break;
+    lines.push('u' + n * 8 + ' le ' + le + '  be ' + be + '  i' + n * 8 + ' le ' + signed(le, n * 8));
+  }
+  for (const a of ANNOTATIONS) {
+    if (at >= a[0] && at < a[0] + a[1]) lines.push('[' + a[2] + '] +0x' + (at - a[0]).toString(16) + (a[3] ? '  ' + a[3] : ''));
+  }
+  return lines.join('\\n');
+}
+function show(e) {
+  const o = Number(e.target.dataset.o);
+  if (Number.isNaN(o)) return;
+  hex[o].classList.add('hl');
+  asc[o].classList.add('hl');
+  tip.textContent = describe(o);
+  tip.style.display = 'block';
+  tip.style.left = (e.clientX + 14) + 'px';
+  tip.style.top = (e.clientY + 14) + 'px';
+}
+function hide(e) {
+  const o = Number(e.target.dataset.o);
+  if (Number.isNaN(o)) return;
+  hex[o].classList.remove('hl');
+  asc[o].classList.remove('hl');
+  tip.style.display = 'none';
+}
+const dump = document.getElementById('dump');
+dump.addEventListener('mouseover', show);
+dump.addEventListener('mouseout', hide);
+";
+
+/// Writes a complete HTML page for `data` to `out`. The page follows the
+/// same `options` as the text dump, so offsets come out the same way
+/// (`--base-addr`, `--relative`, decimal); `color`, `threads` and `json`
+/// don't apply.
+pub fn write_html<W: Write>(
+    out: &mut W,
+    name: &str,
+    data: &[u8],
+    options: &DumpOptions,
+) -> io::Result<()> {
+    let (start, annotations, settings) = (options.start, options.annotations, options.settings);
+    writeln!(out, "<!DOCTYPE html>")?;
+    writeln!(out, "<html><head><meta charset=\"utf-8\">")?;
+    writeln!(out, "<title>hex_viewer: {}</title>", escape(name))?;
+    writeln!(out, "<style>{}", STYLE)?;
+    // One CSS class per annotation, so a byte covered by annotation 3 is
+    // simply `class="h n3"`.
+    for (i, annotation) in annotations.iter().enumerate() {
+        writeln!(
+            out,
+            ".n{} {{ background: {}; }}",
+            i,
+            annotation.color.html()
+        )?;
+    }
+    writeln!(out, "</style></head><body>")?;
+    writeln!(out, "<h1>{}</h1>", escape(name))?;
+    writeln!(
+        out,
+        "<p>{} bytes. Hover a byte to see its offset and value.</p>",
+        data.len()
+    )?;
+    if !options.transforms.is_empty() {
+        writeln!(
+            out,
+            "<p><code>{}</code></p>",
+            escape(&transform::header(options.transforms))
+        )?;
+    }
+
+    write_legend(out, options)?;
+
+    writeln!(out, "<pre id=\"dump\">")?;
+    for line_start in (0..data.len()).step_by(settings.width) {
+        let line = line_start..(line_start + settings.width).min(data.len());
+        write_line(out, data, line, options)?;
+    }
+    writeln!(out, "</pre>")?;
+    writeln!(out, "<div id=\"tip\"></div>")?;
+
+    // The data is embedded as one long hex string: simple, and valid JS.
+    write!(
+        out,
+        "<script>\nconst START = {};\nconst BASE = {}n;\nconst DATA = \"",
+        start, options.base_addr
+    )?;
+    for byte in data {
+        write!(out, "{:02x}", byte)?;
+    }
+    writeln!(out, "\";")?;
+    write!(out, "const ANNOTATIONS = [")?;
+    for annotation in annotations {
+        write!(
+            out,
+            "[{}, {}, \"{}\", \"{}\"],",
+            annotation.start,
+            annotation.len,
+            escape_js(&annotation.label),
+            escape_js(&annotation.comment)
+        )?;
+    }
+    writeln!(out, "];")?;
+    writeln!(out, "{}</script>", SCRIPT)?;
+    writeln!(out, "</body></html>")
+}
+
+fn write_legend<W: Write>(out: &mut W, options: &DumpOptions) -> io::Result<()> {
+    if options.annotations.is_empty() {
+        return Ok(());
+    }
+    writeln!(out, "<ul id=\"legend\">")?;
+    for (i, annotation) in options.annotations.iter().enumerate() {
+        let (first, end) = dump::shown_range(annotation, options);
+        write!(
+            out,
+            "<li><span class=\"sw n{}\"></span>0x{:08x}..0x{:08x} {}",
+            i,
+            first,
+            end,
+            escape(&annotation.label)
+        )?;
+        if !annotation.comment.is_empty() {
+            write!(out, " <i>{}</i>", escape(&annotation.comment))?;
+        }
+        writeln!(out, "</li>")?;
+    }
+    writeln!(out, "</ul>")
+}
+
+/// Writes the dump line for `data[line]`. `data-o` holds the index into
+/// the page's data, which is the file offset minus `start`.
+fn write_line<W: Write>(
+    out: &mut W,
+    data: &[u8],
+    line: std::ops::Range<usize>,
+    options: &DumpOptions,
+) -> io::Result<()> {
+    let (start, annotations, settings) = (options.start, options.annotations, options.settings);
+    let bytes = &data[line.clone()];
+    let offset = start + line.start;
+    let mut shown = Vec::new();
+    dump::write_offset(&mut shown, offset, options);
+    write!(out, "<span class=\"off\">")?;
+    out.write_all(&shown)?;
+    write!(out, "</span>  ")?;
+
+    for i in 0..settings.width {
+        match bytes.get(i) {
+            Some(byte) => write!(
+                out,
+                "<span class=\"h{}\" data-o=\"{}\">{:02x}</span> ",
+                classes(offset + i, annotations),
+                offset + i - start,
+                byte
+            )?,
+            None => write!(out, "   ")?,
+        }
+        if settings.ends_group(i) {
+            write!(out, " ")?;
+        }
+    }
+
+    // Every byte keeps its own text cell, even the empty one after a wide
+    // character, because the script finds cells by position.
+    write!(out, " |")?;
+    for (i, cell) in text::cells(settings.encoding, data, 0, line)
+        .into_iter()
+        .enumerate()
+    {
+        write!(
+            out,
+            "<span class=\"a{}\" data-o=\"{}\">{}</span>",
+            classes(offset + i, annotations),
+            offset + i - start,
+            cell.map_or(String::new(), |c| escape(&c.to_string()))
+        )?;
+    }
+    writeln!(out, "|")
+}
+
+/// Extra CSS classes for the byte at `offset`. When annotations overlap,
+/// the one listed last wins, just like later CSS rules do.
+fn classes(offset: usize, annotations: &[Annotation]) -> String {
+    match annotations.iter().rposition(|a| a.contains(offset)) {
+        Some(i) => format!(" n{}", i),
+        None => String::new(),
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn escape_js(text: &str) -> String {
+    let mut escaped = String::new();
+    for c in text.chars() {
+        match c {
+            '"' | '\\' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            // `</script>` inside a string would end the script block early.
+            '<' => escaped.push_str("\\x3c"),
+            c if c.is_control() => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::annotate::Color;
+    use crate::config::{OffsetBase, Settings};
+
+    fn page(data: &[u8], options: &DumpOptions) -> String {
+        let mut out = Vec::new();
+        write_html(&mut out, "test.bin", data, options).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    fn options<'a>(annotations: &'a [Annotation], settings: &'a Settings) -> DumpOptions<'a> {
+        DumpOptions {
+            start: 0,
+            annotations,
+            transforms: &[],
+            color: false,
+            threads: 1,
+            settings,
+            json: false,
+            base_addr: 0,
+            relative: false,
+            offset_digits: 8,
+        }
+    }
+
+    #[test]
+    fn page_links_hex_and_ascii_cells_and_lists_annotations() {
+        let annotations = vec![Annotation {
+            start: 1,
+            len: 2,
+            label: "<magic>".to_string(),
+            color: Color::Red,
+            comment: String::new(),
+        }];
+        let settings = Settings::default();
+        let page = page(b"A<C", &options(&annotations, &settings));
+
+        assert!(page.contains("<span class=\"off\">00000000</span>"));
+        assert!(page.contains("<span class=\"h\" data-o=\"0\">41</span>"));
+        assert!(page.contains("<span class=\"h n0\" data-o=\"1\">3c</span>"));
+        assert!(page.contains("<span class=\"a n0\" data-o=\"1\">&lt;</span>"));
+        assert!(page.contains("0x00000001..0x00000003 &lt;magic&gt;"));
+        assert!(page.contains("const DATA = \"413c43\";"));
+        assert!(page.contains("[1, 2, \"\\x3cmagic>\", \"\"]"));
+    }
+
+    #[test]
+    fn offsets_follow_the_base_address_and_the_offset_base() {
+        let annotations = vec![Annotation {
+            start: 1,
+            len: 2,
+            label: "hdr".to_string(),
+            color: Color::Red,
+            comment: String::new(),
+        }];
+        let settings = Settings {
+            width: 4,
+            offset_base: OffsetBase::Dec,
+            ..Settings::default()
+        };
+        let based = DumpOptions {
+            base_addr: 1000,
+            ..options(&annotations, &settings)
+        };
+        let html = page(b"abcdef", &based);
+        assert!(html.contains("<span class=\"off\">00001000</span>"));
+        assert!(html.contains("<span class=\"off\">00001004</span>"));
+        // The legend is always hex, like the notes under a text dump.
+        assert!(html.contains("0x000003e9..0x000003eb hdr"));
+        assert!(html.contains("const BASE = 1000n;"));
+
+        let relative = DumpOptions {
+            relative: true,
+            ..options(&annotations, &settings)
+        };
+        let html = page(b"abcdef", &relative);
+        assert!(html.contains("<span class=\"off\">        </span>"));
+        assert!(html.contains("<span class=\"off\">00000003</span>"));
+    }
+}
@@ -0,0 +1,466 @@
+// Machine-readable output (`--json`).
+//
+// Scraping the human format is fragile: the text column changes with the
+// encoding, colors sneak in, annotation notes are interleaved with lines.
+// With `--json` every mode prints NDJSON instead: one JSON object per line,
+// so a script can read the output line by line without loading it whole.
+//
+// We write the JSON by hand. It is only a few kinds of flat records, and
+// the one subtle part (escaping strings) fits in a dozen lines.
+//
+// SCHEMA
+// ======
+//
+// Every record has a `"type"` field saying which of these it is. Offsets
+// are file positions as plain integers, never moved by `--base-addr`;
+// only the `address` of an instruction is. Byte strings are lowercase hex.
+//
+// header       always the first record.
+//   file        string   the path that was read
+//   mode        string   "dump", "disasm" or "xor_brute"
+//   start       integer  offset of the first byte (`--skip`)
+//   encoding    string   the text column encoding, e.g. "utf8"
+//   transforms  array    the transform flags, e.g. ["--xor 41", "--rol 3"]
+//
+// annotation   one per annotation, in offset order, before the data.
+//   start       integer  first byte
+//   end         integer  one past the last byte
+//   label       string
+//   color       string   "red", "green", ...
+//   comment     string   empty when there is none
+//
+// line         one per line of the dump (`--width` bytes, 16 by default)
+//              in "dump" mode; the last one may be shorter.
+//   offset      integer  offset of the first byte
+//   bytes       string   hex, after transforms
+//   text        string   the text column, decoded with `encoding`
+//
+// instruction  one per instruction in "disasm" mode.
+//   address     integer  where the instruction is, counted from `--base-addr`
+//   bytes       string   hex
+//   text        string   e.g. "mov rbp, rsp", or "db 0x06" for unknown bytes
+//
+// xor_key      the ten best keys in "xor_brute" mode, best first.
+//   rank        integer  1 is the best
+//   key         integer  0-255
+//   score       number   higher is more English-like
+//   preview     string   the first 40 bytes XORed with the key
+//
+// Fields may be added to records later, but never removed or renamed, so
+// scripts should ignore fields they do not know.
+
+use std::io::{self, Write};
+
+use crate::annotate::Annotation;
+use crate::disasm::{self, Arch};
+use crate::text::Encoding;
+use crate::transform::Transform;
+
+const HEX_DIGITS: &[u8; 16] = b"0123456789abcdef";
+
+/// Appends `text` to `out` as a quoted JSON string.
+pub fn write_string(out: &mut Vec<u8>, text: &str) {
+    out.push(b'"');
+    for c in text.chars() {
+        match c {
+            '"' => out.extend_from_slice(b"\\\""),
+            '\\' => out.extend_from_slice(b"\\\\"),
+            '\n' => out.extend_from_slice(b"\\n"),
+            '\t' => out.extend_from_slice(b"\\t"),
+            // Any other control character must be escaped as \uXXXX.
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes()),
+        }
+    }
+    out.push(b'"');
+}
+
+/// Appends `bytes` to `out` as a quoted hex string.
+pub fn write_hex(out: &mut Vec<u8>, bytes: &[u8]) {
+    out.push(b'"');
+    for &byte in bytes {
+        out.push(HEX_DIGITS[(byte >> 4) as usize]);
+        out.push(HEX_DIGITS[(byte & 0xf) as usize]);
+    }
+    out.push(b'"');
+}
+
+/// Writes the header record and the annotation records.
+pub fn write_header<W: Write>(
+    out: &mut W,
+    file: &str,
+    mode: &str,
+    start: usize,
+    encoding: Encoding,
+    transforms: &[Transform],
+    annotations: &[Annotation],
+) -> io::Result<()> {
+    let mut record = Vec::new();
+    record.extend_from_slice(b"{\"type\":\"header\",\"file\":");
+    write_string(&mut record, file);
+    let _ = write!(record, ",\"mode\":\"{}\",\"start\":{}", mode, start);
+    let _ = write!(
+        record,
+        ",\"encoding\":\"{}\",\"transforms\":[",
+        encoding.name()
+    );
+    for (i, t) in transforms.iter().enumerate() {
+        if i > 0 {
+            record.push(b',');
+        }
+        write_string(&mut record, &t.describe());
+    }
+    record.extend_from_slice(b"]}\n");
+
+    for a in annotations {
+        let _ = write!(
+            record,
+            "{{\"type\":\"annotation\",\"start\":{},\"end\":{},\"label\":",
+            a.start,
+            a.end()
+        );
+        write_string(&mut record, &a.label);
+        let _ = write!(record, ",\"color\":\"{}\",\"comment\":", a.color.name());
+        write_string(&mut record, &a.comment);
+        record.extend_from_slice(b"}\n");
+    }
+    out.write_all(&record)
+}
+
+/// Appends a `line` record for one line of the dump.
+pub fn write_line(out: &mut Vec<u8>, offset: usize, bytes: &[u8], text: &str) {
+    let _ = write!(out, "{{\"type\":\"line\",\"offset\":{},\"bytes\":", offset);
+    write_hex(out, bytes);
+    out.extend_from_slice(b",\"text\":");
+    write_string(out, text);
+    out.extend_from_slice(b"}\n");
+}
+
+/// Writes an `instruction` record for every instruction in `bytes`.
+pub fn write_instructions<W: Write>(
+    out: &mut W,
+    arch: Arch,
+    bytes: &[u8],
+    address: u64,
+) -> io::Result<()> {
+    let mut record = Vec::new();
+    let mut pos = 0;
+    for (here, len, text) in disasm::disassemble(arch, bytes, address) {
+        record.clear();
+        let _ = write!(
+            record,
+            "{{\"type\":\"instruction\",\"address\":{},\"bytes\":",
+            here
+        );
+        write_hex(&mut record, &bytes[pos..pos + len]);
+        record.extend_from_slice(b",\"text\":");
+        write_string(&mut record, &text);
+        record.extend_from_slice(b"}\n");
+        out.write_all(&record)?;
+        pos += len;
+    }
+    Ok(())
+}
+
+/// Writes one `xor_key` record.
+pub fn write_xor_key<W: Write>(
+    out: &mut W,
+    rank: usize,
+    key: u8,
+    score: f64,
+    preview: &str,
+) -> io::Result<()> {
+    let mut record = Vec::new();
+    let _ = write!(
+        record,
+        "{{\"type\":\"xor_key\",\"rank\":{},\"key\":{},\"score\":{:.4},\"preview\":",
+        rank, key, score
+    );
+    write_string(&mut record, preview);
+    record.extend_from_slice(b"}\n");
+    out.write_all(&record)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::annotate::Color;
+    use crate::config::Settings;
+    use crate::dump::{self, DumpOptions};
+
+    // A tiny JSON parser, so the tests check that the output really is
+    // JSON and not just that it contains the right substrings.
+    #[derive(Debug, PartialEq)]
+    enum Value {
+        Number(f64),
+        String(String),
+        Array(Vec<Value>),
+        Object(Vec<(String, Value)>),
+    }
+
+    fn parse(text: &str) -> Value {
+        let mut chars = text.chars().peekable();
+        let value = parse_value(&mut chars);
+        assert_eq!(chars.next(), None, "trailing input in {}", text);
+        value
+    }
+
+    fn parse_value(chars: &mut std::iter::Peekable<std::str::Chars>) -> Value {
+        match chars.next() {
+            Some('"') => {
+                let mut s = String::new();
+                loop {
+                    match chars.next().expect("unterminated string") {
+                        '"' => return Value::String(s),
+                        '\\' => match chars.next().unwrap() {
+                            'n' => s.push('\n'),
+                            't' => s.push('\t'),
+                            'u' => {
+                                let hex: String = chars.by_ref().take(4).collect();
+                                s.push(
+                                    char::from_u32(u32::from_str_radix(&hex, 16).unwrap()).unwrap(),
+                                );
+                            }
+                            c => s.push(c),
+                        },
+                        c => {
+                            assert!(c >= ' ', "raw control character in string");
+                            s.push(c);
+                        }
+                    }
+                }
+            }
+            Some('[') => {
+                let mut items = Vec::new();
+                if chars.peek() == Some(&']') {
+                    chars.next();
+                    return Value::Array(items);
+                }
+                loop {
+                    items.push(parse_value(chars));
+                    match chars.next() {
+                        Some(',') => continue,
+                        Some(']') => return Value::Array(items),
+                        other => panic!("unexpected {:?} in array", other),
+                    }
+                }
+            }
+            Some('{') => {
+                let mut fields = Vec::new();
+                loop {
+                    let Value::String(key) = parse_value(chars) else {
+                        panic!("object keys must be strings");
+                    };
+                    assert_eq!(chars.next(), Some(':'));
+                    fields.push((key, parse_value(chars)));
+                    match chars.next() {
+                        Some(',') => continue,
+                        Some('}') => return Value::Object(fields),
+                        other => panic!("unexpected {:?} in object", other),
+                    }
+                }
+            }
+            Some(c) if c == '-' || c.is_ascii_digit() => {
+                let mut number = c.to_string();
+                while let Some(&c) = chars.peek() {
+                    if !(c.is_ascii_digit() || c == '.') {
+                        break;
+                    }
+                    number.push(c);
+                    chars.next();
+                }
+                Value::Number(number.parse().unwrap())
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[derive(Clone, Copy)]
+    enum Kind {
+        Integer,
+        Number,
+        Str,
+        Hex,
+        Strings,
+    }
+
+    // The schema from the top of this file, as data.
+    const SCHEMA: &[(&str, &[(&str, Kind)])] = &[
+        (
+            "header",
+            &[
+                ("file", Kind::Str),
+                ("mode", Kind::Str),
+                ("start", Kind::Integer),
+                ("encoding", Kind::Str),
+                ("transforms", Kind::Strings),
+            ],
+        ),
+        (
+            "annotation",
+            &[
+                ("start", Kind::Integer),
+                ("end", Kind::Integer),
+                ("label", Kind::Str),
+                ("color", Kind::Str),
+                ("comment", Kind::Str),
+            ],
+        ),
+        (
+            "line",
+            &[
+                ("offset", Kind::Integer),
+                ("bytes", Kind::Hex),
+                ("text", Kind::Str),
+            ],
+        ),
+        (
+            "instruction",
+            &[
+                ("address", Kind::Integer),
+                ("bytes", Kind::Hex),
+                ("text", Kind::Str),
+            ],
+        ),
+        (
+            "xor_key",
+            &[
+                ("rank", Kind::Integer),
+                ("key", Kind::Integer),
+                ("score", Kind::Number),
+                ("preview", Kind::Str),
+            ],
+        ),
+    ];
+
+    fn matches(kind: Kind, value: &Value) -> bool {
+        match (kind, value) {
+            (Kind::Integer, Value::Number(n)) => n.fract() == 0.0 && *n >= 0.0,
+            (Kind::Number, Value::Number(_)) => true,
+            (Kind::Str, Value::String(_)) => true,
+            (Kind::Hex, Value::String(s)) => {
+                s.len() % 2 == 0 && s.chars().all(|c| matches!(c, '0'..='9' | 'a'..='f'))
+            }
+            (Kind::Strings, Value::Array(items)) => {
+                items.iter().all(|v| matches!(v, Value::String(_)))
+            }
+            _ => false,
+        }
+    }
+
+    /// Checks every line of `output` against the schema and returns the
+    /// parsed records.
+    fn validate(output: &[u8]) -> Vec<Vec<(String, Value)>> {
+        let text = String::from_utf8(output.to_vec()).unwrap();
+        let mut records = Vec::new();
+        for line in text.lines() {
+            let Value::Object(fields) = parse(line) else {
+                panic!("not an object: {}", line);
+            };
+            let Some((_, Value::String(kind))) = fields.first() else {
+                panic!("type must come first: {}", line);
+            };
+            let (_, schema) = SCHEMA
+                .iter()
+                .find(|(name, _)| name == kind)
+                .unwrap_or_else(|| panic!("unknown type: {}", line));
+            assert_eq!(fields.len(), schema.len() + 1, "{}", line);
+            for ((key, value), (name, kind)) in fields[1..].iter().zip(schema.iter()) {
+                assert_eq!(key, name, "{}", line);
+                assert!(matches(*kind, value), "bad {}: {}", name, line);
+            }
+            records.push(fields);
+        }
+        records
+    }
+
+    fn field<'a>(record: &'a [(String, Value)], name: &str) -> &'a Value {
+        &record.iter().find(|(key, _)| key == name).unwrap().1
+    }
+
+    #[test]
+    fn strings_are_escaped() {
+        let mut out = Vec::new();
+        write_string(&mut out, "a\"b\\c\n\u{1b}é");
+        assert_eq!(out, "\"a\\\"b\\\\c\\n\\u001bé\"".as_bytes());
+    }
+
+    #[test]
+    fn header_and_dump_lines_follow_the_schema() {
+        let annotations = vec![Annotation {
+            start: 2,
+            len: 3,
+            label: "quote \"q\"".to_string(),
+            color: Color::Cyan,
+            comment: "tab\there".to_string(),
+        }];
+        let mut out = Vec::new();
+        write_header(
+            &mut out,
+            "a.bin",
+            "dump",
+            0,
+            Encoding::Utf8,
+            &[Transform::Add(1)],
+            &annotations,
+        )
+        .unwrap();
+        let settings = Settings {
+            encoding: Encoding::Utf8,
+            ..Settings::default()
+        };
+        let options = DumpOptions {
+            start: 0,
+            annotations: &annotations,
+            transforms: &[],
+            color: false,
+            threads: 1,
+            settings: &settings,
+            json: true,
+            base_addr: 0,
+            relative: false,
+            offset_digits: 8,
+        };
+        dump::dump("h\"é\\\0 and some more text".as_bytes(), &mut out, &options).unwrap();
+
+        let records = validate(&out);
+        assert_eq!(records.len(), 4);
+        assert_eq!(
+            field(&records[0], "transforms"),
+            &Value::Array(vec![Value::String("--add 1".into())])
+        );
+        assert_eq!(
+            field(&records[1], "label"),
+            &Value::String("quote \"q\"".into())
+        );
+        assert_eq!(
+            field(&records[2], "text"),
+            &Value::String("h\"é•\\␀ and some ".into())
+        );
+        assert_eq!(field(&records[3], "offset"), &Value::Number(16.0));
+    }
+
+    #[test]
+    fn disassembly_and_xor_ranking_follow_the_schema() {
+        let mut out = Vec::new();
+        write_instructions(
+            &mut out,
+            Arch::X86_64,
+            &[0x55, 0x48, 0x89, 0xe5, 0x06],
+            0x1000,
+        )
+        .unwrap();
+        write_xor_key(&mut out, 1, 0x41, 12.5, "Hello\0").unwrap();
+
+        let records = validate(&out);
+        assert_eq!(field(&records[1], "address"), &Value::Number(4097.0));
+        assert_eq!(field(&records[1], "bytes"), &Value::String("4889e5".into()));
+        assert_eq!(field(&records[2], "text"), &Value::String("db 0x06".into()));
+        assert_eq!(
+            field(&records[3], "preview"),
+            &Value::String("Hello\0".into())
+        );
+    }
+}
@@ -1,93 +1,447 @@
+mod annotate;
+mod cli;
+mod config;
+mod disasm;
+mod dump;
+mod error;
+mod html;
+mod json;
+mod splice;
+mod text;
+mod transform;
+mod walk;
+
 use std::env;
 use std::fs::File;
-use std::io::{self, BufReader, Read};
+use std::io::{self, BufReader, BufWriter, IsTerminal, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::process::ExitCode;
+use std::thread;
+
+use error::{Error, Tracked};
 
-// Constants: Magic numbers are bad, named constants are good.
-// 16 bytes is a standard hex view width.
-const BYTES_PER_LINE: usize = 16;
+/// Everything that can fail returns an `Error`, which knows its message
+/// and exit status. See `error.rs` for the list.
+fn main() -> ExitCode {
+    run().unwrap_or_else(|e| e.report())
+}
 
-fn main() -> io::Result<()> {
+fn run() -> Result<ExitCode, Error> {
     // 1. ARGUMENT PARSING
     // We get arguments from the OS. The first one is the program name,
     // so we need the second one (index 1).
     let args: Vec<String> = env::args().collect();
 
-    if args.len() != 2 {
-        eprintln!("Usage: {} <file_path>", args[0]);
-        std::process::exit(1);
+    let options = match cli::parse_args(&args[1..]) {
+        Ok(options) => options,
+        Err(message) => {
+            let status = Error::Usage(message).report();
+            eprintln!("{}", cli::usage(&args[0]));
+            return Ok(status);
+        }
+    };
+
+    // Layout, colors and encoding come from the config file and the
+    // environment first; flags override them. See `config.rs`.
+    let config = config::load(&options.settings)?;
+    if options.print_config {
+        config::write_report(&mut io::stdout().lock(), &config).map_err(Error::output)?;
+        return Ok(ExitCode::SUCCESS);
+    }
+    let settings = &config.settings;
+
+    if options.command != cli::Command::View {
+        run_splice_command(&options)?;
+        return Ok(ExitCode::SUCCESS);
     }
 
-    let filename = &args[1];
+    // Collect every annotation we know about: `--mark` flags first, then
+    // sidecar files in the order they were given.
+    let mut annotations = options.marks.clone();
+    for path in &options.annotation_files {
+        annotations.extend(annotate::load_sidecar(Path::new(path))?);
+    }
+    let annotations = annotate::merge(annotations);
 
-    // 2. OPENING THE FILE
+    if let Some(path) = &options.save_annotations {
+        let file = File::create(path).map_err(|e| Error::writing(path, e))?;
+        let mut out = BufWriter::new(file);
+        annotate::write_sidecar(&mut out, &annotations)
+            .and_then(|()| out.flush())
+            .map_err(|e| Error::writing(path, e))?;
+    }
+
+    // 2. FINDING THE FILES
+    // Directories are walked recursively; see `walk.rs`. With more than
+    // one file, each dump gets a `==> path <==` header, like `head` and
+    // `tail` print.
+    let files = walk::expand(&options.paths, &options.include);
+    if options.html && files.len() != 1 {
+        return Err(Error::Usage(format!(
+            "--html needs exactly one file, found {}",
+            files.len()
+        )));
+    }
+    let headers = files.len() > 1 || walk::has_directory(&options.paths);
+
+    // One bad file should not cost us the rest of the survey: report it,
+    // carry on, and only fail once everything else has been shown.
+    let mut status = ExitCode::SUCCESS;
+    let mut failed = false;
+    for (i, entry) in files.into_iter().enumerate() {
+        let result = match entry {
+            Ok(path) => view_file(
+                &path,
+                headers && i > 0,
+                headers,
+                &options,
+                settings,
+                &annotations,
+            ),
+            Err((path, e)) => Err(Error::opening(&path.display().to_string(), e)),
+        };
+        match result {
+            Ok(()) => {}
+            // Nobody is reading any more (`| head`), so there is no point
+            // in showing the remaining files either.
+            Err(Error::BrokenPipe) => return Ok(ExitCode::SUCCESS),
+            Err(e) => {
+                let code = e.report();
+                if !failed {
+                    status = code;
+                    failed = true;
+                }
+            }
+        }
+    }
+    Ok(status)
+}
+
+/// Shows one file in whichever mode was asked for. `gap` puts a blank
+/// line before the header, which separates it from the previous file.
+fn view_file(
+    path: &Path,
+    gap: bool,
+    header: bool,
+    options: &cli::Options,
+    settings: &config::Settings,
+    annotations: &[annotate::Annotation],
+) -> Result<(), Error> {
+    let filename = &path.display().to_string();
+
+    // 3. OPENING THE FILE
     // We ask the OS to give us a handle to the file.
     // This is a "System Call" under the hood!
-    let file = File::open(filename)?;
+    let mut file = File::open(path).map_err(|e| Error::opening(filename, e))?;
 
-    // We use a BufReader. Why?
-    // Reading byte-by-byte from the disk is slow (lots of system calls).
-    // A buffer reads a big chunk into memory (RAM) at once, which is much faster.
-    let mut reader = BufReader::new(file);
-    let mut buffer = [0u8; BYTES_PER_LINE];
+    // Jump straight to `--skip` instead of reading and discarding bytes:
+    // seeking is a single system call, however far we go.
+    file.seek(SeekFrom::Start(options.skip as u64))
+        .map_err(|e| Error::reading(filename, e))?;
 
-    let mut offset = 0;
+    // The JSON header record already names the file.
+    if header && !options.json {
+        let mut out = io::stdout().lock();
+        if gap {
+            writeln!(out).map_err(Error::output)?;
+        }
+        writeln!(out, "==> {} <==", filename).map_err(Error::output)?;
+    }
 
-    loop {
-        // 3. READING DATA
-        // Try to fill our buffer with bytes from the file.
-        // `n` is the number of bytes we actually read.
-        let n = reader.read(&mut buffer)?;
+    // Where the dump ends, so the offset column can be made wide enough
+    // for the last line from the start. Pipes and devices report a size
+    // of 0; their column simply grows when it has to.
+    let size = file.metadata().map_or(0, |meta| meta.len() as usize);
+    let end = match options.length {
+        Some(n) => size.min(options.skip.saturating_add(n)),
+        None => size,
+    };
+    // Every address shown has to fit: wrapping around to 0 halfway through
+    // would give wrong addresses and a misaligned column. A `--skip` past
+    // the end of the file is still where the addresses start from.
+    let end = end.max(options.skip);
+    if end.checked_add(options.base_addr).is_none() {
+        return Err(Error::Usage(format!(
+            "--base-addr 0x{:x} is too large: '{}' would end past address 0x{:x}",
+            options.base_addr,
+            filename,
+            usize::MAX
+        )));
+    }
 
-        if n == 0 {
-            break; // End of file (EOF)
+    // From here on, reading and writing happen together, so a failure
+    // could come from either side. `Tracked` remembers whether it was us
+    // reading the file; anything else was a write to standard output.
+    let mut input = Tracked::new(file.take(options.length.map_or(u64::MAX, |n| n as u64)));
+    let result = show(&mut input, filename, end, options, settings, annotations);
+    result.map_err(|e| {
+        if input.failed {
+            Error::reading(filename, e)
+        } else if e.kind() == io::ErrorKind::InvalidInput {
+            // A file of unknown size that went on past the last address.
+            Error::Usage(e.to_string())
+        } else {
+            Error::output(e)
         }
+    })
+}
 
-        // 4. DISPLAYING DATA
-        print_hex_line(&buffer[..n], offset);
+/// The body of `view_file`, once the file is open at the right offset.
+fn show<R: Read>(
+    input: &mut R,
+    filename: &str,
+    end: usize,
+    options: &cli::Options,
+    settings: &config::Settings,
+    annotations: &[annotate::Annotation],
+) -> io::Result<()> {
+    let start = options.skip;
 
-        offset += n;
+    // In JSON mode everything starts with a header record saying what
+    // follows, and the annotations, so scripts never see the text headers.
+    if options.json {
+        let mode = if options.xor_brute {
+            "xor_brute"
+        } else if options.disasm.is_some() {
+            "disasm"
+        } else {
+            "dump"
+        };
+        json::write_header(
+            &mut io::stdout().lock(),
+            filename,
+            mode,
+            start,
+            settings.encoding,
+            &options.transforms,
+            annotations,
+        )?;
     }
 
-    Ok(())
+    if options.xor_brute {
+        return print_xor_ranking(
+            &mut BufReader::new(input),
+            start,
+            &options.transforms,
+            options.json,
+        );
+    }
+
+    // Colors are only useful on a real terminal. When the output is piped
+    // into a file or another program, escape codes would just be noise.
+    let color = io::stdout().is_terminal() && !options.json;
+
+    // By default we render on as many threads as the machine has cores.
+    let threads = match options.threads {
+        Some(n) => n,
+        None => thread::available_parallelism().map_or(1, |n| n.get()),
+    };
+
+    // The HTML page shows its offsets just like the text dump, so both take
+    // these options.
+    let dump_options = dump::DumpOptions {
+        start,
+        annotations,
+        transforms: &options.transforms,
+        color,
+        threads,
+        settings,
+        json: options.json,
+        base_addr: options.base_addr,
+        relative: options.relative,
+        offset_digits: dump::offset_width(end + options.base_addr, settings.offset_base),
+    };
+
+    // The HTML page and the disassembly need every byte up front, so these
+    // modes read the whole selected range into memory. The disassembly
+    // counts from `--base-addr`, so branch targets come out as the real
+    // addresses.
+    if options.html || options.disasm.is_some() {
+        let address = (start + options.base_addr) as u64;
+        let mut data = Vec::new();
+        BufReader::new(input).read_to_end(&mut data)?;
+        transform::apply_all(&options.transforms, &mut data, start);
+        let mut out = BufWriter::new(io::stdout().lock());
+
+        if let Some(arch) = options.disasm {
+            if options.json {
+                json::write_instructions(&mut out, arch, &data, address)?;
+                return out.flush();
+            }
+            if !options.transforms.is_empty() {
+                writeln!(out, "{}", transform::header(&options.transforms))?;
+            }
+            disasm::write_listing(&mut out, arch, &data, address)?;
+            return out.flush();
+        }
+        return html::write_html(&mut out, filename, &data, &dump_options);
+    }
+
+    // A transformed dump says so up front, in a form you can paste back
+    // into the command line to get exactly the same output again.
+    // (`println!` would panic if the reader has gone away; `writeln!`
+    // hands us the error instead.)
+    if !options.transforms.is_empty() && !options.json {
+        writeln!(
+            io::stdout().lock(),
+            "{}",
+            transform::header(&options.transforms)
+        )?;
+    }
+
+    // 4. READING AND DISPLAYING DATA
+    // The dump module reads big blocks and formats them in parallel.
+    // See `dump.rs` for why that is so much faster than printing per byte.
+    dump::dump(input, &mut io::stdout().lock(), &dump_options)
 }
 
-/// Formats and prints a single line of the hex dump.
-///
-/// Format:
-/// OFFSET   HEX BYTES                  ASCII
-/// 00000000 48 65 6c 6c 6f 20 57 6f... Hello Wo...
-fn print_hex_line(bytes: &[u8], offset: usize) {
-    // Print the offset (where we are in the file)
-    // %08x means "print as hex, pad with 0s to 8 characters"
-    print!("{:08x}  ", offset);
-
-    // Print the Hexadecimal representation
-    for i in 0..BYTES_PER_LINE {
-        if i < bytes.len() {
-            print!("{:02x} ", bytes[i]);
-        } else {
-            print!("   "); // Padding for partial lines
+/// Runs `extract` or `splice`, writing the result to `--output`.
+fn run_splice_command(options: &cli::Options) -> Result<(), Error> {
+    let output = options.output.as_deref().unwrap_or_default();
+    // `File::create` empties the file straight away, so writing over the
+    // input would destroy it before we read a single byte.
+    let inputs = [&options.paths[0], &options.blob];
+    if inputs.iter().any(|input| same_file(input, output)) {
+        return Err(Error::Usage(format!(
+            "--output '{}' must not be one of the inputs",
+            output
+        )));
+    }
+
+    let path = &options.paths[0];
+    let file = File::open(path).map_err(|e| Error::opening(path, e))?;
+    let size = file.metadata().map_err(|e| Error::reading(path, e))?.len();
+    let mut input = Tracked::new(file);
+    let blob = match options.command {
+        cli::Command::Splice => {
+            std::fs::read(&options.blob).map_err(|e| Error::opening(&options.blob, e))?
         }
+        _ => Vec::new(),
+    };
+    let file = File::create(output).map_err(|e| Error::writing(output, e))?;
+    let mut out = BufWriter::new(file);
+    let align = options.align.map(|n| n as u64);
 
-        // Add an extra space in the middle for readability
-        if i == 7 {
-            print!(" ");
+    let result = if options.command == cli::Command::Extract {
+        let length = options.length.map(|n| n as u64);
+        splice::extract(
+            &mut input,
+            size,
+            options.skip as u64,
+            length,
+            align,
+            &mut out,
+        )
+    } else {
+        let placement = splice::Placement {
+            offset: options.skip as u64,
+            length: options.length.map(|n| n as u64),
+            insert: options.insert,
+            align,
+        };
+        splice::splice(&mut input, size, &blob, placement, &mut out)
+    };
+    let result = result.and_then(|written| out.flush().map(|()| written));
+    // Don't leave a half-written file behind when something fails.
+    let written = result.map_err(|e| {
+        let _ = std::fs::remove_file(output);
+        if e.kind() == io::ErrorKind::InvalidInput {
+            // A size check: the range on the command line does not fit.
+            Error::Usage(e.to_string())
+        } else if input.failed {
+            Error::reading(path, e)
+        } else {
+            Error::writing(output, e)
         }
+    })?;
+
+    eprintln!("wrote {} bytes to {}", written, output);
+    Ok(())
+}
+
+/// True if both paths name the same existing file.
+fn same_file(a: &str, b: &str) -> bool {
+    match (std::fs::canonicalize(a), std::fs::canonicalize(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
     }
+}
 
-    print!(" |");
+/// Prints the ten most English-looking single-byte XOR keys for the data
+/// at `start`, after any other transforms have been applied.
+fn print_xor_ranking<R: Read>(
+    reader: &mut R,
+    start: usize,
+    transforms: &[transform::Transform],
+    json: bool,
+) -> io::Result<()> {
+    let mut sample = Vec::new();
+    reader
+        .take(transform::BRUTE_FORCE_SAMPLE as u64)
+        .read_to_end(&mut sample)?;
+    transform::apply_all(transforms, &mut sample, start);
 
-    // Print the ASCII representation
-    for &byte in bytes {
-        // We only print printable characters (ASCII 32-126).
-        // Everything else (like newlines or null bytes) gets a dot.
-        if byte >= 32 && byte <= 126 {
-            print!("{}", byte as char);
-        } else {
-            print!(".");
+    let mut out = io::stdout().lock();
+    if !json {
+        if !transforms.is_empty() {
+            writeln!(out, "{}", transform::header(transforms))?;
         }
+        writeln!(out, "rank  key        score  preview")?;
+    }
+
+    for (rank, (key, score)) in transform::rank_xor_keys(&sample)
+        .into_iter()
+        .take(10)
+        .enumerate()
+    {
+        let preview: String = sample.iter().take(40).map(|b| printable(b ^ key)).collect();
+        if json {
+            json::write_xor_key(&mut out, rank + 1, key, score, &preview)?;
+            continue;
+        }
+        writeln!(
+            out,
+            "{:>4}  --xor {:02x}  {:>6.2}  |{}|",
+            rank + 1,
+            key,
+            score,
+            preview
+        )?;
+    }
+
+    Ok(())
+}
+
+/// The character shown for `byte` in the ASCII column.
+///
+/// We only print printable characters (ASCII 32-126).
+/// Everything else (like newlines or null bytes) gets a dot.
+fn printable(byte: u8) -> char {
+    if (32..=126).contains(&byte) {
+        byte as char
+    } else {
+        '.'
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    print!("|");
-    println!(); // Newline
+    #[test]
+    fn base_addresses_past_the_largest_one_are_usage_errors() {
+        let path = env::temp_dir().join(format!("hex_viewer_base_{}", std::process::id()));
+        std::fs::write(&path, b"abcd").unwrap();
+        // Skipping past the end still starts the addresses at the skip.
+        let options = cli::Options {
+            skip: 0x1_0000_0000,
+            base_addr: 0xffff_ffff_0000_0000,
+            disasm: Some(disasm::Arch::X86_64),
+            ..cli::Options::default()
+        };
+        let settings = config::Settings::default();
+        let result = view_file(&path, false, false, &options, &settings, &[]);
+        let _ = std::fs::remove_file(&path);
+        assert!(matches!(result, Err(Error::Usage(_))), "{:?}", result);
+    }
 }
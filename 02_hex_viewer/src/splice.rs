@@ -0,0 +1,228 @@
+// Cutting byte ranges out of files, and putting new bytes in.
+//
+// `extract` copies a range (say, one partition of a disk image) into a
+// file of its own. `splice` does the opposite: it drops a blob into a copy
+// of the target, either overwriting bytes in place or inserting them and
+// shifting everything after. We never modify the target itself, so a
+// wrong offset costs nothing but a retry.
+//
+// Both stream their data with `io::copy`, so a multi-gigabyte image never
+// has to fit in memory. Only the blob being spliced in is loaded whole.
+//
+// A range that does not fit comes back as an `InvalidInput` error, so the
+// caller can tell a bad `--skip` or `--length` from a failing disk.
+
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+/// Where and how a blob goes into the target.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Placement {
+    pub offset: u64,
+    /// The size of the region being replaced. When given, the blob must be
+    /// exactly this long: a quick guard against splicing the wrong file.
+    pub length: Option<u64>,
+    /// Shift the rest of the target back instead of overwriting it.
+    pub insert: bool,
+    /// Pad with zero bytes up to a multiple of this many bytes.
+    pub align: Option<u64>,
+}
+
+/// Copies `length` bytes (or everything) from `offset` in `input`, a file
+/// of `size` bytes, to `out`. Returns how many bytes were written,
+/// including alignment padding.
+pub fn extract<R: Read + Seek, W: Write>(
+    input: &mut R,
+    size: u64,
+    offset: u64,
+    length: Option<u64>,
+    align: Option<u64>,
+    out: &mut W,
+) -> io::Result<u64> {
+    if offset > size {
+        return Err(invalid(format!(
+            "offset 0x{:x} is past the end of the file ({} bytes)",
+            offset, size
+        )));
+    }
+    let length = length.unwrap_or(size - offset);
+    // `offset <= size` here, so this can't overflow the way
+    // `offset + length > size` would for a huge length.
+    if length > size - offset {
+        return Err(invalid(format!(
+            "{} bytes at 0x{:x} run past the end of the file ({} bytes)",
+            length, offset, size
+        )));
+    }
+
+    input.seek(SeekFrom::Start(offset))?;
+    let copied = io::copy(&mut input.take(length), out)?;
+    let padding = padding(copied, align);
+    write_zeros(out, padding)?;
+    Ok(copied + padding)
+}
+
+/// Writes a copy of `target` (a file of `size` bytes) to `out`, with `blob`
+/// placed as described by `placement`. Returns the size of the copy.
+pub fn splice<R: Read + Seek, W: Write>(
+    target: &mut R,
+    size: u64,
+    blob: &[u8],
+    placement: Placement,
+    out: &mut W,
+) -> io::Result<u64> {
+    let offset = placement.offset;
+    if offset > size {
+        return Err(invalid(format!(
+            "offset 0x{:x} is past the end of the target ({} bytes)",
+            offset, size
+        )));
+    }
+
+    // 1. PAD THE BLOB
+    // Padding happens first, so the size checks below see the bytes that
+    // will actually be written.
+    let mut blob = blob.to_vec();
+    let padded = blob.len() as u64 + padding(blob.len() as u64, placement.align);
+    blob.resize(padded as usize, 0);
+    let blob_len = blob.len() as u64;
+
+    // 2. CHECK THE SIZES
+    if let Some(length) = placement.length
+        && length != blob_len
+    {
+        return Err(invalid(format!(
+            "blob is {} bytes but the region is {} bytes",
+            blob_len, length
+        )));
+    }
+    // How many bytes of the target the blob replaces.
+    let replaced = if placement.insert { 0 } else { blob_len };
+    if replaced > size - offset {
+        return Err(invalid(format!(
+            "a {}-byte blob at 0x{:x} runs past the end of the target ({} bytes); \
+             use --insert to grow it",
+            blob_len, offset, size
+        )));
+    }
+
+    // 3. WRITE THE COPY: the bytes before, the blob, the bytes after.
+    target.seek(SeekFrom::Start(0))?;
+    io::copy(&mut target.take(offset), out)?;
+    out.write_all(&blob)?;
+    target.seek(SeekFrom::Start(offset + replaced))?;
+    io::copy(target, out)?;
+
+    Ok(size + blob_len - replaced)
+}
+
+/// How many bytes it takes to round `len` up to a multiple of `align`.
+fn padding(len: u64, align: Option<u64>) -> u64 {
+    match align {
+        Some(align) if align > 1 => (align - len % align) % align,
+        _ => 0,
+    }
+}
+
+fn write_zeros<W: Write>(out: &mut W, count: u64) -> io::Result<()> {
+    io::copy(&mut io::repeat(0).take(count), out)?;
+    Ok(())
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    const TARGET: &[u8] = b"0123456789";
+
+    fn run_splice(blob: &[u8], placement: Placement) -> io::Result<Vec<u8>> {
+        let mut out = Vec::new();
+        let size = splice(&mut Cursor::new(TARGET), 10, blob, placement, &mut out)?;
+        assert_eq!(size, out.len() as u64);
+        Ok(out)
+    }
+
+    #[test]
+    fn extracts_ranges_with_optional_padding() {
+        let mut out = Vec::new();
+        extract(&mut Cursor::new(TARGET), 10, 2, Some(3), Some(4), &mut out).unwrap();
+        assert_eq!(out, b"234\0");
+
+        let mut out = Vec::new();
+        extract(&mut Cursor::new(TARGET), 10, 7, None, None, &mut out).unwrap();
+        assert_eq!(out, b"789");
+
+        let err = extract(
+            &mut Cursor::new(TARGET),
+            10,
+            8,
+            Some(4),
+            None,
+            &mut Vec::new(),
+        );
+        assert!(err.unwrap_err().to_string().contains("past the end"));
+
+        let huge = extract(
+            &mut Cursor::new(TARGET),
+            10,
+            1,
+            Some(u64::MAX),
+            None,
+            &mut Vec::new(),
+        );
+        assert!(huge.unwrap_err().to_string().contains("past the end"));
+    }
+
+    #[test]
+    fn overwrites_or_inserts() {
+        let at = |offset| Placement {
+            offset,
+            ..Placement::default()
+        };
+        assert_eq!(run_splice(b"ab", at(3)).unwrap(), b"012ab56789");
+        assert_eq!(run_splice(b"ab", at(8)).unwrap(), b"01234567ab");
+
+        let insert = Placement {
+            insert: true,
+            ..at(10)
+        };
+        assert_eq!(run_splice(b"ab", insert).unwrap(), b"0123456789ab");
+    }
+
+    #[test]
+    fn checks_sizes_after_padding() {
+        let placement = Placement {
+            offset: 4,
+            length: Some(4),
+            align: Some(4),
+            insert: false,
+        };
+        assert_eq!(run_splice(b"ab", placement).unwrap(), b"0123ab\0\089");
+
+        let wrong_size = Placement {
+            align: None,
+            ..placement
+        };
+        assert!(
+            run_splice(b"ab", wrong_size)
+                .unwrap_err()
+                .to_string()
+                .contains("region is 4")
+        );
+
+        let too_long = Placement {
+            offset: 8,
+            ..Placement::default()
+        };
+        assert!(
+            run_splice(b"abc", too_long)
+                .unwrap_err()
+                .to_string()
+                .contains("--insert")
+        );
+    }
+}
@@ -0,0 +1,331 @@
+// Decoding the text column.
+//
+// The classic ASCII column prints a dot for anything outside 32..=126, which
+// makes UTF-8 text, Latin-1 strings or mainframe (EBCDIC) records
+// unreadable. This module turns bytes into characters for a handful of
+// encodings, while keeping the one rule a hex dump cannot break:
+//
+//     every byte gets exactly one column.
+//
+// That needs some care:
+//
+// * A multi-byte character (UTF-8, UTF-16) is drawn in the column of its
+//   first byte. Its other bytes are drawn as `•`.
+// * A wide character (most CJK, many emoji) takes two columns, so the
+//   column after it is left empty.
+// * A character may start at the end of one line and finish on the next.
+//   It is drawn on whichever line has room for it, and the decoder is given
+//   a few bytes of context on each side of the line so it can tell.
+// * Control characters become Unicode "control pictures" (␀, ␊, ␛...)
+//   instead of dots, so you can still see which control character it is.
+
+use std::ops::Range;
+
+/// How many bytes of context we need on each side of a line: the longest
+/// character (4 bytes in UTF-8 and UTF-16) minus one.
+pub const CONTEXT: usize = 3;
+
+/// Drawn for the second, third and fourth bytes of a multi-byte character.
+const CONTINUATION: char = '•';
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Encoding {
+    /// The classic column: printable ASCII, and a dot for everything else.
+    #[default]
+    Ascii,
+    Utf8,
+    Latin1,
+    Utf16Le,
+    Utf16Be,
+    /// EBCDIC, as used on IBM mainframes (code page 037).
+    Ebcdic,
+    /// The original IBM PC character set.
+    Cp437,
+}
+
+impl Encoding {
+    pub fn from_name(name: &str) -> Option<Encoding> {
+        match name.to_ascii_lowercase().as_str() {
+            "ascii" => Some(Encoding::Ascii),
+            "utf8" | "utf-8" => Some(Encoding::Utf8),
+            "latin1" | "latin-1" | "iso-8859-1" => Some(Encoding::Latin1),
+            "utf16le" | "utf-16le" => Some(Encoding::Utf16Le),
+            "utf16be" | "utf-16be" => Some(Encoding::Utf16Be),
+            "ebcdic" | "cp037" => Some(Encoding::Ebcdic),
+            "cp437" => Some(Encoding::Cp437),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Encoding::Ascii => "ascii",
+            Encoding::Utf8 => "utf8",
+            Encoding::Latin1 => "latin1",
+            Encoding::Utf16Le => "utf16le",
+            Encoding::Utf16Be => "utf16be",
+            Encoding::Ebcdic => "ebcdic",
+            Encoding::Cp437 => "cp437",
+        }
+    }
+
+    /// True when a character can span several bytes, which means lines
+    /// need context from their neighbours to be decoded correctly.
+    pub fn is_multi_byte(self) -> bool {
+        matches!(self, Encoding::Utf8 | Encoding::Utf16Le | Encoding::Utf16Be)
+    }
+}
+
+/// Decodes the text column for one line.
+///
+/// `window` holds the line plus up to `CONTEXT` bytes on either side, and
+/// `line` is where the line sits inside it. `window_offset` is the position
+/// of `window[0]` counted from the start of the dump; UTF-16 code units are
+/// aligned to even positions counted from there.
+///
+/// The result has one entry per byte of the line. `None` means "nothing to
+/// draw here", because the wide character before it already uses this column.
+pub fn cells(
+    encoding: Encoding,
+    window: &[u8],
+    window_offset: usize,
+    line: Range<usize>,
+) -> Vec<Option<char>> {
+    if !encoding.is_multi_byte() {
+        return window[line]
+            .iter()
+            .map(|&b| Some(single_byte(encoding, b)))
+            .collect();
+    }
+
+    let mut cells = vec![Some(CONTINUATION); line.len()];
+
+    // Start decoding far enough back to catch a character that began on the
+    // previous line. UTF-8 resynchronises by itself; UTF-16 must start on an
+    // even position.
+    let mut pos = line.start.saturating_sub(CONTEXT);
+    if encoding != Encoding::Utf8 && (window_offset + pos) % 2 == 1 {
+        pos += 1;
+    }
+
+    while pos < line.end {
+        let (c, len) = decode(encoding, &window[pos..]);
+        place(&mut cells, c, pos..pos + len, &line);
+        pos += len;
+    }
+    cells
+}
+
+/// Decides which line draws the character covering `span`, and draws it
+/// if it is this one.
+fn place(cells: &mut [Option<char>], c: char, span: Range<usize>, line: &Range<usize>) {
+    let width = if is_wide(c) { 2 } else { 1 };
+    let visible = span.start.max(line.start)..span.end.min(line.end);
+    if visible.is_empty() {
+        return;
+    }
+
+    if span.start < line.start {
+        // The character started on the previous line. That line drew it if
+        // it had room; otherwise we do, at our first column.
+        if width <= line.start - span.start || width > visible.len() {
+            return;
+        }
+    } else if width > visible.len() {
+        // Not enough room before the end of this line: the next line draws it.
+        return;
+    }
+
+    let column = visible.start - line.start;
+    cells[column] = Some(c);
+    if width == 2 {
+        cells[column + 1] = None;
+    }
+}
+
+/// Decodes one character from the start of `bytes` and returns it (ready
+/// to display) with the number of bytes it used. Invalid or truncated
+/// input decodes as a single '.' so the dump always moves forward.
+fn decode(encoding: Encoding, bytes: &[u8]) -> (char, usize) {
+    match encoding {
+        Encoding::Utf8 => decode_utf8(bytes),
+        Encoding::Utf16Le => decode_utf16(bytes, u16::from_le_bytes),
+        Encoding::Utf16Be => decode_utf16(bytes, u16::from_be_bytes),
+        _ => (single_byte(encoding, bytes[0]), 1),
+    }
+}
+
+fn decode_utf8(bytes: &[u8]) -> (char, usize) {
+    let len = match bytes[0] {
+        0x00..=0x7f => 1,
+        0xc2..=0xdf => 2,
+        0xe0..=0xef => 3,
+        0xf0..=0xf4 => 4,
+        _ => return ('.', 1),
+    };
+    // `from_utf8` does the hard part: overlong forms, surrogates, range.
+    match bytes.get(..len).and_then(|b| std::str::from_utf8(b).ok()) {
+        Some(text) => (display(text.chars().next().unwrap_or('.')), len),
+        None => ('.', 1),
+    }
+}
+
+fn decode_utf16(bytes: &[u8], unit: fn([u8; 2]) -> u16) -> (char, usize) {
+    let Some(first) = bytes.get(..2) else {
+        return ('.', 1);
+    };
+    let first = unit([first[0], first[1]]);
+    let second = bytes.get(2..4).map(|b| unit([b[0], b[1]]));
+
+    let units: Vec<u16> = std::iter::once(first).chain(second).collect();
+    match char::decode_utf16(units).next() {
+        Some(Ok(c)) => (display(c), c.len_utf16() * 2),
+        _ => ('.', 2),
+    }
+}
+
+fn single_byte(encoding: Encoding, byte: u8) -> char {
+    match encoding {
+        Encoding::Ascii => crate::printable(byte),
+        Encoding::Ebcdic => display(CP037_TO_LATIN1[byte as usize] as char),
+        Encoding::Cp437 if byte >= 0x80 => display(CP437_HIGH[(byte - 0x80) as usize]),
+        // Latin-1 is the first 256 Unicode code points, and the low half of
+        // CP437 is ASCII, so the byte value is the character.
+        _ => display(byte as char),
+    }
+}
+
+/// Turns a decoded character into something safe to draw in one cell.
+fn display(c: char) -> char {
+    match c {
+        // U+2400.. are pictures of the C0 control characters: ␀ ␁ ... ␟
+        '\u{0}'..='\u{1f}' => char::from_u32(0x2400 + c as u32).unwrap_or('.'),
+        '\u{7f}' => '␡',
+        // C1 controls, soft hyphen, zero-width and combining characters
+        // would either do something to the terminal or take no space.
+        '\u{80}'..='\u{9f}' | '\u{ad}' => '.',
+        '\u{300}'..='\u{36f}' | '\u{200b}'..='\u{200f}' | '\u{feff}' => '.',
+        c => c,
+    }
+}
+
+/// True for characters that terminals draw two columns wide. This covers
+/// the big East Asian blocks and emoji, which is what shows up in practice.
+fn is_wide(c: char) -> bool {
+    matches!(c as u32,
+        0x1100..=0x115f
+        | 0x2e80..=0x303e
+        | 0x3041..=0x33ff
+        | 0x3400..=0x4dbf
+        | 0x4e00..=0x9fff
+        | 0xa000..=0xa4cf
+        | 0xac00..=0xd7a3
+        | 0xf900..=0xfaff
+        | 0xfe30..=0xfe4f
+        | 0xff00..=0xff60
+        | 0xffe0..=0xffe6
+        | 0x1f300..=0x1f64f
+        | 0x1f900..=0x1f9ff
+        | 0x20000..=0x3fffd)
+}
+
+// EBCDIC code page 037 happens to contain exactly the 256 Latin-1
+// characters, just in a different order, so one byte-to-byte table does it.
+const CP037_TO_LATIN1: [u8; 256] = [
+    0x00, 0x01, 0x02, 0x03, 0x9c, 0x09, 0x86, 0x7f, 0x97, 0x8d, 0x8e, 0x0b, 0x0c, 0x0d, 0x0e, 0x0f,
+    0x10, 0x11, 0x12, 0x13, 0x9d, 0x85, 0x08, 0x87, 0x18, 0x19, 0x92, 0x8f, 0x1c, 0x1d, 0x1e, 0x1f,
+    0x80, 0x81, 0x82, 0x83, 0x84, 0x0a, 0x17, 0x1b, 0x88, 0x89, 0x8a, 0x8b, 0x8c, 0x05, 0x06, 0x07,
+    0x90, 0x91, 0x16, 0x93, 0x94, 0x95, 0x96, 0x04, 0x98, 0x99, 0x9a, 0x9b, 0x14, 0x15, 0x9e, 0x1a,
+    0x20, 0xa0, 0xe2, 0xe4, 0xe0, 0xe1, 0xe3, 0xe5, 0xe7, 0xf1, 0xa2, 0x2e, 0x3c, 0x28, 0x2b, 0x7c,
+    0x26, 0xe9, 0xea, 0xeb, 0xe8, 0xed, 0xee, 0xef, 0xec, 0xdf, 0x21, 0x24, 0x2a, 0x29, 0x3b, 0xac,
+    0x2d, 0x2f, 0xc2, 0xc4, 0xc0, 0xc1, 0xc3, 0xc5, 0xc7, 0xd1, 0xa6, 0x2c, 0x25, 0x5f, 0x3e, 0x3f,
+    0xf8, 0xc9, 0xca, 0xcb, 0xc8, 0xcd, 0xce, 0xcf, 0xcc, 0x60, 0x3a, 0x23, 0x40, 0x27, 0x3d, 0x22,
+    0xd8, 0x61, 0x62, 0x63, 0x64, 0x65, 0x66, 0x67, 0x68, 0x69, 0xab, 0xbb, 0xf0, 0xfd, 0xfe, 0xb1,
+    0xb0, 0x6a, 0x6b, 0x6c, 0x6d, 0x6e, 0x6f, 0x70, 0x71, 0x72, 0xaa, 0xba, 0xe6, 0xb8, 0xc6, 0xa4,
+    0xb5, 0x7e, 0x73, 0x74, 0x75, 0x76, 0x77, 0x78, 0x79, 0x7a, 0xa1, 0xbf, 0xd0, 0xdd, 0xde, 0xae,
+    0x5e, 0xa3, 0xa5, 0xb7, 0xa9, 0xa7, 0xb6, 0xbc, 0xbd, 0xbe, 0x5b, 0x5d, 0xaf, 0xa8, 0xb4, 0xd7,
+    0x7b, 0x41, 0x42, 0x43, 0x44, 0x45, 0x46, 0x47, 0x48, 0x49, 0xad, 0xf4, 0xf6, 0xf2, 0xf3, 0xf5,
+    0x7d, 0x4a, 0x4b, 0x4c, 0x4d, 0x4e, 0x4f, 0x50, 0x51, 0x52, 0xb9, 0xfb, 0xfc, 0xf9, 0xfa, 0xff,
+    0x5c, 0xf7, 0x53, 0x54, 0x55, 0x56, 0x57, 0x58, 0x59, 0x5a, 0xb2, 0xd4, 0xd6, 0xd2, 0xd3, 0xd5,
+    0x30, 0x31, 0x32, 0x33, 0x34, 0x35, 0x36, 0x37, 0x38, 0x39, 0xb3, 0xdb, 0xdc, 0xd9, 0xda, 0x9f,
+];
+
+// The top half of the original IBM PC character set: accented letters,
+// box drawing and a little math. The bottom half is plain ASCII.
+const CP437_HIGH: [char; 128] = [
+    'Ç', 'ü', 'é', 'â', 'ä', 'à', 'å', 'ç', 'ê', 'ë', 'è', 'ï', 'î', 'ì', 'Ä', 'Å', 'É', 'æ', 'Æ',
+    'ô', 'ö', 'ò', 'û', 'ù', 'ÿ', 'Ö', 'Ü', '¢', '£', '¥', '₧', 'ƒ', 'á', 'í', 'ó', 'ú', 'ñ', 'Ñ',
+    'ª', 'º', '¿', '⌐', '¬', '½', '¼', '¡', '«', '»', '░', '▒', '▓', '│', '┤', '╡', '╢', '╖', '╕',
+    '╣', '║', '╗', '╝', '╜', '╛', '┐', '└', '┴', '┬', '├', '─', '┼', '╞', '╟', '╚', '╔', '╩', '╦',
+    '╠', '═', '╬', '╧', '╨', '╤', '╥', '╙', '╘', '╒', '╓', '╫', '╪', '┘', '┌', '█', '▄', '▌', '▐',
+    '▀', 'α', 'ß', 'Γ', 'π', 'Σ', 'σ', 'µ', 'τ', 'Φ', 'Θ', 'Ω', 'δ', '∞', 'φ', 'ε', '∩', '≡', '±',
+    '≥', '≤', '⌠', '⌡', '÷', '≈', '°', '∙', '·', '√', 'ⁿ', '²', '■', '\u{a0}',
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn line(encoding: Encoding, bytes: &[u8]) -> String {
+        cells(encoding, bytes, 0, 0..bytes.len())
+            .into_iter()
+            .flatten()
+            .collect()
+    }
+
+    #[test]
+    fn single_byte_encodings() {
+        assert_eq!(line(Encoding::Ascii, b"A\x00\xe9"), "A..");
+        assert_eq!(line(Encoding::Latin1, b"caf\xe9\x00\n"), "café␀␊");
+        assert_eq!(
+            line(Encoding::Ebcdic, &[0xc8, 0x85, 0x93, 0x93, 0x96, 0x25]),
+            "Hello␊"
+        );
+        assert_eq!(line(Encoding::Cp437, &[0xc9, 0xcd, 0xbb, 0x7f]), "╔═╗␡");
+    }
+
+    #[test]
+    fn utf8_keeps_one_column_per_byte() {
+        // é is two bytes, 日 is three bytes but two columns wide.
+        assert_eq!(line(Encoding::Utf8, "é日!".as_bytes()), "é•日•!");
+        assert_eq!(line(Encoding::Utf8, b"\xff\xc3"), "..");
+    }
+
+    #[test]
+    fn characters_spanning_lines_are_drawn_once() {
+        // "ab日" split as [a b 0xe6] [0x97 0xa5]: 日 has no room on the
+        // first line (one column left, needs two), so the second line draws it.
+        let bytes = "ab日".as_bytes();
+        let first: String = cells(Encoding::Utf8, bytes, 0, 0..3)
+            .into_iter()
+            .flatten()
+            .collect();
+        let second: String = cells(Encoding::Utf8, bytes, 0, 3..5)
+            .into_iter()
+            .flatten()
+            .collect();
+        assert_eq!(first, "ab•");
+        assert_eq!(second, "日");
+
+        // "aé" split as [a 0xc3] [0xa9]: é fits on the first line.
+        let bytes = "aé".as_bytes();
+        let first: String = cells(Encoding::Utf8, bytes, 0, 0..2)
+            .into_iter()
+            .flatten()
+            .collect();
+        let second: String = cells(Encoding::Utf8, bytes, 0, 2..3)
+            .into_iter()
+            .flatten()
+            .collect();
+        assert_eq!(first, "aé");
+        assert_eq!(second, "•");
+    }
+
+    #[test]
+    fn utf16_in_both_byte_orders() {
+        let le: Vec<u8> = "Hi😀".encode_utf16().flat_map(u16::to_le_bytes).collect();
+        let be: Vec<u8> = "Hi😀".encode_utf16().flat_map(u16::to_be_bytes).collect();
+        assert_eq!(line(Encoding::Utf16Le, &le), "H•i•😀••");
+        assert_eq!(line(Encoding::Utf16Be, &be), "H•i•😀••");
+    }
+}
@@ -0,0 +1,204 @@
+// Byte transforms applied before display.
+//
+// Obfuscated data (CTF challenges, malware configs, "encrypted" save games)
+// is very often just XORed with a key or rotated a few bits. Undoing that
+// on the fly lets us read the plain text without writing a script first.
+//
+// Transforms compose: `--xor 41 --rol 3` first XORs every byte, then
+// rotates the result. They always run in the order they were given.
+
+/// The largest sample `--xor-brute` looks at. Scoring more than this does
+/// not change the ranking, it just takes longer.
+pub const BRUTE_FORCE_SAMPLE: usize = 64 * 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Transform {
+    /// XOR with a key that repeats every `key.len()` bytes.
+    Xor(Vec<u8>),
+    Add(u8),
+    Sub(u8),
+    RotateLeft(u32),
+    RotateRight(u32),
+    /// Mirror the bits of every byte: 0b0000_0001 becomes 0b1000_0000.
+    BitReverse,
+}
+
+impl Transform {
+    /// Transforms `bytes` in place. `offset` is the file position of
+    /// `bytes[0]`; a repeating key has to line up with the file, not with
+    /// whatever chunk we happen to be holding.
+    pub fn apply(&self, bytes: &mut [u8], offset: usize) {
+        match self {
+            Transform::Xor(key) => {
+                for (i, byte) in bytes.iter_mut().enumerate() {
+                    *byte ^= key[(offset + i) % key.len()];
+                }
+            }
+            Transform::Add(n) => bytes.iter_mut().for_each(|b| *b = b.wrapping_add(*n)),
+            Transform::Sub(n) => bytes.iter_mut().for_each(|b| *b = b.wrapping_sub(*n)),
+            Transform::RotateLeft(n) => bytes.iter_mut().for_each(|b| *b = b.rotate_left(*n)),
+            Transform::RotateRight(n) => bytes.iter_mut().for_each(|b| *b = b.rotate_right(*n)),
+            Transform::BitReverse => bytes.iter_mut().for_each(|b| *b = b.reverse_bits()),
+        }
+    }
+
+    /// A short description that doubles as the flag needed to reproduce it.
+    pub fn describe(&self) -> String {
+        match self {
+            Transform::Xor(key) => {
+                let hex: String = key.iter().map(|b| format!("{:02x}", b)).collect();
+                format!("--xor {}", hex)
+            }
+            Transform::Add(n) => format!("--add {}", n),
+            Transform::Sub(n) => format!("--sub {}", n),
+            Transform::RotateLeft(n) => format!("--rol {}", n),
+            Transform::RotateRight(n) => format!("--ror {}", n),
+            Transform::BitReverse => "--bitrev".to_string(),
+        }
+    }
+}
+
+pub fn apply_all(transforms: &[Transform], bytes: &mut [u8], offset: usize) {
+    for transform in transforms {
+        transform.apply(bytes, offset);
+    }
+}
+
+/// The header line printed above a transformed dump, e.g.
+/// `# transforms: --xor 41 --rol 3`.
+pub fn header(transforms: &[Transform]) -> String {
+    let flags: Vec<String> = transforms.iter().map(Transform::describe).collect();
+    format!("# transforms: {}", flags.join(" "))
+}
+
+/// Parses an XOR key written as hex digits: `41`, `0x41` or `deadbeef`.
+pub fn parse_key(text: &str) -> Result<Vec<u8>, String> {
+    let digits = text.strip_prefix("0x").unwrap_or(text);
+    if !digits.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(format!("invalid key '{}': not hex", text));
+    }
+    if digits.is_empty() || !digits.len().is_multiple_of(2) {
+        return Err(format!(
+            "invalid key '{}': expected an even number of hex digits",
+            text
+        ));
+    }
+
+    // Only ASCII digits are left, so every two bytes are two characters.
+    (0..digits.len())
+        .step_by(2)
+        .map(|i| {
+            u8::from_str_radix(&digits[i..i + 2], 16)
+                .map_err(|_| format!("invalid key '{}': not hex", text))
+        })
+        .collect()
+}
+
+// Relative frequency (in percent) of each letter in English text, a..z.
+const LETTER_FREQUENCY: [f64; 26] = [
+    8.2, 1.5, 2.8, 4.3, 12.7, 2.2, 2.0, 6.1, 7.0, 0.15, 0.77, 4.0, 2.4, 6.7, 7.5, 1.9, 0.095, 6.0,
+    6.3, 9.1, 2.8, 0.98, 2.4, 0.15, 2.0, 0.074,
+];
+
+/// How much `bytes` looks like English text, per byte.
+///
+/// Letters score by how common they are, spaces score like a common
+/// letter, other printable characters are neutral, and anything a text
+/// file would not contain costs points. Higher is more English-like.
+pub fn english_score(bytes: &[u8]) -> f64 {
+    if bytes.is_empty() {
+        return 0.0;
+    }
+
+    let total: f64 = bytes
+        .iter()
+        .map(|&b| match b {
+            b'a'..=b'z' => LETTER_FREQUENCY[(b - b'a') as usize],
+            // Capitals are a bit less likely than their lowercase letters.
+            b'A'..=b'Z' => LETTER_FREQUENCY[(b - b'A') as usize] * 0.5,
+            b' ' => 13.0,
+            b'\n' | b'\r' | b'\t' => 1.0,
+            33..=126 => 0.0,
+            _ => -10.0,
+        })
+        .sum();
+
+    total / bytes.len() as f64
+}
+
+/// Tries every single-byte XOR key and returns `(key, score)` pairs, best
+/// first. A key of 0 is included: maybe the data was never obfuscated.
+pub fn rank_xor_keys(sample: &[u8]) -> Vec<(u8, f64)> {
+    let mut scratch = vec![0u8; sample.len()];
+    let mut ranking: Vec<(u8, f64)> = (0..=255u8)
+        .map(|key| {
+            for (out, &b) in scratch.iter_mut().zip(sample) {
+                *out = b ^ key;
+            }
+            (key, english_score(&scratch))
+        })
+        .collect();
+
+    ranking.sort_by(|a, b| b.1.total_cmp(&a.1));
+    ranking
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn repeating_xor_follows_file_offsets() {
+        let key = Transform::Xor(vec![0x01, 0x02]);
+
+        let mut whole = [0u8; 4];
+        key.apply(&mut whole, 0);
+        assert_eq!(whole, [1, 2, 1, 2]);
+
+        // The same bytes read as a chunk starting at offset 1.
+        let mut chunk = [0u8; 3];
+        key.apply(&mut chunk, 1);
+        assert_eq!(chunk, [2, 1, 2]);
+    }
+
+    #[test]
+    fn transforms_compose_in_order() {
+        let transforms = [
+            Transform::Add(1),
+            Transform::RotateLeft(4),
+            Transform::BitReverse,
+        ];
+        let mut bytes = [0x0f];
+        apply_all(&transforms, &mut bytes, 0);
+        // 0x0f + 1 = 0x10, rotated left by 4 = 0x01, reversed = 0x80.
+        assert_eq!(bytes, [0x80]);
+        assert_eq!(
+            header(&transforms),
+            "# transforms: --add 1 --rol 4 --bitrev"
+        );
+    }
+
+    #[test]
+    fn parses_keys() {
+        assert_eq!(parse_key("41").unwrap(), vec![0x41]);
+        assert_eq!(
+            parse_key("0xdeadbeef").unwrap(),
+            vec![0xde, 0xad, 0xbe, 0xef]
+        );
+        assert!(parse_key("abc").is_err());
+        assert!(parse_key("zz").is_err());
+        assert!(parse_key("").is_err());
+        // 'é' is two bytes, so the length alone looks fine.
+        assert_eq!(
+            parse_key("aé1"),
+            Err("invalid key 'aé1': not hex".to_string())
+        );
+    }
+
+    #[test]
+    fn brute_force_finds_single_byte_key() {
+        let plain = b"The quick brown fox jumps over the lazy dog, twice over.";
+        let hidden: Vec<u8> = plain.iter().map(|b| b ^ 0x5a).collect();
+        assert_eq!(rank_xor_keys(&hidden)[0].0, 0x5a);
+    }
+}
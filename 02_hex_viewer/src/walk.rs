@@ -0,0 +1,190 @@
+// Turning the paths on the command line into a list of files.
+//
+// Files are taken as they are. Directories are walked recursively, in
+// sorted order so the output is the same on every run, and only the files
+// whose names match an `--include` pattern are kept (all of them when
+// there is no pattern).
+//
+// A directory we cannot read does not stop the walk: it becomes an error
+// entry in the list, and the caller reports it and carries on.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// One input: the file to show, or a path we could not get at and why.
+pub type Entry = Result<PathBuf, (PathBuf, io::Error)>;
+
+/// Expands `paths` into the files to show, in order.
+pub fn expand(paths: &[String], include: &[String]) -> Vec<Entry> {
+    let mut entries = Vec::new();
+    for path in paths {
+        let path = PathBuf::from(path);
+        match fs::metadata(&path) {
+            Ok(meta) if meta.is_dir() => walk(&path, include, &mut entries),
+            // A file named on the command line is always shown, even if it
+            // does not match the patterns: you asked for it by name.
+            Ok(_) => entries.push(Ok(path)),
+            Err(e) => entries.push(Err((path, e))),
+        }
+    }
+    entries
+}
+
+/// True if any of `paths` is a directory, i.e. there may be many files.
+pub fn has_directory(paths: &[String]) -> bool {
+    paths.iter().any(|path| Path::new(path).is_dir())
+}
+
+fn walk(dir: &Path, include: &[String], entries: &mut Vec<Entry>) {
+    let mut children: Vec<(PathBuf, fs::FileType)> = match fs::read_dir(dir) {
+        Ok(list) => list
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| Some((entry.path(), entry.file_type().ok()?)))
+            .collect(),
+        Err(e) => {
+            entries.push(Err((dir.to_path_buf(), e)));
+            return;
+        }
+    };
+    children.sort_by(|a, b| a.0.cmp(&b.0));
+
+    for (path, kind) in children {
+        if kind.is_dir() {
+            walk(&path, include, entries);
+            continue;
+        }
+        // We follow symlinks to files, but not to directories: a link back
+        // to a parent would send us round in circles forever. Sockets,
+        // FIFOs and devices are skipped too, since reading a FIFO blocks.
+        let is_file = if kind.is_symlink() {
+            fs::metadata(&path).is_ok_and(|meta| meta.is_file())
+        } else {
+            kind.is_file()
+        };
+        if is_file && included(&path, include) {
+            entries.push(Ok(path));
+        }
+    }
+}
+
+fn included(path: &Path, include: &[String]) -> bool {
+    let name = path
+        .file_name()
+        .map(|n| n.to_string_lossy())
+        .unwrap_or_default();
+    include.is_empty() || include.iter().any(|pattern| glob_match(pattern, &name))
+}
+
+/// Matches `name` against a shell-style pattern: `*` is any run of
+/// characters, `?` any one character, `[abc]`, `[a-z]` and `[!abc]` are
+/// character classes.
+pub fn glob_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+
+    // The classic two-pointer match: on a mismatch we go back to the last
+    // `*` and let it swallow one more character.
+    let (mut p, mut n) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+    while n < name.len() {
+        if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, n));
+            p += 1;
+            continue;
+        }
+        if p < pattern.len()
+            && let Some(len) = match_one(&pattern[p..], name[n])
+        {
+            p += len;
+            n += 1;
+            continue;
+        }
+        match star {
+            Some((star_p, star_n)) => {
+                p = star_p + 1;
+                n = star_n + 1;
+                star = Some((star_p, star_n + 1));
+            }
+            None => return false,
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Matches `c` against the pattern element at the start of `pattern`.
+/// Returns how many pattern characters the element used, if it matched.
+fn match_one(pattern: &[char], c: char) -> Option<usize> {
+    match pattern[0] {
+        '?' => Some(1),
+        '[' => {
+            // A `[` that is never closed is just a `[`.
+            let Some(close) = pattern.iter().skip(2).position(|&p| p == ']') else {
+                return (c == '[').then_some(1);
+            };
+            let close = close + 2;
+            let (negate, class) = match pattern[1] {
+                '!' | '^' => (true, &pattern[2..close]),
+                _ => (false, &pattern[1..close]),
+            };
+            let mut found = false;
+            let mut i = 0;
+            while i < class.len() {
+                if i + 2 < class.len() && class[i + 1] == '-' {
+                    found |= (class[i]..=class[i + 2]).contains(&c);
+                    i += 3;
+                } else {
+                    found |= class[i] == c;
+                    i += 1;
+                }
+            }
+            (found != negate).then_some(close + 1)
+        }
+        p => (p == c).then_some(1),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn globs_match_like_the_shell() {
+        assert!(glob_match("*.bin", "firmware.bin"));
+        assert!(!glob_match("*.bin", "firmware.bin.gz"));
+        assert!(glob_match("part?.img", "part1.img"));
+        assert!(glob_match("*[0-9]", "sda3"));
+        assert!(!glob_match("[!s]*", "sda3"));
+        assert!(glob_match("a*b*c", "aXbYbZc"));
+        assert!(glob_match("*", ""));
+        assert!(glob_match("[x", "[x"));
+    }
+
+    #[test]
+    fn walks_directories_in_order_and_filters_names() {
+        let root = std::env::temp_dir().join(format!("hex_viewer_walk_{}", std::process::id()));
+        fs::create_dir_all(root.join("sub")).unwrap();
+        for name in ["b.bin", "a.bin", "notes.txt", "sub/c.bin"] {
+            fs::write(root.join(name), b"x").unwrap();
+        }
+
+        let paths = vec![
+            root.to_string_lossy().into_owned(),
+            "/no/such/file".to_string(),
+        ];
+        let entries = expand(&paths, &["*.bin".to_string()]);
+        let names: Vec<String> = entries
+            .iter()
+            .map(|entry| match entry {
+                Ok(path) => path.strip_prefix(&root).unwrap().display().to_string(),
+                Err((path, _)) => format!("error: {}", path.display()),
+            })
+            .collect();
+        assert_eq!(
+            names,
+            ["a.bin", "b.bin", "sub/c.bin", "error: /no/such/file"]
+        );
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,317 @@
+// Integer arithmetic: `$((expression))`.
+//
+//     echo $((1 + 2 * 3))          7
+//     echo $(( (1 + 2) * 3 ))      9
+//     i=$((i + 1))                 variables can go without the `$`
+//     echo $((7 / 2)) $((7 % 2))   3 1: integers only, rounded toward zero
+//
+// The operators are those of C, from the loosest to the tightest:
+//
+//     a ? b : c
+//     ||   &&   |   ^   &
+//     == !=   < <= > >=   << >>
+//     + -   * / %   ** (power)
+//     - + ! ~ in front of a value
+//
+// Comparisons, `!`, `&&` and `||` give 1 for true and 0 for false, and
+// `&&`, `||` and `?:` only work out the side they need. Numbers are
+// 64-bit; like in C, going past the largest one wraps around. They can be
+// written in hex (`0xff`) or octal (`017`). A variable that is unset or
+// empty counts as 0.
+//
+// The expression has been expanded when it gets here (see `expand.rs`),
+// so `$x` and `$(cmd)` in it are already their values. Only bare names
+// like `i` are left to look up.
+//
+// Assignments like `$((i += 1))` are not supported; write `i=$((i + 1))`.
+
+use crate::shell::Shell;
+
+/// The value of `expression`.
+pub fn evaluate(expression: &str, shell: &Shell) -> Result<i64, String> {
+    let mut parser = Parser {
+        tokens: tokenize(expression)?,
+        next: 0,
+    };
+    // `$(( ))` is 0, like in other shells.
+    if parser.tokens.is_empty() {
+        return Ok(0);
+    }
+    let expr = parser.ternary()?;
+    if let Some(token) = parser.tokens.get(parser.next) {
+        return Err(format!("unexpected '{}'", token));
+    }
+    eval(&expr, shell)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Number(i64),
+    Name(String),
+    Operator(&'static str),
+}
+
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Token::Number(n) => write!(f, "{}", n),
+            Token::Name(name) => write!(f, "{}", name),
+            Token::Operator(op) => write!(f, "{}", op),
+        }
+    }
+}
+
+/// Longest first, so that `**` isn't read as two `*`.
+const OPERATORS: [&str; 25] = [
+    "**", "<<", ">>", "<=", ">=", "==", "!=", "&&", "||", "+", "-", "*", "/", "%", "<", ">", "&",
+    "|", "^", "!", "~", "?", ":", "(", ")",
+];
+
+/// The binary operators, one level of precedence per entry, loosest first.
+const LEVELS: [&[&str]; 10] = [
+    &["||"],
+    &["&&"],
+    &["|"],
+    &["^"],
+    &["&"],
+    &["==", "!="],
+    &["<", "<=", ">", ">="],
+    &["<<", ">>"],
+    &["+", "-"],
+    &["*", "/", "%"],
+];
+
+fn tokenize(text: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut rest = text.trim_start();
+    while !rest.is_empty() {
+        let c = rest.chars().next().unwrap_or_default();
+        let len = if c.is_ascii_alphanumeric() || c == '_' {
+            let len = rest
+                .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+                .unwrap_or(rest.len());
+            let word = &rest[..len];
+            tokens.push(match c.is_ascii_digit() {
+                true => Token::Number(number(word)?),
+                false => Token::Name(word.to_string()),
+            });
+            len
+        } else if let Some(op) = OPERATORS.iter().find(|op| rest.starts_with(**op)) {
+            tokens.push(Token::Operator(op));
+            op.len()
+        } else if c == '=' {
+            return Err("assignment is not supported, use NAME=$((...))".to_string());
+        } else {
+            return Err(format!("unexpected '{}'", c));
+        };
+        rest = rest[len..].trim_start();
+    }
+    Ok(tokens)
+}
+
+/// Reads `17`, `0x11` or `021`.
+fn number(text: &str) -> Result<i64, String> {
+    let parsed = if let Some(hex) = text.strip_prefix("0x").or(text.strip_prefix("0X")) {
+        i64::from_str_radix(hex, 16)
+    } else if text.len() > 1
+        && let Some(octal) = text.strip_prefix('0')
+    {
+        i64::from_str_radix(octal, 8)
+    } else {
+        text.parse()
+    };
+    parsed.map_err(|_| format!("not a number: {}", text))
+}
+
+/// An expression, parsed.
+#[derive(Debug)]
+enum Expr {
+    Number(i64),
+    Name(String),
+    Unary(&'static str, Box<Expr>),
+    Binary(&'static str, Box<Expr>, Box<Expr>),
+    Ternary(Box<Expr>, Box<Expr>, Box<Expr>),
+}
+
+/// A recursive descent parser, one function per level of precedence.
+struct Parser {
+    tokens: Vec<Token>,
+    next: usize,
+}
+
+impl Parser {
+    /// Takes the next token if it is one of `ops`.
+    fn take(&mut self, ops: &[&'static str]) -> Option<&'static str> {
+        match self.tokens.get(self.next) {
+            Some(Token::Operator(op)) if ops.contains(op) => {
+                self.next += 1;
+                Some(op)
+            }
+            _ => None,
+        }
+    }
+
+    /// `a ? b : c`, which groups to the right: `a ? b : c ? d : e` is
+    /// `a ? b : (c ? d : e)`.
+    fn ternary(&mut self) -> Result<Expr, String> {
+        let condition = self.binary(0)?;
+        if self.take(&["?"]).is_none() {
+            return Ok(condition);
+        }
+        let then = self.ternary()?;
+        if self.take(&[":"]).is_none() {
+            return Err("'?' needs a ':'".to_string());
+        }
+        let otherwise = self.ternary()?;
+        Ok(Expr::Ternary(
+            Box::new(condition),
+            Box::new(then),
+            Box::new(otherwise),
+        ))
+    }
+
+    /// The operators of `LEVELS[level]` and the tighter ones. These group
+    /// to the left: `8 - 4 - 2` is `(8 - 4) - 2`.
+    fn binary(&mut self, level: usize) -> Result<Expr, String> {
+        if level == LEVELS.len() {
+            return self.power();
+        }
+        let mut left = self.binary(level + 1)?;
+        while let Some(op) = self.take(LEVELS[level]) {
+            let right = self.binary(level + 1)?;
+            left = Expr::Binary(op, Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    /// `**` groups to the right: `2 ** 3 ** 2` is `2 ** 9`.
+    fn power(&mut self) -> Result<Expr, String> {
+        let base = self.unary()?;
+        if self.take(&["**"]).is_none() {
+            return Ok(base);
+        }
+        let exponent = self.power()?;
+        Ok(Expr::Binary("**", Box::new(base), Box::new(exponent)))
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        match self.take(&["-", "+", "!", "~"]) {
+            Some(op) => Ok(Expr::Unary(op, Box::new(self.unary()?))),
+            None => self.primary(),
+        }
+    }
+
+    fn primary(&mut self) -> Result<Expr, String> {
+        let token = self.tokens.get(self.next).cloned();
+        self.next += 1;
+        match token {
+            Some(Token::Number(n)) => Ok(Expr::Number(n)),
+            Some(Token::Name(name)) => Ok(Expr::Name(name)),
+            Some(Token::Operator("(")) => {
+                let inside = self.ternary()?;
+                match self.take(&[")"]) {
+                    Some(_) => Ok(inside),
+                    None => Err("'(' needs a ')'".to_string()),
+                }
+            }
+            Some(token) => Err(format!("unexpected '{}'", token)),
+            None => Err("missing a value at the end".to_string()),
+        }
+    }
+}
+
+fn eval(expr: &Expr, shell: &Shell) -> Result<i64, String> {
+    let truth = |b: bool| b as i64;
+    Ok(match expr {
+        Expr::Number(n) => *n,
+        Expr::Name(name) => match shell.get_var(name).map(str::trim) {
+            None | Some("") => 0,
+            Some(value) => {
+                number(value).map_err(|_| format!("{}: not a number: {}", name, value))?
+            }
+        },
+        Expr::Unary(op, value) => {
+            let value = eval(value, shell)?;
+            match *op {
+                "-" => value.wrapping_neg(),
+                "!" => truth(value == 0),
+                "~" => !value,
+                _ => value,
+            }
+        }
+        Expr::Ternary(condition, then, otherwise) => match eval(condition, shell)? {
+            0 => eval(otherwise, shell)?,
+            _ => eval(then, shell)?,
+        },
+        // The right side is only worked out if it matters: `0 && 1 / 0`
+        // is fine.
+        Expr::Binary("&&", a, b) => truth(eval(a, shell)? != 0 && eval(b, shell)? != 0),
+        Expr::Binary("||", a, b) => truth(eval(a, shell)? != 0 || eval(b, shell)? != 0),
+        Expr::Binary(op, a, b) => {
+            let (a, b) = (eval(a, shell)?, eval(b, shell)?);
+            match *op {
+                "+" => a.wrapping_add(b),
+                "-" => a.wrapping_sub(b),
+                "*" => a.wrapping_mul(b),
+                "/" | "%" if b == 0 => return Err("division by zero".to_string()),
+                "/" => a.wrapping_div(b),
+                "%" => a.wrapping_rem(b),
+                "**" if b < 0 => return Err("negative exponent".to_string()),
+                "**" => a.wrapping_pow(u32::try_from(b).unwrap_or(u32::MAX)),
+                "<<" => a.wrapping_shl(b as u32),
+                ">>" => a.wrapping_shr(b as u32),
+                "<" => truth(a < b),
+                "<=" => truth(a <= b),
+                ">" => truth(a > b),
+                ">=" => truth(a >= b),
+                "==" => truth(a == b),
+                "!=" => truth(a != b),
+                "&" => a & b,
+                "^" => a ^ b,
+                _ => a | b,
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn calc(expression: &str) -> Result<i64, String> {
+        let mut shell = Shell::default();
+        shell.set_var("x", "6".to_string());
+        shell.set_var("empty", String::new());
+        shell.set_var("word", "abc".to_string());
+        evaluate(expression, &shell)
+    }
+
+    #[test]
+    fn precedence_and_grouping() {
+        assert_eq!(calc("1 + 2 * 3"), Ok(7));
+        assert_eq!(calc("(1 + 2) * 3"), Ok(9));
+        assert_eq!(calc("8 - 4 - 2"), Ok(2));
+        assert_eq!(calc("2 ** 3 ** 2"), Ok(512));
+        assert_eq!(calc("-2 ** 2"), Ok(4));
+        assert_eq!(calc("1 + 2 == 3 && 4 > 5 || !0"), Ok(1));
+        assert_eq!(calc("x > 5 ? x * 2 : 0"), Ok(12));
+        assert_eq!(calc("0 ? 1 : 2 ? 3 : 4"), Ok(3));
+        assert_eq!(calc("1 << 4 | 3 & ~1 ^ 0x10"), Ok(18));
+        assert_eq!(calc("7 / 2 + -7 % 2 + 017"), Ok(17));
+        assert_eq!(calc(""), Ok(0));
+    }
+
+    #[test]
+    fn variables_and_errors() {
+        assert_eq!(calc("x + empty + unset"), Ok(6));
+        assert_eq!(calc("1 / (x - 6)"), Err("division by zero".to_string()));
+        assert_eq!(calc("5 % 0"), Err("division by zero".to_string()));
+        assert_eq!(calc("0 && 1 / 0"), Ok(0));
+        assert_eq!(calc("word + 1"), Err("word: not a number: abc".to_string()));
+        assert_eq!(calc("1 +"), Err("missing a value at the end".to_string()));
+        assert_eq!(calc("(1"), Err("'(' needs a ')'".to_string()));
+        assert_eq!(calc("1 2"), Err("unexpected '2'".to_string()));
+        assert_eq!(calc("09"), Err("not a number: 09".to_string()));
+        assert_eq!(calc("9223372036854775807 + 1"), Ok(i64::MIN));
+    }
+}
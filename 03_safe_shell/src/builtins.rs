@@ -0,0 +1,628 @@
+// Commands the shell runs itself instead of starting a program.
+//
+// Some commands *have* to be builtins, because they change the shell:
+// `cd` changes the shell's current directory, `exit` ends it, `set`
+// switches its options, `export` and `unset` change its variables and
+// `fg`, `bg` and `wait` deal with its jobs, `history` with its history,
+// and `source` runs a script inside it. `break`, `continue`, `return` and
+// `local` change what the shell does next in a loop or function. A child
+// process could only change its own state, and then die.
+//
+// Every builtin writes to `out` and `err` instead of straight to standard
+// output and error, so that it can be a stage of a pipeline and have its
+// output redirected: `set -o | grep pipefail`, `cd nowhere 2> /dev/null`.
+// Errors writing the error messages themselves are ignored: there is
+// nowhere left to report them.
+
+use std::env;
+use std::io::Write;
+
+use crate::jobs;
+use crate::script;
+use crate::shell::{self, Control, Options, Shell, Var};
+
+/// The names of the builtins, for tab completion.
+pub const NAMES: [&str; 18] = [
+    ".", "bg", "break", "cd", "continue", "env", "exit", "export", "fg", "history", "jobs",
+    "local", "pwd", "return", "set", "source", "unset", "wait",
+];
+
+/// Whether the command `words` is run by the shell itself.
+pub fn is_builtin(words: &[String]) -> bool {
+    match words.first().map(String::as_str) {
+        // `env` on its own lists the environment, which only the shell
+        // knows. `env NAME=value cmd` is the real program.
+        Some("env") => words.len() == 1,
+        Some(name) => NAMES.contains(&name),
+        None => false,
+    }
+}
+
+/// Runs the builtin `words[0]` with the arguments `words[1..]`, and
+/// returns its exit status.
+pub fn run(shell: &mut Shell, words: &[String], out: &mut dyn Write, err: &mut dyn Write) -> i32 {
+    let args = &words[1..];
+    match words[0].as_str() {
+        "bg" => bg(shell, args, out, err),
+        "break" => loop_control(shell, "break", args, err, Control::Break),
+        "cd" => cd(shell, args, out, err),
+        "continue" => loop_control(shell, "continue", args, err, Control::Continue),
+        "env" => env(shell, out, err),
+        "exit" => exit(shell, args, err),
+        "export" => export(shell, args, out, err),
+        "fg" => fg(shell, args, out, err),
+        "history" => history(shell, args, out, err),
+        "jobs" => list_jobs(shell, out, err),
+        "local" => local(shell, args, err),
+        "pwd" => pwd(out, err),
+        "return" => return_from_function(shell, args, err),
+        "set" => set(shell, args, out, err),
+        "source" | "." => source(shell, args, err),
+        "unset" => unset(shell, args, err),
+        "wait" => wait(shell, args, err),
+        name => {
+            let _ = writeln!(err, "{}: not a builtin", name);
+            1
+        }
+    }
+}
+
+/// `cd [DIR]`: changes the current directory, to the home directory if
+/// none is given. `cd -` goes back to the previous one.
+fn cd(shell: &mut Shell, args: &[String], out: &mut dyn Write, err: &mut dyn Write) -> i32 {
+    // Why is this a built-in? Because if we ran 'cd' as a child process,
+    // it would change *its own* directory and then die. The parent shell would stay put!
+    // We must change the shell's own current directory.
+    let back = args.first().is_some_and(|arg| arg == "-");
+    let new_dir = match args.first() {
+        None => shell.get_var("HOME").ok_or("HOME not set"),
+        Some(_) if back => shell.get_var("OLDPWD").ok_or("OLDPWD not set"),
+        Some(dir) => Ok(dir.as_str()),
+    };
+    let new_dir = match new_dir {
+        Ok(dir) => dir.to_string(),
+        Err(message) => {
+            let _ = writeln!(err, "cd: {}", message);
+            return 1;
+        }
+    };
+    // `cd -` says where it went: the command line doesn't show it.
+    let announce = |out: &mut dyn Write, err: &mut dyn Write| {
+        if back {
+            write_or_complain("cd", out, err, &format!("{}\n", new_dir))
+        } else {
+            0
+        }
+    };
+
+    // As a pipeline stage, `cd` runs in a "subshell" and must not move the
+    // real shell. The current directory belongs to the whole process,
+    // threads included, so we only check that the directory exists.
+    if shell.in_pipeline {
+        return match std::fs::metadata(&new_dir) {
+            Ok(meta) if meta.is_dir() => announce(out, err),
+            Ok(_) => {
+                let _ = writeln!(err, "cd: {}: not a directory", new_dir);
+                1
+            }
+            Err(e) => {
+                let _ = writeln!(err, "cd: {}: {}", new_dir, e);
+                1
+            }
+        };
+    }
+    let old_dir = env::current_dir();
+    match env::set_current_dir(&new_dir) {
+        Ok(()) => {
+            // Other programs look at $PWD, and `cd -` at $OLDPWD.
+            if let Ok(old_dir) = old_dir {
+                shell.set_var("OLDPWD", old_dir.display().to_string());
+            }
+            if let Ok(dir) = env::current_dir() {
+                shell.set_var("PWD", dir.display().to_string());
+            }
+            announce(out, err)
+        }
+        Err(e) => {
+            let _ = writeln!(err, "cd: {}: {}", new_dir, e);
+            1
+        }
+    }
+}
+
+/// `env`: prints the environment programs get, as `NAME=value` lines.
+fn env(shell: &Shell, out: &mut dyn Write, err: &mut dyn Write) -> i32 {
+    let mut text = String::new();
+    for (name, value) in shell.environment() {
+        text += &format!("{}={}\n", name, value);
+    }
+    write_or_complain("env", out, err, &text)
+}
+
+/// `export NAME[=VALUE]...`: sets the variables (if given a value) and
+/// passes them on to programs from now on. On its own it lists them, in
+/// a form that can be pasted back into the shell.
+fn export(shell: &mut Shell, args: &[String], out: &mut dyn Write, err: &mut dyn Write) -> i32 {
+    if args.is_empty() {
+        let mut text = String::new();
+        for (name, value) in shell.environment() {
+            text += &format!("export {}={}\n", name, quote(value));
+        }
+        return write_or_complain("export", out, err, &text);
+    }
+    let mut status = 0;
+    for arg in args {
+        let (name, value) = match arg.split_once('=') {
+            Some((name, value)) => (name, Some(value)),
+            None => (arg.as_str(), None),
+        };
+        if !shell::is_name(name) {
+            let _ = writeln!(err, "export: '{}': not a valid name", arg);
+            status = 1;
+            continue;
+        }
+        // `export NAME` with no value and no variable yet exports an empty
+        // one.
+        let value = value
+            .map(str::to_string)
+            .unwrap_or_else(|| shell.get_var(name).unwrap_or_default().to_string());
+        shell.set_var(name, value);
+        if let Some(var) = shell.vars.get_mut(name) {
+            var.exported = true;
+        }
+    }
+    status
+}
+
+/// `unset NAME...`: removes the variables. Unsetting one that doesn't
+/// exist is fine.
+fn unset(shell: &mut Shell, args: &[String], err: &mut dyn Write) -> i32 {
+    let mut status = 0;
+    for name in args {
+        if shell::is_name(name) {
+            shell.vars.remove(name);
+        } else {
+            let _ = writeln!(err, "unset: '{}': not a valid name", name);
+            status = 1;
+        }
+    }
+    status
+}
+
+/// `it's` as `'it'\''s'`: single quotes, which keep everything as it is,
+/// except for single quotes themselves.
+pub fn quote(text: &str) -> String {
+    format!("'{}'", text.replace('\'', "'\\''"))
+}
+
+/// `exit [N]`: leaves the shell with status N, or with the status of the
+/// last command.
+fn exit(shell: &mut Shell, args: &[String], err: &mut dyn Write) -> i32 {
+    let status = match args.first() {
+        None => shell.last_status,
+        Some(arg) => match arg.parse::<i32>() {
+            // Exit statuses are a single byte: `exit 256` is `exit 0`.
+            Ok(n) => n & 0xff,
+            Err(_) => {
+                let _ = writeln!(err, "exit: {}: numeric argument required", arg);
+                2
+            }
+        },
+    };
+    shell.exit = Some(status);
+    status
+}
+
+/// `break [N]` leaves the loop it is in, or N loops. `continue [N]` goes
+/// on with the next round of the loop, or of the Nth one out.
+fn loop_control(
+    shell: &mut Shell,
+    name: &str,
+    args: &[String],
+    err: &mut dyn Write,
+    control: fn(usize) -> Control,
+) -> i32 {
+    let count = match args {
+        [] => 1,
+        [arg] => match arg.parse::<usize>() {
+            Ok(count) if count > 0 => count,
+            _ => {
+                let _ = writeln!(err, "{}: {}: needs a number of loops, 1 or more", name, arg);
+                return 1;
+            }
+        },
+        _ => {
+            let _ = writeln!(err, "{}: too many arguments", name);
+            return 1;
+        }
+    };
+    if shell.loops == 0 {
+        let _ = writeln!(err, "{}: only meaningful in a loop", name);
+        return 1;
+    }
+    // `break 5` in two loops leaves both.
+    shell.control = Some(control(count.min(shell.loops)));
+    0
+}
+
+/// `return [N]`: leaves the function, with status N or the status of the
+/// last command.
+fn return_from_function(shell: &mut Shell, args: &[String], err: &mut dyn Write) -> i32 {
+    if shell.frames.is_empty() {
+        let _ = writeln!(err, "return: can only be used in a function");
+        return 1;
+    }
+    let status = match args.first() {
+        None => shell.last_status,
+        Some(arg) => match arg.parse::<i32>() {
+            Ok(n) => n & 0xff,
+            Err(_) => {
+                let _ = writeln!(err, "return: {}: numeric argument required", arg);
+                2
+            }
+        },
+    };
+    shell.control = Some(Control::Return);
+    status
+}
+
+/// `local NAME[=VALUE]...`: gives the function being called variables of
+/// its own. When it returns, they are what they were before. Without a
+/// value, the variable starts out unset.
+fn local(shell: &mut Shell, args: &[String], err: &mut dyn Write) -> i32 {
+    let Some(frame) = shell.frames.last_mut() else {
+        let _ = writeln!(err, "local: can only be used in a function");
+        return 1;
+    };
+    let mut status = 0;
+    for arg in args {
+        let (name, value) = match arg.split_once('=') {
+            Some((name, value)) => (name, Some(value)),
+            None => (arg.as_str(), None),
+        };
+        if !shell::is_name(name) {
+            let _ = writeln!(err, "local: {}: not a valid name", name);
+            status = 1;
+            continue;
+        }
+        // Only the value from before the function counts: `local A=1;
+        // local A=2` still puts back the outer `A`.
+        let old = shell.vars.get(name).cloned();
+        if !frame.iter().any(|(saved, _)| saved == name) {
+            frame.push((name.to_string(), old.clone()));
+        }
+        match value {
+            Some(value) => {
+                let var = Var {
+                    value: value.to_string(),
+                    exported: old.is_some_and(|var| var.exported),
+                };
+                shell.vars.insert(name.to_string(), var);
+            }
+            None => {
+                shell.vars.remove(name);
+            }
+        }
+    }
+    status
+}
+
+/// `jobs`: lists the background and stopped jobs. Jobs that have finished
+/// are listed one last time, and then forgotten.
+fn list_jobs(shell: &mut Shell, out: &mut dyn Write, err: &mut dyn Write) -> i32 {
+    let pipefail = shell.options.pipefail;
+    let mut lines = shell.jobs.reap(pipefail);
+    lines.extend(shell.jobs.listing(pipefail));
+    let text: String = lines.iter().map(|line| format!("{}\n", line)).collect();
+    write_or_complain("jobs", out, err, &text)
+}
+
+/// `fg [JOB]`: brings a job to the foreground, the current one by default.
+fn fg(shell: &mut Shell, args: &[String], out: &mut dyn Write, err: &mut dyn Write) -> i32 {
+    match jobs::fg(shell, args.first().map(String::as_str), out) {
+        Ok(status) => status,
+        Err(message) => {
+            let _ = writeln!(err, "fg: {}", message);
+            1
+        }
+    }
+}
+
+/// `bg [JOB]`: lets a stopped job go on in the background.
+fn bg(shell: &mut Shell, args: &[String], out: &mut dyn Write, err: &mut dyn Write) -> i32 {
+    match jobs::bg(shell, args.first().map(String::as_str)) {
+        Ok(line) => write_or_complain("bg", out, err, &format!("{}\n", line)),
+        Err(message) => {
+            let _ = writeln!(err, "bg: {}", message);
+            1
+        }
+    }
+}
+
+/// `wait [JOB|PID]...`: waits for the given jobs to finish and returns the
+/// status of the last one. Without arguments, waits for all of them.
+fn wait(shell: &mut Shell, args: &[String], err: &mut dyn Write) -> i32 {
+    if args.is_empty() {
+        let mut index = 0;
+        while index < shell.jobs.len() {
+            let count = shell.jobs.len();
+            jobs::wait(shell, index);
+            // A stopped job stays in the table; skip it.
+            if shell.jobs.len() == count {
+                index += 1;
+            }
+        }
+        return 0;
+    }
+    let mut status = 0;
+    for arg in args {
+        let index = if arg.starts_with('%') {
+            shell.jobs.find(Some(arg))
+        } else {
+            arg.parse::<i32>()
+                .ok()
+                .and_then(|pid| jobs::find_pid(shell, pid))
+                .ok_or_else(|| format!("{}: not a job of this shell", arg))
+        };
+        status = match index {
+            Ok(index) => jobs::wait(shell, index),
+            Err(message) => {
+                let _ = writeln!(err, "wait: {}", message);
+                127
+            }
+        };
+    }
+    status
+}
+
+/// `history` lists the commands typed so far, numbered for `!n`.
+/// `history N` lists the last N, and `history -c` forgets them all.
+fn history(shell: &mut Shell, args: &[String], out: &mut dyn Write, err: &mut dyn Write) -> i32 {
+    let entries = shell.history.entries();
+    let count = match args {
+        [] => entries.len(),
+        [flag] if flag == "-c" => {
+            return match shell.history.clear() {
+                Ok(()) => 0,
+                Err(e) => {
+                    let _ = writeln!(err, "history: {}", e);
+                    1
+                }
+            };
+        }
+        [count] => match count.parse::<usize>() {
+            Ok(count) => count.min(entries.len()),
+            Err(_) => {
+                let _ = writeln!(err, "history: {}: not a number", count);
+                return 2;
+            }
+        },
+        _ => {
+            let _ = writeln!(err, "history: usage: history [-c] [N]");
+            return 2;
+        }
+    };
+    let first = entries.len() - count;
+    let mut text = String::new();
+    for (i, entry) in entries.iter().enumerate().skip(first) {
+        text += &format!("{:>5}  {}\n", i + 1, entry);
+    }
+    write_or_complain("history", out, err, &text)
+}
+
+/// `pwd`: prints the current directory.
+fn pwd(out: &mut dyn Write, err: &mut dyn Write) -> i32 {
+    match env::current_dir() {
+        Ok(dir) => write_or_complain("pwd", out, err, &format!("{}\n", dir.display())),
+        Err(e) => {
+            let _ = writeln!(err, "pwd: {}", e);
+            1
+        }
+    }
+}
+
+/// `set -o` lists the options, `set -o NAME` turns one on and
+/// `set +o NAME` turns it off. `set +o` lists them as commands. `-e` and
+/// `-x` are short for `-o errexit` and `-o xtrace`, and `set -- ARGS`
+/// replaces the arguments, `$1` and so on.
+fn set(shell: &mut Shell, args: &[String], out: &mut dyn Write, err: &mut dyn Write) -> i32 {
+    match args {
+        [] => return write_or_complain("set", out, err, &list_options(&shell.options, false)),
+        [flag] if flag == "-o" => {
+            return write_or_complain("set", out, err, &list_options(&shell.options, false));
+        }
+        [flag] if flag == "+o" => {
+            return write_or_complain("set", out, err, &list_options(&shell.options, true));
+        }
+        _ => {}
+    }
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            shell.args = args.cloned().collect();
+            break;
+        }
+        let on = arg.starts_with('-');
+        let letters = match arg.strip_prefix(['-', '+']) {
+            Some(letters) if !letters.is_empty() => letters,
+            _ => {
+                let _ = writeln!(err, "set: usage: set [-ex] [-o|+o option] [-- arg...]");
+                return 2;
+            }
+        };
+        let names: Vec<&str> = match letters {
+            "o" => match args.next() {
+                Some(name) => vec![name],
+                None => {
+                    let _ = writeln!(err, "set: {}: needs an option name", arg);
+                    return 2;
+                }
+            },
+            _ => letters
+                .chars()
+                .map(|letter| match letter {
+                    'e' => "errexit",
+                    'x' => "xtrace",
+                    _ => "",
+                })
+                .collect(),
+        };
+        for name in names {
+            match shell.options.get_mut(name) {
+                Some(option) => *option = on,
+                None if letters == "o" => {
+                    let _ = writeln!(err, "set: {}: no such option", name);
+                    return 1;
+                }
+                None => {
+                    let _ = writeln!(err, "set: {}: no such option", arg);
+                    return 2;
+                }
+            }
+        }
+    }
+    0
+}
+
+/// `source FILE [ARGS]`, or `. FILE [ARGS]`: runs the script FILE in this
+/// shell, so that it can set variables and change the directory. With
+/// ARGS, they are the script's `$1` and so on until it is done.
+fn source(shell: &mut Shell, args: &[String], err: &mut dyn Write) -> i32 {
+    let Some((path, script_args)) = args.split_first() else {
+        let _ = writeln!(err, "source: usage: source FILE [ARGS]");
+        return 2;
+    };
+    let saved = if script_args.is_empty() {
+        None
+    } else {
+        Some(std::mem::replace(&mut shell.args, script_args.to_vec()))
+    };
+    let status = match script::run_file(shell, path) {
+        Ok(status) => status,
+        Err(e) => {
+            let _ = writeln!(err, "source: {}: {}", path, e);
+            1
+        }
+    };
+    if let Some(saved) = saved {
+        shell.args = saved;
+    }
+    status
+}
+
+/// `pipefail        off`, or `set +o pipefail` when `as_commands`.
+fn list_options(options: &Options, as_commands: bool) -> String {
+    let mut text = String::new();
+    for name in Options::NAMES {
+        let on = options.get(name).unwrap_or_default();
+        if as_commands {
+            text += &format!("set {}o {}\n", if on { '-' } else { '+' }, name);
+        } else {
+            text += &format!("{:<15} {}\n", name, if on { "on" } else { "off" });
+        }
+    }
+    text
+}
+
+/// Writes `text` to `out`. The reader at the other end of a pipe may be
+/// gone already, so this can fail; we say so and return status 1.
+fn write_or_complain(name: &str, out: &mut dyn Write, err: &mut dyn Write, text: &str) -> i32 {
+    match out.write_all(text.as_bytes()).and_then(|()| out.flush()) {
+        Ok(()) => 0,
+        Err(e) => {
+            let _ = writeln!(err, "{}: write error: {}", name, e);
+            1
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run_line(shell: &mut Shell, line: &str) -> (i32, String) {
+        let words: Vec<String> = line.split(' ').map(str::to_string).collect();
+        let mut out = Vec::new();
+        let status = run(shell, &words, &mut out, &mut Vec::new());
+        (status, String::from_utf8(out).unwrap())
+    }
+
+    #[test]
+    fn set_switches_and_lists_options() {
+        let mut shell = Shell::default();
+        assert_eq!(run_line(&mut shell, "set -o pipefail").0, 0);
+        assert!(shell.options.pipefail);
+        assert_eq!(
+            run_line(&mut shell, "set -o"),
+            (
+                0,
+                "errexit         off\nfailglob        off\nnoclobber       off\npipefail        on\n\
+                 showstatus      off\nxtrace          off\n"
+                    .to_string()
+            )
+        );
+        run_line(&mut shell, "set +o pipefail");
+        assert_eq!(
+            run_line(&mut shell, "set +o"),
+            (
+                0,
+                "set +o errexit\nset +o failglob\nset +o noclobber\nset +o pipefail\n\
+                 set +o showstatus\nset +o xtrace\n"
+                    .to_string()
+            )
+        );
+        assert_eq!(run_line(&mut shell, "set -o nonsense").0, 1);
+        assert_eq!(run_line(&mut shell, "set -ex +o pipefail -- a b").0, 0);
+        assert!(shell.options.errexit && shell.options.xtrace);
+        assert_eq!(shell.args, ["a", "b"]);
+        assert_eq!(run_line(&mut shell, "set +x -q").0, 2);
+        assert!(!shell.options.xtrace);
+    }
+
+    #[test]
+    fn export_and_unset_change_the_environment() {
+        let mut shell = Shell::default();
+        shell.set_var("LOCAL", "x".to_string());
+        assert_eq!(run_line(&mut shell, "export A=1 B=it's").0, 0);
+        assert_eq!(run_line(&mut shell, "env").1, "A=1\nB=it's\n");
+        assert_eq!(
+            run_line(&mut shell, "export").1,
+            "export A='1'\nexport B='it'\\''s'\n"
+        );
+
+        // Exporting keeps the value; unsetting forgets variable and value.
+        run_line(&mut shell, "export LOCAL");
+        assert_eq!(shell.environment().get("LOCAL"), Some(&"x"));
+        run_line(&mut shell, "unset A LOCAL NEVER");
+        assert_eq!(run_line(&mut shell, "env").1, "B=it's\n");
+        assert_eq!(run_line(&mut shell, "export 1A=x").0, 1);
+    }
+
+    #[test]
+    fn cd_goes_home_or_back() {
+        // In a pipeline `cd` only checks the directory, so this test
+        // doesn't move the other tests around.
+        let mut shell = Shell {
+            in_pipeline: true,
+            ..Shell::default()
+        };
+        assert_eq!(run_line(&mut shell, "cd -").0, 1);
+        shell.set_var("OLDPWD", "/".to_string());
+        assert_eq!(run_line(&mut shell, "cd -"), (0, "/\n".to_string()));
+        assert_eq!(run_line(&mut shell, "cd").0, 1);
+        shell.set_var("HOME", "/".to_string());
+        assert_eq!(run_line(&mut shell, "cd"), (0, String::new()));
+    }
+
+    #[test]
+    fn exit_defaults_to_the_last_status() {
+        let mut shell = Shell {
+            last_status: 3,
+            ..Shell::default()
+        };
+        assert_eq!(run_line(&mut shell, "exit").0, 3);
+        assert_eq!(shell.exit, Some(3));
+        run_line(&mut shell, "exit 258");
+        assert_eq!(shell.exit, Some(2));
+    }
+}
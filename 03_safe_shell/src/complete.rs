@@ -0,0 +1,365 @@
+// Tab completion.
+//
+// Press Tab and the shell finishes the word you are typing, as far as it
+// can tell what you mean:
+//
+//     gi<Tab>          git               a command: a builtin, or a program
+//                                        in one of the `$PATH` directories
+//     cat src/ma<Tab>  cat src/main.rs   a file; directories get a `/`
+//     echo $HO<Tab>    echo $HOME        a variable
+//
+// If there is more than one way to go on, Tab adds what they all have in
+// common, and a second Tab lists them.
+//
+// `complete` works on the text before the cursor and returns what could
+// go there, without touching the terminal; `Line::complete` in `editor.rs`
+// puts the result into the line.
+
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+
+use crate::builtins;
+use crate::shell::{self, Shell};
+
+/// What Tab can put in place of the end of the line.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Completion {
+    /// Where the text to replace starts, in bytes.
+    pub start: usize,
+    /// The text to replace, without quotes or backslashes.
+    pub prefix: String,
+    /// Everything that could go there, sorted.
+    pub candidates: Vec<String>,
+    /// Whether the candidates need backslashes before special characters:
+    /// yes for file names, unless a quote is still open, and never for
+    /// variable names.
+    pub escape: bool,
+    /// Whether a single candidate is followed by a space, ready for the
+    /// next word. Not after a variable or a directory, which often go on:
+    /// `$HOME/`, `src/main.rs`.
+    pub space: bool,
+}
+
+/// Remembers the programs found in `$PATH`, because reading all of those
+/// directories on every Tab would be slow. When `PATH` changes, they are
+/// read again.
+#[derive(Debug, Default)]
+pub struct Completer {
+    /// The `PATH` the programs were found with, and the command names:
+    /// the programs and the builtins.
+    commands: Option<(String, Vec<String>)>,
+}
+
+impl Completer {
+    pub fn complete(&mut self, line: &str, shell: &Shell) -> Completion {
+        let path = shell.get_var("PATH").unwrap_or_default();
+        if self.commands.as_ref().is_none_or(|(seen, _)| seen != path) {
+            self.commands = Some((path.to_string(), commands(path)));
+        }
+        let commands = self.commands.as_ref().map_or(&[][..], |(_, names)| names);
+        let mut vars: Vec<&str> = shell.vars.keys().map(String::as_str).collect();
+        vars.sort();
+        complete(line, commands, &vars, shell.get_var("HOME"))
+    }
+}
+
+/// The builtins, `override`, and the programs in the directories of `path`.
+fn commands(path: &str) -> Vec<String> {
+    let mut names: Vec<String> = builtins::NAMES
+        .iter()
+        .chain(&["override"])
+        .map(|name| name.to_string())
+        .collect();
+    for dir in path.split(':').filter(|dir| !dir.is_empty()) {
+        for entry in fs::read_dir(dir).into_iter().flatten().flatten() {
+            // `fs::metadata` follows symlinks, which many programs are.
+            let executable = fs::metadata(entry.path())
+                .is_ok_and(|meta| meta.is_file() && meta.permissions().mode() & 0o111 != 0);
+            if executable && let Ok(name) = entry.file_name().into_string() {
+                names.push(name);
+            }
+        }
+    }
+    names.sort();
+    names.dedup();
+    names
+}
+
+/// One character of the word being completed, as the shell will see it.
+struct Char {
+    /// Where it is in the line.
+    at: usize,
+    c: char,
+    /// In single quotes or after a backslash: never special.
+    quoted: bool,
+}
+
+/// What could complete `line`, given the command names, variable names
+/// and home directory to choose from.
+pub fn complete(line: &str, commands: &[String], vars: &[&str], home: Option<&str>) -> Completion {
+    // 1. FIND THE WORD
+    // Go through the line as the lexer would, to know where the last word
+    // starts, and whether it is where a command name goes: at the start,
+    // or after `|`, `&` or `;`.
+    let mut word: Vec<Char> = Vec::new();
+    let mut command_position = true;
+    let (mut single, mut double) = (false, false);
+    let mut chars = line.char_indices();
+    while let Some((at, c)) = chars.next() {
+        match c {
+            '\'' if !double => single = !single,
+            '"' if !single => double = !double,
+            '\\' if !single => {
+                if let Some((_, c)) = chars.next() {
+                    word.push(Char {
+                        at,
+                        c,
+                        quoted: true,
+                    });
+                }
+            }
+            ' ' | '\t' | '|' | '&' | ';' | '<' | '>' if !single && !double => {
+                if !word.is_empty() {
+                    // `NAME=value cmd`, `if cmd` and `override cmd`: the
+                    // command is still to come.
+                    let text: String = word.iter().map(|ch| ch.c).collect();
+                    let assignment = text
+                        .split_once('=')
+                        .is_some_and(|(name, _)| shell::is_name(name));
+                    let reserved = [
+                        "if", "then", "elif", "else", "while", "until", "do", "{", "override",
+                    ]
+                    .contains(&text.as_str());
+                    command_position &= assignment || reserved;
+                    word.clear();
+                }
+                match c {
+                    '|' | '&' | ';' => command_position = true,
+                    '<' | '>' => command_position = false,
+                    _ => {}
+                }
+            }
+            c => word.push(Char {
+                at,
+                c,
+                quoted: single,
+            }),
+        }
+    }
+    let word_start = word.first().map_or(line.len(), |ch| ch.at);
+    // Where the text from `word[i]` on starts in the line.
+    let start_of = |i: usize| word.get(i).map_or(line.len(), |ch| ch.at);
+    let text_from =
+        |i: usize| -> String { word[i.min(word.len())..].iter().map(|ch| ch.c).collect() };
+
+    // 2. A VARIABLE
+    // `$NAME` or `${NAME` at the end of the word.
+    let name_chars = word
+        .iter()
+        .rev()
+        .take_while(|ch| ch.c.is_ascii_alphanumeric() || ch.c == '_')
+        .count();
+    let mut dollar = word.len() - name_chars;
+    if dollar > 0 && word[dollar - 1].c == '{' {
+        dollar -= 1;
+    }
+    if dollar > 0 && word[dollar - 1].c == '$' && !word[dollar - 1].quoted {
+        let name_start = word.len() - name_chars;
+        let prefix = text_from(name_start);
+        let candidates = vars
+            .iter()
+            .filter(|var| var.starts_with(&prefix))
+            .map(|var| var.to_string())
+            .collect();
+        return Completion {
+            start: start_of(name_start),
+            prefix,
+            candidates,
+            escape: false,
+            space: false,
+        };
+    }
+
+    // 3. A COMMAND
+    let has_slash = word.iter().any(|ch| ch.c == '/');
+    if command_position && !has_slash {
+        let prefix = text_from(0);
+        let candidates = commands
+            .iter()
+            .filter(|name| name.starts_with(&prefix))
+            .cloned()
+            .collect();
+        return Completion {
+            start: word_start,
+            prefix,
+            candidates,
+            escape: !single && !double,
+            space: true,
+        };
+    }
+
+    // 4. A FILE
+    // Only the part after the last `/` is completed; the directory part
+    // stays as it was typed.
+    let name_start = word.iter().rposition(|ch| ch.c == '/').map_or(0, |i| i + 1);
+    let mut dir: String = word[..name_start].iter().map(|ch| ch.c).collect();
+    if let (Some(rest), Some(home)) = (dir.strip_prefix("~/"), home) {
+        dir = format!("{}/{}", home, rest);
+    }
+    let prefix = text_from(name_start);
+    let mut candidates = Vec::new();
+    let read_from = if dir.is_empty() { "." } else { dir.as_str() };
+    for entry in fs::read_dir(read_from).into_iter().flatten().flatten() {
+        let Ok(mut name) = entry.file_name().into_string() else {
+            continue;
+        };
+        // Hidden files only when asked for, like `ls` and `*`.
+        if !name.starts_with(&prefix) || (name.starts_with('.') && !prefix.starts_with('.')) {
+            continue;
+        }
+        if fs::metadata(entry.path()).is_ok_and(|meta| meta.is_dir()) {
+            name.push('/');
+        }
+        candidates.push(name);
+    }
+    candidates.sort();
+    Completion {
+        start: start_of(name_start),
+        prefix,
+        candidates,
+        escape: !single && !double,
+        space: true,
+    }
+}
+
+/// `name` with a backslash before every character the lexer would treat
+/// specially, so that it stays one plain word.
+pub fn escape(name: &str) -> String {
+    let mut text = String::new();
+    for c in name.chars() {
+        if " \t'\"\\$*?[]&|;<>()#!~`".contains(c) {
+            text.push('\\');
+        }
+        text.push(c);
+    }
+    text
+}
+
+/// The longest text every candidate starts with.
+pub fn common_prefix(candidates: &[String]) -> String {
+    let Some(first) = candidates.first() else {
+        return String::new();
+    };
+    let mut prefix = first.as_str();
+    for candidate in &candidates[1..] {
+        // Where they first differ, or where the shorter one ends.
+        let len = prefix
+            .char_indices()
+            .zip(candidate.chars())
+            .find(|((_, a), b)| a != b)
+            .map_or(prefix.len().min(candidate.len()), |((at, _), _)| at);
+        prefix = &prefix[..len];
+    }
+    prefix.to_string()
+}
+
+/// The candidates in columns, going down and then across like `ls`, for
+/// a terminal `width` characters wide.
+pub fn listing(candidates: &[String], width: usize) -> String {
+    let column = candidates
+        .iter()
+        .map(|c| c.chars().count())
+        .max()
+        .unwrap_or(0)
+        + 2;
+    let columns = (width / column).max(1);
+    let rows = candidates.len().div_ceil(columns);
+    let mut text = String::new();
+    for row in 0..rows {
+        let mut line = String::new();
+        for candidate in candidates.iter().skip(row).step_by(rows) {
+            line += &format!("{:<width$}", candidate, width = column);
+        }
+        text += line.trim_end();
+        text.push('\n');
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn commands() -> Vec<String> {
+        ["cargo", "cat", "cd", "grep"].map(String::from).to_vec()
+    }
+
+    fn candidates(line: &str) -> Vec<String> {
+        complete(
+            line,
+            &commands(),
+            &["HOME", "HOSTNAME", "PATH"],
+            Some("/home/ana"),
+        )
+        .candidates
+    }
+
+    #[test]
+    fn completes_commands_where_a_command_goes() {
+        assert_eq!(candidates("ca"), ["cargo", "cat"]);
+        assert_eq!(candidates("ls | gr"), ["grep"]);
+        assert_eq!(candidates("X=1 c"), ["cargo", "cat", "cd"]);
+        assert_eq!(candidates("if true; then gr"), ["grep"]);
+        assert!(candidates("echo then gr").is_empty());
+        let completion = complete("echo a; ca", &commands(), &[], None);
+        assert_eq!((completion.start, completion.prefix.as_str()), (8, "ca"));
+    }
+
+    #[test]
+    fn completes_variables_after_a_dollar() {
+        assert_eq!(candidates("echo $HO"), ["HOME", "HOSTNAME"]);
+        assert_eq!(candidates("echo \"${P"), ["PATH"]);
+        assert_eq!(candidates("$"), ["HOME", "HOSTNAME", "PATH"]);
+        // Not a variable in single quotes.
+        assert!(!candidates("echo '$HO").contains(&"HOME".to_string()));
+        let completion = complete("cd $HO", &[], &["HOME"], None);
+        assert_eq!((completion.start, completion.space), (4, false));
+    }
+
+    #[test]
+    fn completes_files_with_a_slash_after_directories() {
+        let dir = std::env::temp_dir().join(format!("safe_shell_complete_{}", std::process::id()));
+        let dir = dir.to_str().unwrap();
+        fs::create_dir_all(format!("{}/src dir", dir)).unwrap();
+        for file in ["main.rs", "many words.txt", ".hidden"] {
+            fs::write(format!("{}/{}", dir, file), "").unwrap();
+        }
+
+        assert_eq!(
+            candidates(&format!("cat {}/ma", dir)),
+            ["main.rs", "many words.txt"]
+        );
+        assert_eq!(candidates(&format!("cat {}/s", dir)), ["src dir/"]);
+        assert_eq!(candidates(&format!("cat {}/.", dir)), [".hidden"]);
+        assert_eq!(
+            candidates(&format!("cat {}/many\\ w", dir)),
+            ["many words.txt"]
+        );
+        // A path is never a command name to look up.
+        assert_eq!(
+            candidates(&format!("{}/ma", dir)),
+            ["main.rs", "many words.txt"]
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn escapes_prefixes_and_lists() {
+        assert_eq!(escape("a b&c"), "a\\ b\\&c");
+        let names = ["main.rs", "many.txt", "map"].map(String::from);
+        assert_eq!(common_prefix(&names), "ma");
+        assert_eq!(common_prefix(&names[..1]), "main.rs");
+        assert_eq!(listing(&names, 20), "main.rs   map\nmany.txt\n");
+        assert_eq!(listing(&names, 80), "main.rs   many.txt  map\n");
+    }
+}
@@ -0,0 +1,757 @@
+// The line editor: reading a command from the terminal, one key at a time.
+//
+// Normally the terminal does the editing itself ("canonical mode"): it
+// collects a line, handles Backspace, and only hands the line over when
+// you press Enter. It knows nothing about arrow keys, though, which arrive
+// as escape sequences like `ESC [ D` and end up on screen as `^[[D`.
+//
+// So we switch the terminal to raw mode (see `sys.rs`) while reading, get
+// every key as it is pressed, and draw the line ourselves:
+//
+//     Left, Right, Ctrl-B, Ctrl-F         move one character
+//     Alt-B, Alt-F, Ctrl-Left, Ctrl-Right move one word
+//     Home, End, Ctrl-A, Ctrl-E           go to the start or the end
+//     Backspace, Delete, Ctrl-D           delete a character (Ctrl-D on an
+//                                         empty line ends the shell)
+//     Ctrl-K, Ctrl-U                      cut to the end, or to the start
+//     Ctrl-W, Alt-D                       cut the word before, or after
+//     Ctrl-Y                              paste what was cut last
+//     Up, Down, Ctrl-P, Ctrl-N            go through the history
+//     Ctrl-R                              search the history (again: older)
+//     Tab                                 complete the word (see
+//                                         `complete.rs`); twice: list
+//                                         the choices
+//     Ctrl-L                              clear the screen
+//     Ctrl-C                              give up on the line
+//
+// The editing itself is done by `Line`, which only sees keys and knows
+// nothing about terminals, so that it can be tested on its own.
+//
+// If standard input turns out not to be a terminal, there is nothing to
+// edit and lines are read as they are.
+
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, IsTerminal, Read, Write};
+use std::os::fd::{AsFd, AsRawFd};
+
+use crate::complete::{self, Completion};
+use crate::history::History;
+use crate::sys;
+
+pub struct Editor {
+    /// Our own handle on the terminal, `None` if standard input isn't one.
+    terminal: Option<File>,
+    /// Bytes read but not used yet: the rest of an escape sequence, or
+    /// keys typed ahead.
+    pending: VecDeque<u8>,
+    /// The text cut last, for Ctrl-Y. It is kept from one line to the next.
+    kill: String,
+}
+
+impl Editor {
+    pub fn new() -> Editor {
+        let stdin = io::stdin();
+        let terminal = if stdin.is_terminal() {
+            stdin.as_fd().try_clone_to_owned().ok().map(File::from)
+        } else {
+            None
+        };
+        Editor {
+            terminal,
+            pending: VecDeque::new(),
+            kill: String::new(),
+        }
+    }
+
+    /// Shows `prompt` and reads a line, without its newline. Returns `None`
+    /// at the end of the input: Ctrl-D, or the end of a file or pipe, and
+    /// an `Interrupted` error after Ctrl-C, which throws the line away.
+    /// `complete` is asked what Tab can add to the text before the cursor.
+    pub fn read_line(
+        &mut self,
+        prompt: &str,
+        history: &History,
+        complete: &mut dyn FnMut(&str) -> Completion,
+    ) -> io::Result<Option<String>> {
+        let Some(terminal) = &self.terminal else {
+            print!("{}", prompt);
+            io::stdout().flush()?;
+            let mut input = String::new();
+            return match io::stdin().read_line(&mut input)? {
+                0 => Ok(None),
+                _ => Ok(Some(input.trim_end_matches(['\n', '\r']).to_string())),
+            };
+        };
+
+        // 1. SWITCH TO RAW MODE
+        // The guard switches back when we return, whichever way we do.
+        let fd = terminal.as_raw_fd();
+        let _raw = RawMode::new(fd)?;
+        let mut line = Line::new(history.entries(), std::mem::take(&mut self.kill));
+        let mut stdout = io::stdout().lock();
+        // The row of the line the cursor is on, when the line is wider
+        // than the terminal and takes several rows.
+        let mut row = 0;
+
+        loop {
+            // 2. DRAW THE LINE
+            // Asked every time, so that resizing the window just works.
+            let width = sys::terminal_width(fd).unwrap_or(80);
+            let (text, after_cursor) = line.view(prompt);
+            let screen;
+            (screen, row) = draw(&text, after_cursor, width, row);
+            stdout.write_all(screen.as_bytes())?;
+            stdout.flush()?;
+
+            // 3. READ A KEY AND ACT ON IT
+            let Some(key) = self.read_key()? else {
+                writeln!(stdout)?;
+                return Ok(None);
+            };
+            let outcome = line.handle(key);
+            self.kill = line.kill.clone();
+            match outcome {
+                Outcome::Continue => {}
+                Outcome::Done(text) => {
+                    // Drawn once more: the line may still show a search.
+                    let width = sys::terminal_width(fd).unwrap_or(80);
+                    let (screen, _) = draw(&format!("{}{}", prompt, text), 0, width, row);
+                    writeln!(stdout, "{}", screen)?;
+                    return Ok(Some(text));
+                }
+                Outcome::Cancel => {
+                    writeln!(stdout, "^C")?;
+                    return Err(io::ErrorKind::Interrupted.into());
+                }
+                Outcome::End => {
+                    writeln!(stdout)?;
+                    return Ok(None);
+                }
+                // Clear the screen and go to its top left corner.
+                Outcome::Clear => {
+                    stdout.write_all(b"\x1b[H\x1b[2J")?;
+                    row = 0;
+                }
+                Outcome::Complete => {
+                    let completion = complete(&line.before_cursor());
+                    if let Some(candidates) = line.complete(completion) {
+                        // The list goes below the line, which is drawn
+                        // again after it.
+                        let width = sys::terminal_width(fd).unwrap_or(80);
+                        let listing = complete::listing(&candidates, width);
+                        write!(stdout, "\n{}", listing)?;
+                        row = 0;
+                    }
+                }
+            }
+        }
+    }
+
+    /// The next key pressed, or `None` if the terminal is gone.
+    fn read_key(&mut self) -> io::Result<Option<Key>> {
+        let Some(mut terminal) = self.terminal.as_ref() else {
+            return Ok(None);
+        };
+        loop {
+            if let Some((key, len)) = parse_key(self.pending.make_contiguous()) {
+                self.pending.drain(..len);
+                return Ok(Some(key));
+            }
+            let mut buf = [0; 64];
+            match terminal.read(&mut buf) {
+                Ok(0) => return Ok(None),
+                Ok(n) => self.pending.extend(&buf[..n]),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+/// What to write to show `text` (the prompt and the line) with the cursor
+/// `after_cursor` characters from its end, on a terminal `width` columns
+/// wide, when the cursor was left on `row` of the last line drawn. Also
+/// returns the row the cursor ends up on.
+///
+/// A line wider than the terminal wraps onto more rows. Going back to the
+/// start of only the last one would leave the others on screen, once for
+/// each key, so we first go up to the first row (`ESC [ n A`), and clear
+/// everything below the text when we're done (`ESC [ J`).
+fn draw(text: &str, after_cursor: usize, width: usize, row: usize) -> (String, usize) {
+    let width = width.max(1);
+    let mut screen = String::new();
+    if row > 0 {
+        screen += &format!("\x1b[{}A", row);
+    }
+    screen += "\r";
+    screen += text;
+    let len = text.chars().count();
+    // After the last column the terminal waits for the next character
+    // before it goes to the next row. We go there right away, so that the
+    // cursor is always where the counting below says it is.
+    if len > 0 && len.is_multiple_of(width) {
+        screen += "\r\n";
+    }
+    screen += "\x1b[J";
+
+    // From the end of the text back to the cursor.
+    let cursor = len - after_cursor;
+    let (cursor_row, column) = (cursor / width, cursor % width);
+    let up = len / width - cursor_row;
+    if up > 0 {
+        screen += &format!("\x1b[{}A", up);
+    }
+    screen += "\r";
+    if column > 0 {
+        screen += &format!("\x1b[{}C", column);
+    }
+    (screen, cursor_row)
+}
+
+/// Raw mode for as long as it lives.
+struct RawMode {
+    fd: i32,
+    saved: sys::Termios,
+}
+
+impl RawMode {
+    fn new(fd: i32) -> io::Result<RawMode> {
+        let saved = sys::terminal_mode(fd)?;
+        sys::set_terminal_mode(fd, &saved.raw())?;
+        Ok(RawMode { fd, saved })
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        let _ = sys::set_terminal_mode(self.fd, &self.saved);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Key {
+    Char(char),
+    /// A letter pressed with Ctrl, in lower case: `Ctrl('a')`.
+    Ctrl(char),
+    /// A key pressed with Alt, which the terminal sends as ESC and the key.
+    Alt(char),
+    Enter,
+    Tab,
+    Backspace,
+    Delete,
+    Left,
+    Right,
+    Up,
+    Down,
+    Home,
+    End,
+    WordLeft,
+    WordRight,
+    /// Any other key, or a sequence we don't understand.
+    Unknown,
+}
+
+/// The key at the start of `bytes` and how many bytes it takes, or `None`
+/// if more bytes are needed to tell.
+pub fn parse_key(bytes: &[u8]) -> Option<(Key, usize)> {
+    let key = match *bytes.first()? {
+        b'\r' | b'\n' => Key::Enter,
+        b'\t' => Key::Tab,
+        0x7f | 0x08 => Key::Backspace,
+        b @ 1..=26 => Key::Ctrl((b'a' + b - 1) as char),
+        0x1b => return parse_escape(bytes),
+        b if b < 0x20 => Key::Unknown,
+        b if b < 0x80 => Key::Char(b as char),
+        // A character outside ASCII, in UTF-8: the first byte says how
+        // many bytes there are.
+        b => {
+            let len = match b {
+                0xc0..=0xdf => 2,
+                0xe0..=0xef => 3,
+                0xf0..=0xf7 => 4,
+                _ => return Some((Key::Unknown, 1)),
+            };
+            let bytes = bytes.get(..len)?;
+            return match std::str::from_utf8(bytes)
+                .ok()
+                .and_then(|s| s.chars().next())
+            {
+                Some(c) => Some((Key::Char(c), len)),
+                None => Some((Key::Unknown, 1)),
+            };
+        }
+    };
+    Some((key, 1))
+}
+
+/// An escape sequence: `ESC [ ...` or `ESC O ...` for the special keys,
+/// `ESC x` for Alt-x.
+fn parse_escape(bytes: &[u8]) -> Option<(Key, usize)> {
+    match *bytes.get(1)? {
+        b'[' => {
+            // `ESC [`, numbers separated by `;`, and a final letter or `~`:
+            // `ESC [ 3 ~` is Delete, `ESC [ 1 ; 5 C` is Ctrl-Right.
+            let end = bytes[2..].iter().position(|b| (0x40..=0x7e).contains(b))? + 2;
+            let params = &bytes[2..end];
+            let ctrl = params.ends_with(b";5");
+            let key = match (bytes[end], params) {
+                (b'C', _) if ctrl => Key::WordRight,
+                (b'D', _) if ctrl => Key::WordLeft,
+                (b'A', _) => Key::Up,
+                (b'B', _) => Key::Down,
+                (b'C', _) => Key::Right,
+                (b'D', _) => Key::Left,
+                (b'H', _) | (b'~', b"1" | b"7") => Key::Home,
+                (b'F', _) | (b'~', b"4" | b"8") => Key::End,
+                (b'~', b"3") => Key::Delete,
+                _ => Key::Unknown,
+            };
+            Some((key, end + 1))
+        }
+        b'O' => {
+            let key = match *bytes.get(2)? {
+                b'A' => Key::Up,
+                b'B' => Key::Down,
+                b'C' => Key::Right,
+                b'D' => Key::Left,
+                b'H' => Key::Home,
+                b'F' => Key::End,
+                _ => Key::Unknown,
+            };
+            Some((key, 3))
+        }
+        0x7f => Some((Key::Alt('\x7f'), 2)),
+        b if b.is_ascii_graphic() => Some((Key::Alt(b.to_ascii_lowercase() as char), 2)),
+        _ => Some((Key::Unknown, 1)),
+    }
+}
+
+/// What the editor should do after a key.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Outcome {
+    Continue,
+    /// Enter: the line is done.
+    Done(String),
+    /// Ctrl-C: forget the line.
+    Cancel,
+    /// Ctrl-D on an empty line: the end of the input.
+    End,
+    /// Ctrl-L: clear the screen.
+    Clear,
+    /// Tab: ask for completions, and hand them to `Line::complete`.
+    Complete,
+}
+
+/// The line being edited.
+pub struct Line<'a> {
+    chars: Vec<char>,
+    /// Where the cursor is: the index of the character it is on.
+    cursor: usize,
+    history: &'a [String],
+    /// The history entry being shown. `history.len()` is the new line.
+    index: usize,
+    /// The new line, kept while going through the history.
+    draft: Vec<char>,
+    kill: String,
+    search: Option<Search>,
+    /// Whether the last key was a Tab that left more than one choice.
+    /// Another Tab right after it lists them.
+    tabbed: bool,
+}
+
+/// A Ctrl-R search.
+struct Search {
+    query: String,
+    /// The history entry found last.
+    found: Option<usize>,
+    /// Whether the query matches nothing (older than `found`).
+    failed: bool,
+}
+
+impl<'a> Line<'a> {
+    pub fn new(history: &'a [String], kill: String) -> Line<'a> {
+        Line {
+            chars: Vec::new(),
+            cursor: 0,
+            history,
+            index: history.len(),
+            draft: Vec::new(),
+            kill,
+            search: None,
+            tabbed: false,
+        }
+    }
+
+    pub fn text(&self) -> String {
+        self.chars.iter().collect()
+    }
+
+    /// The text before the cursor: what Tab completes.
+    pub fn before_cursor(&self) -> String {
+        self.chars[..self.cursor].iter().collect()
+    }
+
+    /// What to show after `prompt`, and how many characters of it come
+    /// after the cursor.
+    pub fn view(&self, prompt: &str) -> (String, usize) {
+        let Some(search) = &self.search else {
+            let text = format!("{}{}", prompt, self.text());
+            return (text, self.chars.len() - self.cursor);
+        };
+        let failed = if search.failed { "failed " } else { "" };
+        let found = search.found.map_or("", |i| self.history[i].as_str());
+        let text = format!("({}reverse-i-search)'{}': {}", failed, search.query, found);
+        // The cursor goes where the query was found.
+        let at = found.rfind(&search.query).unwrap_or(0);
+        (text, found[at..].chars().count())
+    }
+
+    pub fn handle(&mut self, key: Key) -> Outcome {
+        if self.search.is_some() && self.handle_search(key) {
+            return Outcome::Continue;
+        }
+        if key == Key::Tab {
+            return Outcome::Complete;
+        }
+        self.tabbed = false;
+        let len = self.chars.len();
+        match key {
+            Key::Char(c) => {
+                self.chars.insert(self.cursor, c);
+                self.cursor += 1;
+            }
+            Key::Enter => return Outcome::Done(self.text()),
+            Key::Backspace if self.cursor > 0 => {
+                self.cursor -= 1;
+                self.chars.remove(self.cursor);
+            }
+            Key::Ctrl('d') if len == 0 => return Outcome::End,
+            Key::Delete | Key::Ctrl('d') if self.cursor < len => {
+                self.chars.remove(self.cursor);
+            }
+            Key::Left | Key::Ctrl('b') => self.cursor = self.cursor.saturating_sub(1),
+            Key::Right | Key::Ctrl('f') => self.cursor = (self.cursor + 1).min(len),
+            Key::Home | Key::Ctrl('a') => self.cursor = 0,
+            Key::End | Key::Ctrl('e') => self.cursor = len,
+            Key::WordLeft | Key::Alt('b') => self.cursor = self.word_start(),
+            Key::WordRight | Key::Alt('f') => self.cursor = self.word_end(),
+            Key::Ctrl('k') => self.cut(self.cursor, len),
+            Key::Ctrl('u') => self.cut(0, self.cursor),
+            Key::Ctrl('w') => {
+                // Ctrl-W goes back to a space, not to a word boundary:
+                // it cuts all of `../src/main.rs`.
+                let mut start = self.cursor;
+                while start > 0 && self.chars[start - 1] == ' ' {
+                    start -= 1;
+                }
+                while start > 0 && self.chars[start - 1] != ' ' {
+                    start -= 1;
+                }
+                self.cut(start, self.cursor);
+            }
+            Key::Alt('d') => self.cut(self.cursor, self.word_end()),
+            Key::Alt('\x7f') => self.cut(self.word_start(), self.cursor),
+            Key::Ctrl('y') => {
+                let kill: Vec<char> = self.kill.chars().collect();
+                self.chars
+                    .splice(self.cursor..self.cursor, kill.iter().copied());
+                self.cursor += kill.len();
+            }
+            Key::Up | Key::Ctrl('p') if self.index > 0 => self.show_entry(self.index - 1),
+            Key::Down | Key::Ctrl('n') if self.index < self.history.len() => {
+                self.show_entry(self.index + 1)
+            }
+            Key::Ctrl('r') => {
+                self.search = Some(Search {
+                    query: String::new(),
+                    found: None,
+                    failed: false,
+                })
+            }
+            Key::Ctrl('l') => return Outcome::Clear,
+            Key::Ctrl('c') => return Outcome::Cancel,
+            // Anything else: nothing to do.
+            _ => {}
+        }
+        Outcome::Continue
+    }
+
+    /// Handles `key` during a Ctrl-R search. Returns false if the key
+    /// ended the search and should be handled as usual: Enter runs the
+    /// line found, Left starts editing it.
+    fn handle_search(&mut self, key: Key) -> bool {
+        let Some(search) = &mut self.search else {
+            return false;
+        };
+        let history = self.history;
+        // The newest entry before `before` that has the query in it.
+        let find = |search: &mut Search, before: usize| match (0..before)
+            .rev()
+            .find(|&i| history[i].contains(&search.query))
+        {
+            Some(i) => {
+                search.found = Some(i);
+                search.failed = false;
+            }
+            None => search.failed = true,
+        };
+        match key {
+            Key::Char(c) => {
+                search.query.push(c);
+                // The entry found so far may still match.
+                let before = search.found.map_or(history.len(), |i| i + 1);
+                find(search, before);
+            }
+            Key::Backspace => {
+                search.query.pop();
+                find(search, history.len());
+            }
+            Key::Ctrl('r') => {
+                let before = search.found.unwrap_or(history.len());
+                find(search, before);
+            }
+            // Give up, and get back the line from before the search.
+            Key::Ctrl('g') | Key::Ctrl('c') => self.search = None,
+            _ => {
+                if let Some(i) = search.found {
+                    let at = history[i].rfind(&search.query).unwrap_or(0);
+                    self.show_entry(i);
+                    self.cursor = history[i][..at].chars().count();
+                }
+                self.search = None;
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Puts `completion` in the line: the one candidate there is, or as
+    /// much as all of them have in common. Returns the candidates to list
+    /// on the second Tab in a row.
+    pub fn complete(&mut self, completion: Completion) -> Option<Vec<String>> {
+        let common = complete::common_prefix(&completion.candidates);
+        let single = completion.candidates.len() == 1;
+        if single || common.len() > completion.prefix.len() {
+            let mut text = match completion.escape {
+                true => complete::escape(&common),
+                false => common,
+            };
+            if single && completion.space && !text.ends_with('/') {
+                text.push(' ');
+            }
+            // `start` is in bytes, the cursor in characters.
+            let before = self.before_cursor();
+            let start = before[..completion.start].chars().count();
+            self.chars.splice(start..self.cursor, text.chars());
+            self.cursor = start + text.chars().count();
+            // Still more than one way to go: the next Tab lists them.
+            self.tabbed = !single;
+            return None;
+        }
+        if self.tabbed && !completion.candidates.is_empty() {
+            self.tabbed = false;
+            return Some(completion.candidates);
+        }
+        self.tabbed = true;
+        None
+    }
+
+    /// Replaces the line with history entry `index`, keeping the new line
+    /// for when we come back to it.
+    fn show_entry(&mut self, index: usize) {
+        if self.index == self.history.len() {
+            self.draft = self.chars.clone();
+        }
+        self.index = index;
+        self.chars = match self.history.get(index) {
+            Some(entry) => entry.chars().collect(),
+            None => self.draft.clone(),
+        };
+        self.cursor = self.chars.len();
+    }
+
+    /// Cuts the characters `start..end`, for Ctrl-Y.
+    fn cut(&mut self, start: usize, end: usize) {
+        if start < end {
+            self.kill = self.chars.drain(start..end).collect();
+            self.cursor = start;
+        }
+    }
+
+    /// Where the word before the cursor starts. Words are made of letters
+    /// and digits.
+    fn word_start(&self) -> usize {
+        let mut at = self.cursor;
+        while at > 0 && !self.chars[at - 1].is_alphanumeric() {
+            at -= 1;
+        }
+        while at > 0 && self.chars[at - 1].is_alphanumeric() {
+            at -= 1;
+        }
+        at
+    }
+
+    /// Where the word under or after the cursor ends.
+    fn word_end(&self) -> usize {
+        let mut at = self.cursor;
+        while at < self.chars.len() && !self.chars[at].is_alphanumeric() {
+            at += 1;
+        }
+        while at < self.chars.len() && self.chars[at].is_alphanumeric() {
+            at += 1;
+        }
+        at
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn typed<'a>(line: &mut Line<'a>, text: &str) {
+        for c in text.chars() {
+            line.handle(Key::Char(c));
+        }
+    }
+
+    #[test]
+    fn parses_keys_and_escape_sequences() {
+        assert_eq!(parse_key(b"a"), Some((Key::Char('a'), 1)));
+        assert_eq!(parse_key(b"\r"), Some((Key::Enter, 1)));
+        assert_eq!(parse_key(b"\x01"), Some((Key::Ctrl('a'), 1)));
+        assert_eq!(parse_key(b"\x1b[D"), Some((Key::Left, 3)));
+        assert_eq!(parse_key(b"\x1b[3~x"), Some((Key::Delete, 4)));
+        assert_eq!(parse_key(b"\x1b[1;5C"), Some((Key::WordRight, 6)));
+        assert_eq!(parse_key(b"\x1bOH"), Some((Key::Home, 3)));
+        assert_eq!(parse_key(b"\x1bb"), Some((Key::Alt('b'), 2)));
+        assert_eq!(parse_key("é".as_bytes()), Some((Key::Char('é'), 2)));
+        // Not all there yet.
+        assert_eq!(parse_key(b"\x1b["), None);
+        assert_eq!(parse_key(&"é".as_bytes()[..1]), None);
+    }
+
+    #[test]
+    fn draws_long_lines_over_the_rows_they_take() {
+        // Fits: back to the start, then right to the cursor.
+        assert_eq!(
+            draw("> ab", 1, 20, 0),
+            ("\r> ab\x1b[J\r\x1b[3C".to_string(), 0)
+        );
+        // 10 characters on 4 columns take 3 rows; the cursor is on the last.
+        assert_eq!(
+            draw("> abcdefgh", 0, 4, 0),
+            ("\r> abcdefgh\x1b[J\r\x1b[2C".to_string(), 2)
+        );
+        // The next key starts from the first row again, and the cursor goes
+        // up to the middle one.
+        assert_eq!(
+            draw("> abcdefgh", 5, 4, 2),
+            ("\x1b[2A\r> abcdefgh\x1b[J\x1b[1A\r\x1b[1C".to_string(), 1)
+        );
+        // Exactly full: the cursor goes on to the next row itself.
+        assert_eq!(draw("> ab", 0, 4, 0), ("\r> ab\r\n\x1b[J\r".to_string(), 1));
+    }
+
+    #[test]
+    fn edits_moves_and_cuts() {
+        let mut line = Line::new(&[], String::new());
+        typed(&mut line, "echo hello");
+        line.handle(Key::WordLeft);
+        line.handle(Key::Ctrl('k'));
+        typed(&mut line, "big ");
+        line.handle(Key::Ctrl('y'));
+        assert_eq!(line.text(), "echo big hello");
+        line.handle(Key::Home);
+        line.handle(Key::Delete);
+        line.handle(Key::Right);
+        line.handle(Key::Backspace);
+        assert_eq!(line.text(), "ho big hello");
+        assert_eq!(line.view("> "), ("> ho big hello".to_string(), 12));
+        line.handle(Key::End);
+        line.handle(Key::Ctrl('w'));
+        assert_eq!(line.text(), "ho big ");
+        assert_eq!(
+            line.handle(Key::Enter),
+            Outcome::Done("ho big ".to_string())
+        );
+        line.handle(Key::Ctrl('u'));
+        assert_eq!(line.handle(Key::Ctrl('d')), Outcome::End);
+    }
+
+    #[test]
+    fn completes_once_and_lists_on_the_second_tab() {
+        let completion = |line: &Line, names: &[&str]| {
+            let names: Vec<String> = names.iter().map(|name| name.to_string()).collect();
+            complete::complete(&line.before_cursor(), &names, &[], None)
+        };
+        let mut line = Line::new(&[], String::new());
+        typed(&mut line, "ca");
+        line.handle(Key::Left);
+        assert_eq!(line.handle(Key::Tab), Outcome::Complete);
+        // Only what is before the cursor counts.
+        assert_eq!(line.complete(completion(&line, &["cd", "grep"])), None);
+        assert_eq!(line.text(), "cd a");
+
+        let mut line = Line::new(&[], String::new());
+        typed(&mut line, "c");
+        let names = ["cargo", "cat"];
+        assert_eq!(line.complete(completion(&line, &names)), None);
+        assert_eq!(line.text(), "ca");
+        assert_eq!(
+            line.complete(completion(&line, &names)),
+            Some(vec!["cargo".to_string(), "cat".to_string()])
+        );
+        typed(&mut line, "r");
+        assert_eq!(line.complete(completion(&line, &names)), None);
+        assert_eq!(line.text(), "cargo ");
+    }
+
+    #[test]
+    fn goes_through_the_history_and_back() {
+        let history = ["ls".to_string(), "pwd".to_string()];
+        let mut line = Line::new(&history, String::new());
+        typed(&mut line, "ec");
+        line.handle(Key::Up);
+        assert_eq!(line.text(), "pwd");
+        line.handle(Key::Up);
+        line.handle(Key::Up);
+        assert_eq!(line.text(), "ls");
+        line.handle(Key::Down);
+        line.handle(Key::Down);
+        assert_eq!(line.text(), "ec");
+    }
+
+    #[test]
+    fn searches_the_history_backwards() {
+        let history = [
+            "cargo build".to_string(),
+            "ls".to_string(),
+            "cargo test".to_string(),
+        ];
+        let mut line = Line::new(&history, String::new());
+        typed(&mut line, "draft");
+        line.handle(Key::Ctrl('r'));
+        typed(&mut line, "car");
+        assert_eq!(
+            line.view("> "),
+            ("(reverse-i-search)'car': cargo test".to_string(), 10)
+        );
+        line.handle(Key::Ctrl('r'));
+        assert_eq!(line.view("> ").0, "(reverse-i-search)'car': cargo build");
+        line.handle(Key::Ctrl('r'));
+        assert!(line.view("> ").0.starts_with("(failed reverse-i-search)"));
+        assert_eq!(
+            line.handle(Key::Enter),
+            Outcome::Done("cargo build".to_string())
+        );
+
+        // Ctrl-G gives the old line back.
+        let mut line = Line::new(&history, String::new());
+        typed(&mut line, "draft");
+        line.handle(Key::Ctrl('r'));
+        typed(&mut line, "ls");
+        line.handle(Key::Ctrl('g'));
+        assert_eq!(line.text(), "draft");
+    }
+}
@@ -0,0 +1,1026 @@
+// Running command lines.
+//
+// A line is a list of and-or chains (see `parser.rs`), run one after the
+// other. In a chain, `&&` runs the next pipeline only if the last one
+// succeeded (exit status 0), and `||` only if it failed:
+//
+//     cargo build && ./run || echo failed; echo done
+//
+// The status of each pipeline is kept in `$?` right away, so that the
+// next one on the same line can look at it: `false; echo $?` prints 1.
+//
+// With `set -e`, a pipeline that fails ends the shell, unless `&&` or
+// `||` is there to deal with the failure. With `set -x`, every command is
+// printed after expansion, just before it runs: `+ ls -l /home/ana`.
+//
+// In `ls | grep txt | wc -l` every stage runs at the same time, connected
+// by OS pipes: `ls` writes into the first pipe while `grep` reads from it
+// and writes into the second one, which `wc` reads. Running the stages one
+// after another instead would need the whole output of `ls` in memory, and
+// would never finish for endless producers like `yes | head`.
+//
+//     ls  --pipe-->  grep txt  --pipe-->  wc -l  --> terminal
+//
+// Builtins can be stages too. They run on a thread of the shell, writing
+// into the pipe like any program would.
+//
+// Each stage can also have its own redirections (see `redirect.rs`), which
+// win over the pipes: in `ls > list.txt | wc -l`, `wc` gets nothing.
+//
+// Variables are expanded just before a pipeline starts (see `expand.rs`).
+// `NAME=value` in front of a command only sets the variable for that
+// command: `LANG=C sort` leaves the shell's own `LANG` alone.
+//
+// The exit status of a pipeline is the one of its last stage. With
+// `set -o pipefail` it is the one of the last stage that failed, so that
+// `false | cat` counts as a failure.
+//
+// The processes of a pipeline form a job, in a process group of their
+// own. Waiting for it, and running it in the background, is up to
+// `jobs.rs`.
+//
+// Compound commands (`if`, `while`, `until`, `for`, `case` and `{ ...; }`)
+// run the lists inside them, on the real shell, so that `cd` and
+// variables inside them stick. A function call does the same with the
+// function's body, after setting `$1` and so on to its arguments. As a
+// stage of a pipeline, or in the background, they run on a thread with a
+// copy of the shell, like builtins.
+//
+// Before anything runs, the command policy has its say (see `policy.rs`):
+// a pipeline with a command it refuses doesn't start at all.
+//
+// `$(command)` runs the command in a copy of the shell too, with its
+// standard output going into a pipe that the shell reads (see `capture`).
+
+use std::env;
+use std::fs::File;
+use std::io::{self, PipeReader, Read, Write};
+use std::mem;
+use std::os::fd::OwnedFd;
+use std::os::unix::process::{CommandExt, ExitStatusExt};
+use std::process::{Command, ExitStatus, Stdio};
+use std::sync::Arc;
+use std::thread;
+
+use crate::builtins;
+use crate::expand;
+use crate::glob;
+use crate::jobs::{self, Job, Stage};
+use crate::lexer;
+use crate::parser::{self, Compound, CompoundCommand, Connector, List, Pipeline, SimpleCommand};
+use crate::policy;
+use crate::redirect::{self, Fds};
+use crate::shell::{Control, Function, Shell, Var};
+use crate::sys;
+
+/// How many function calls can be running inside each other. Beyond
+/// that it is surely a function calling itself without end, which would
+/// crash the shell once its stack is full.
+const MAX_CALL_DEPTH: usize = 200;
+
+/// Runs `list`, which was read from `line`. Returns the status of the
+/// last pipeline that ran.
+pub fn run_list(shell: &mut Shell, list: &List, line: &str) -> i32 {
+    let mut status = 0;
+    for and_or in &list.items {
+        status = run_pipeline(shell, &and_or.first, line);
+        shell.last_status = status;
+        // Whether `status` comes from the last pipeline of the chain. A
+        // failure before `&&` or `||` is one the chain was checking for.
+        let mut last = and_or.rest.is_empty();
+        for (i, (connector, pipeline)) in and_or.rest.iter().enumerate() {
+            if stop(shell) {
+                return status;
+            }
+            let run = match connector {
+                Connector::And => status == 0,
+                Connector::Or => status != 0,
+            };
+            if run {
+                status = run_pipeline(shell, pipeline, line);
+                shell.last_status = status;
+                last = i + 1 == and_or.rest.len();
+            }
+        }
+        if shell.options.errexit && status != 0 && last && shell.exit.is_none() {
+            shell.exit = Some(status);
+        }
+        if stop(shell) {
+            break;
+        }
+    }
+    status
+}
+
+/// Whether the rest of the line is skipped: after `exit`, `break`,
+/// `continue` or `return`, or when Ctrl-C killed the command in the
+/// foreground. `sleep 60; sleep 60` should stop at the first Ctrl-C, not
+/// need one for each.
+fn stop(shell: &Shell) -> bool {
+    let interrupted = shell.last_status == 128 + sys::SIGINT && shell.terminal.is_some();
+    shell.exit.is_some() || shell.control.is_some() || interrupted
+}
+
+/// Runs `pipeline`, which was read from `line`. Returns its exit status,
+/// or 0 right away if it runs in the background.
+pub fn run_pipeline(shell: &mut Shell, pipeline: &Pipeline, line: &str) -> i32 {
+    // With `set -o failglob`, a pattern that matches nothing stops the
+    // whole pipeline before anything starts. Compound commands expand
+    // their words as they go.
+    let mut words = Vec::new();
+    for command in &pipeline.commands {
+        words.push(match command {
+            parser::Command::Simple(command) => match expand::expand_words(&command.words, shell) {
+                Ok(words) => words,
+                Err(message) => {
+                    eprintln!("safe_shell: {}", message);
+                    return 1;
+                }
+            },
+            _ => Vec::new(),
+        });
+    }
+
+    if shell.options.xtrace {
+        for words in words.iter().filter(|words| !words.is_empty()) {
+            let words: Vec<String> = words.iter().map(|word| trace_quote(word)).collect();
+            eprintln!("+ {}", words.join(" "));
+        }
+    }
+
+    // The policy checks every stage before any of them starts: half a
+    // pipeline is no better than none. 126 is what shells use for "found
+    // it, but can't run it".
+    for (command, words) in pipeline.commands.iter().zip(&mut words) {
+        if !policy::permit(shell, command, words) {
+            return 126;
+        }
+    }
+
+    // A builtin or function on its own runs right here, on the real shell,
+    // so that `cd` and `exit` work. So do plain assignments like `A=1`,
+    // compound commands and function definitions.
+    if let [command] = &pipeline.commands[..]
+        && !pipeline.background
+    {
+        match command {
+            parser::Command::Simple(command)
+                if words[0].is_empty() || runs_here(shell, &words[0]) =>
+            {
+                return run_here(shell, command, &words[0]);
+            }
+            parser::Command::Compound(command) => return run_compound(shell, command, line),
+            parser::Command::Function(function) => {
+                // The spans in the body point into `line`, which is all we
+                // keep of it.
+                let text = line.get(..function.span.end).unwrap_or(line);
+                let defined = Function {
+                    body: function.body.clone(),
+                    text: Arc::from(text),
+                };
+                shell.functions.insert(function.name.clone(), defined);
+                return 0;
+            }
+            parser::Command::Simple(_) => {}
+        }
+    }
+
+    // 1. START EVERY STAGE
+    // Each stage reads from the pipe the stage before it writes into. The
+    // first one reads the terminal and the last one writes to it.
+    let mut stages = Vec::new();
+    let mut input: Option<PipeReader> = None;
+    // The job's process group: the one of its first process.
+    let mut pgid = None;
+    for (i, (command, words)) in pipeline.commands.iter().zip(&words).enumerate() {
+        let is_last = i + 1 == pipeline.commands.len();
+        let (next_input, output) = if is_last {
+            (None, None)
+        } else {
+            match io::pipe() {
+                Ok((reader, writer)) => (Some(reader), Some(writer)),
+                Err(e) => {
+                    eprintln!("safe_shell: cannot create a pipe: {}", e);
+                    stages.push(Stage::done(1));
+                    break;
+                }
+            }
+        };
+        let mut fds = [
+            input.take().map(OwnedFd::from),
+            output.map(OwnedFd::from),
+            None,
+        ];
+        // Without job control, a background job can't be kept from
+        // reading what is typed for the shell. It reads nothing instead.
+        if i == 0 && pipeline.background && shell.terminal.is_none() {
+            fds[0] = File::open("/dev/null").ok().map(OwnedFd::from);
+        }
+        let stage = start(shell, command, words, fds, pipeline.background, pgid, line);
+        pgid = pgid.or(stage.pid());
+        stages.push(stage);
+        input = next_input;
+    }
+
+    // 2. WAIT FOR ALL OF THEM, OR DON'T
+    // Every stage has to be waited for, not just the last one, or the
+    // finished processes would stay around as zombies. For a background
+    // job that happens later, see `Jobs::reap`.
+    let text = line
+        .get(pipeline.span.start..pipeline.span.end)
+        .unwrap_or_default();
+    if pipeline.background {
+        let last_pid = stages.iter().rev().find_map(Stage::pid);
+        let id = shell
+            .jobs
+            .add(Job::new(format!("{} &", text), pgid, stages));
+        if shell.terminal.is_some() {
+            eprintln!("[{}] {}", id, last_pid.unwrap_or(0));
+        }
+        return 0;
+    }
+    jobs::foreground(shell, Job::new(text.to_string(), pgid, stages))
+}
+
+/// Whether the command `words` runs in the shell itself: a function or a
+/// builtin.
+fn runs_here(shell: &Shell, words: &[String]) -> bool {
+    shell.functions.contains_key(&words[0]) || builtins::is_builtin(words)
+}
+
+/// Runs a builtin or a function, or a command with no words at all, in
+/// the shell itself.
+fn run_here(shell: &mut Shell, command: &SimpleCommand, words: &[String]) -> i32 {
+    let mut fds = Fds::default();
+    let redirected = redirect::inherit(&mut fds, shell)
+        .and_then(|()| redirect::apply(&command.redirects, &mut fds, shell));
+    if let Err(message) = redirected {
+        eprintln!("safe_shell: {}", message);
+        return 1;
+    }
+
+    // `A=1` on its own sets a shell variable for good. Its status is the
+    // one of the last `$(command)` in it, so that `A=$(false)` fails.
+    if words.is_empty() {
+        shell.last_substitution.set(None);
+        for assignment in &command.assignments {
+            match expand::expand_word(&assignment.value, shell) {
+                Ok(value) => shell.set_var(&assignment.name, value),
+                Err(message) => {
+                    eprintln!("safe_shell: {}", message);
+                    return 1;
+                }
+            }
+        }
+        return shell.last_substitution.take().unwrap_or(0);
+    }
+
+    // `A=1 builtin` only sets it while the builtin runs.
+    let saved = match assign_temporarily(shell, command) {
+        Ok(saved) => saved,
+        Err(message) => {
+            eprintln!("safe_shell: {}", message);
+            return 1;
+        }
+    };
+    let status = match shell.functions.get(&words[0]).cloned() {
+        // `f > file`: everything in the function writes to the file.
+        Some(function) => with_io(shell, fds, |shell| call(shell, &function, words)),
+        None => {
+            let [_, out, err] = fds;
+            builtins::run(shell, words, &mut writer(out, 1), &mut writer(err, 2))
+        }
+    };
+    restore(shell, saved);
+    status
+}
+
+/// Calls `function` with the arguments `words[1..]`.
+fn call(shell: &mut Shell, function: &Function, words: &[String]) -> i32 {
+    if shell.frames.len() >= MAX_CALL_DEPTH {
+        eprintln!(
+            "{}: more than {} function calls inside each other",
+            words[0], MAX_CALL_DEPTH
+        );
+        return 1;
+    }
+    let args = mem::replace(&mut shell.args, words[1..].to_vec());
+    shell.frames.push(Vec::new());
+    let status = run_compound(shell, &function.body, &function.text);
+    let locals = shell.frames.pop().unwrap_or_default();
+    restore(shell, locals);
+    shell.args = args;
+    if shell.control == Some(Control::Return) {
+        shell.control = None;
+    }
+    status
+}
+
+/// Runs `command` and its redirections, which was read from `line`.
+fn run_compound(shell: &mut Shell, command: &CompoundCommand, line: &str) -> i32 {
+    if command.redirects.is_empty() {
+        return run_compound_kind(shell, &command.kind, line);
+    }
+    let mut fds = Fds::default();
+    let redirected = redirect::inherit(&mut fds, shell)
+        .and_then(|()| redirect::apply(&command.redirects, &mut fds, shell));
+    if let Err(message) = redirected {
+        eprintln!("safe_shell: {}", message);
+        return 1;
+    }
+    with_io(shell, fds, |shell| {
+        run_compound_kind(shell, &command.kind, line)
+    })
+}
+
+fn run_compound_kind(shell: &mut Shell, kind: &Compound, line: &str) -> i32 {
+    match kind {
+        Compound::Group(list) => run_list(shell, list, line),
+
+        // The first condition that succeeds picks the branch.
+        Compound::If {
+            branches,
+            otherwise,
+        } => {
+            for (condition, body) in branches {
+                let status = run_condition(shell, condition, line);
+                if stop(shell) {
+                    return status;
+                }
+                if status == 0 {
+                    return run_list(shell, body, line);
+                }
+            }
+            match otherwise {
+                Some(body) => run_list(shell, body, line),
+                None => 0,
+            }
+        }
+
+        // The status of a loop is the one of the last round of its body,
+        // or 0 if the body never ran.
+        Compound::Loop {
+            until,
+            condition,
+            body,
+        } => {
+            let mut status = 0;
+            shell.loops += 1;
+            loop {
+                let test = run_condition(shell, condition, line);
+                if stop(shell) {
+                    if next_round(shell) {
+                        continue;
+                    }
+                    break;
+                }
+                if (test == 0) == *until {
+                    break;
+                }
+                status = run_list(shell, body, line);
+                if !next_round(shell) {
+                    break;
+                }
+            }
+            shell.loops -= 1;
+            status
+        }
+
+        Compound::For { name, words, body } => {
+            let items = match words {
+                Some(words) => match expand::expand_words(words, shell) {
+                    Ok(items) => items,
+                    Err(message) => {
+                        eprintln!("safe_shell: {}", message);
+                        return 1;
+                    }
+                },
+                None => shell.args.clone(),
+            };
+            let mut status = 0;
+            shell.loops += 1;
+            for item in items {
+                shell.set_var(name, item);
+                status = run_list(shell, body, line);
+                if !next_round(shell) {
+                    break;
+                }
+            }
+            shell.loops -= 1;
+            status
+        }
+
+        // The first branch with a matching pattern runs. Quoted parts of
+        // a pattern match themselves: `"*")` is a literal star.
+        Compound::Case { word, arms } => {
+            let text = match expand::expand_word(word, shell) {
+                Ok(text) => text,
+                Err(message) => {
+                    eprintln!("safe_shell: {}", message);
+                    return 1;
+                }
+            };
+            for arm in arms {
+                for pattern in &arm.patterns {
+                    match expand::expand_pattern(pattern, shell) {
+                        Ok(pattern) if glob::matches(&pattern, &text) => {
+                            return run_list(shell, &arm.body, line);
+                        }
+                        Ok(_) => {}
+                        Err(message) => {
+                            eprintln!("safe_shell: {}", message);
+                            return 1;
+                        }
+                    }
+                }
+            }
+            0
+        }
+    }
+}
+
+/// Runs the condition of an `if`, `while` or `until`. A failure there is
+/// an answer, not an error, so `set -e` leaves it alone.
+fn run_condition(shell: &mut Shell, condition: &List, line: &str) -> i32 {
+    let errexit = mem::replace(&mut shell.options.errexit, false);
+    let status = run_list(shell, condition, line);
+    shell.options.errexit = errexit;
+    status
+}
+
+/// After a round of a loop: whether the next one runs. `break` and
+/// `continue` are handled here, or handed on to the loop around this one
+/// when they are for more than one loop.
+fn next_round(shell: &mut Shell) -> bool {
+    match shell.control {
+        Some(Control::Break(n)) => {
+            shell.control = (n > 1).then(|| Control::Break(n - 1));
+            false
+        }
+        Some(Control::Continue(n)) if n > 1 => {
+            shell.control = Some(Control::Continue(n - 1));
+            false
+        }
+        Some(Control::Continue(_)) => {
+            shell.control = None;
+            true
+        }
+        _ => !stop(shell),
+    }
+}
+
+/// Runs `run` with `fds` as where the shell's commands read and write.
+fn with_io(shell: &mut Shell, fds: Fds, run: impl FnOnce(&mut Shell) -> i32) -> i32 {
+    let io = fds.map(|fd| fd.map(Arc::new));
+    let saved = mem::replace(&mut shell.io, io);
+    let status = run(shell);
+    shell.io = saved;
+    status
+}
+
+/// Puts back variables saved by `assign_temporarily` or `local`, last
+/// saved first.
+fn restore(shell: &mut Shell, saved: Vec<(String, Option<Var>)>) {
+    for (name, var) in saved.into_iter().rev() {
+        match var {
+            Some(var) => shell.vars.insert(name, var),
+            None => shell.vars.remove(&name),
+        };
+    }
+}
+
+/// Sets the `NAME=value` assignments of `command` as exported variables,
+/// and returns what they were before. If a value can't be expanded,
+/// nothing is set.
+fn assign_temporarily(
+    shell: &mut Shell,
+    command: &SimpleCommand,
+) -> Result<Vec<(String, Option<Var>)>, String> {
+    let mut values = Vec::new();
+    for assignment in &command.assignments {
+        values.push(expand::expand_word(&assignment.value, shell)?);
+    }
+    let mut saved = Vec::new();
+    for (assignment, value) in command.assignments.iter().zip(values) {
+        let var = Var {
+            value,
+            exported: true,
+        };
+        let old = shell.vars.insert(assignment.name.clone(), var);
+        saved.push((assignment.name.clone(), old));
+    }
+    Ok(saved)
+}
+
+/// Starts one stage of a pipeline: `command`, whose words expanded to
+/// `words`, read from `line`. `fds` are the pipe ends it is connected to;
+/// its own redirections are applied on top. A process joins group
+/// `pgid`, or starts a new one if there is none yet.
+fn start(
+    shell: &Shell,
+    command: &parser::Command,
+    words: &[String],
+    mut fds: Fds,
+    background: bool,
+    pgid: Option<i32>,
+    line: &str,
+) -> Stage {
+    if let Err(message) = redirect::inherit(&mut fds, shell) {
+        eprintln!("safe_shell: {}", message);
+        return Stage::done(1);
+    }
+    let command = match command {
+        parser::Command::Simple(command) => command,
+        parser::Command::Compound(command) => {
+            let (command, line) = (command.clone(), line.to_string());
+            let mut shell = subshell(shell, fds, background);
+            return Stage::builtin(thread::spawn(move || {
+                run_compound(&mut shell, &command, &line)
+            }));
+        }
+        // Defined in a copy of the shell that is gone right away.
+        parser::Command::Function(_) => return Stage::done(0),
+    };
+
+    // A file that can't be opened stops the command before it starts,
+    // exactly like in other shells.
+    if let Err(message) = redirect::apply(&command.redirects, &mut fds, shell) {
+        eprintln!("safe_shell: {}", message);
+        return Stage::done(1);
+    }
+    // `> file` on its own just creates (or empties) the file. Assignments
+    // in a pipeline are lost with the stage's subshell, as elsewhere.
+    if words.is_empty() {
+        return Stage::done(0);
+    }
+
+    // A function runs on a thread too, with the rest of its stage.
+    if let Some(function) = shell.functions.get(&words[0]).cloned() {
+        let mut shell = subshell(shell, fds, background);
+        if let Err(message) = assign_temporarily(&mut shell, command) {
+            eprintln!("safe_shell: {}", message);
+            return Stage::done(1);
+        }
+        let words = words.to_vec();
+        return Stage::builtin(thread::spawn(move || call(&mut shell, &function, &words)));
+    }
+
+    // The stage gets its own copy of the shell, with its temporary
+    // variables set.
+    let mut shell = Shell {
+        in_pipeline: true,
+        ..shell.clone()
+    };
+    if let Err(message) = assign_temporarily(&mut shell, command) {
+        eprintln!("safe_shell: {}", message);
+        return Stage::done(1);
+    }
+
+    if builtins::is_builtin(words) {
+        // Builtins don't read their input. Closing it now lets the stage
+        // before see that nobody is listening, instead of blocking forever
+        // on a full pipe.
+        let [input, out, err] = fds;
+        drop(input);
+        let words = words.to_vec();
+        let (mut out, mut err) = (writer(out, 1), writer(err, 2));
+        // When the thread ends, `out` is dropped, which closes the pipe:
+        // that is how the next stage learns there is no more input.
+        return Stage::builtin(thread::spawn(move || {
+            builtins::run(&mut shell, &words, &mut out, &mut err)
+        }));
+    }
+
+    // External Command
+    // We ask the OS to spawn a new process. It gets exactly the exported
+    // variables as its environment: `unset` ones are gone, even if the
+    // shell itself was started with them.
+    let mut process = match shell_script(&words[0]) {
+        // A script without `#!`: the system doesn't know what to run it
+        // with, so we run it ourselves, as other shells do.
+        true => {
+            let mut process = Command::new(env::current_exe().unwrap_or_default());
+            process.args(words);
+            process
+        }
+        false => {
+            let mut process = Command::new(&words[0]);
+            process.args(&words[1..]);
+            process
+        }
+    };
+    process.env_clear().envs(shell.environment());
+    let [input, out, err] = fds;
+    if let Some(fd) = input {
+        process.stdin(Stdio::from(fd));
+    }
+    if let Some(fd) = out {
+        process.stdout(Stdio::from(fd));
+    }
+    if let Some(fd) = err {
+        process.stderr(Stdio::from(fd));
+    }
+    if let Some(terminal) = shell.terminal {
+        process.process_group(pgid.unwrap_or(0));
+        // This runs in the child, just before it becomes the new program.
+        // A foreground job takes the terminal itself too: the shell does
+        // the same, but only once the whole pipeline has started, and the
+        // program may want to read before that.
+        let fd = terminal.fd;
+        let pre_exec = move || {
+            if !background {
+                let _ = sys::set_foreground(fd, sys::process_group());
+            }
+            sys::default_job_signals();
+            Ok(())
+        };
+        // Safe because the closure only makes system calls that are safe
+        // between `fork` and `exec`: no allocating, no locks.
+        unsafe { process.pre_exec(pre_exec) };
+    }
+    // `process` (and with it our copy of the pipe ends) is dropped when
+    // we return. That matters: a reader only sees the end of its input
+    // once *every* copy of the write end is closed.
+    match process.spawn() {
+        Ok(child) => Stage::process(child.id() as i32),
+        Err(e) => {
+            // This usually happens if the command doesn't exist.
+            if e.kind() == io::ErrorKind::NotFound {
+                eprintln!("Command not found: '{}'", words[0]);
+                Stage::done(127)
+            } else {
+                eprintln!("{}: {}", words[0], e);
+                Stage::done(126)
+            }
+        }
+    }
+}
+
+/// Whether `path` names a script without a `#!` line: a file that starts
+/// neither with `#!` nor like a compiled program does (`\x7fELF`).
+fn shell_script(path: &str) -> bool {
+    if !path.contains('/') {
+        return false;
+    }
+    let mut start = [0; 4];
+    match File::open(path).and_then(|mut file| file.read(&mut start)) {
+        Ok(len) => !start[..len].starts_with(b"#!") && !start[..len].starts_with(b"\x7fELF"),
+        Err(_) => false,
+    }
+}
+
+/// A copy of the shell for a compound command or function that runs as a
+/// stage of a pipeline, reading and writing `fds`. In the background it
+/// leaves the terminal alone: the programs it starts stay in the shell's
+/// process group.
+fn subshell(shell: &Shell, fds: Fds, background: bool) -> Shell {
+    Shell {
+        in_pipeline: true,
+        io: fds.map(|fd| fd.map(Arc::new)),
+        terminal: if background { None } else { shell.terminal },
+        ..shell.clone()
+    }
+}
+
+/// Runs `text`, the command of a `$(command)`, in a copy of `shell`, and
+/// returns what it wrote to its standard output, without the newlines at
+/// the end. Its exit status is kept in `shell.last_substitution`.
+///
+/// The error is for a command that can't run at all, like one with a
+/// syntax error: then the command using the `$(...)` fails too, instead of
+/// carrying on with an empty value.
+pub fn capture(shell: &Shell, text: &str) -> Result<String, String> {
+    // The lexer checks the command of a `$(...)` as it reads it, but a bad
+    // one that got past it must not quietly run as nothing.
+    let list = match lexer::tokenize(text).and_then(parser::parse) {
+        Ok(Some(list)) => list,
+        Ok(None) => return Ok(String::new()),
+        Err(e) => {
+            shell.last_substitution.set(Some(2));
+            return Err(e.render(text));
+        }
+    };
+    let (mut reader, writer) = io::pipe().map_err(|e| format!("cannot create a pipe: {}", e))?;
+
+    // 1. READ WHILE IT RUNS
+    // Waiting for the command first would hang as soon as it writes more
+    // than a pipe holds (64 KiB on Linux), so a thread reads meanwhile.
+    let reading = thread::spawn(move || {
+        let mut output = Vec::new();
+        let _ = reader.read_to_end(&mut output);
+        output
+    });
+
+    // 2. RUN IT IN A COPY OF THE SHELL
+    // So `$(cd /tmp)` or `$(exit)` change nothing here. Dropping the copy
+    // closes its end of the pipe, which ends the reading.
+    let mut copy = Shell {
+        in_pipeline: true,
+        ..shell.clone()
+    };
+    copy.io[1] = Some(Arc::new(OwnedFd::from(writer)));
+    let status = run_list(&mut copy, &list, text);
+    drop(copy);
+    shell.last_substitution.set(Some(status));
+
+    let output = reading.join().unwrap_or_default();
+    Ok(String::from_utf8_lossy(&output)
+        .trim_end_matches('\n')
+        .to_string())
+}
+
+/// `word` as `set -x` shows it: quoted only if it has to be.
+fn trace_quote(word: &str) -> String {
+    let plain = |c: char| c.is_ascii_alphanumeric() || "-_./=:,+%@".contains(c);
+    if !word.is_empty() && word.chars().all(plain) {
+        word.to_string()
+    } else {
+        builtins::quote(word)
+    }
+}
+
+/// Where a builtin writes: the redirected or piped `fd`, or else the
+/// shell's own standard output (1) or error (2).
+fn writer(fd: Option<OwnedFd>, default: u32) -> Box<dyn Write + Send> {
+    match fd {
+        Some(fd) => Box::new(File::from(fd)),
+        None if default == 1 => Box::new(io::stdout()),
+        None => Box::new(io::stderr()),
+    }
+}
+
+/// The shell's number for how a process ended: its exit code, or 128 plus
+/// the signal number if a signal killed it (141 for SIGPIPE).
+pub fn exit_code(status: ExitStatus) -> i32 {
+    match status.code() {
+        Some(code) => code,
+        None => 128 + status.signal().unwrap_or(0),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::tokenize;
+    use crate::parser::parse;
+
+    fn run_line(shell: &mut Shell, line: &str) -> i32 {
+        let list = parse(tokenize(line).unwrap()).unwrap().unwrap();
+        run_list(shell, &list, line)
+    }
+
+    #[test]
+    fn scripts_without_a_shebang_are_run_by_us() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = std::env::temp_dir().join(format!("safe_shell_script_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let script = dir.join("plain");
+        std::fs::write(&script, "exit 7\n").unwrap();
+        std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let path = script.to_str().unwrap();
+        assert!(shell_script(path));
+        assert!(!shell_script("/bin/sh"));
+        std::fs::remove_dir_all(dir).unwrap();
+        assert!(!shell_script("plain"));
+    }
+
+    #[test]
+    fn and_or_chains_depend_on_the_last_status() {
+        let mut shell = Shell::default();
+        assert_eq!(run_line(&mut shell, "true && false || exit 3; true"), 3);
+        assert_eq!(shell.exit, Some(3));
+
+        let mut shell = Shell::default();
+        assert_eq!(run_line(&mut shell, "false && exit 1 || true"), 0);
+        assert_eq!(run_line(&mut shell, "true || exit 1 && false"), 1);
+        assert_eq!(shell.exit, None);
+        assert_eq!(run_line(&mut shell, "false; A=$?; true && B=$?"), 0);
+        assert_eq!(shell.get_var("A"), Some("1"));
+        assert_eq!(shell.get_var("B"), Some("0"));
+        assert_eq!(run_line(&mut shell, "exit; true"), 0);
+        assert_eq!(shell.exit, Some(0));
+    }
+
+    #[test]
+    fn status_comes_from_the_last_stage_unless_pipefail() {
+        let mut shell = Shell::default();
+        assert_eq!(run_line(&mut shell, "false | true"), 0);
+        assert_eq!(run_line(&mut shell, "true | false"), 1);
+        shell.options.pipefail = true;
+        assert_eq!(run_line(&mut shell, "false | true"), 1);
+        assert_eq!(run_line(&mut shell, "no-such-command-here | true"), 127);
+    }
+
+    #[test]
+    fn stages_run_at_the_same_time() {
+        // `yes` never stops by itself: this only ends because `head` quits
+        // and `yes` then dies writing to a closed pipe.
+        let mut shell = Shell::default();
+        assert_eq!(run_line(&mut shell, "yes | head -c 0"), 0);
+        shell.options.pipefail = true;
+        assert_eq!(run_line(&mut shell, "yes | head -c 0"), 141);
+    }
+
+    #[test]
+    fn builtins_in_pipelines_leave_the_shell_alone() {
+        let mut shell = Shell::default();
+        assert_eq!(run_line(&mut shell, "set -o pipefail | cat"), 0);
+        assert!(!shell.options.pipefail);
+        assert_eq!(run_line(&mut shell, "exit 3 | cat"), 0);
+        assert_eq!(shell.exit, None);
+        assert_eq!(run_line(&mut shell, "pwd | grep -q /"), 0);
+    }
+
+    #[test]
+    fn assignments_before_a_command_are_temporary() {
+        let path = std::env::temp_dir().join(format!("safe_shell_vars_{}", std::process::id()));
+        let path = path.to_str().unwrap();
+        let mut shell = Shell::default();
+        run_line(&mut shell, "A=1 B=two");
+        run_line(&mut shell, "export B");
+        let line = format!("A=$B C=\"x y\" env > {}", path);
+        assert_eq!(run_line(&mut shell, &line), 0);
+        let text = std::fs::read_to_string(path).unwrap();
+        assert!(text.contains("A=two\nB=two\nC=x y\n"), "{}", text);
+        std::fs::remove_file(path).unwrap();
+
+        // The builtin `export` sees the temporary value; afterwards the
+        // shell has its own again.
+        run_line(&mut shell, "A=temp export D=$A");
+        assert_eq!(shell.get_var("A"), Some("1"));
+        assert_eq!(shell.get_var("D"), Some("1"));
+        assert!(!shell.environment().contains_key("A"));
+        assert_eq!(run_line(&mut shell, "X=1 env | grep -q X=1"), 0);
+    }
+
+    #[test]
+    fn background_jobs_run_while_the_shell_goes_on() {
+        let mut shell = Shell::default();
+        assert_eq!(run_line(&mut shell, "sleep 0.2 | exit 4 &"), 0);
+        assert_eq!(run_line(&mut shell, "true &"), 0);
+        assert_eq!(
+            shell.jobs.listing(false),
+            [
+                format!("[1]-  {:<24}sleep 0.2 | exit 4 &", "Running"),
+                format!("[2]+  {:<24}true &", "Running"),
+            ]
+        );
+        // `wait %N` returns the job's status; `wait` waits for all jobs.
+        assert_eq!(run_line(&mut shell, "wait %1"), 4);
+        assert_eq!(run_line(&mut shell, "wait"), 0);
+        assert_eq!(shell.jobs.len(), 0);
+        assert_eq!(run_line(&mut shell, "fg"), 1);
+    }
+
+    #[test]
+    fn stages_can_redirect_their_own_input_and_output() {
+        let path = std::env::temp_dir().join(format!("safe_shell_exec_{}", std::process::id()));
+        let path = path.to_str().unwrap();
+        let mut shell = Shell::default();
+        let line = format!("tr a-z A-Z <<< hello | cat > {} 2>&1", path);
+        assert_eq!(run_line(&mut shell, &line), 0);
+        run_line(&mut shell, &format!("pwd >> {}", path));
+        run_line(&mut shell, &format!("cd /no/such/dir 2>> {}", path));
+        let text = std::fs::read_to_string(path).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines[0], "HELLO");
+        assert!(lines[2].starts_with("cd: /no/such/dir"), "{}", text);
+        std::fs::remove_file(path).unwrap();
+
+        assert_eq!(run_line(&mut shell, "cat < /no/such/file"), 1);
+    }
+
+    #[test]
+    fn if_and_case_pick_a_branch() {
+        let mut shell = Shell::default();
+        let line = "for x in 1 2 3; do if test $x = 1; then A=$A-one; elif test $x = 2; then A=$A-two; else A=$A-else; fi; done";
+        assert_eq!(run_line(&mut shell, line), 0);
+        assert_eq!(shell.get_var("A"), Some("-one-two-else"));
+        assert_eq!(run_line(&mut shell, "if false; then true; fi"), 0);
+
+        let line = "for x in main.rs '*' .hidden x; do case $x in\n  *.rs) B=$B-rust;;\n  \"*\") B=$B-star;;\n  .*|y) B=$B-dot;;\n  *) B=$B-other\nesac; done";
+        assert_eq!(run_line(&mut shell, line), 0);
+        assert_eq!(shell.get_var("B"), Some("-rust-star-dot-other"));
+    }
+
+    #[test]
+    fn loops_break_and_continue() {
+        let mut shell = Shell::default();
+        let line = "for x in a b c d; do if test $x = b; then continue; fi; if test $x = d; then break; fi; R=$R$x; done";
+        assert_eq!(run_line(&mut shell, line), 0);
+        assert_eq!(shell.get_var("R"), Some("ac"));
+
+        run_line(&mut shell, "until test \"$U\" = xxx; do U=x$U; done");
+        assert_eq!(shell.get_var("U"), Some("xxx"));
+
+        let line = "for a in 1 2; do for b in 1 2; do N=$N$a$b; break 2; done; N=never; done";
+        run_line(&mut shell, line);
+        assert_eq!(shell.get_var("N"), Some("11"));
+        let line = "for a in 1 2; do for b in 1 2; do M=$M$a$b; continue 2; done; done";
+        run_line(&mut shell, line);
+        assert_eq!(shell.get_var("M"), Some("1121"));
+        assert_eq!(shell.control, None);
+        assert_eq!(run_line(&mut shell, "break"), 1);
+
+        // `for x` goes through the arguments.
+        shell.args = vec!["p".to_string(), "q r".to_string()];
+        run_line(&mut shell, "for x; do S=\"$S[$x]\"; done");
+        assert_eq!(shell.get_var("S"), Some("[p][q r]"));
+    }
+
+    #[test]
+    fn functions_have_arguments_locals_and_return() {
+        let mut shell = Shell::default();
+        let line = "f() {\n  local A=inner B\n  C=\"$1 $# $A\"\n  return 3\n  C=never\n}\nA=outer; B=b; f x y";
+        assert_eq!(run_line(&mut shell, line), 3);
+        assert_eq!(shell.get_var("C"), Some("x 2 inner"));
+        assert_eq!(shell.get_var("A"), Some("outer"));
+        assert_eq!(shell.get_var("B"), Some("b"));
+        assert!(shell.frames.is_empty() && shell.control.is_none());
+
+        // A function can call itself, but not without end.
+        run_line(
+            &mut shell,
+            "count() { D=$D.; if test $1 != 0; then count ${2}; fi; }",
+        );
+        run_line(&mut shell, "count 1 0");
+        assert_eq!(shell.get_var("D"), Some(".."));
+        run_line(&mut shell, "forever() { forever; }");
+        assert_eq!(run_line(&mut shell, "forever"), 1);
+
+        assert_eq!(run_line(&mut shell, "return"), 1);
+        assert_eq!(run_line(&mut shell, "local X"), 1);
+        // As a pipeline stage it runs in a copy of the shell.
+        assert_eq!(
+            run_line(&mut shell, "g() { E=1; echo hi; }; g | grep -q hi"),
+            0
+        );
+        assert_eq!(shell.get_var("E"), None);
+    }
+
+    #[test]
+    fn compound_commands_have_redirections_and_pipes() {
+        let path = std::env::temp_dir().join(format!("safe_shell_compound_{}", std::process::id()));
+        let path = path.to_str().unwrap();
+        let mut shell = Shell::default();
+        let line = format!(
+            "for x in a b; do echo $x; done > {}; {{ echo c; echo d >&2; }} 2>> {} > /dev/null",
+            path, path
+        );
+        assert_eq!(run_line(&mut shell, &line), 0);
+        assert_eq!(std::fs::read_to_string(path).unwrap(), "a\nb\nd\n");
+        std::fs::remove_file(path).unwrap();
+
+        assert_eq!(
+            run_line(&mut shell, "for x in a b; do echo $x; done | grep -q b"),
+            0
+        );
+        assert_eq!(run_line(&mut shell, "echo x | { grep -q y; }"), 1);
+    }
+
+    #[test]
+    fn errexit_leaves_conditions_alone() {
+        let mut shell = Shell::default();
+        shell.options.errexit = true;
+        assert_eq!(
+            run_line(
+                &mut shell,
+                "if false; then A=1; fi; while false; do :; done; B=2"
+            ),
+            0
+        );
+        assert_eq!(shell.get_var("B"), Some("2"));
+        assert_eq!(run_line(&mut shell, "if true; then false; fi; C=3"), 1);
+        assert_eq!(shell.exit, Some(1));
+    }
+
+    #[test]
+    fn command_substitution_captures_the_output() {
+        let mut shell = Shell::default();
+        let line = "A=\"$(printf 'a  b\\n\\n\\n')\"; B=$(cd /; echo $A | wc -w)";
+        assert_eq!(run_line(&mut shell, line), 0);
+        assert_eq!(shell.get_var("A"), Some("a  b"));
+        assert_eq!(shell.get_var("B"), Some("2"));
+        assert_eq!(run_line(&mut shell, "C=$(echo x; false)"), 1);
+        assert_eq!(shell.get_var("C"), Some("x"));
+
+        // More than a pipe holds.
+        assert_eq!(capture(&shell, "seq 1 100000").map(|s| s.len()), Ok(588894));
+
+        // A syntax error inside is an error of the expansion, which fails
+        // the command, like `$((1 / 0))` below.
+        let error = capture(&shell, "echo (x)").unwrap_err();
+        assert!(error.starts_with("syntax error: "), "{}", error);
+        assert_eq!(shell.last_substitution.get(), Some(2));
+        let word = lexer::Word {
+            parts: vec![lexer::WordPart::Command {
+                text: "echo (x)".to_string(),
+                quoted: false,
+            }],
+        };
+        assert_eq!(expand::expand_word(&word, &shell), Err(error));
+
+        assert_eq!(run_line(&mut shell, "D=$((1 / 0))"), 1);
+        assert_eq!(shell.get_var("D"), None);
+    }
+}
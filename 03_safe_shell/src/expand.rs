@@ -0,0 +1,436 @@
+// Expansion: turning the words of a command into the arguments it gets.
+//
+// The lexer leaves variables in the words (see `WordPart`). Just before a
+// command runs, each one is replaced by its value:
+//
+//     echo "$HOME" ${EDITOR:-vi}    with HOME=/home/ana, EDITOR unset
+//     echo /home/ana vi
+//
+// Some variables are special:
+//
+//     $?         the exit status of the last command
+//     $0         the name of the script
+//     $1 ... $9  its arguments (`${10}` for the tenth one, and so on)
+//     $#         how many arguments there are
+//     $@         all of them; `"$@"` is special, it gives each argument
+//                as a word of its own, spaces and all
+//
+// Quotes matter here. The value of an unquoted variable is split into
+// separate arguments at whitespace, and an empty one disappears entirely;
+// inside double quotes the value stays exactly one argument:
+//
+//     FILES="a b"     cat $FILES     ->  cat a b        (two arguments)
+//                     cat "$FILES"   ->  cat 'a b'      (one argument)
+//     EMPTY=          grep x $EMPTY  ->  grep x         (no argument)
+//                     grep x "$EMPTY" -> grep x ''      (empty argument)
+//
+// That is why scripts are told to "always quote your variables".
+//
+// Two more expansions happen to unquoted text:
+//
+//     ~/notes    ~ at the start of a word is the home directory ($HOME),
+//                and ~ana is the home directory of the user ana
+//     *.rs       a pattern is replaced by the files it matches (see
+//                `glob.rs`); one that matches nothing stays as it is,
+//                or is an error with `set -o failglob`
+//
+// `"~"` and `'*.rs'` are left alone, as is anything else in quotes.
+//
+// Commands can be expanded too. `$(command)` (or the older `` `command` ``)
+// runs the command and is replaced by what it wrote, minus the newlines at
+// the end; unquoted, that is split like a variable. `$((expression))` is
+// replaced by the value of an integer expression (see `arith.rs`):
+//
+//     echo "today is $(date +%A)"       today is Monday
+//     rm $(ls *.tmp)                    one argument per file
+//     echo $((6 * 7))                   42
+
+use std::borrow::Cow;
+use std::fs;
+
+use crate::arith;
+use crate::exec;
+use crate::glob;
+use crate::lexer::{Word, WordPart};
+use crate::shell::Shell;
+
+/// Expands `words` into arguments, splitting unquoted values and
+/// replacing patterns with the files they match. The error is for a
+/// pattern that matched nothing, with `set -o failglob`, or for a
+/// `$((expression))` that can't be worked out.
+pub fn expand_words(words: &[Word], shell: &Shell) -> Result<Vec<String>, String> {
+    let mut fields = Fields::new(true);
+    for word in words {
+        fields.word(word, shell);
+        fields.finish();
+    }
+    if let Some(error) = fields.error {
+        return Err(error);
+    }
+
+    let mut args = Vec::new();
+    for field in fields.done {
+        if !field.glob {
+            args.push(field.text);
+            continue;
+        }
+        let paths = glob::expand(&field.pattern);
+        if !paths.is_empty() {
+            args.extend(paths);
+        } else if shell.options.failglob {
+            return Err(format!("no match: {}", field.text));
+        } else {
+            args.push(field.text);
+        }
+    }
+    Ok(args)
+}
+
+/// Expands `word` into exactly one string, without splitting or patterns.
+/// Used where there can only be one: `NAME=$value`, `> $file` and
+/// `<<< $text`.
+pub fn expand_word(word: &Word, shell: &Shell) -> Result<String, String> {
+    let mut fields = Fields::new(false);
+    fields.word(word, shell);
+    match fields.error {
+        Some(error) => Err(error),
+        None => Ok(fields.current.map(|field| field.text).unwrap_or_default()),
+    }
+}
+
+/// Expands `word` into a pattern for `glob::matches`, in which only the
+/// unquoted `*`, `?` and `[` are special. Used for `case` patterns.
+pub fn expand_pattern(word: &Word, shell: &Shell) -> Result<String, String> {
+    let mut fields = Fields::new(false);
+    fields.word(word, shell);
+    match fields.error {
+        Some(error) => Err(error),
+        None => Ok(fields
+            .current
+            .map(|field| field.pattern)
+            .unwrap_or_default()),
+    }
+}
+
+/// Arguments being put together.
+struct Fields {
+    /// Whether unquoted values are split at whitespace.
+    split: bool,
+    done: Vec<Field>,
+    /// The argument being built. `None` means there is none yet, which is
+    /// different from an empty one: `""` is an argument, `$EMPTY` isn't.
+    current: Option<Field>,
+    /// The first thing that went wrong. The rest is still expanded, but
+    /// the result is thrown away.
+    error: Option<String>,
+}
+
+#[derive(Default)]
+struct Field {
+    text: String,
+    /// The same text as a pattern for `glob`, with every quoted character
+    /// escaped, so that only the unquoted ones are special.
+    pattern: String,
+    /// Whether an unquoted `*`, `?` or `[` made it in.
+    glob: bool,
+}
+
+impl Fields {
+    fn new(split: bool) -> Fields {
+        Fields {
+            split,
+            done: Vec::new(),
+            current: None,
+            error: None,
+        }
+    }
+
+    fn word(&mut self, word: &Word, shell: &Shell) {
+        let mut parts = &word.parts[..];
+        if let Some((home, rest)) = tilde(word, shell) {
+            // A home directory with spaces in it is still one argument.
+            self.push(&home, true);
+            self.push(rest, false);
+            parts = &parts[1..];
+        }
+        for part in parts {
+            self.expand(part, shell);
+        }
+    }
+
+    fn expand(&mut self, part: &WordPart, shell: &Shell) {
+        match part {
+            WordPart::Literal { text, quoted } => self.push(text, *quoted),
+            WordPart::Variable {
+                name,
+                default,
+                quoted,
+            } => {
+                if name == "@" && *quoted && self.split {
+                    // `"$@"`: one argument for each argument, and none at
+                    // all if there aren't any.
+                    for (i, arg) in shell.args.iter().enumerate() {
+                        if i > 0 {
+                            self.finish();
+                        }
+                        self.push(arg, true);
+                    }
+                    return;
+                }
+                let value = lookup(shell, name);
+                match (value.as_deref(), default) {
+                    // `${NAME:-default}` uses the default when the variable
+                    // is unset *or* empty. The default is a word of its own,
+                    // with its own quoting.
+                    (None | Some(""), Some(default)) => self.word(default, shell),
+                    (value, _) => self.push(value.unwrap_or_default(), *quoted),
+                }
+            }
+            WordPart::Command { text, quoted } => match exec::capture(shell, text) {
+                Ok(output) => self.push(&output, *quoted),
+                Err(e) => {
+                    self.error.get_or_insert(e);
+                }
+            },
+            WordPart::Arithmetic { expression, quoted } => {
+                let value = expand_word(expression, shell).and_then(|text| {
+                    arith::evaluate(&text, shell).map_err(|e| format!("{}: {}", text.trim(), e))
+                });
+                match value {
+                    Ok(value) => self.push(&value.to_string(), *quoted),
+                    Err(e) => {
+                        self.error.get_or_insert(e);
+                    }
+                }
+            }
+        }
+    }
+
+    fn push(&mut self, text: &str, quoted: bool) {
+        if quoted || !self.split {
+            self.current.get_or_insert_default().push(text, quoted);
+            return;
+        }
+        // Every run of whitespace ends the argument being built. Text
+        // before the first one still belongs to it: in `a$X` with X="b c",
+        // the arguments are `ab` and `c`.
+        for (i, piece) in text.split([' ', '\t', '\n']).enumerate() {
+            if i > 0 {
+                self.finish();
+            }
+            if !piece.is_empty() {
+                self.current.get_or_insert_default().push(piece, false);
+            }
+        }
+    }
+
+    fn finish(&mut self) {
+        if let Some(field) = self.current.take() {
+            self.done.push(field);
+        }
+    }
+}
+
+impl Field {
+    fn push(&mut self, text: &str, quoted: bool) {
+        self.text.push_str(text);
+        for c in text.chars() {
+            let special = matches!(c, '*' | '?' | '[');
+            if c == '\\' || (quoted && (special || c == ']')) {
+                self.pattern.push('\\');
+            } else if special {
+                self.glob = true;
+            }
+            self.pattern.push(c);
+        }
+    }
+}
+
+/// The value of variable `name`, special ones included.
+fn lookup<'a>(shell: &'a Shell, name: &str) -> Option<Cow<'a, str>> {
+    match name {
+        "?" => Some(Cow::Owned(shell.last_status.to_string())),
+        "#" => Some(Cow::Owned(shell.args.len().to_string())),
+        "@" => Some(Cow::Owned(shell.args.join(" "))),
+        "0" => Some(Cow::Borrowed(&shell.name)),
+        _ if name.starts_with(|c: char| c.is_ascii_digit()) => {
+            let n: usize = name.parse().ok()?;
+            let arg = shell.args.get(n.checked_sub(1)?)?;
+            Some(Cow::Borrowed(arg))
+        }
+        _ => shell.get_var(name).map(Cow::Borrowed),
+    }
+}
+
+/// `~` or `~user` at the start of `word`, if it is written plainly: the
+/// home directory, and the rest of the word's first part.
+fn tilde<'a>(word: &'a Word, shell: &Shell) -> Option<(String, &'a str)> {
+    let Some(WordPart::Literal {
+        text,
+        quoted: false,
+    }) = word.parts.first()
+    else {
+        return None;
+    };
+    let rest = text.strip_prefix('~')?;
+    // The user name goes up to the first `/`, and must be plain text:
+    // `~$USER` is not expanded.
+    let (user, rest) = match rest.find('/') {
+        Some(slash) => rest.split_at(slash),
+        None if word.parts.len() == 1 => (rest, ""),
+        None => return None,
+    };
+    let home = if user.is_empty() {
+        shell.get_var("HOME")?.to_string()
+    } else {
+        home_of(user)?
+    };
+    Some((home, rest))
+}
+
+/// The home directory of `user`, from `/etc/passwd`. Lines there look like
+/// `ana:x:1000:1000:Ana:/home/ana:/bin/bash`.
+fn home_of(user: &str) -> Option<String> {
+    let passwd = fs::read_to_string("/etc/passwd").ok()?;
+    passwd.lines().find_map(|line| {
+        let fields: Vec<&str> = line.split(':').collect();
+        (fields.len() >= 6 && fields[0] == user).then(|| fields[5].to_string())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::{TokenKind, tokenize};
+
+    fn words(line: &str) -> Vec<Word> {
+        tokenize(line)
+            .unwrap()
+            .into_iter()
+            .filter_map(|token| match token.kind {
+                TokenKind::Word(word) => Some(word),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn expand(line: &str, shell: &Shell) -> Vec<String> {
+        expand_words(&words(line), shell).unwrap()
+    }
+
+    #[test]
+    fn splits_unquoted_values_only() {
+        let mut shell = Shell::default();
+        shell.set_var("FILES", " a  b ".to_string());
+        shell.set_var("EMPTY", String::new());
+        assert_eq!(expand("cat $FILES", &shell), ["cat", "a", "b"]);
+        assert_eq!(expand("cat \"$FILES\"", &shell), ["cat", " a  b "]);
+        assert_eq!(expand("x$FILES'y'", &shell), ["x", "a", "b", "y"]);
+        assert_eq!(expand("x${FILES}y", &shell), expand("x a b y", &shell));
+        assert_eq!(expand("grep $EMPTY $UNSET x", &shell), ["grep", "x"]);
+        assert_eq!(expand("grep \"$EMPTY\" x", &shell), ["grep", "", "x"]);
+        assert_eq!(
+            expand("echo '$FILES' \\$FILES", &shell),
+            ["echo", "$FILES", "$FILES"]
+        );
+    }
+
+    #[test]
+    fn positional_parameters_and_quoted_at() {
+        let mut shell = Shell {
+            name: "script.sh".to_string(),
+            args: vec!["a b".to_string(), "".to_string(), "c".to_string()],
+            ..Shell::default()
+        };
+        assert_eq!(
+            expand("$0 $# $1 $3 $4", &shell),
+            ["script.sh", "3", "a", "b", "c"]
+        );
+        assert_eq!(expand("x\"$@\"y", &shell), ["xa b", "", "cy"]);
+        assert_eq!(expand("$@", &shell), ["a", "b", "c"]);
+        assert_eq!(expand_word(&words("\"$@\"")[0], &shell).unwrap(), "a b  c");
+        shell.args.clear();
+        assert_eq!(expand("cmd \"$@\"", &shell), ["cmd"]);
+    }
+
+    #[test]
+    fn defaults_apply_to_unset_and_empty_variables() {
+        let mut shell = Shell::default();
+        shell.set_var("EMPTY", String::new());
+        shell.set_var("NAME", "ana".to_string());
+        assert_eq!(
+            expand(
+                "${NAME:-x} ${EMPTY:-y} ${UNSET:-a b} \"${UNSET:-a b}\"",
+                &shell
+            ),
+            ["ana", "y", "a", "b", "a b"]
+        );
+        assert_eq!(expand("${UNSET:-$NAME}${NAME}s", &shell), ["anaanas"]);
+
+        // Without splitting, the value stays as it is.
+        let word = &words("${UNSET:-a  $NAME}")[0];
+        assert_eq!(expand_word(word, &shell).unwrap(), "a  ana");
+    }
+
+    #[test]
+    fn tilde_is_the_home_directory_when_unquoted() {
+        let mut shell = Shell::default();
+        shell.set_var("HOME", "/home/my self".to_string());
+        assert_eq!(
+            expand("~ ~/notes '~' \\~ ~\"/x\" a~ ~root", &shell),
+            [
+                "/home/my self",
+                "/home/my self/notes",
+                "~",
+                "~",
+                "~/x",
+                "a~",
+                "/root"
+            ]
+        );
+        assert_eq!(
+            expand_word(&words("~/out")[0], &shell).unwrap(),
+            "/home/my self/out"
+        );
+        assert_eq!(expand("~no_such_user_here", &shell), ["~no_such_user_here"]);
+    }
+
+    #[test]
+    fn patterns_match_files_unless_quoted() {
+        let dir = std::env::temp_dir().join(format!("safe_shell_expand_{}", std::process::id()));
+        let dir = dir.to_str().unwrap();
+        fs::create_dir_all(dir).unwrap();
+        for file in ["a.rs", "b.rs", "*.rs"] {
+            fs::write(format!("{}/{}", dir, file), "").unwrap();
+        }
+        let mut shell = Shell::default();
+        shell.set_var("DIR", dir.to_string());
+        shell.set_var("PATTERN", "?.rs".to_string());
+        let names = |line: &str, shell: &Shell| -> Result<Vec<String>, String> {
+            let args = expand_words(&words(line), shell)?;
+            Ok(args.iter().map(|arg| arg.replace(dir, "")).collect())
+        };
+
+        assert_eq!(
+            names("$DIR/*.rs", &shell).unwrap(),
+            ["/*.rs", "/a.rs", "/b.rs"]
+        );
+        assert_eq!(
+            names("$DIR/$PATTERN", &shell).unwrap(),
+            ["/*.rs", "/a.rs", "/b.rs"]
+        );
+        assert_eq!(names("\"$DIR\"/[a]*", &shell).unwrap(), ["/a.rs"]);
+        assert_eq!(
+            names("\"$DIR/*.rs\" $DIR/'*'.rs", &shell).unwrap(),
+            ["/*.rs", "/*.rs"]
+        );
+        assert_eq!(names("$DIR/*.txt", &shell).unwrap(), ["/*.txt"]);
+        shell.options.failglob = true;
+        assert!(
+            names("$DIR/*.txt", &shell)
+                .unwrap_err()
+                .starts_with("no match: ")
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
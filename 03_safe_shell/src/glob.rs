@@ -0,0 +1,276 @@
+// Filename patterns ("globs").
+//
+// In `ls *.rs` it is the shell, not `ls`, that finds the files: `ls` gets
+// `lexer.rs main.rs parser.rs`, already sorted. The patterns are:
+//
+//     *        any run of characters, even none
+//     ?        any one character
+//     [abc]    one of the characters; `[a-z]` is a range and `[!abc]`
+//              (or `[^abc]`) anything else
+//     **       as a whole path component: any number of directories,
+//              so `src/**/*.rs` finds `.rs` files at any depth
+//     \x       the character x itself, even if it is one of the above
+//
+// A `*` or `?` never matches a `/`, and never matches a leading `.`:
+// `*` doesn't list hidden files, `.*` does.
+//
+// Matching is done one path component at a time, reading each directory
+// only when the pattern needs it.
+
+use std::fs;
+
+/// Whether `pattern` contains anything that makes it a pattern rather than
+/// a plain name.
+pub fn has_magic(pattern: &str) -> bool {
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                chars.next();
+            }
+            '*' | '?' | '[' => return true,
+            _ => {}
+        }
+    }
+    false
+}
+
+/// The paths matching `pattern`, sorted. Empty if nothing matches.
+pub fn expand(pattern: &str) -> Vec<String> {
+    let (mut paths, rest) = match pattern.strip_prefix('/') {
+        Some(rest) => (vec!["/".to_string()], rest),
+        None => (vec![String::new()], pattern),
+    };
+    let components: Vec<&str> = rest.split('/').filter(|c| !c.is_empty()).collect();
+
+    for (i, component) in components.iter().enumerate() {
+        let is_last = i + 1 == components.len();
+        let mut next = Vec::new();
+        for base in &paths {
+            if *component == "**" {
+                // `**` is the directory itself and everything below it. At
+                // the end of the pattern, files count too.
+                next.push(base.clone());
+                walk(base, is_last, &mut next);
+            } else if !has_magic(component) {
+                // Plain names are just added: whether they exist is
+                // checked once, at the end.
+                next.push(join(base, &unescape(component)));
+            } else {
+                for name in read_dir(base) {
+                    let hidden_ok = component.starts_with('.') || component.starts_with("\\.");
+                    if (hidden_ok || !name.starts_with('.')) && matches(component, &name) {
+                        next.push(join(base, &name));
+                    }
+                }
+            }
+        }
+        paths = next;
+    }
+
+    // `dir/` only matches directories.
+    let dirs_only = pattern.ends_with('/') && !components.is_empty();
+    paths.retain(|path| match fs::metadata(path) {
+        Ok(meta) => meta.is_dir() || !dirs_only,
+        // A dangling symlink still exists, as far as `ls` is concerned.
+        Err(_) => !dirs_only && fs::symlink_metadata(path).is_ok(),
+    });
+    if dirs_only {
+        for path in &mut paths {
+            path.push('/');
+        }
+    }
+    paths.sort();
+    paths.dedup();
+    paths
+}
+
+/// Whether `name` matches `pattern` (with no `/` in either).
+pub fn matches(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+
+    // Walk both at once. At a `*` we first try matching nothing, and
+    // remember where we were: when a later character doesn't match, the
+    // `*` takes one more character and we try again from there.
+    let (mut p, mut n) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+    while n < name.len() {
+        // How far the pattern moves if this character matches.
+        let step = match pattern.get(p) {
+            Some('*') => {
+                star = Some((p + 1, n));
+                p += 1;
+                continue;
+            }
+            Some('?') => Some(1),
+            Some('[') => match class(&pattern[p + 1..], name[n]) {
+                Some((true, len)) => Some(len + 1),
+                Some((false, _)) => None,
+                // `[` with no `]` is just a bracket.
+                None => (name[n] == '[').then_some(1),
+            },
+            Some('\\') if p + 1 < pattern.len() => (pattern[p + 1] == name[n]).then_some(2),
+            Some(&c) => (c == name[n]).then_some(1),
+            None => None,
+        };
+        match (step, star) {
+            (Some(len), _) => {
+                p += len;
+                n += 1;
+            }
+            (None, Some((after_star, from))) => {
+                p = after_star;
+                n = from + 1;
+                star = Some((after_star, from + 1));
+            }
+            (None, None) => return false,
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Matches `c` against the class starting right after a `[`. Returns
+/// whether it matched and how much of `rest` the class takes, its `]`
+/// included, or `None` if the class is never closed.
+fn class(rest: &[char], c: char) -> Option<(bool, usize)> {
+    let negated = matches!(rest.first(), Some('!' | '^'));
+    let mut i = negated as usize;
+    let mut matched = false;
+    // A `]` first in the class is a member, not the end: `[]]`.
+    let mut first = true;
+    loop {
+        let mut low = *rest.get(i)?;
+        if low == ']' && !first {
+            return Some((matched != negated, i + 1));
+        }
+        first = false;
+        if low == '\\' {
+            i += 1;
+            low = *rest.get(i)?;
+        }
+        i += 1;
+        let mut high = low;
+        if rest.get(i) == Some(&'-') && rest.get(i + 1).is_some_and(|&c| c != ']') {
+            high = rest[i + 1];
+            i += 2;
+        }
+        if low <= c && c <= high {
+            matched = true;
+        }
+    }
+}
+
+/// `pattern` with its backslashes removed.
+fn unescape(pattern: &str) -> String {
+    let mut text = String::new();
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        text.push(if c == '\\' {
+            chars.next().unwrap_or(c)
+        } else {
+            c
+        });
+    }
+    text
+}
+
+fn join(base: &str, name: &str) -> String {
+    match base {
+        "" => name.to_string(),
+        "/" => format!("/{}", name),
+        _ => format!("{}/{}", base, name),
+    }
+}
+
+/// The names in directory `base` (the current one if empty), sorted.
+/// Unreadable directories simply have no names.
+fn read_dir(base: &str) -> Vec<String> {
+    let dir = if base.is_empty() { "." } else { base };
+    let mut names: Vec<String> = fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .collect();
+    names.sort();
+    names
+}
+
+/// Adds everything below `base` that isn't hidden: directories, and files
+/// too if `files`. Symlinks to directories are not followed, so a link
+/// loop can't make this run forever.
+fn walk(base: &str, files: bool, paths: &mut Vec<String>) {
+    for name in read_dir(base) {
+        if name.starts_with('.') {
+            continue;
+        }
+        let path = join(base, &name);
+        let is_dir = fs::symlink_metadata(&path).is_ok_and(|meta| meta.is_dir());
+        if is_dir || files {
+            paths.push(path.clone());
+        }
+        if is_dir {
+            walk(&path, files, paths);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_stars_questions_and_classes() {
+        assert!(matches("*.rs", "main.rs"));
+        assert!(matches("*.rs", ".rs"));
+        assert!(!matches("*.rs", "main.rsx"));
+        assert!(matches("a*b*c", "aXXbYbc"));
+        assert!(matches("?at", "cat"));
+        assert!(!matches("?at", "at"));
+        assert!(matches("[ch]at", "hat"));
+        assert!(matches("[a-c]at", "bat"));
+        assert!(!matches("[!a-c]at", "bat"));
+        assert!(matches("[]]", "]"));
+        assert!(matches("[", "["));
+        assert!(matches("\\*", "*"));
+        assert!(!matches("\\*", "x"));
+    }
+
+    #[test]
+    fn expands_paths_component_by_component() {
+        let root = std::env::temp_dir().join(format!("safe_shell_glob_{}", std::process::id()));
+        let root = root.to_str().unwrap();
+        for dir in ["src/lexer", "src/.hidden", "docs"] {
+            fs::create_dir_all(format!("{}/{}", root, dir)).unwrap();
+        }
+        for file in [
+            "src/main.rs",
+            "src/lexer/mod.rs",
+            "src/.hidden/x.rs",
+            "a.txt",
+            ".b.txt",
+        ] {
+            fs::write(format!("{}/{}", root, file), "").unwrap();
+        }
+        let glob = |pattern: &str| -> Vec<String> {
+            expand(&format!("{}/{}", root, pattern))
+                .into_iter()
+                .map(|path| path[root.len() + 1..].to_string())
+                .collect()
+        };
+
+        assert_eq!(glob("*.txt"), ["a.txt"]);
+        assert_eq!(glob(".*.txt"), [".b.txt"]);
+        assert_eq!(glob("*/"), ["docs/", "src/"]);
+        assert_eq!(glob("src/*.rs"), ["src/main.rs"]);
+        assert_eq!(glob("**/*.rs"), ["src/lexer/mod.rs", "src/main.rs"]);
+        assert_eq!(
+            glob("src/**"),
+            ["src", "src/lexer", "src/lexer/mod.rs", "src/main.rs"]
+        );
+        assert!(glob("*.none").is_empty());
+        assert!(glob("nothing/*").is_empty());
+        fs::remove_dir_all(root).unwrap();
+    }
+}
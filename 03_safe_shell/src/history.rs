@@ -0,0 +1,234 @@
+// Command history.
+//
+// Every line you type is remembered, so that you can get it back with the
+// up arrow (see `editor.rs`), list it with `history`, or run it again with
+// history expansion:
+//
+//     !!      the previous command       sudo !!
+//     !n      command number n           !42
+//     !-n     the n-th previous command  !-2
+//
+// A `!` in single quotes, after a backslash, or followed by a space, `=`,
+// `(` or the end of the line is just a `!`.
+//
+// The history is kept in a file between sessions, `~/.safe_shell_history`
+// unless `$HISTFILE` says otherwise, and holds at most `$HISTSIZE`
+// commands (1000 by default). A command that is typed again moves to the
+// end instead of being there twice, and a line that starts with a space is
+// not remembered at all: a handy way to keep a secret out of the file.
+
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::PathBuf;
+
+#[derive(Debug, Clone)]
+pub struct History {
+    entries: Vec<String>,
+    /// Where the history is saved. `None` keeps it in memory only.
+    path: Option<PathBuf>,
+    /// The most commands kept, in memory and in the file.
+    limit: usize,
+}
+
+impl Default for History {
+    fn default() -> History {
+        History {
+            entries: Vec::new(),
+            path: None,
+            limit: History::DEFAULT_LIMIT,
+        }
+    }
+}
+
+impl History {
+    pub const DEFAULT_LIMIT: usize = 1000;
+
+    /// The history saved in `path`. A file that doesn't exist yet is an
+    /// empty history.
+    pub fn load(path: PathBuf, limit: usize) -> io::Result<History> {
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e),
+        };
+        let mut history = History {
+            entries: Vec::new(),
+            path: Some(path),
+            limit,
+        };
+        for line in text.lines() {
+            history.push(line);
+        }
+        Ok(history)
+    }
+
+    /// The commands, oldest first. Command number n is `entries()[n - 1]`.
+    pub fn entries(&self) -> &[String] {
+        &self.entries
+    }
+
+    /// Remembers `line`, and saves the history. Blank lines and lines that
+    /// start with a space are left out.
+    pub fn add(&mut self, line: &str) -> io::Result<()> {
+        let line = line.trim_end_matches(['\n', '\r']);
+        if line.trim().is_empty() || line.starts_with(' ') {
+            return Ok(());
+        }
+        self.push(line);
+        self.save()
+    }
+
+    /// `history -c`: forgets everything, in the file too.
+    pub fn clear(&mut self) -> io::Result<()> {
+        self.entries.clear();
+        self.save()
+    }
+
+    fn push(&mut self, line: &str) {
+        self.entries.retain(|entry| entry != line);
+        self.entries.push(line.to_string());
+        if self.entries.len() > self.limit {
+            let extra = self.entries.len() - self.limit;
+            self.entries.drain(..extra);
+        }
+    }
+
+    /// Writes the whole history. It goes to a new file first, which then
+    /// replaces the old one: a crash halfway can't leave half a history.
+    /// Only the user can read it, since commands can have secrets in them.
+    fn save(&self) -> io::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let mut text = String::new();
+        for entry in &self.entries {
+            text.push_str(entry);
+            text.push('\n');
+        }
+        let new = path.with_extension("new");
+        // The mode only applies to a file that is created, so one left
+        // over from a crash goes first.
+        let _ = fs::remove_file(&new);
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(&new)?;
+        file.write_all(text.as_bytes())?;
+        fs::rename(&new, path)
+    }
+
+    /// Replaces `!!`, `!n` and `!-n` in `line`. Returns `None` if there
+    /// was nothing to replace, and an error for a command that isn't in
+    /// the history.
+    pub fn expand(&self, line: &str) -> Result<Option<String>, String> {
+        let mut result = String::new();
+        let mut changed = false;
+        // Only single quotes protect a `!`; double quotes don't.
+        let (mut in_quotes, mut in_double_quotes) = (false, false);
+        let mut chars = line.char_indices();
+        while let Some((at, c)) = chars.next() {
+            match c {
+                '\'' if !in_double_quotes => in_quotes = !in_quotes,
+                '"' if !in_quotes => in_double_quotes = !in_double_quotes,
+                // `\!` stays as it is; the lexer removes the backslash.
+                '\\' if !in_quotes => {
+                    result.push(c);
+                    if let Some((_, next)) = chars.next() {
+                        result.push(next);
+                    }
+                    continue;
+                }
+                '!' if !in_quotes => {
+                    let rest = &line[at + 1..];
+                    // How many characters of `rest` name the command.
+                    let digits = |s: &str| s.chars().take_while(char::is_ascii_digit).count();
+                    let (entry, len) = if rest.starts_with('!') {
+                        (self.entries.last(), 1)
+                    } else if let Some(number) = rest.strip_prefix('-') {
+                        let len = digits(number);
+                        let back: usize = number[..len].parse().unwrap_or(0);
+                        let entry = self.entries.len().checked_sub(back).filter(|_| back > 0);
+                        (entry.and_then(|i| self.entries.get(i)), len + 1)
+                    } else if digits(rest) > 0 {
+                        let len = digits(rest);
+                        let number: usize = rest[..len].parse().unwrap_or(0);
+                        (number.checked_sub(1).and_then(|i| self.entries.get(i)), len)
+                    } else {
+                        // `!` followed by anything else is not an event.
+                        result.push(c);
+                        continue;
+                    };
+                    match entry {
+                        Some(entry) => result.push_str(entry),
+                        None => return Err(format!("!{}: event not found", &rest[..len])),
+                    }
+                    // Skip what we just replaced.
+                    for _ in 0..len {
+                        chars.next();
+                    }
+                    changed = true;
+                    continue;
+                }
+                _ => {}
+            }
+            result.push(c);
+        }
+        Ok(changed.then_some(result))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    fn history(lines: &[&str]) -> History {
+        let mut history = History::default();
+        for line in lines {
+            history.add(line).unwrap();
+        }
+        history
+    }
+
+    #[test]
+    fn remembers_commands_once_and_up_to_the_limit() {
+        let mut history = history(&["ls", "  ", " secret", "pwd\n", "ls"]);
+        assert_eq!(history.entries(), ["pwd", "ls"]);
+        history.limit = 2;
+        history.add("cd").unwrap();
+        assert_eq!(history.entries(), ["ls", "cd"]);
+    }
+
+    #[test]
+    fn saves_to_and_loads_from_a_file() {
+        let path = std::env::temp_dir().join(format!("safe_shell_history_{}", std::process::id()));
+        let mut saved = History::load(path.clone(), 3).unwrap();
+        for line in ["a", "b", "c", "d"] {
+            saved.add(line).unwrap();
+        }
+        let loaded = History::load(path.clone(), 3).unwrap();
+        assert_eq!(loaded.entries(), ["b", "c", "d"]);
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn expands_bang_events() {
+        let history = history(&["ls -l", "echo hi", "pwd"]);
+        let expand = |line: &str| history.expand(line);
+        assert_eq!(expand("sudo !!"), Ok(Some("sudo pwd".to_string())));
+        assert_eq!(expand("!1 | wc"), Ok(Some("ls -l | wc".to_string())));
+        assert_eq!(expand("!-2!-1"), Ok(Some("echo hipwd".to_string())));
+        assert_eq!(expand("echo hi! '!!' \\!! a != b"), Ok(None));
+        assert_eq!(
+            expand("echo \"it's !!\""),
+            Ok(Some("echo \"it's pwd\"".to_string()))
+        );
+        assert_eq!(expand("!4"), Err("!4: event not found".to_string()));
+        assert!(expand("!-0").is_err());
+        assert!(History::default().expand("!!").is_err());
+    }
+}
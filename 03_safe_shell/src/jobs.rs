@@ -0,0 +1,474 @@
+// Jobs: pipelines the shell has started and not forgotten yet.
+//
+// A pipeline normally runs in the foreground: the shell waits for it, and
+// it owns the terminal, so that Ctrl-C and Ctrl-Z go to it and not to the
+// shell. With `&` at the end it runs in the background instead, and the
+// shell prompts again right away.
+//
+//     > sleep 60 &
+//     [1] 4242
+//     > vim notes.txt          (Ctrl-Z)
+//     [2]+  Stopped                 vim notes.txt
+//     > jobs
+//     [1]-  Running                 sleep 60 &
+//     [2]+  Stopped                 vim notes.txt
+//     > fg                     back to vim
+//
+// The kernel makes this work with process groups. Every job gets a group
+// of its own, and the terminal has one foreground group: only that group
+// may read from it, and it gets the signals for Ctrl-C and Ctrl-Z.
+//
+// Finished background jobs are reported before the next prompt.
+
+use std::fmt;
+use std::io::{self, IsTerminal, Write};
+use std::os::fd::{AsFd, IntoRawFd, RawFd};
+use std::os::unix::process::ExitStatusExt;
+use std::thread::JoinHandle;
+
+use crate::exec::exit_code;
+use crate::shell::Shell;
+use crate::sys;
+
+/// The job table. A pipeline stage's copy of the shell (see `shell.rs`)
+/// gets an empty one, like a subshell.
+#[derive(Debug, Default)]
+pub struct Jobs {
+    list: Vec<Job>,
+}
+
+impl Clone for Jobs {
+    fn clone(&self) -> Jobs {
+        Jobs::default()
+    }
+}
+
+/// The terminal the shell controls, when it runs interactively.
+#[derive(Debug, Clone, Copy)]
+pub struct Terminal {
+    pub fd: RawFd,
+    /// The shell's own process group.
+    pub pgid: i32,
+}
+
+/// Takes control of the terminal, if standard input is one. Returns `None`
+/// otherwise (input from a pipe or a file), or if that fails.
+pub fn take_terminal() -> Option<Terminal> {
+    let stdin = io::stdin();
+    if !stdin.is_terminal() {
+        return None;
+    }
+    // Our own copy of the terminal, which stays open even in a child whose
+    // standard input was replaced by a pipe (see `exec.rs`).
+    let fd = stdin.as_fd().try_clone_to_owned().ok()?.into_raw_fd();
+    let pgid = match sys::take_terminal(fd) {
+        Ok(pgid) => pgid,
+        Err(e) => {
+            eprintln!("safe_shell: no job control: {}", e);
+            return None;
+        }
+    };
+    sys::ignore_job_signals();
+    Some(Terminal { fd, pgid })
+}
+
+#[derive(Debug)]
+pub struct Job {
+    /// The number in `[1]` and `%1`. 0 until the job is in the table.
+    pub id: usize,
+    /// `None` if no process was started, only builtins.
+    pub pgid: Option<i32>,
+    /// The command line, for `jobs`.
+    pub text: String,
+    pub stages: Vec<Stage>,
+}
+
+/// One stage of a job.
+#[derive(Debug)]
+pub struct Stage {
+    kind: StageKind,
+    state: State,
+}
+
+#[derive(Debug)]
+enum StageKind {
+    Process(i32),
+    /// A builtin on a thread of the shell. The handle is gone once the
+    /// thread has been joined.
+    Builtin(Option<JoinHandle<i32>>),
+    /// Nothing was started: the command failed early, or was only
+    /// redirections.
+    Nothing,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum State {
+    Running,
+    Stopped,
+    /// Finished, with this exit status.
+    Done(i32),
+}
+
+impl Stage {
+    pub fn process(pid: i32) -> Stage {
+        Stage {
+            kind: StageKind::Process(pid),
+            state: State::Running,
+        }
+    }
+
+    pub fn builtin(thread: JoinHandle<i32>) -> Stage {
+        Stage {
+            kind: StageKind::Builtin(Some(thread)),
+            state: State::Running,
+        }
+    }
+
+    pub fn done(status: i32) -> Stage {
+        Stage {
+            kind: StageKind::Nothing,
+            state: State::Done(status),
+        }
+    }
+
+    pub fn pid(&self) -> Option<i32> {
+        match self.kind {
+            StageKind::Process(pid) => Some(pid),
+            _ => None,
+        }
+    }
+}
+
+impl Job {
+    pub fn new(text: String, pgid: Option<i32>, stages: Vec<Stage>) -> Job {
+        Job {
+            id: 0,
+            pgid,
+            text,
+            stages,
+        }
+    }
+
+    /// Stopped if any stage is, done once all of them are.
+    pub fn state(&self, pipefail: bool) -> State {
+        if self
+            .stages
+            .iter()
+            .any(|stage| stage.state == State::Stopped)
+        {
+            return State::Stopped;
+        }
+        let mut statuses = Vec::new();
+        for stage in &self.stages {
+            match stage.state {
+                State::Done(status) => statuses.push(status),
+                _ => return State::Running,
+            }
+        }
+        // Like in `exec.rs`: the last stage's status, or with pipefail the
+        // last one that failed.
+        let status = if pipefail {
+            statuses.iter().rev().find(|&&s| s != 0).copied()
+        } else {
+            None
+        };
+        State::Done(status.or(statuses.last().copied()).unwrap_or(0))
+    }
+
+    /// Collects what happened to the job's processes. With `block`, waits
+    /// until the job has finished or stopped.
+    pub fn update(&mut self, block: bool) {
+        for stage in &mut self.stages {
+            let StageKind::Process(pid) = stage.kind else {
+                continue;
+            };
+            // A stopped process only changes again when we continue it.
+            while matches!(stage.state, State::Running | State::Stopped) {
+                let status = match sys::wait(pid, block && stage.state == State::Running) {
+                    Ok(Some(status)) => status,
+                    Ok(None) => break,
+                    Err(e) => {
+                        eprintln!("Failed to wait on child: {}", e);
+                        stage.state = State::Done(1);
+                        break;
+                    }
+                };
+                stage.state = if status.stopped_signal().is_some() {
+                    State::Stopped
+                } else if status.continued() {
+                    State::Running
+                } else {
+                    State::Done(exit_code(status))
+                };
+                if !block || stage.state == State::Stopped {
+                    break;
+                }
+            }
+        }
+
+        // A stopped job keeps its builtins' threads: one may be blocked
+        // writing to a stopped process, and joining it would hang.
+        if self
+            .stages
+            .iter()
+            .any(|stage| stage.state == State::Stopped)
+        {
+            return;
+        }
+        for stage in &mut self.stages {
+            if let StageKind::Builtin(thread) = &mut stage.kind
+                && thread.as_ref().is_some_and(|t| block || t.is_finished())
+                && let Some(thread) = thread.take()
+            {
+                // A builtin that panicked has failed, but the shell
+                // carries on.
+                stage.state = State::Done(thread.join().unwrap_or(1));
+            }
+        }
+    }
+
+    /// Lets stopped processes go on.
+    fn resume(&mut self) {
+        if let Some(pgid) = self.pgid
+            && let Err(e) = sys::signal_group(pgid, sys::SIGCONT)
+        {
+            eprintln!("safe_shell: cannot continue job {}: {}", self.id, e);
+        }
+        for stage in &mut self.stages {
+            if stage.state == State::Stopped {
+                stage.state = State::Running;
+            }
+        }
+    }
+}
+
+impl Jobs {
+    /// Adds `job` to the table, and returns its number.
+    pub fn add(&mut self, mut job: Job) -> usize {
+        let id = self.list.iter().map(|job| job.id).max().unwrap_or(0) + 1;
+        job.id = id;
+        self.list.push(job);
+        id
+    }
+
+    pub fn len(&self) -> usize {
+        self.list.len()
+    }
+
+    /// Finds the job for `spec`: `%2` or `2`, or `%+`/`%%` for the current
+    /// job (the newest) and `%-` for the one before. No `spec` is the
+    /// current job.
+    pub fn find(&self, spec: Option<&str>) -> Result<usize, String> {
+        let current = self.list.len().checked_sub(1);
+        let index = match spec.map(|spec| spec.strip_prefix('%').unwrap_or(spec)) {
+            None | Some("" | "+" | "%") => current,
+            Some("-") => self.list.len().checked_sub(2).or(current),
+            Some(number) => number
+                .parse::<usize>()
+                .ok()
+                .and_then(|id| self.list.iter().position(|job| job.id == id)),
+        };
+        match (index, spec) {
+            (Some(index), _) => Ok(index),
+            (None, None) => Err("no current job".to_string()),
+            (None, Some(spec)) => Err(format!("{}: no such job", spec)),
+        }
+    }
+
+    pub fn remove(&mut self, index: usize) -> Job {
+        self.list.remove(index)
+    }
+
+    /// Collects what happened to the jobs in the background. Finished
+    /// ones leave the table, and are returned to be reported.
+    pub fn reap(&mut self, pipefail: bool) -> Vec<String> {
+        for job in &mut self.list {
+            job.update(false);
+        }
+        let mut report = Vec::new();
+        let count = self.list.len();
+        let mut index = 0;
+        while index < self.list.len() {
+            let job = &self.list[index];
+            if let State::Done(_) = job.state(pipefail) {
+                report.push(self.describe(index, count, pipefail));
+                self.list.remove(index);
+            } else {
+                index += 1;
+            }
+        }
+        report
+    }
+
+    /// `jobs`: a line for every job.
+    pub fn listing(&self, pipefail: bool) -> Vec<String> {
+        (0..self.list.len())
+            .map(|index| self.describe(index, self.list.len(), pipefail))
+            .collect()
+    }
+
+    /// `[1]+  Running                 sleep 60 &`. `+` marks the current
+    /// job and `-` the one before, out of `count`.
+    fn describe(&self, index: usize, count: usize, pipefail: bool) -> String {
+        let job = &self.list[index];
+        let marker = if index + 1 == count {
+            '+'
+        } else if index + 2 == count {
+            '-'
+        } else {
+            ' '
+        };
+        format!(
+            "[{}]{}  {:<24}{}",
+            job.id,
+            marker,
+            job.state(pipefail).to_string(),
+            job.text
+        )
+    }
+}
+
+impl fmt::Display for State {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            State::Running => write!(f, "Running"),
+            State::Stopped => write!(f, "Stopped"),
+            State::Done(0) => write!(f, "Done"),
+            State::Done(status) if status > 128 => match status - 128 {
+                sys::SIGHUP => write!(f, "Hangup"),
+                sys::SIGINT => write!(f, "Interrupt"),
+                sys::SIGKILL => write!(f, "Killed"),
+                sys::SIGTERM => write!(f, "Terminated"),
+                signal => write!(f, "Signal {}", signal),
+            },
+            State::Done(status) => write!(f, "Exit {}", status),
+        }
+    }
+}
+
+/// Runs `job` in the foreground: gives it the terminal, waits until it
+/// finishes or is stopped, and takes the terminal back. A stopped job goes
+/// (back) into the table. Returns the exit status, 128 + SIGTSTP if the
+/// job was stopped.
+pub fn foreground(shell: &mut Shell, mut job: Job) -> i32 {
+    let terminal = shell.terminal;
+    if let (Some(terminal), Some(pgid)) = (terminal, job.pgid) {
+        let _ = sys::set_foreground(terminal.fd, pgid);
+    }
+    job.update(true);
+    if let Some(terminal) = terminal
+        && let Err(e) = sys::set_foreground(terminal.fd, terminal.pgid)
+    {
+        eprintln!("safe_shell: cannot take back the terminal: {}", e);
+    }
+
+    match job.state(shell.options.pipefail) {
+        // After Ctrl-C the cursor is still after the `^C` the terminal
+        // printed: start the prompt on a line of its own.
+        State::Done(status) if status == 128 + sys::SIGINT && terminal.is_some() => {
+            eprintln!();
+            status
+        }
+        State::Done(status) => status,
+        _ => {
+            // The cursor is still after the `^Z` the terminal printed.
+            let text = job.text.clone();
+            let id = shell.jobs.add(job);
+            eprintln!("\n[{}]+  {:<24}{}", id, State::Stopped.to_string(), text);
+            128 + sys::SIGTSTP
+        }
+    }
+}
+
+/// `fg`: continues a job in the foreground. Its command line goes to
+/// `out` first, so you can see what you are back in.
+pub fn fg(shell: &mut Shell, spec: Option<&str>, out: &mut dyn Write) -> Result<i32, String> {
+    let index = shell.jobs.find(spec)?;
+    let mut job = shell.jobs.remove(index);
+    if let Some(text) = job.text.strip_suffix(" &") {
+        job.text = text.to_string();
+    }
+    let _ = writeln!(out, "{}", job.text).and_then(|()| out.flush());
+    job.resume();
+    Ok(foreground(shell, job))
+}
+
+/// `bg`: continues a stopped job in the background.
+pub fn bg(shell: &mut Shell, spec: Option<&str>) -> Result<String, String> {
+    let index = shell.jobs.find(spec)?;
+    let job = &mut shell.jobs.list[index];
+    if job.state(false) != State::Stopped {
+        return Err(format!("job {} is already running", job.id));
+    }
+    if !job.text.ends_with(" &") {
+        job.text.push_str(" &");
+    }
+    job.resume();
+    Ok(format!("[{}]+ {}", job.id, job.text))
+}
+
+/// `wait`: waits for the job at `index` to finish (or stop), without
+/// giving it the terminal. Returns its status.
+pub fn wait(shell: &mut Shell, index: usize) -> i32 {
+    let pipefail = shell.options.pipefail;
+    let job = &mut shell.jobs.list[index];
+    job.update(true);
+    match job.state(pipefail) {
+        State::Done(status) => {
+            shell.jobs.remove(index);
+            status
+        }
+        _ => 128 + sys::SIGTSTP,
+    }
+}
+
+/// The index in the table of the job with process `pid`.
+pub fn find_pid(shell: &Shell, pid: i32) -> Option<usize> {
+    shell
+        .jobs
+        .list
+        .iter()
+        .position(|job| job.stages.iter().any(|stage| stage.pid() == Some(pid)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process::Command;
+
+    // The job reaps the process itself, with `waitpid`.
+    #[allow(clippy::zombie_processes)]
+    fn job(command: &str) -> Job {
+        let child = Command::new("sh").args(["-c", command]).spawn().unwrap();
+        let pid = child.id() as i32;
+        Job::new(command.to_string(), None, vec![Stage::process(pid)])
+    }
+
+    #[test]
+    fn finished_jobs_are_reported_once() {
+        let mut jobs = Jobs::default();
+        assert_eq!(jobs.add(job("exit 3")), 1);
+        assert_eq!(jobs.add(job("sleep 5")), 2);
+        assert_eq!(jobs.find(None), Ok(1));
+        assert_eq!(jobs.find(Some("%1")), Ok(0));
+        assert_eq!(jobs.find(Some("%-")), Ok(0));
+        assert!(jobs.find(Some("%7")).is_err());
+
+        jobs.list[0].update(true);
+        assert_eq!(
+            jobs.listing(false),
+            [
+                format!("[1]-  {:<24}exit 3", "Exit 3"),
+                format!("[2]+  {:<24}sleep 5", "Running"),
+            ]
+        );
+        assert_eq!(jobs.reap(false).len(), 1);
+        assert!(jobs.reap(false).is_empty());
+
+        let pid = jobs.list[0].stages[0].pid().unwrap();
+        Command::new("kill").arg(pid.to_string()).status().unwrap();
+        jobs.list[0].update(true);
+        assert_eq!(jobs.list[0].state(false), State::Done(128 + sys::SIGTERM));
+        assert_eq!(jobs.list[0].state(false).to_string(), "Terminated");
+    }
+}
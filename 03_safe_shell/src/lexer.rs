@@ -0,0 +1,975 @@
+// Turning a command line into tokens.
+//
+// The first version of the shell split the line with `split_whitespace()`.
+// That breaks as soon as an argument contains a space:
+//
+//     grep "hello world" notes.txt
+//
+// came out as `grep`, `"hello`, `world"` and `notes.txt`. There was also no
+// way at all to pass an empty argument. The lexer below follows the quoting
+// rules of the POSIX shell, which every shell user already knows:
+//
+//     'single quotes'   everything inside is taken literally
+//     "double quotes"   literal too, except that a backslash still escapes
+//                       `"`, `\`, `$` and `` ` ``
+//     \x                outside quotes, the next character is literal
+//     # comment         from a `#` at the start of a word to the end of
+//                       the line
+//     $NAME  ${NAME}  ${NAME:-default}  $?  $1  $#  $@
+//                       variables, also inside double quotes (but not
+//                       single ones); they are filled in just before the
+//                       command runs, see `expand.rs`
+//     $(command)  `command`
+//                       what the command writes, also in double quotes
+//     $((1 + 2))        the value of an integer expression, see `arith.rs`
+//     |  &  ;  &&  ||  (  )  ;;  <  >  >>  >|  <<<  &>  >&
+//                       operators, unless quoted or escaped; a digit
+//                       right before a redirection names the file
+//                       descriptor, as in `2>` or `2>&1`
+//
+// A newline is a token too: in a script, or in an `if` typed over several
+// lines, it ends a command just like `;` does.
+//
+// Pieces written next to each other form one word, so `a"b c"'d'` is the
+// single argument `ab cd`, and `""` is an empty argument.
+//
+// Every token remembers where it came from (its span), so that an error
+// can point at the exact column instead of just saying "syntax error".
+
+use std::fmt;
+use std::iter::Peekable;
+use std::str::CharIndices;
+
+use crate::parser;
+
+/// A range of byte positions in the line, `start..end`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TokenKind {
+    /// A word with its quotes and escapes already removed.
+    Word(Word),
+    /// `|`, which connects two commands in a pipeline.
+    Pipe,
+    /// `&`, which runs a pipeline in the background.
+    Background,
+    /// `;`, which separates commands run one after the other.
+    Semicolon,
+    /// `&&`: run what follows only if what came before succeeded.
+    And,
+    /// `||`: run what follows only if what came before failed.
+    Or,
+    /// The end of a line, which ends a command like `;`.
+    Newline,
+    /// `(`, as in `name() { ...; }` and `case` patterns: `(a|b)`.
+    LeftParen,
+    /// `)`
+    RightParen,
+    /// `;;`, which ends a `case` branch.
+    DoubleSemicolon,
+    /// A redirection operator, with the file descriptor written before
+    /// it, if any: `2>` is `Redirect { fd: Some(2), op: Write }`.
+    Redirect { fd: Option<u32>, op: RedirectOp },
+}
+
+/// A word as written: pieces of text and the variables between them.
+/// `"$HOME"/notes` is a variable and then the text `/notes`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Word {
+    pub parts: Vec<WordPart>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WordPart {
+    /// Plain text. `quoted` text was inside quotes or escaped, so the
+    /// shell must leave it exactly as it is.
+    Literal { text: String, quoted: bool },
+    /// `$NAME`, `${NAME}` or `${NAME:-default}`. `quoted` is true inside
+    /// double quotes, where the value stays one word even if it has spaces.
+    Variable {
+        name: String,
+        default: Option<Word>,
+        quoted: bool,
+    },
+    /// `$(command)` or `` `command` ``: the text of the command, which is
+    /// parsed again when it runs.
+    Command { text: String, quoted: bool },
+    /// `$((expression))`. The expression is a word of its own, as if in
+    /// double quotes: `$x` and `$(cmd)` in it are expanded first.
+    Arithmetic { expression: Word, quoted: bool },
+}
+
+impl Word {
+    /// The word's text, if it has no variables in it.
+    pub fn literal(&self) -> Option<String> {
+        let mut text = String::new();
+        for part in &self.parts {
+            match part {
+                WordPart::Literal { text: more, .. } => text.push_str(more),
+                _ => return None,
+            }
+        }
+        Some(text)
+    }
+
+    /// The word's text if it is written plainly: no quotes, escapes or
+    /// variables. Only such a word can be a reserved word like `if`;
+    /// `'if'` and `\if` are ordinary commands.
+    pub fn plain(&self) -> Option<&str> {
+        match &self.parts[..] {
+            [
+                WordPart::Literal {
+                    text,
+                    quoted: false,
+                },
+            ] => Some(text),
+            _ => None,
+        }
+    }
+
+    fn push_char(&mut self, c: char, quoted: bool) {
+        self.push_str(c.encode_utf8(&mut [0; 4]), quoted);
+    }
+
+    /// Called at a closing quote. Makes sure the word ends in something
+    /// quoted, even if the quotes were empty: that is what makes `""` a
+    /// word at all.
+    fn close_quotes(&mut self) {
+        let quoted = match self.parts.last() {
+            Some(
+                WordPart::Literal { quoted, .. }
+                | WordPart::Variable { quoted, .. }
+                | WordPart::Command { quoted, .. }
+                | WordPart::Arithmetic { quoted, .. },
+            ) => *quoted,
+            None => false,
+        };
+        if !quoted {
+            self.push_str("", true);
+        }
+    }
+
+    fn push_str(&mut self, more: &str, quoted: bool) {
+        match self.parts.last_mut() {
+            Some(WordPart::Literal { text, quoted: q }) if *q == quoted => text.push_str(more),
+            _ => self.parts.push(WordPart::Literal {
+                text: more.to_string(),
+                quoted,
+            }),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RedirectOp {
+    /// `<`
+    Read,
+    /// `>`
+    Write,
+    /// `>>`
+    Append,
+    /// `>|`, which overwrites even with `set -o noclobber`.
+    Clobber,
+    /// `<<<`, a here-string.
+    HereString,
+    /// `&>`, standard output and standard error together.
+    WriteBoth,
+    /// `>&`, as in `2>&1`.
+    Duplicate,
+}
+
+impl RedirectOp {
+    pub fn symbol(self) -> &'static str {
+        match self {
+            RedirectOp::Read => "<",
+            RedirectOp::Write => ">",
+            RedirectOp::Append => ">>",
+            RedirectOp::Clobber => ">|",
+            RedirectOp::HereString => "<<<",
+            RedirectOp::WriteBoth => "&>",
+            RedirectOp::Duplicate => ">&",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SyntaxError {
+    pub message: String,
+    pub span: Span,
+    /// The input ended too early: a quote, an `if` or a `|` was still
+    /// open. More lines may finish it; see `script.rs`.
+    pub incomplete: bool,
+}
+
+impl SyntaxError {
+    /// Shows the error under the line it came from, with a marker under
+    /// the offending part:
+    ///
+    /// syntax error: unterminated double quote
+    ///   echo "hello
+    ///        ^~~~~~
+    ///
+    /// In text of several lines, that is the line the error starts on.
+    pub fn render(&self, text: &str) -> String {
+        let start = self.span.start.min(text.len());
+        let line_start = text[..start].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = text[start..].find('\n').map_or(text.len(), |i| start + i);
+        let line = text[line_start..line_end].trim_end_matches('\r');
+        // Spans count bytes, but the terminal shows characters.
+        let column = text[line_start..start].chars().count();
+        let end = self.span.end.clamp(start, line_start + line.len());
+        let width = text.get(start..end).map_or(0, |text| text.chars().count());
+        format!(
+            "syntax error: {}\n  {}\n  {}^{}",
+            self.message,
+            line,
+            " ".repeat(column),
+            "~".repeat(width.saturating_sub(1))
+        )
+    }
+
+    /// The error for input that ends before what was started at `span`
+    /// is finished.
+    pub fn incomplete(message: String, span: Span) -> SyntaxError {
+        SyntaxError {
+            message,
+            span,
+            incomplete: true,
+        }
+    }
+}
+
+impl fmt::Display for SyntaxError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "syntax error: {}", self.message)
+    }
+}
+
+/// Splits `line` into tokens.
+pub fn tokenize(line: &str) -> Result<Vec<Token>, SyntaxError> {
+    let mut tokens = Vec::new();
+    // `char_indices` gives us the byte position of every character, which
+    // is what the spans are made of.
+    let mut chars = line.char_indices().peekable();
+
+    // The word being built, and where it started. `None` means we are
+    // between words. We can't use "the word is empty" for that, because
+    // `""` is a real, empty word.
+    let mut word: Option<(Word, usize)> = None;
+
+    while let Some((at, c)) = chars.next() {
+        match c {
+            // 1. WHITESPACE ends the current word, if any.
+            ' ' | '\t' | '\r' => finish_word(&mut word, at, &mut tokens),
+
+            // OPERATORS end the word too, and are tokens of their own:
+            // `a|b` is the same as `a | b`.
+            '\n' | '|' | ';' | '(' | ')' => {
+                finish_word(&mut word, at, &mut tokens);
+                let kind = match c {
+                    '\n' => TokenKind::Newline,
+                    '(' => TokenKind::LeftParen,
+                    ')' => TokenKind::RightParen,
+                    ';' if chars.next_if(|&(_, next)| next == ';').is_some() => {
+                        TokenKind::DoubleSemicolon
+                    }
+                    ';' => TokenKind::Semicolon,
+                    _ if chars.next_if(|&(_, next)| next == '|').is_some() => TokenKind::Or,
+                    _ => TokenKind::Pipe,
+                };
+                tokens.push(operator(kind, at));
+            }
+            '<' | '>' => {
+                // `2>`: a digit right before the operator is the file
+                // descriptor, but only when written plainly. In `'2'>` or
+                // `a2>`, the word is an argument like any other.
+                let fd = word
+                    .as_ref()
+                    .filter(|(_, start)| at - start == 1)
+                    .and_then(|(word, _)| word.literal())
+                    .and_then(|text| text.parse::<u32>().ok());
+                let start = match fd {
+                    Some(_) => word.take().map_or(at, |(_, start)| start),
+                    None => {
+                        finish_word(&mut word, at, &mut tokens);
+                        at
+                    }
+                };
+                let (op, end) = redirect_op(c, at, &mut chars)?;
+                tokens.push(Token {
+                    kind: TokenKind::Redirect { fd, op },
+                    span: Span { start, end },
+                });
+            }
+            '&' if chars.peek().is_some_and(|&(_, next)| next == '>') => {
+                finish_word(&mut word, at, &mut tokens);
+                chars.next();
+                tokens.push(Token {
+                    kind: TokenKind::Redirect {
+                        fd: None,
+                        op: RedirectOp::WriteBoth,
+                    },
+                    span: Span {
+                        start: at,
+                        end: at + 2,
+                    },
+                });
+            }
+            '&' => {
+                finish_word(&mut word, at, &mut tokens);
+                let kind = match chars.next_if(|&(_, next)| next == '&') {
+                    Some(_) => TokenKind::And,
+                    None => TokenKind::Background,
+                };
+                tokens.push(operator(kind, at));
+            }
+
+            // 2. COMMENTS only start at the beginning of a word:
+            // `echo a#b` prints `a#b`. The newline after one still ends
+            // the command.
+            '#' if word.is_none() => while chars.next_if(|&(_, next)| next != '\n').is_some() {},
+
+            // 3. ANYTHING ELSE is part of a word: quotes, escapes and `$`
+            // included.
+            c => {
+                let (word, _) = word.get_or_insert_with(|| (Word::default(), at));
+                word_char(c, at, &mut chars, line, word)?;
+            }
+        }
+    }
+
+    let end = line.trim_end_matches(['\n', '\r']).len();
+    finish_word(&mut word, end, &mut tokens);
+    Ok(tokens)
+}
+
+/// An operator token starting at `at`.
+fn operator(kind: TokenKind, at: usize) -> Token {
+    let len = match kind {
+        TokenKind::And | TokenKind::Or | TokenKind::DoubleSemicolon => 2,
+        _ => 1,
+    };
+    Token {
+        kind,
+        span: Span {
+            start: at,
+            end: at + len,
+        },
+    }
+}
+
+/// Adds the word being built, if any, as a token ending at `end`.
+fn finish_word(word: &mut Option<(Word, usize)>, end: usize, tokens: &mut Vec<Token>) {
+    if let Some((word, start)) = word.take() {
+        tokens.push(Token {
+            kind: TokenKind::Word(word),
+            span: Span {
+                start,
+                end: end.max(start),
+            },
+        });
+    }
+}
+
+/// Adds the character `c` (found at byte `at`, outside any quotes) to
+/// `word`, together with whatever it starts: a quoted string, an escaped
+/// character or a variable.
+fn word_char(
+    c: char,
+    at: usize,
+    chars: &mut Peekable<CharIndices>,
+    line: &str,
+    word: &mut Word,
+) -> Result<(), SyntaxError> {
+    match c {
+        // SINGLE QUOTES: everything up to the next `'` is literal, `$`
+        // included.
+        '\'' => loop {
+            match chars.next() {
+                Some((_, '\'')) => break word.close_quotes(),
+                Some((_, c)) => word.push_char(c, true),
+                None => return Err(unterminated("single quote", at, line)),
+            }
+        },
+
+        // DOUBLE QUOTES: like single quotes, but a backslash still escapes
+        // the few characters that are special inside them, and variables
+        // are still expanded.
+        '"' => loop {
+            match chars.next() {
+                Some((_, '"')) => break word.close_quotes(),
+                Some((_, '\\')) => quoted_backslash(chars, word),
+                Some((at, '$')) => dollar(at, chars, line, word, true)?,
+                Some((at, '`')) => backquote(at, chars, line, word, true)?,
+                Some((_, c)) => word.push_char(c, true),
+                None => return Err(unterminated("double quote", at, line)),
+            }
+        },
+
+        // BACKSLASH: the next character loses any special meaning.
+        '\\' => match chars.next() {
+            // Backslash-newline is a line continuation: it vanishes.
+            Some((_, '\n')) => {}
+            Some((_, c)) => word.push_char(c, true),
+            // At the very end it is waiting for the next line.
+            None => {
+                return Err(SyntaxError::incomplete(
+                    "nothing to escape after '\\'".to_string(),
+                    Span {
+                        start: at,
+                        end: line.len(),
+                    },
+                ));
+            }
+        },
+
+        '$' => dollar(at, chars, line, word, false)?,
+        '`' => backquote(at, chars, line, word, false)?,
+
+        c => word.push_char(c, false),
+    }
+    Ok(())
+}
+
+/// A backslash inside double quotes.
+fn quoted_backslash(chars: &mut Peekable<CharIndices>, word: &mut Word) {
+    match chars.peek() {
+        Some(&(_, c @ ('"' | '\\' | '$' | '`'))) => {
+            word.push_char(c, true);
+            chars.next();
+        }
+        // A backslash before a newline joins the lines.
+        Some(&(_, '\n')) => {
+            chars.next();
+        }
+        // Anywhere else the backslash is just a backslash: "C:\temp"
+        // stays as it is.
+        _ => word.push_char('\\', true),
+    }
+}
+
+/// Reads what follows a `$` at byte `at`: `NAME`, `{NAME}`,
+/// `{NAME:-default}`, `(command)` or `((expression))`. A `$` that starts
+/// none of them is just a dollar sign, as in `echo $5.00` or `echo $`.
+fn dollar(
+    at: usize,
+    chars: &mut Peekable<CharIndices>,
+    line: &str,
+    word: &mut Word,
+    quoted: bool,
+) -> Result<(), SyntaxError> {
+    if chars.next_if(|&(_, c)| c == '(').is_some() {
+        let part = match chars.next_if(|&(_, c)| c == '(') {
+            Some(_) => WordPart::Arithmetic {
+                expression: arithmetic(at, chars, line)?,
+                quoted,
+            },
+            None => {
+                let text = command_text(at, chars, line)?;
+                check_command(text, at + 2)?;
+                WordPart::Command {
+                    text: text.to_string(),
+                    quoted,
+                }
+            }
+        };
+        word.parts.push(part);
+        return Ok(());
+    }
+    let braced = chars.next_if(|&(_, c)| c == '{').is_some();
+    let name = variable_name(chars, braced);
+    if !braced {
+        if name.is_empty() {
+            word.push_char('$', quoted);
+        } else {
+            word.parts.push(WordPart::Variable {
+                name,
+                default: None,
+                quoted,
+            });
+        }
+        return Ok(());
+    }
+
+    // Anything in braces other than a name, `}` and `:-` is something we
+    // don't support (yet), and guessing would do the wrong thing.
+    let bad = |chars: &mut Peekable<CharIndices>| {
+        let end = chars.peek().map_or(line.len(), |&(i, _)| i + 1);
+        SyntaxError {
+            message: "bad substitution: only ${NAME} and ${NAME:-default} are supported"
+                .to_string(),
+            span: Span { start: at, end },
+            incomplete: false,
+        }
+    };
+    if name.is_empty() {
+        return Err(bad(chars));
+    }
+    let default = match chars.next() {
+        Some((_, '}')) => None,
+        Some((_, ':')) if chars.next_if(|&(_, c)| c == '-').is_some() => {
+            Some(default_word(at, chars, line, quoted)?)
+        }
+        None => return Err(unterminated("'${'", at, line)),
+        Some(_) => return Err(bad(chars)),
+    };
+    word.parts.push(WordPart::Variable {
+        name,
+        default,
+        quoted,
+    });
+    Ok(())
+}
+
+/// Reads a variable name (letters, digits and `_`, not starting with a
+/// digit), or a special one: `?` (the status of the last command), `#`
+/// (the number of arguments), `@` (all of them) or a digit (one of them).
+/// In braces, the digits go on: `${10}`. Returns an empty string if there
+/// is no name.
+fn variable_name(chars: &mut Peekable<CharIndices>, braced: bool) -> String {
+    let mut name = String::new();
+    if let Some((_, c)) = chars.next_if(|&(_, c)| matches!(c, '?' | '#' | '@')) {
+        name.push(c);
+    } else if let Some((_, c)) = chars.next_if(|&(_, c)| c.is_ascii_digit()) {
+        name.push(c);
+        while braced && let Some((_, c)) = chars.next_if(|&(_, c)| c.is_ascii_digit()) {
+            name.push(c);
+        }
+    } else if let Some((_, c)) = chars.next_if(|&(_, c)| c.is_ascii_alphabetic() || c == '_') {
+        name.push(c);
+        while let Some((_, c)) = chars.next_if(|&(_, c)| c.is_ascii_alphanumeric() || c == '_') {
+            name.push(c);
+        }
+    }
+    name
+}
+
+/// Reads the default in `${NAME:-default}`, up to the closing `}`. It is
+/// a word of its own, with quotes and variables, and inside double quotes
+/// it is quoted too.
+fn default_word(
+    at: usize,
+    chars: &mut Peekable<CharIndices>,
+    line: &str,
+    quoted: bool,
+) -> Result<Word, SyntaxError> {
+    let mut word = Word::default();
+    loop {
+        match chars.next() {
+            Some((_, '}')) => return Ok(word),
+            Some((_, '\\')) if quoted => quoted_backslash(chars, &mut word),
+            Some((i, '$')) if quoted => dollar(i, chars, line, &mut word, true)?,
+            Some((_, c)) if quoted => word.push_char(c, true),
+            Some((i, c)) => word_char(c, i, chars, line, &mut word)?,
+            None => return Err(unterminated("'${'", at, line)),
+        }
+    }
+}
+
+/// Reads the command in `$(command)`, whose `$(` is at byte `at`, up to
+/// the `)` that closes it. Parentheses inside have to pair up, except in
+/// quotes: `$(echo ")")` is fine.
+fn command_text<'a>(
+    at: usize,
+    chars: &mut Peekable<CharIndices>,
+    line: &'a str,
+) -> Result<&'a str, SyntaxError> {
+    let mut depth = 0;
+    // Skips to the `close` quote, past escaped ones if `escapes`.
+    let skip_quoted = |chars: &mut Peekable<CharIndices>, close: char, escapes: bool| loop {
+        match chars.next() {
+            Some((_, '\\')) if escapes => {
+                chars.next();
+            }
+            Some((_, c)) if c == close => return Ok(()),
+            Some(_) => {}
+            None => return Err(unterminated("'$('", at, line)),
+        }
+    };
+    loop {
+        match chars.next() {
+            Some((i, ')')) if depth == 0 => return Ok(&line[at + 2..i]),
+            Some((_, ')')) => depth -= 1,
+            Some((_, '(')) => depth += 1,
+            Some((_, '\\')) => {
+                chars.next();
+            }
+            Some((_, '\'')) => skip_quoted(chars, '\'', false)?,
+            Some((_, '"')) => skip_quoted(chars, '"', true)?,
+            Some((_, '`')) => skip_quoted(chars, '`', true)?,
+            Some(_) => {}
+            None => return Err(unterminated("'$('", at, line)),
+        }
+    }
+}
+
+/// Reads `` `command` ``, whose first backquote is at byte `at`. Inside,
+/// a backslash only escapes `` ` ``, `\` and `$`.
+fn backquote(
+    at: usize,
+    chars: &mut Peekable<CharIndices>,
+    line: &str,
+    word: &mut Word,
+    quoted: bool,
+) -> Result<(), SyntaxError> {
+    let mut text = String::new();
+    loop {
+        match chars.next() {
+            Some((_, '`')) => break,
+            Some((_, '\\')) => match chars.next_if(|&(_, c)| matches!(c, '`' | '\\' | '$')) {
+                Some((_, c)) => text.push(c),
+                None => text.push('\\'),
+            },
+            Some((_, c)) => text.push(c),
+            None => return Err(unterminated("backquote", at, line)),
+        }
+    }
+    check_command(&text, at + 1)?;
+    word.parts.push(WordPart::Command { text, quoted });
+    Ok(())
+}
+
+/// Reads the expression in `$((expression))`, whose `$((` is at byte
+/// `at`, up to the `))` that closes it.
+fn arithmetic(
+    at: usize,
+    chars: &mut Peekable<CharIndices>,
+    line: &str,
+) -> Result<Word, SyntaxError> {
+    let mut expression = Word::default();
+    let mut depth = 0;
+    loop {
+        match chars.next() {
+            Some((i, ')')) if depth == 0 => {
+                if chars.next_if(|&(_, c)| c == ')').is_none() {
+                    return Err(SyntaxError {
+                        message: "'$((' needs '))' to close it".to_string(),
+                        span: Span {
+                            start: at,
+                            end: i + 1,
+                        },
+                        incomplete: false,
+                    });
+                }
+                return Ok(expression);
+            }
+            Some((_, c @ ('(' | ')'))) => {
+                depth = if c == '(' { depth + 1 } else { depth - 1 };
+                expression.push_char(c, true);
+            }
+            Some((_, '\\')) => quoted_backslash(chars, &mut expression),
+            Some((i, '$')) => dollar(i, chars, line, &mut expression, true)?,
+            Some((i, '`')) => backquote(i, chars, line, &mut expression, true)?,
+            Some((_, c)) => expression.push_char(c, true),
+            None => return Err(unterminated("'$(('", at, line)),
+        }
+    }
+}
+
+/// Parses the command of a `$(command)` found at byte `offset` right
+/// away, so that a mistake in it is reported before anything on the line
+/// runs.
+fn check_command(text: &str, offset: usize) -> Result<(), SyntaxError> {
+    match tokenize(text).and_then(parser::parse) {
+        Ok(_) => Ok(()),
+        Err(e) => Err(SyntaxError {
+            span: Span {
+                start: e.span.start + offset,
+                end: e.span.end + offset,
+            },
+            // The `)` is there: nothing more can finish it.
+            incomplete: false,
+            ..e
+        }),
+    }
+}
+
+/// Reads the rest of a redirection operator that starts with `first` at
+/// byte `at`. Returns the operator and where it ends.
+fn redirect_op(
+    first: char,
+    at: usize,
+    chars: &mut Peekable<CharIndices>,
+) -> Result<(RedirectOp, usize), SyntaxError> {
+    // Operators are all ASCII, so every character is one byte.
+    let mut end = at + 1;
+    let mut next_is = |c: char| {
+        let found = chars.next_if(|&(_, next)| next == c).is_some();
+        end += found as usize;
+        found
+    };
+    let op = match first {
+        '<' if next_is('<') => {
+            if !next_is('<') {
+                return Err(SyntaxError {
+                    message: "here-documents ('<<') are not supported; use '<<<'".to_string(),
+                    span: Span {
+                        start: at,
+                        end: at + 2,
+                    },
+                    incomplete: false,
+                });
+            }
+            RedirectOp::HereString
+        }
+        '<' => RedirectOp::Read,
+        _ if next_is('>') => RedirectOp::Append,
+        _ if next_is('|') => RedirectOp::Clobber,
+        _ if next_is('&') => RedirectOp::Duplicate,
+        _ => RedirectOp::Write,
+    };
+    Ok((op, end))
+}
+
+/// The error for a quote opened at `at` and never closed.
+fn unterminated(what: &str, at: usize, line: &str) -> SyntaxError {
+    SyntaxError::incomplete(
+        format!("unterminated {}", what),
+        Span {
+            start: at,
+            end: line.trim_end_matches(['\n', '\r']).len(),
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(line: &str) -> Vec<String> {
+        tokenize(line)
+            .unwrap()
+            .into_iter()
+            .map(|token| match token.kind {
+                TokenKind::Word(word) => word.literal().unwrap(),
+                TokenKind::Pipe => "|".to_string(),
+                TokenKind::Background => "&".to_string(),
+                TokenKind::Semicolon => ";".to_string(),
+                TokenKind::And => "&&".to_string(),
+                TokenKind::Or => "||".to_string(),
+                TokenKind::Newline => "\n".to_string(),
+                TokenKind::LeftParen => "(".to_string(),
+                TokenKind::RightParen => ")".to_string(),
+                TokenKind::DoubleSemicolon => ";;".to_string(),
+                TokenKind::Redirect { fd, op } => {
+                    format!(
+                        "{}{}",
+                        fd.map(|fd| fd.to_string()).unwrap_or_default(),
+                        op.symbol()
+                    )
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn splits_words_and_honours_quotes() {
+        assert_eq!(
+            words("grep \"hello world\" file\n"),
+            ["grep", "hello world", "file", "\n"]
+        );
+        assert_eq!(words("echo '' \"\" x"), ["echo", "", "", "x"]);
+        assert_eq!(words("a\"b c\"'d'"), ["ab cd"]);
+        assert_eq!(
+            words(r#"echo 'it''s' "say \"hi\"" a\ b"#),
+            ["echo", "its", "say \"hi\"", "a b"]
+        );
+        assert_eq!(words(r#"echo "C:\temp" '\n'"#), ["echo", r"C:\temp", r"\n"]);
+        assert_eq!(words("ls -l # all of it\nwc"), ["ls", "-l", "\n", "wc"]);
+        assert_eq!(words("echo a#b"), ["echo", "a#b"]);
+        assert_eq!(words("echo one\\\ntwo"), ["echo", "onetwo"]);
+    }
+
+    #[test]
+    fn pipes_are_operators_unless_quoted() {
+        let pipes: Vec<bool> = tokenize("ls|wc '|' \\|")
+            .unwrap()
+            .iter()
+            .map(|token| token.kind == TokenKind::Pipe)
+            .collect();
+        assert_eq!(pipes, [false, true, false, false, false]);
+    }
+
+    #[test]
+    fn finds_redirections_and_their_file_descriptors() {
+        assert_eq!(
+            words("cmd<in >out 2>>log 2>&1 &>all >|force <<<'text'"),
+            [
+                "cmd", "<", "in", ">", "out", "2>>", "log", "2>&", "1", "&>", "all", ">|", "force",
+                "<<<", "text"
+            ]
+        );
+        // Only a plain digit right before the operator is a descriptor.
+        assert_eq!(
+            words("echo 12>x a2>y '2'>z"),
+            ["echo", "12", ">", "x", "a2", ">", "y", "2", ">", "z"]
+        );
+        assert_eq!(words("echo a\\>b '>'"), ["echo", "a>b", ">"]);
+        assert_eq!(words("sleep 1& '&'"), ["sleep", "1", "&", "&"]);
+        assert_eq!(
+            words("a&&b||c;d|e >|f ';'"),
+            [
+                "a", "&&", "b", "||", "c", ";", "d", "|", "e", ">|", "f", ";"
+            ]
+        );
+        assert!(tokenize("cat <<EOF").is_err());
+    }
+
+    #[test]
+    fn finds_the_operators_of_compound_commands() {
+        assert_eq!(
+            words("f() { a;; }\ncase x in (y) z;; esac"),
+            [
+                "f", "(", ")", "{", "a", ";;", "}", "\n", "case", "x", "in", "(", "y", ")", "z",
+                ";;", "esac"
+            ]
+        );
+        assert_eq!(words("echo '(' \\) \"\n\""), ["echo", "(", ")", "\n"]);
+        let plain = |line: &str| match tokenize(line).unwrap().remove(0).kind {
+            TokenKind::Word(word) => word.plain().map(str::to_string),
+            _ => None,
+        };
+        assert_eq!(plain("if"), Some("if".to_string()));
+        assert_eq!(plain("'if'"), None);
+        assert_eq!(plain("\\if"), None);
+    }
+
+    fn parts(line: &str) -> Vec<WordPart> {
+        match tokenize(line).unwrap().remove(0).kind {
+            TokenKind::Word(word) => word.parts,
+            other => panic!("not a word: {:?}", other),
+        }
+    }
+
+    fn literal(text: &str, quoted: bool) -> WordPart {
+        WordPart::Literal {
+            text: text.to_string(),
+            quoted,
+        }
+    }
+
+    fn variable(name: &str, default: Option<Vec<WordPart>>, quoted: bool) -> WordPart {
+        WordPart::Variable {
+            name: name.to_string(),
+            default: default.map(|parts| Word { parts }),
+            quoted,
+        }
+    }
+
+    #[test]
+    fn finds_variables_outside_single_quotes() {
+        assert_eq!(
+            parts("$HOME/\"$USER\"'$PATH'"),
+            [
+                variable("HOME", None, false),
+                literal("/", false),
+                variable("USER", None, true),
+                literal("$PATH", true),
+            ]
+        );
+        assert_eq!(
+            parts("${A}b${B:-x $C}\"${D:-'e'}\""),
+            [
+                variable("A", None, false),
+                literal("b", false),
+                variable(
+                    "B",
+                    Some(vec![literal("x ", false), variable("C", None, false)]),
+                    false
+                ),
+                variable("D", Some(vec![literal("'e'", true)]), true),
+            ]
+        );
+        assert_eq!(
+            parts("$?${#}$12${12}\"$@\""),
+            [
+                variable("?", None, false),
+                variable("#", None, false),
+                variable("1", None, false),
+                literal("2", false),
+                variable("12", None, false),
+                variable("@", None, true),
+            ]
+        );
+        // A `$` that starts no name is just a dollar sign.
+        assert_eq!(parts("$%$"), [literal("$%$", false)]);
+        assert!(tokenize("echo ${A").is_err());
+        assert!(tokenize("echo ${A/x/y}").is_err());
+        assert!(tokenize("echo ${}").is_err());
+    }
+
+    #[test]
+    fn finds_command_substitutions_and_arithmetic() {
+        let command = |text: &str, quoted| WordPart::Command {
+            text: text.to_string(),
+            quoted,
+        };
+        assert_eq!(
+            parts("$(ls \")\" '(')\"`echo \\`a\\``\""),
+            [command("ls \")\" '('", false), command("echo `a`", true)]
+        );
+        assert_eq!(
+            parts("$(( ($x + 1) * 2 ))"),
+            [WordPart::Arithmetic {
+                expression: Word {
+                    parts: vec![
+                        literal(" (", true),
+                        variable("x", None, true),
+                        literal(" + 1) * 2 ", true),
+                    ]
+                },
+                quoted: false,
+            }]
+        );
+        // A missing `)` may still come on the next line; a mistake inside
+        // is reported where it is.
+        assert!(tokenize("echo $(ls").unwrap_err().incomplete);
+        assert!(tokenize("echo `ls").unwrap_err().incomplete);
+        let err = tokenize("echo $(ls | | wc)").unwrap_err();
+        assert_eq!(err.span, Span { start: 12, end: 13 });
+        assert!(!err.incomplete);
+    }
+
+    #[test]
+    fn tokens_know_where_they_came_from() {
+        let tokens = tokenize("ls  'my dir'\n").unwrap();
+        assert_eq!(tokens[0].span, Span { start: 0, end: 2 });
+        assert_eq!(tokens[1].span, Span { start: 4, end: 12 });
+    }
+
+    #[test]
+    fn errors_point_at_the_column() {
+        let line = "echo \"hello\n";
+        let err = tokenize(line).unwrap_err();
+        assert_eq!(err.span, Span { start: 5, end: 11 });
+        assert_eq!(
+            err.render(line),
+            "syntax error: unterminated double quote\n  echo \"hello\n       ^~~~~~"
+        );
+        // Columns count characters, not bytes.
+        let err = tokenize("echo é 'x").unwrap_err();
+        assert!(err.render("echo é 'x").ends_with("\n         ^~"));
+        assert!(err.incomplete);
+
+        // In text of several lines, the line the error is on.
+        let err = SyntaxError {
+            message: "unexpected 'fi'".to_string(),
+            span: Span { start: 8, end: 10 },
+            incomplete: false,
+        };
+        assert_eq!(
+            err.render("if true\nfi\nmore"),
+            "syntax error: unexpected 'fi'\n  fi\n  ^~"
+        );
+    }
+}
@@ -1,80 +1,241 @@
+mod arith;
+mod builtins;
+mod complete;
+mod editor;
+mod exec;
+mod expand;
+mod glob;
+mod history;
+mod jobs;
+mod lexer;
+mod parser;
+mod policy;
+mod redirect;
+mod script;
+mod shell;
+mod sys;
+
 use std::env;
-use std::io::{self, Write};
-use std::process::Command;
+use std::fs;
+use std::io::{self, IsTerminal};
+use std::path::PathBuf;
+use std::process;
+use std::sync::Arc;
+
+use complete::Completer;
+use editor::Editor;
+use history::History;
+use policy::Policy;
+use shell::Shell;
 
 fn main() {
+    let args: Vec<String> = env::args().collect();
+    let mut shell = Shell::from_environment();
+    // Better not to start at all than to run without the rules someone
+    // wrote down.
+    shell.policy = match load_policy(&shell) {
+        Ok(policy) => Arc::new(policy),
+        Err(e) => {
+            eprintln!("safe_shell: {}", e);
+            process::exit(2);
+        }
+    };
+
+    // Which commands to run: `-c` gives them right away, a script name
+    // says where to read them, and without either they come from standard
+    // input. Only there, and only from a terminal, does someone type them.
+    let status = match args.get(1).map(String::as_str) {
+        Some("-c") => {
+            let Some(command) = args.get(2) else {
+                eprintln!("safe_shell: -c: needs a command");
+                process::exit(2);
+            };
+            // `safe_shell -c 'echo $1' name one`: like a script called
+            // `name`, with `one` as its argument.
+            if let Some(name) = args.get(3) {
+                shell.name = name.clone();
+            }
+            shell.args = args.iter().skip(4).cloned().collect();
+            script::run_lines(
+                &mut shell,
+                command.lines().map(|line| Ok(line.to_string())),
+                "-c",
+            )
+        }
+        Some(option) if option.starts_with('-') => {
+            eprintln!("safe_shell: {}: unknown option", option);
+            eprintln!("usage: safe_shell [SCRIPT [ARGS...] | -c COMMAND [NAME [ARGS...]]]");
+            process::exit(2);
+        }
+        Some(path) => {
+            shell.name = path.to_string();
+            shell.args = args[2..].to_vec();
+            script::run_file(&mut shell, path).unwrap_or_else(|e| {
+                eprintln!("safe_shell: {}: {}", path, e);
+                script::read_error_status(&e)
+            })
+        }
+        None if !io::stdin().is_terminal() => {
+            script::run_lines(&mut shell, io::stdin().lines(), "safe_shell")
+        }
+        None => interactive(shell),
+    };
+    process::exit(shell.exit.unwrap_or(status));
+}
+
+/// Reads commands from the terminal and runs them, until `exit` or Ctrl-D.
+fn interactive(mut shell: Shell) -> ! {
+    // Job control (see `jobs.rs`) needs a terminal to control.
+    shell.terminal = jobs::take_terminal();
+    // Only commands typed by hand are worth remembering.
+    shell.history = load_history(&shell);
+    let mut editor = Editor::new();
+    let mut completer = Completer::default();
     println!("Welcome to Safe-Shell 🐚");
     println!("Type 'exit' to quit.");
 
+    // The lines of a command that isn't finished yet: an `if` without its
+    // `fi`, say. Until it is, the prompt is `... `.
+    let mut text = String::new();
     loop {
-        // 1. PRINT PROMPT
-        // We use print! instead of println! because we want the cursor to stay on the same line.
-        print!("> ");
-        // Standard output is line-buffered. We need to flush it to ensure the prompt appears immediately.
-        if let Err(e) = io::stdout().flush() {
-            eprintln!("Error flushing stdout: {}", e);
-            continue;
-        }
-
-        // 2. READ INPUT
-        let mut input = String::new();
-        // stdin().read_line() blocks until the user presses Enter.
-        if let Err(e) = io::stdin().read_line(&mut input) {
-            eprintln!("Error reading input: {}", e);
-            continue;
+        // Background jobs that finished while the last command ran.
+        let pipefail = shell.options.pipefail;
+        for line in shell.jobs.reap(pipefail) {
+            eprintln!("{}", line);
         }
 
-        // 3. PARSE INPUT
-        // trim() removes the newline character at the end.
-        // split_whitespace() breaks the string into words.
-        let mut parts = input.trim().split_whitespace();
-
-        // The first part is the command name.
-        let command = match parts.next() {
-            Some(cmd) => cmd,
-            None => continue, // Empty input
+        // 1. READ INPUT
+        // The editor shows the prompt and lets you edit the line with the
+        // arrow keys, go through the history, complete words with Tab, and
+        // so on. See `editor.rs` and `complete.rs`.
+        let complete = &mut |line: &str| completer.complete(line, &shell);
+        let prompt = match text.is_empty() {
+            true => prompt(&shell),
+            false => "... ".to_string(),
         };
-
-        // The rest are arguments.
-        let args: Vec<&str> = parts.collect();
-
-        // 4. EXECUTE COMMAND
-        // We handle built-in commands (like 'cd' and 'exit') differently from external programs.
-        match command {
-            "exit" => {
+        let mut input = match editor.read_line(&prompt, &shell.history, complete) {
+            Ok(Some(input)) => input,
+            // Ctrl-D: like `exit`. In the middle of a command it only
+            // ends the command, which can't be run.
+            Ok(None) if !text.is_empty() => {
+                if let Err(e) = script::run_line(&mut shell, &text) {
+                    eprintln!("{}", e.render(&text));
+                }
+                shell.last_status = 2;
+                text.clear();
+                continue;
+            }
+            Ok(None) => {
                 println!("Goodbye! 👋");
-                break;
+                process::exit(shell.last_status);
             }
-            "cd" => {
-                // Change Directory
-                // Why is this a built-in? Because if we ran 'cd' as a child process,
-                // it would change *its own* directory and then die. The parent shell would stay put!
-                // We must change the shell's own current directory.
-                let new_dir = args.get(0).unwrap_or(&"/");
-                if let Err(e) = env::set_current_dir(new_dir) {
-                    eprintln!("cd: {}", e);
-                }
+            // Ctrl-C: forget the command, however many lines it had.
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {
+                text.clear();
+                continue;
             }
-            _ => {
-                // External Command
-                // We ask the OS to spawn a new process.
-                let child_process = Command::new(command).args(args).spawn();
+            Err(e) => {
+                eprintln!("Error reading input: {}", e);
+                continue;
+            }
+        };
 
-                match child_process {
-                    Ok(mut child) => {
-                        // The child is running. We must wait for it to finish.
-                        // If we didn't wait, the shell would print the next prompt immediately,
-                        // mixing its output with the child's output (a race condition!).
-                        if let Err(e) = child.wait() {
-                            eprintln!("Failed to wait on child: {}", e);
-                        }
-                    }
-                    Err(e) => {
-                        // This usually happens if the command doesn't exist.
-                        eprintln!("Command not found: '{}' ({})", command, e);
-                    }
-                }
+        // 2. HISTORY
+        // `!!` and friends are replaced before anything else looks at the
+        // line, and the line that results is what gets remembered.
+        match shell.history.expand(&input) {
+            Ok(Some(expanded)) => {
+                // Show what is about to run, like other shells do.
+                println!("{}", expanded);
+                input = expanded;
+            }
+            Ok(None) => {}
+            Err(e) => {
+                eprintln!("safe_shell: {}", e);
+                continue;
+            }
+        }
+        if let Err(e) = shell.history.add(&input) {
+            eprintln!("safe_shell: can't save the history: {}", e);
+        }
+
+        // 3. PARSE AND EXECUTE
+        // The lexer breaks the line into words and operators, taking quotes
+        // and backslashes into account, and the parser groups them into
+        // commands (see `lexer.rs` and `parser.rs`). Builtins (like 'cd'
+        // and 'exit') run inside the shell, everything else as a child
+        // process (see `exec.rs`). If the command goes on, as in `if true`,
+        // we read the next line first.
+        if !text.is_empty() {
+            text.push('\n');
+        }
+        text.push_str(&input);
+        match script::run_line(&mut shell, &text) {
+            Err(e) if e.incomplete => continue,
+            Err(e) => {
+                eprintln!("{}", e.render(&text));
+                // What other shells use for "you typed it wrong".
+                shell.last_status = 2;
             }
+            Ok(_) => {}
+        }
+        text.clear();
+        if let Some(status) = shell.exit {
+            println!("Goodbye! 👋");
+            process::exit(status);
+        }
+    }
+}
+
+/// `> `, or `[1] > ` after a command failed with `set -o showstatus`.
+fn prompt(shell: &Shell) -> String {
+    if shell.options.showstatus && shell.last_status != 0 {
+        format!("[{}] > ", shell.last_status)
+    } else {
+        "> ".to_string()
+    }
+}
+
+/// The history saved by earlier sessions: in `$HISTFILE`, or
+/// `~/.safe_shell_history`, keeping at most `$HISTSIZE` commands.
+fn load_history(shell: &Shell) -> History {
+    let path = match (shell.get_var("HISTFILE"), shell.get_var("HOME")) {
+        (Some(file), _) => PathBuf::from(file),
+        (None, Some(home)) => PathBuf::from(home).join(".safe_shell_history"),
+        (None, None) => return History::default(),
+    };
+    let limit = shell
+        .get_var("HISTSIZE")
+        .and_then(|size| size.parse().ok())
+        .unwrap_or(History::DEFAULT_LIMIT);
+    History::load(path, limit).unwrap_or_else(|e| {
+        eprintln!("safe_shell: can't read the history: {}", e);
+        History::default()
+    })
+}
+
+/// The command policy, from `$SAFE_SHELL_POLICY` or
+/// `~/.safe_shell_policy`, or the built-in one if there is no such file.
+/// Overrides are logged in `~/.safe_shell_audit` unless it says otherwise.
+fn load_policy(shell: &Shell) -> Result<Policy, String> {
+    let home = shell.get_var("HOME");
+    let path = match (shell.get_var("SAFE_SHELL_POLICY"), home) {
+        (Some(file), _) => Some(PathBuf::from(file)),
+        (None, Some(home)) => Some(PathBuf::from(home).join(".safe_shell_policy")),
+        (None, None) => None,
+    };
+    // Only the default file may be missing: a policy that was asked for
+    // by name is meant to be there.
+    let named = shell.get_var("SAFE_SHELL_POLICY").is_some();
+    let mut policy = match path.map(|path| (fs::read_to_string(&path), path)) {
+        Some((Ok(text), path)) => Policy::parse(&text, &path.display().to_string(), home)?,
+        Some((Err(e), path)) if named || e.kind() != io::ErrorKind::NotFound => {
+            return Err(format!("{}: {}", path.display(), e));
         }
+        _ => Policy::parse(policy::DEFAULT, "built-in", home)?,
+    };
+    if policy.audit.is_none() {
+        policy.audit = home.map(|home| PathBuf::from(home).join(".safe_shell_audit"));
     }
+    Ok(policy)
 }
@@ -0,0 +1,1144 @@
+// Turning tokens into commands.
+//
+// The lexer gives us a flat list of words and operators. The parser groups
+// them into the structure the shell actually runs: a pipeline of simple
+// commands, where each command is a program name, its arguments and its
+// redirections.
+//
+//     sort < names.txt | uniq -c > counts.txt
+//     \______________/   \__________________/
+//         command              command
+//     \______________________________________/
+//                     pipeline
+//
+// Pipelines are joined with `&&` and `||` into and-or lists, and those are
+// separated by `;` or `&` to make up the whole line:
+//
+//     cargo build && ./run || echo failed; echo done
+//     \_________/    \___/    \_________/  \_______/
+//      pipeline   pipeline  pipeline    pipeline
+//     \_________________________________/  \_______/
+//                   and-or                   and-or
+//
+// A command can also be a compound command, made of whole lists, which
+// can go on over many lines (newlines separate commands, like `;`):
+//
+//     if list; then list; elif list; then list; else list; fi
+//     while list; do list; done        until list; do list; done
+//     for name in words; do list; done
+//     case word in pattern|pattern) list;; ... esac
+//     { list; }
+//     name() { list; }                 a function definition
+//
+// Words like `if` and `done` are only reserved where a command name goes,
+// and only when written plainly: `echo done` and `'if'` are ordinary
+// words. When the input ends inside a compound command, the error says
+// so (`incomplete`), and the shell reads another line.
+//
+// Redirections can go anywhere in a command: `> out ls -l` is the same as
+// `ls -l > out`. After a compound command they apply to all of it.
+//
+// Words of the form `NAME=value` before the command name are assignments,
+// not arguments: `LANG=C sort` runs `sort` with `LANG` set to `C`. After
+// the name they are ordinary arguments again, as in `env LANG=C`.
+//
+// The words still contain their variables. They are only expanded when the
+// command runs (see `expand.rs`), because a variable can change between
+// parsing and running.
+
+use std::iter::Peekable;
+use std::sync::Arc;
+use std::vec;
+
+use crate::lexer::{RedirectOp, Span, SyntaxError, Token, TokenKind, Word, WordPart};
+use crate::shell;
+
+/// A program (or builtin), its arguments and its redirections.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SimpleCommand {
+    /// `NAME=value` words before the command name.
+    pub assignments: Vec<Assignment>,
+    /// The command name first, then the arguments. Empty for a command
+    /// that is only redirections or assignments, like `> empty.txt`.
+    pub words: Vec<Word>,
+    /// In the order they were written, which is the order they happen in.
+    pub redirects: Vec<Redirect>,
+    pub span: Span,
+}
+
+/// `NAME=value`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Assignment {
+    pub name: String,
+    pub value: Word,
+}
+
+/// One redirection, like `2> errors.log`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Redirect {
+    /// The file descriptor being redirected: 0, 1 or 2.
+    pub fd: u32,
+    pub kind: RedirectKind,
+    /// The file name, the here-string text, or the descriptor to copy.
+    pub target: Word,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RedirectKind {
+    /// `< file`
+    Read,
+    /// `> file`, `>> file` (append) and `>| file` (force: overwrite even
+    /// with `set -o noclobber`).
+    Write { append: bool, force: bool },
+    /// `&> file`: standard output and standard error both go to the file.
+    WriteBoth,
+    /// `<<< text`: the text and a newline are the input.
+    HereString,
+    /// `2>&1`: the descriptor becomes a copy of another one.
+    Duplicate(u32),
+}
+
+/// Commands connected with `|`: each one's output is the next one's input.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pipeline {
+    pub commands: Vec<Command>,
+    /// Ended with `&`: the shell doesn't wait for it.
+    pub background: bool,
+    /// From the first command to the last, without the `&`.
+    pub span: Span,
+}
+
+/// A whole line, or the body of a compound command: and-or lists, run
+/// one after the other.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct List {
+    pub items: Vec<AndOr>,
+}
+
+/// Pipelines joined with `&&` and `||`. They go strictly from left to
+/// right: in `a || b && c`, `c` runs if `a` or `b` succeeded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AndOr {
+    pub first: Pipeline,
+    pub rest: Vec<(Connector, Pipeline)>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Connector {
+    /// `&&`: run the next pipeline if the last one succeeded.
+    And,
+    /// `||`: run the next pipeline if the last one failed.
+    Or,
+}
+
+/// A stage of a pipeline.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    Simple(SimpleCommand),
+    Compound(CompoundCommand),
+    /// `name() { ...; }`
+    Function(FunctionDef),
+}
+
+impl Command {
+    pub fn span(&self) -> Span {
+        match self {
+            Command::Simple(command) => command.span,
+            Command::Compound(command) => command.span,
+            Command::Function(function) => function.span,
+        }
+    }
+}
+
+/// A command made of other commands, and the redirections for all of
+/// them: `while read line; do ...; done < list.txt`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompoundCommand {
+    pub kind: Compound,
+    pub redirects: Vec<Redirect>,
+    /// From the word that opens it to the one that closes it.
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Compound {
+    /// `{ list; }`
+    Group(List),
+    /// `if list; then list; elif list; then list; else list; fi`: the
+    /// condition and body of the `if` and of every `elif`, and the `else`.
+    If {
+        branches: Vec<(List, List)>,
+        otherwise: Option<List>,
+    },
+    /// `while list; do list; done`, or `until` when `until` is true.
+    Loop {
+        until: bool,
+        condition: List,
+        body: List,
+    },
+    /// `for name in words; do list; done`. Without `in`, the words are
+    /// the arguments: `"$@"`.
+    For {
+        name: String,
+        words: Option<Vec<Word>>,
+        body: List,
+    },
+    /// `case word in pattern|pattern) list;; ... esac`
+    Case { word: Word, arms: Vec<CaseArm> },
+}
+
+/// `a|b) list;;` in a `case`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CaseArm {
+    pub patterns: Vec<Word>,
+    pub body: List,
+}
+
+/// `name() { ...; }`. The body is shared by the definition and the shell
+/// that keeps it, which can be a copy on another thread.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FunctionDef {
+    pub name: String,
+    pub body: Arc<CompoundCommand>,
+    pub span: Span,
+}
+
+type Tokens = Peekable<vec::IntoIter<Token>>;
+
+/// Reserved words that end a list when they come where a command name
+/// goes. Anywhere else they are ordinary words: `echo done`.
+const CLOSERS: [&str; 8] = ["then", "elif", "else", "fi", "do", "done", "esac", "}"];
+
+/// Reserved words that start a compound command, and the word that ends
+/// it.
+const OPENERS: [(&str, &str); 6] = [
+    ("if", "fi"),
+    ("while", "done"),
+    ("until", "done"),
+    ("for", "done"),
+    ("case", "esac"),
+    ("{", "}"),
+];
+
+/// Parses a whole line, or a whole script. An empty line (or one with
+/// only a comment) gives `None`.
+pub fn parse(tokens: Vec<Token>) -> Result<Option<List>, SyntaxError> {
+    let mut tokens = tokens.into_iter().peekable();
+    let list = list(&mut tokens)?;
+    // `fi` or `)` with nothing open.
+    if let Some(token) = tokens.next() {
+        return Err(unexpected(&describe(&token), token.span));
+    }
+    Ok((!list.items.is_empty()).then_some(list))
+}
+
+/// Parses and-or lists separated by `;`, `&` and newlines, up to the end
+/// or to what ends the list: a reserved word like `fi`, `)` or `;;`. That
+/// is left for the caller to check.
+fn list(tokens: &mut Tokens) -> Result<List, SyntaxError> {
+    let mut items = Vec::new();
+    loop {
+        skip_newlines(tokens);
+        if tokens.peek().is_none_or(ends_list) {
+            break;
+        }
+        let mut and_or = and_or(tokens)?;
+        // `;`, `&` and newlines finish the and-or list; so do the end and
+        // whatever ends the whole list.
+        match tokens.peek().cloned() {
+            Some(token) if matches!(token.kind, TokenKind::Semicolon | TokenKind::Newline) => {
+                tokens.next();
+            }
+            Some(token) if token.kind == TokenKind::Background => {
+                // That would need the whole list to run in the background,
+                // in a shell of its own.
+                if !and_or.rest.is_empty() {
+                    return Err(SyntaxError {
+                        message: "'&' after '&&' or '||' is not supported".to_string(),
+                        span: token.span,
+                        incomplete: false,
+                    });
+                }
+                tokens.next();
+                and_or.first.background = true;
+            }
+            // `{ ls; } x` or `echo (`.
+            Some(token) if !ends_list(&token) => {
+                return Err(unexpected(&describe(&token), token.span));
+            }
+            _ => {}
+        }
+        items.push(and_or);
+    }
+    Ok(List { items })
+}
+
+/// Pipelines joined with `&&` and `||`.
+fn and_or(tokens: &mut Tokens) -> Result<AndOr, SyntaxError> {
+    let mut and_or = AndOr {
+        first: pipeline(tokens, None)?,
+        rest: Vec::new(),
+    };
+    while let Some(token) =
+        tokens.next_if(|token| matches!(token.kind, TokenKind::And | TokenKind::Or))
+    {
+        let connector = match token.kind {
+            TokenKind::And => Connector::And,
+            _ => Connector::Or,
+        };
+        // `make &&` at the end of a line goes on on the next one.
+        skip_newlines(tokens);
+        let next = pipeline(tokens, Some(&token))?;
+        and_or.rest.push((connector, next));
+    }
+    Ok(and_or)
+}
+
+/// Parses one pipeline, up to the operator after it or the end of the
+/// line. `after` is the operator before it, if any.
+fn pipeline(tokens: &mut Tokens, after: Option<&Token>) -> Result<Pipeline, SyntaxError> {
+    let mut commands = vec![command(tokens, after)?];
+    while let Some(pipe) = tokens.next_if(|token| token.kind == TokenKind::Pipe) {
+        skip_newlines(tokens);
+        commands.push(command(tokens, Some(&pipe))?);
+    }
+    let span = Span {
+        start: commands[0].span().start,
+        end: commands[commands.len() - 1].span().end,
+    };
+    Ok(Pipeline {
+        commands,
+        background: false,
+        span,
+    })
+}
+
+/// Parses one command. `after` is the operator before it, if any.
+fn command(tokens: &mut Tokens, after: Option<&Token>) -> Result<Command, SyntaxError> {
+    let Some(token) = tokens.peek() else {
+        // `ls |` or `ls &&` at the end: the rest may be on the next line.
+        let after = after.expect("`list` only asks for a command if there are tokens");
+        let message = match after.kind {
+            TokenKind::Pipe => "a pipe needs a command after it".to_string(),
+            _ => format!("'{}' needs a command after it", symbol(&after.kind)),
+        };
+        return Err(SyntaxError::incomplete(message, after.span));
+    };
+    let keyword = reserved(token);
+    if let Some(&(_, closer)) = OPENERS.iter().find(|(opener, _)| Some(*opener) == keyword) {
+        return compound(tokens, closer).map(Command::Compound);
+    }
+    if ends_list(token) || !matches!(token.kind, TokenKind::Word(_) | TokenKind::Redirect { .. }) {
+        // `| wc`, `ls | | wc`, `ls && || b` or `; ls`: there is no
+        // command where one should be.
+        return Err(unexpected(&describe(token), token.span));
+    }
+    simple(tokens)
+}
+
+/// Parses a simple command, or a function definition: `name() ...`.
+fn simple(tokens: &mut Tokens) -> Result<Command, SyntaxError> {
+    let mut command = Builder::default();
+    while let Some(token) = tokens
+        .next_if(|token| matches!(token.kind, TokenKind::Word(_) | TokenKind::Redirect { .. }))
+    {
+        match token.kind {
+            TokenKind::Word(word) => {
+                if command.span.is_none()
+                    && tokens
+                        .peek()
+                        .is_some_and(|next| next.kind == TokenKind::LeftParen)
+                {
+                    return function(tokens, word, token.span);
+                }
+                match assignment(&word) {
+                    Some(assignment) if command.words.is_empty() => {
+                        command.assignments.push(assignment)
+                    }
+                    _ => command.words.push(word),
+                }
+                command.extend(token.span);
+            }
+            TokenKind::Redirect { fd, op } => {
+                let (redirect, span) = redirection(tokens, fd, op, token.span)?;
+                command.extend(span);
+                command.redirects.push(redirect);
+            }
+            _ => unreachable!("only words and redirections are taken"),
+        }
+    }
+    let command = command
+        .finish()
+        .expect("`command` only asks for a simple command at a word or redirection");
+    Ok(Command::Simple(command))
+}
+
+/// Parses `name() body`, from the `(`. The body is a compound command,
+/// usually `{ ...; }`.
+fn function(tokens: &mut Tokens, name: Word, span: Span) -> Result<Command, SyntaxError> {
+    let function_name = |c: char| c.is_ascii_alphanumeric() || "_-.".contains(c);
+    let name = match name.plain() {
+        Some(name) if name.chars().all(function_name) && !CLOSERS.contains(&name) => name,
+        _ => {
+            return Err(SyntaxError {
+                message: "not a valid function name".to_string(),
+                span,
+                incomplete: false,
+            });
+        }
+    }
+    .to_string();
+    tokens.next();
+    let open = Open {
+        word: format!("{}()", name),
+        span,
+        closer: ")",
+    };
+    expect_operator(tokens, TokenKind::RightParen, &open)?;
+    skip_newlines(tokens);
+    let body = match tokens.peek() {
+        Some(token) => {
+            let keyword = reserved(token);
+            match OPENERS.iter().find(|(opener, _)| Some(*opener) == keyword) {
+                Some(&(_, closer)) => compound(tokens, closer)?,
+                None => {
+                    return Err(SyntaxError {
+                        message: "a function body must be a compound command, like '{ ...; }'"
+                            .to_string(),
+                        span: token.span,
+                        incomplete: false,
+                    });
+                }
+            }
+        }
+        None => {
+            return Err(SyntaxError::incomplete(
+                format!("'{}' needs a body", open.word),
+                span,
+            ));
+        }
+    };
+    let span = Span {
+        start: span.start,
+        end: body.span.end,
+    };
+    Ok(Command::Function(FunctionDef {
+        name,
+        body: Arc::new(body),
+        span,
+    }))
+}
+
+/// Something being parsed that has to be closed, for the error when it
+/// isn't.
+struct Open {
+    /// How it starts: `if`.
+    word: String,
+    span: Span,
+    /// What closes it: `fi`.
+    closer: &'static str,
+}
+
+impl Open {
+    /// The error for input that ends before it is closed. More lines may
+    /// still close it.
+    fn unfinished(&self) -> SyntaxError {
+        SyntaxError::incomplete(
+            format!("'{}' needs a '{}'", self.word, self.closer),
+            self.span,
+        )
+    }
+
+    /// The error for `token` coming where it doesn't belong, or for the
+    /// end of the input.
+    fn unexpected(&self, token: Option<&Token>) -> SyntaxError {
+        match token {
+            Some(token) => unexpected(&describe(token), token.span),
+            None => self.unfinished(),
+        }
+    }
+}
+
+/// Parses a compound command, from the reserved word that opens it to
+/// `closer`, and the redirections after it.
+fn compound(tokens: &mut Tokens, closer: &'static str) -> Result<CompoundCommand, SyntaxError> {
+    let opener = tokens.next().expect("`command` found the opening word");
+    let open = Open {
+        word: describe(&opener),
+        span: opener.span,
+        closer,
+    };
+    let kind = match open.word.as_str() {
+        "if" => if_clause(tokens, &open)?,
+        "while" | "until" => {
+            let condition = body(tokens, &open)?;
+            expect(tokens, "do", &open)?;
+            Compound::Loop {
+                until: open.word == "until",
+                condition,
+                body: body(tokens, &open)?,
+            }
+        }
+        "for" => for_clause(tokens, &open)?,
+        "case" => case_clause(tokens, &open)?,
+        _ => Compound::Group(body(tokens, &open)?),
+    };
+    let end = expect(tokens, closer, &open)?;
+
+    let mut span = Span {
+        start: opener.span.start,
+        end: end.end,
+    };
+    let mut redirects = Vec::new();
+    while let Some(token) = tokens.next_if(|token| matches!(token.kind, TokenKind::Redirect { .. }))
+    {
+        let TokenKind::Redirect { fd, op } = token.kind else {
+            unreachable!("only redirections are taken");
+        };
+        let (redirect, redirect_span) = redirection(tokens, fd, op, token.span)?;
+        span.end = redirect_span.end;
+        redirects.push(redirect);
+    }
+    Ok(CompoundCommand {
+        kind,
+        redirects,
+        span,
+    })
+}
+
+/// `if`, from after the `if` to before the `fi`.
+fn if_clause(tokens: &mut Tokens, open: &Open) -> Result<Compound, SyntaxError> {
+    let mut branches = Vec::new();
+    loop {
+        let condition = body(tokens, open)?;
+        expect(tokens, "then", open)?;
+        branches.push((condition, body(tokens, open)?));
+        if tokens
+            .next_if(|token| reserved(token) == Some("elif"))
+            .is_none()
+        {
+            break;
+        }
+    }
+    let otherwise = match tokens.next_if(|token| reserved(token) == Some("else")) {
+        Some(_) => Some(body(tokens, open)?),
+        None => None,
+    };
+    Ok(Compound::If {
+        branches,
+        otherwise,
+    })
+}
+
+/// `for`, from after the `for` to before the `done`.
+fn for_clause(tokens: &mut Tokens, open: &Open) -> Result<Compound, SyntaxError> {
+    let name = match tokens.next() {
+        Some(Token {
+            kind: TokenKind::Word(word),
+            span,
+        }) => match word.plain() {
+            Some(name) if shell::is_name(name) => name.to_string(),
+            _ => {
+                return Err(SyntaxError {
+                    message: "'for' needs a variable name".to_string(),
+                    span,
+                    incomplete: false,
+                });
+            }
+        },
+        token => return Err(open.unexpected(token.as_ref())),
+    };
+    // `for x; do` and `for x do` go through the arguments.
+    tokens.next_if(|token| token.kind == TokenKind::Semicolon);
+    skip_newlines(tokens);
+    let mut words = None;
+    if tokens
+        .next_if(|token| reserved(token) == Some("in"))
+        .is_some()
+    {
+        let mut list = Vec::new();
+        while let Some(token) = tokens.next_if(|token| matches!(token.kind, TokenKind::Word(_))) {
+            if let TokenKind::Word(word) = token.kind {
+                list.push(word);
+            }
+        }
+        match tokens.next() {
+            Some(token) if matches!(token.kind, TokenKind::Semicolon | TokenKind::Newline) => {}
+            token => return Err(open.unexpected(token.as_ref())),
+        }
+        words = Some(list);
+    }
+    skip_newlines(tokens);
+    expect(tokens, "do", open)?;
+    Ok(Compound::For {
+        name,
+        words,
+        body: body(tokens, open)?,
+    })
+}
+
+/// `case`, from after the `case` to before the `esac`.
+fn case_clause(tokens: &mut Tokens, open: &Open) -> Result<Compound, SyntaxError> {
+    let word = match tokens.next() {
+        Some(Token {
+            kind: TokenKind::Word(word),
+            ..
+        }) => word,
+        token => return Err(open.unexpected(token.as_ref())),
+    };
+    skip_newlines(tokens);
+    expect(tokens, "in", open)?;
+    let mut arms = Vec::new();
+    loop {
+        skip_newlines(tokens);
+        if tokens
+            .peek()
+            .is_none_or(|token| reserved(token) == Some("esac"))
+        {
+            break;
+        }
+        // `(a|b)` or `a|b)`: the `(` is optional.
+        tokens.next_if(|token| token.kind == TokenKind::LeftParen);
+        let mut patterns = Vec::new();
+        loop {
+            match tokens.next() {
+                Some(Token {
+                    kind: TokenKind::Word(word),
+                    ..
+                }) => patterns.push(word),
+                token => return Err(open.unexpected(token.as_ref())),
+            }
+            match tokens.next() {
+                Some(token) if token.kind == TokenKind::Pipe => {}
+                Some(token) if token.kind == TokenKind::RightParen => break,
+                token => return Err(open.unexpected(token.as_ref())),
+            }
+        }
+        // A branch can be empty: `*) ;;`.
+        let body = list(tokens)?;
+        arms.push(CaseArm { patterns, body });
+        // The last branch can go without its `;;`.
+        if tokens
+            .next_if(|token| token.kind == TokenKind::DoubleSemicolon)
+            .is_none()
+        {
+            break;
+        }
+    }
+    Ok(Compound::Case { word, arms })
+}
+
+/// A list with at least one command in it: the condition or the body of
+/// a compound command.
+fn body(tokens: &mut Tokens, open: &Open) -> Result<List, SyntaxError> {
+    let list = list(tokens)?;
+    if list.items.is_empty() {
+        // `if then` or `while true; do done`.
+        return Err(open.unexpected(tokens.peek()));
+    }
+    Ok(list)
+}
+
+/// Takes the reserved word `word`, which has to come next. Returns where
+/// it was.
+fn expect(tokens: &mut Tokens, word: &str, open: &Open) -> Result<Span, SyntaxError> {
+    match tokens.next_if(|token| reserved(token) == Some(word)) {
+        Some(token) => Ok(token.span),
+        None => Err(open.unexpected(tokens.peek())),
+    }
+}
+
+/// Takes the operator `kind`, which has to come next.
+fn expect_operator(tokens: &mut Tokens, kind: TokenKind, open: &Open) -> Result<(), SyntaxError> {
+    match tokens.next_if(|token| token.kind == kind) {
+        Some(_) => Ok(()),
+        None => Err(open.unexpected(tokens.peek())),
+    }
+}
+
+fn skip_newlines(tokens: &mut Tokens) {
+    while tokens
+        .next_if(|token| token.kind == TokenKind::Newline)
+        .is_some()
+    {}
+}
+
+/// The reserved word `token` is, if it can be one: a plain word.
+fn reserved(token: &Token) -> Option<&str> {
+    match &token.kind {
+        TokenKind::Word(word) => word.plain(),
+        _ => None,
+    }
+}
+
+/// Whether `token` ends a list, where a command name would go.
+fn ends_list(token: &Token) -> bool {
+    matches!(
+        token.kind,
+        TokenKind::RightParen | TokenKind::DoubleSemicolon
+    ) || reserved(token).is_some_and(|word| CLOSERS.contains(&word))
+}
+
+/// Reads the target of a redirection operator (at `span`), which is
+/// always the next word: `> out`. Returns the redirection and the span
+/// of operator and target together.
+fn redirection(
+    tokens: &mut Tokens,
+    fd: Option<u32>,
+    op: RedirectOp,
+    span: Span,
+) -> Result<(Redirect, Span), SyntaxError> {
+    match tokens.next_if(|token| matches!(token.kind, TokenKind::Word(_))) {
+        Some(Token {
+            kind: TokenKind::Word(target),
+            span: target_span,
+        }) => {
+            let both = Span {
+                start: span.start,
+                end: target_span.end,
+            };
+            Ok((redirect(fd, op, target, span)?, both))
+        }
+        _ => Err(SyntaxError {
+            message: format!("'{}' needs a target after it", op.symbol()),
+            span,
+            incomplete: false,
+        }),
+    }
+}
+
+/// How a token is written, for error messages.
+fn describe(token: &Token) -> String {
+    match &token.kind {
+        TokenKind::Word(word) => word.plain().unwrap_or("word").to_string(),
+        kind => symbol(kind).to_string(),
+    }
+}
+
+/// How an operator is written, for error messages.
+fn symbol(kind: &TokenKind) -> &'static str {
+    match kind {
+        TokenKind::Pipe => "|",
+        TokenKind::Background => "&",
+        TokenKind::Semicolon => ";",
+        TokenKind::And => "&&",
+        TokenKind::Or => "||",
+        TokenKind::Newline => "newline",
+        TokenKind::LeftParen => "(",
+        TokenKind::RightParen => ")",
+        TokenKind::DoubleSemicolon => ";;",
+        TokenKind::Word(_) | TokenKind::Redirect { .. } => "word",
+    }
+}
+
+/// A command being put together, token by token.
+#[derive(Default)]
+struct Builder {
+    assignments: Vec<Assignment>,
+    words: Vec<Word>,
+    redirects: Vec<Redirect>,
+    /// `None` until the command has its first token.
+    span: Option<Span>,
+}
+
+impl Builder {
+    /// Grows the command's span to cover `span` too.
+    fn extend(&mut self, span: Span) {
+        self.span = Some(match self.span {
+            Some(own) => Span {
+                start: own.start.min(span.start),
+                end: own.end.max(span.end),
+            },
+            None => span,
+        });
+    }
+
+    /// The finished command, or `None` if it has no tokens at all.
+    fn finish(&mut self) -> Option<SimpleCommand> {
+        let span = self.span.take()?;
+        Some(SimpleCommand {
+            assignments: std::mem::take(&mut self.assignments),
+            words: std::mem::take(&mut self.words),
+            redirects: std::mem::take(&mut self.redirects),
+            span,
+        })
+    }
+}
+
+/// Builds a `Redirect` from the operator (at `span`) and its target.
+fn redirect(
+    fd: Option<u32>,
+    op: RedirectOp,
+    target: Word,
+    span: Span,
+) -> Result<Redirect, SyntaxError> {
+    let reads = matches!(op, RedirectOp::Read | RedirectOp::HereString);
+    let fd = fd.unwrap_or(if reads { 0 } else { 1 });
+    // Programs only get standard input, output and error from us.
+    if fd > 2 {
+        return Err(SyntaxError {
+            message: "only file descriptors 0, 1 and 2 can be redirected".to_string(),
+            span,
+            incomplete: false,
+        });
+    }
+    let kind = match op {
+        RedirectOp::Read => RedirectKind::Read,
+        RedirectOp::Write => RedirectKind::Write {
+            append: false,
+            force: false,
+        },
+        RedirectOp::Append => RedirectKind::Write {
+            append: true,
+            force: false,
+        },
+        RedirectOp::Clobber => RedirectKind::Write {
+            append: false,
+            force: true,
+        },
+        RedirectOp::WriteBoth => RedirectKind::WriteBoth,
+        RedirectOp::HereString => RedirectKind::HereString,
+        RedirectOp::Duplicate => match target.literal().map(|text| text.parse::<u32>()) {
+            Some(Ok(source)) if source <= 2 => RedirectKind::Duplicate(source),
+            _ => {
+                return Err(SyntaxError {
+                    message: "'>&' needs 0, 1 or 2 after it".to_string(),
+                    span,
+                    incomplete: false,
+                });
+            }
+        },
+    };
+    Ok(Redirect {
+        fd,
+        kind,
+        target,
+        span,
+    })
+}
+
+/// The assignment in `word`, if it is one: `NAME=` written plainly at the
+/// start. `'A=1'` and `$A=1` are ordinary words.
+fn assignment(word: &Word) -> Option<Assignment> {
+    let Some(WordPart::Literal {
+        text,
+        quoted: false,
+    }) = word.parts.first()
+    else {
+        return None;
+    };
+    let (name, rest) = text.split_once('=')?;
+    if !shell::is_name(name) {
+        return None;
+    }
+    let mut value = Word {
+        parts: word.parts[1..].to_vec(),
+    };
+    if !rest.is_empty() {
+        let first = WordPart::Literal {
+            text: rest.to_string(),
+            quoted: false,
+        };
+        value.parts.insert(0, first);
+    }
+    Some(Assignment {
+        name: name.to_string(),
+        value,
+    })
+}
+
+fn unexpected(what: &str, span: Span) -> SyntaxError {
+    SyntaxError {
+        message: format!("unexpected '{}'", what),
+        span,
+        incomplete: false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::tokenize;
+
+    fn parse_line(line: &str) -> Result<Option<Pipeline>, SyntaxError> {
+        parse_list(line).map(|list| list.map(|mut list| list.items.remove(0).first))
+    }
+
+    fn parse_list(line: &str) -> Result<Option<List>, SyntaxError> {
+        parse(tokenize(line).unwrap())
+    }
+
+    fn simple(command: &Command) -> &SimpleCommand {
+        match command {
+            Command::Simple(command) => command,
+            other => panic!("not a simple command: {:?}", other),
+        }
+    }
+
+    fn texts(words: &[Word]) -> Vec<String> {
+        words.iter().map(|word| word.literal().unwrap()).collect()
+    }
+
+    #[test]
+    fn splits_pipelines_into_commands() {
+        let pipeline = parse_line("ls -l | grep 'a b'|wc\n").unwrap().unwrap();
+        let words: Vec<Vec<String>> = pipeline
+            .commands
+            .iter()
+            .map(|c| texts(&simple(c).words))
+            .collect();
+        assert_eq!(words, [&["ls", "-l"][..], &["grep", "a b"], &["wc"]]);
+        assert_eq!(pipeline.commands[1].span(), Span { start: 8, end: 18 });
+        assert_eq!(parse_line("  # nothing\n").unwrap(), None);
+    }
+
+    #[test]
+    fn rejects_pipes_without_commands() {
+        assert_eq!(parse_line("| wc").unwrap_err().span.start, 0);
+        assert_eq!(parse_line("ls | | wc").unwrap_err().span.start, 5);
+        let err = parse_line("ls -l |").unwrap_err();
+        assert_eq!(err.message, "a pipe needs a command after it");
+        assert_eq!(err.span.start, 6);
+    }
+
+    #[test]
+    fn collects_redirections_anywhere_in_a_command() {
+        let pipeline = parse_line("> out sort 2>&1 -r < in | > empty")
+            .unwrap()
+            .unwrap();
+        let sort = simple(&pipeline.commands[0]);
+        assert_eq!(texts(&sort.words), ["sort", "-r"]);
+        let redirects: Vec<(u32, RedirectKind, String)> = sort
+            .redirects
+            .iter()
+            .map(|r| (r.fd, r.kind, r.target.literal().unwrap()))
+            .collect();
+        let write = RedirectKind::Write {
+            append: false,
+            force: false,
+        };
+        assert_eq!(
+            redirects,
+            [
+                (1, write, "out".to_string()),
+                (2, RedirectKind::Duplicate(1), "1".to_string()),
+                (0, RedirectKind::Read, "in".to_string()),
+            ]
+        );
+        // A command can be nothing but redirections.
+        assert!(simple(&pipeline.commands[1]).words.is_empty());
+    }
+
+    #[test]
+    fn assignments_come_before_the_command_name() {
+        let pipeline = parse_line("A=1 B= C=\"x y\"z env D=2 'E=3'")
+            .unwrap()
+            .unwrap();
+        let command = simple(&pipeline.commands[0]);
+        let assignments: Vec<(&str, String)> = command
+            .assignments
+            .iter()
+            .map(|a| (a.name.as_str(), a.value.literal().unwrap()))
+            .collect();
+        assert_eq!(
+            assignments,
+            [
+                ("A", "1".to_string()),
+                ("B", "".to_string()),
+                ("C", "x yz".to_string())
+            ]
+        );
+        assert_eq!(texts(&command.words), ["env", "D=2", "E=3"]);
+
+        // Not names: these are commands.
+        let pipeline = parse_line("1A=x | a-b=c | $A=1").unwrap().unwrap();
+        assert!(
+            pipeline
+                .commands
+                .iter()
+                .all(|c| simple(c).assignments.is_empty())
+        );
+    }
+
+    #[test]
+    fn ampersand_ends_a_background_pipeline() {
+        let line = "sleep 5 | cat &  ";
+        let pipeline = parse_line(line).unwrap().unwrap();
+        assert!(pipeline.background);
+        assert_eq!(
+            &line[pipeline.span.start..pipeline.span.end],
+            "sleep 5 | cat"
+        );
+        assert!(!parse_line("ls").unwrap().unwrap().background);
+        assert!(parse_line("&").is_err());
+        assert!(parse_line("ls | &").is_err());
+        assert!(parse_list("a && b &").is_err());
+
+        // Something can come after it.
+        let list = parse_list("a & b").unwrap().unwrap();
+        assert!(list.items[0].first.background);
+        assert!(!list.items[1].first.background);
+    }
+
+    #[test]
+    fn splits_lists_and_and_or_chains() {
+        let list = parse_list("make && ./run || echo failed; echo done;")
+            .unwrap()
+            .unwrap();
+        let firsts: Vec<Vec<String>> = list
+            .items
+            .iter()
+            .map(|item| texts(&simple(&item.first.commands[0]).words))
+            .collect();
+        assert_eq!(firsts, [&["make"][..], &["echo", "done"]]);
+        let rest: Vec<(Connector, Vec<String>)> = list.items[0]
+            .rest
+            .iter()
+            .map(|(connector, pipeline)| (*connector, texts(&simple(&pipeline.commands[0]).words)))
+            .collect();
+        assert_eq!(
+            rest,
+            [
+                (Connector::And, vec!["./run".to_string()]),
+                (
+                    Connector::Or,
+                    vec!["echo".to_string(), "failed".to_string()]
+                )
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_operators_without_commands() {
+        assert_eq!(parse_list("; ls").unwrap_err().message, "unexpected ';'");
+        assert_eq!(parse_list("ls;;").unwrap_err().message, "unexpected ';;'");
+        assert_eq!(
+            parse_list("ls && || b").unwrap_err().message,
+            "unexpected '||'"
+        );
+        let err = parse_list("ls &&").unwrap_err();
+        assert_eq!(err.message, "'&&' needs a command after it");
+        assert_eq!(err.span, Span { start: 3, end: 5 });
+    }
+
+    #[test]
+    fn rejects_incomplete_redirections() {
+        assert!(parse_line("ls >").is_err());
+        assert!(parse_line("ls > | wc").is_err());
+        assert!(parse_line("ls 2>&x").is_err());
+        assert!(parse_line("ls 3> x").is_err());
+    }
+
+    fn compound(line: &str) -> Compound {
+        match parse_line(line).unwrap().unwrap().commands.remove(0) {
+            Command::Compound(command) => command.kind,
+            other => panic!("not a compound command: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn parses_compound_commands() {
+        let Compound::If {
+            branches,
+            otherwise,
+        } = compound("if a; then b; elif c\nthen d; e\nelse f; fi")
+        else {
+            panic!("not an if");
+        };
+        assert_eq!(branches.len(), 2);
+        assert_eq!(branches[1].1.items.len(), 2);
+        assert!(otherwise.is_some());
+
+        let Compound::For { name, words, .. } = compound("for x in a 'b c'; do echo $x; done")
+        else {
+            panic!("not a for");
+        };
+        assert_eq!(
+            (name.as_str(), texts(&words.unwrap())),
+            ("x", vec!["a".to_string(), "b c".to_string()])
+        );
+        assert!(matches!(
+            compound("for x\ndo :; done"),
+            Compound::For { words: None, .. }
+        ));
+        assert!(matches!(
+            compound("until a; do b; done"),
+            Compound::Loop { until: true, .. }
+        ));
+
+        let Compound::Case { arms, .. } = compound("case $x in\n(a|b) one;;\n*) ;;\nc) two\nesac")
+        else {
+            panic!("not a case");
+        };
+        let patterns: Vec<Vec<String>> = arms.iter().map(|arm| texts(&arm.patterns)).collect();
+        assert_eq!(patterns, [&["a", "b"][..], &["*"], &["c"]]);
+        assert!(arms[1].body.items.is_empty());
+
+        // Redirections after the end apply to all of it.
+        let pipeline = parse_line("{ a; b; } > out | wc").unwrap().unwrap();
+        let Command::Compound(group) = &pipeline.commands[0] else {
+            panic!("not a group");
+        };
+        assert_eq!(group.redirects.len(), 1);
+        assert_eq!(group.span, Span { start: 0, end: 15 });
+    }
+
+    #[test]
+    fn reserved_words_only_count_where_a_command_goes() {
+        let pipeline = parse_line("echo if then fi done").unwrap().unwrap();
+        assert_eq!(texts(&simple(&pipeline.commands[0]).words).len(), 5);
+        assert!(parse_line("'if' x").is_ok());
+        assert_eq!(parse_list("fi").unwrap_err().message, "unexpected 'fi'");
+        assert_eq!(
+            parse_list("if a; fi").unwrap_err().message,
+            "unexpected 'fi'"
+        );
+        assert_eq!(
+            parse_list("if then fi").unwrap_err().message,
+            "unexpected 'then'"
+        );
+        assert_eq!(
+            parse_list("{ ls; } x").unwrap_err().message,
+            "unexpected 'x'"
+        );
+        assert!(parse_list("while a; do done").is_err());
+    }
+
+    #[test]
+    fn parses_function_definitions() {
+        let list = parse_list("greet() {\n  echo hi $1\n}; greet you")
+            .unwrap()
+            .unwrap();
+        let Command::Function(function) = &list.items[0].first.commands[0] else {
+            panic!("not a function");
+        };
+        assert_eq!(function.name, "greet");
+        assert!(matches!(function.body.kind, Compound::Group(_)));
+        assert_eq!(list.items.len(), 2);
+        assert!(parse_list("f() echo").is_err());
+        assert!(parse_list("'f'() { :; }").is_err());
+    }
+
+    #[test]
+    fn knows_when_the_input_is_incomplete() {
+        let incomplete = |line: &str| parse_list(line).unwrap_err().incomplete;
+        for line in [
+            "if a",
+            "if a; then",
+            "while a; do b",
+            "for x in",
+            "case x in a)",
+            "f()",
+            "{",
+            "ls |",
+            "ls &&",
+        ] {
+            assert!(incomplete(line), "{}", line);
+        }
+        let err = parse_list("for x in a b; do\n  echo").unwrap_err();
+        assert_eq!(err.message, "'for' needs a 'done'");
+        assert!(!incomplete("if a; fi"));
+        assert!(!incomplete("ls | | wc"));
+    }
+}
@@ -0,0 +1,734 @@
+// The command policy: what Safe-Shell refuses to run, or asks about first.
+//
+// Before a pipeline starts, each of its commands is checked against a
+// list of rules. That happens after expansion, so `rm -rf $DIR` is
+// checked with the value of DIR. The rules come from
+// `~/.safe_shell_policy`, or the file `$SAFE_SHELL_POLICY` names:
+//
+//     # action  command    arguments
+//     deny      rm         -r|-R|--recursive  /|/*|~
+//     confirm   rm         -r|-R|--recursive
+//     confirm   mkfs|mkfs.*
+//     allow     git|cargo|ls
+//     otherwise confirm
+//     root      ~/projects/site
+//     audit     ~/.safe_shell_audit
+//
+// A rule applies when its command matches, and each argument pattern
+// after it matches one of the arguments. `a|b` means either one; the rest
+// is like file patterns (see `glob.rs`), with two twists:
+//
+//     -rf      short options, together or not: `-rf`, `-fr`, `-r -f` and
+//              `-vfr` all have both
+//     /etc/*   a pattern that starts with `/` or `~` is a path: it is
+//              matched against the absolute path of each argument, one
+//              component at a time, so `rm -r ..` in `/home` is `rm -r /`,
+//              and `/*` is `/etc` but not `/etc/hosts`
+//
+// The command is matched by its name alone (`/bin/rm` is `rm`), also
+// behind `sudo`, `env`, `nice` and the like: `sudo rm -rf /` is still
+// `rm -rf /`.
+//
+// When several rules match, the strictest one wins: `deny` runs nothing,
+// `confirm` asks first, and `allow` just runs the command. A program no
+// rule matches gets the `otherwise` action, `allow` unless the file says
+// so: with `otherwise deny`, the `allow` rules list the only programs
+// that can run. Builtins and functions are left out of that, but not out
+// of the rules that name them.
+//
+// With a `root`, a command that writes outside that directory needs
+// confirmation: a redirection like `> /etc/hosts`, or the files that
+// programs like `rm`, `mv`, `cp` and `touch` change (see `WRITERS`).
+//
+// Whatever decided is shown: the rule, with the file and line it is on.
+// Asking happens on the terminal (`/dev/tty`), even in a script. Where
+// there is none, the answer is no.
+//
+// `override` in front of a command runs it whatever the rules say. Every
+// override is written to the `audit` file (`~/.safe_shell_audit` unless
+// the policy says otherwise) first, with the time, the user (by uid, with
+// `$USER` next to it for readability), the directory and the rule it got
+// past. If that can't be done, the command doesn't run. Refused commands
+// and the answers to questions go there too.
+//
+// Without a policy file, the built-in rules in `DEFAULT` apply.
+//
+// Why not a sandbox? This is a seatbelt against typos and copy-pasted
+// commands, nothing more: rules see the command line, not what programs
+// do, so `sh -c 'rm -rf /'` or a script that deletes files gets past
+// them. Paths are worked out from their text, without following symlinks.
+
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Component, Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::builtins;
+use crate::expand;
+use crate::glob;
+use crate::lexer::WordPart;
+use crate::parser::{Command, Redirect, RedirectKind};
+use crate::shell::Shell;
+use crate::sys;
+
+/// The rules used when there is no policy file.
+pub const DEFAULT: &str = "\
+deny     rm           -r|-R|--recursive  /|/*|~
+confirm  rm           -r|-R|--recursive
+confirm  dd
+confirm  mkfs|mkfs.*
+confirm  chmod|chown  -R|--recursive
+";
+
+/// Programs that run the command after them.
+const WRAPPERS: [&str; 8] = [
+    "sudo", "doas", "env", "nice", "nohup", "time", "timeout", "xargs",
+];
+
+/// Programs that change the files named in their arguments, and which of
+/// the arguments those are.
+const WRITERS: [(&str, Writes); 15] = [
+    ("rm", Writes::All),
+    ("rmdir", Writes::All),
+    ("mkdir", Writes::All),
+    ("touch", Writes::All),
+    ("truncate", Writes::All),
+    ("shred", Writes::All),
+    ("tee", Writes::All),
+    ("mv", Writes::All),
+    ("cp", Writes::Last),
+    ("ln", Writes::Last),
+    ("install", Writes::Last),
+    ("chmod", Writes::AllButFirst),
+    ("chown", Writes::AllButFirst),
+    ("chgrp", Writes::AllButFirst),
+    ("dd", Writes::Output),
+];
+
+#[derive(Debug, Clone, Copy)]
+enum Writes {
+    All,
+    /// The target of `cp a b dir`.
+    Last,
+    /// The files of `chmod 644 a b`.
+    AllButFirst,
+    /// `of=file`.
+    Output,
+}
+
+/// What happens to a command, from the mildest to the strictest.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Action {
+    #[default]
+    Allow,
+    Confirm,
+    Deny,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Policy {
+    rules: Vec<Rule>,
+    /// What happens to programs no rule matches, and the line that said
+    /// so.
+    otherwise: (Action, Option<String>),
+    /// Writing outside this directory needs confirmation.
+    root: Option<PathBuf>,
+    /// Where overrides and refusals are logged.
+    pub audit: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone)]
+struct Rule {
+    action: Action,
+    /// Patterns for the command name, any of which will do.
+    command: Vec<String>,
+    /// For each argument pattern, its alternatives.
+    args: Vec<Vec<String>>,
+    /// The rule as the user sees it: `file:line: text`.
+    source: String,
+}
+
+/// What the policy says about a command: the strictest action that
+/// applies, and why.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Verdict {
+    pub action: Action,
+    pub reason: Option<String>,
+}
+
+impl Policy {
+    /// Reads the rules in `text`, from the file `name`. A `~` at the start
+    /// of a path is `home`.
+    pub fn parse(text: &str, name: &str, home: Option<&str>) -> Result<Policy, String> {
+        let mut policy = Policy::default();
+        for (i, line) in text.lines().enumerate() {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let Some((&keyword, rest)) = fields.split_first() else {
+                continue;
+            };
+            if keyword.starts_with('#') {
+                continue;
+            }
+            let error = |message: String| format!("{}:{}: {}", name, i + 1, message);
+            let source = format!("{}:{}: {}", name, i + 1, fields.join(" "));
+            match (keyword, rest) {
+                ("root" | "audit", [path]) => {
+                    let path = PathBuf::from(tilde(path, home));
+                    if !path.is_absolute() {
+                        return Err(error(format!("{}: not an absolute path", path.display())));
+                    }
+                    if keyword == "audit" {
+                        policy.audit = Some(path);
+                    } else {
+                        // `cd` into a symlinked root lands in the real
+                        // directory, which is what the current directory
+                        // then says.
+                        policy.root = Some(fs::canonicalize(&path).unwrap_or(normalize(&path)));
+                    }
+                }
+                ("root" | "audit", _) => {
+                    return Err(error(format!("'{}' needs one path", keyword)));
+                }
+                ("otherwise", [action]) => {
+                    let action = parse_action(action).ok_or_else(|| {
+                        error(format!("'{}': not allow, confirm or deny", action))
+                    })?;
+                    policy.otherwise = (action, Some(source));
+                }
+                ("otherwise", _) => return Err(error("'otherwise' needs one action".to_string())),
+                (_, rest) => {
+                    let action = parse_action(keyword)
+                        .ok_or_else(|| error(format!("unknown rule '{}'", keyword)))?;
+                    let Some((command, args)) = rest.split_first() else {
+                        return Err(error(format!("'{}' needs a command", keyword)));
+                    };
+                    let alternatives = |pattern: &str| -> Vec<String> {
+                        pattern.split('|').map(|p| tilde(p, home)).collect()
+                    };
+                    policy.rules.push(Rule {
+                        action,
+                        command: alternatives(command),
+                        args: args.iter().map(|arg| alternatives(arg)).collect(),
+                        source,
+                    });
+                }
+            }
+        }
+        Ok(policy)
+    }
+
+    /// Checks the command `words`, run in `cwd`, whose redirections write
+    /// to `writes`: `None` there is a file that is only named when the
+    /// command runs, by `> $(cmd)`. `program` says whether `words` starts
+    /// a program, rather than a builtin or function.
+    pub fn check(
+        &self,
+        words: &[String],
+        writes: &[Option<String>],
+        cwd: &Path,
+        program: bool,
+    ) -> Verdict {
+        let mut verdict = Verdict {
+            action: Action::Allow,
+            reason: None,
+        };
+        let mut stricter = |action: Action, reason: &str| {
+            if action > verdict.action {
+                verdict = Verdict {
+                    action,
+                    reason: Some(reason.to_string()),
+                };
+            }
+        };
+
+        let mut matched = false;
+        for command in commands(words) {
+            let name = base_name(&command[0]);
+            let args = &command[1..];
+            for rule in self
+                .rules
+                .iter()
+                .filter(|rule| rule.matches(name, args, cwd))
+            {
+                matched = true;
+                stricter(rule.action, &rule.source);
+            }
+            if let Some(root) = &self.root {
+                for path in written(name, args) {
+                    if !absolute(path, cwd).starts_with(root) {
+                        stricter(Action::Confirm, &outside(root, path));
+                    }
+                }
+            }
+        }
+        if let Some(root) = &self.root {
+            for target in writes {
+                match target {
+                    Some(path) if absolute(path, cwd).starts_with(root) => {}
+                    Some(path) => stricter(Action::Confirm, &outside(root, path)),
+                    None => stricter(
+                        Action::Confirm,
+                        "writes to a file that `$(...)` names, which can't be checked first",
+                    ),
+                }
+            }
+        }
+        if program && !matched {
+            let (action, source) = &self.otherwise;
+            stricter(*action, source.as_deref().unwrap_or_default());
+        }
+        verdict
+    }
+}
+
+impl Rule {
+    fn matches(&self, name: &str, args: &[String], cwd: &Path) -> bool {
+        self.command
+            .iter()
+            .any(|pattern| glob::matches(pattern, name))
+            && self.args.iter().all(|alternatives| {
+                alternatives
+                    .iter()
+                    .any(|pattern| arg_matches(pattern, args, cwd))
+            })
+    }
+}
+
+/// Whether one of `args` matches `pattern`.
+fn arg_matches(pattern: &str, args: &[String], cwd: &Path) -> bool {
+    let options = args.iter().take_while(|arg| *arg != "--");
+    if let Some(letters) = pattern.strip_prefix('-')
+        && !letters.is_empty()
+        && letters.chars().all(|c| c.is_ascii_alphanumeric())
+    {
+        // `-rf`: each letter in some `-xyz` argument.
+        let given: String = options
+            .filter(|arg| !arg.starts_with("--"))
+            .filter_map(|arg| arg.strip_prefix('-'))
+            .collect();
+        return letters.chars().all(|c| given.contains(c));
+    }
+    if pattern.starts_with('/') {
+        let pattern: Vec<&str> = pattern.split('/').filter(|part| !part.is_empty()).collect();
+        return operands(args).any(|arg| {
+            let path = absolute(arg, cwd);
+            let parts: Vec<_> = path
+                .iter()
+                .skip(1)
+                .map(|part| part.to_string_lossy())
+                .collect();
+            parts.len() == pattern.len()
+                && pattern
+                    .iter()
+                    .zip(&parts)
+                    .all(|(pattern, part)| glob::matches(pattern, part))
+        });
+    }
+    args.iter().any(|arg| glob::matches(pattern, arg))
+}
+
+/// `words`, and if it starts with a wrapper like `sudo`, the commands it
+/// may run: any later word that isn't an option or `NAME=value` could be
+/// where the command starts, since we can't know which options of the
+/// wrapper take a value.
+fn commands(words: &[String]) -> Vec<&[String]> {
+    let mut found = Vec::new();
+    if words.is_empty() {
+        return found;
+    }
+    found.push(words);
+    if WRAPPERS.contains(&base_name(&words[0])) {
+        for (i, word) in words.iter().enumerate().skip(1) {
+            if !word.starts_with('-') && !word.contains('=') {
+                found.push(&words[i..]);
+            }
+        }
+    }
+    found
+}
+
+/// The arguments that aren't options: everything after `--`, and
+/// anything else that doesn't start with `-`.
+fn operands(args: &[String]) -> impl Iterator<Item = &str> {
+    let end = args.iter().position(|arg| arg == "--");
+    args.iter().enumerate().filter_map(move |(i, arg)| {
+        let after_end = end.is_some_and(|end| i > end);
+        (after_end || (!arg.starts_with('-') && Some(i) != end)).then_some(arg.as_str())
+    })
+}
+
+/// The files the program `name` changes when given `args`.
+fn written<'a>(name: &str, args: &'a [String]) -> Vec<&'a str> {
+    let Some((_, writes)) = WRITERS.iter().find(|(writer, _)| *writer == name) else {
+        return Vec::new();
+    };
+    let operands: Vec<&str> = operands(args).collect();
+    match writes {
+        Writes::All => operands,
+        Writes::Last => operands.last().copied().into_iter().collect(),
+        Writes::AllButFirst => operands.into_iter().skip(1).collect(),
+        Writes::Output => args
+            .iter()
+            .filter_map(|arg| arg.strip_prefix("of="))
+            .collect(),
+    }
+}
+
+/// The files the `redirects` write to. `None` is a name with `$(...)` in
+/// it: finding it out would run the command twice.
+fn redirect_targets(redirects: &[Redirect], shell: &Shell) -> Vec<Option<String>> {
+    redirects
+        .iter()
+        .filter(|redirect| {
+            matches!(
+                redirect.kind,
+                RedirectKind::Write { .. } | RedirectKind::WriteBoth
+            )
+        })
+        .map(|redirect| {
+            let parts = &redirect.target.parts;
+            if parts
+                .iter()
+                .any(|part| matches!(part, WordPart::Command { .. }))
+            {
+                return None;
+            }
+            expand::expand_word(&redirect.target, shell).ok()
+        })
+        .collect()
+}
+
+/// Checks `command`, whose words expanded to `words`, before it runs.
+/// Asks if the policy says so, and logs what needs logging. A leading
+/// `override` is taken off `words`. Returns whether the command may run;
+/// if not, the user has been told why.
+pub fn permit(shell: &Shell, command: &Command, words: &mut Vec<String>) -> bool {
+    let overridden = words.first().is_some_and(|word| word == "override");
+    if overridden {
+        words.remove(0);
+        if words.is_empty() {
+            eprintln!("override: needs a command");
+            return false;
+        }
+    }
+    let redirects = match command {
+        Command::Simple(command) => &command.redirects,
+        Command::Compound(command) => &command.redirects,
+        Command::Function(_) => return true,
+    };
+    let writes = redirect_targets(redirects, shell);
+    let cwd = env::current_dir().unwrap_or_default();
+    let program = !words.is_empty()
+        && !shell.functions.contains_key(&words[0])
+        && !builtins::is_builtin(words);
+    let verdict = shell.policy.check(words, &writes, &cwd, program);
+    let text = words.join(" ");
+    let reason = verdict.reason.as_deref().unwrap_or("no rule");
+
+    if overridden {
+        return match audit(shell, "override", &text, reason) {
+            Ok(()) => true,
+            Err(e) => {
+                eprintln!(
+                    "safe_shell: override: not running {}: can't log it: {}",
+                    text, e
+                );
+                false
+            }
+        };
+    }
+    let allowed = match verdict.action {
+        Action::Allow => return true,
+        Action::Deny => {
+            eprintln!("safe_shell: denied by policy: {}", text);
+            eprintln!("  rule: {}", reason);
+            let _ = audit(shell, "denied", &text, reason);
+            false
+        }
+        Action::Confirm => {
+            eprintln!("safe_shell: needs confirmation: {}", text);
+            eprintln!("  rule: {}", reason);
+            let yes = ask("run it? [y/N] ");
+            let _ = audit(
+                shell,
+                if yes { "confirmed" } else { "declined" },
+                &text,
+                reason,
+            );
+            yes
+        }
+    };
+    if !allowed {
+        eprintln!("  (`override {}` runs it anyway, and is logged)", text);
+    }
+    allowed
+}
+
+/// Asks a yes-or-no question on the terminal.
+fn ask(question: &str) -> bool {
+    let terminal = OpenOptions::new().read(true).write(true).open("/dev/tty");
+    let answer = terminal.and_then(|mut terminal| {
+        write!(terminal, "{}", question)?;
+        let mut answer = String::new();
+        BufReader::new(terminal).read_line(&mut answer)?;
+        Ok(answer)
+    });
+    match answer {
+        Ok(answer) => matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"),
+        Err(e) => {
+            eprintln!("  can't ask ({}), so no", e);
+            false
+        }
+    }
+}
+
+/// Appends a line about `event` to the audit log.
+fn audit(shell: &Shell, event: &str, command: &str, reason: &str) -> io::Result<()> {
+    let Some(path) = &shell.policy.audit else {
+        return Err(io::Error::other("there is no audit log"));
+    };
+    let cwd = env::current_dir().unwrap_or_default();
+    // The uid is what counts: `$USER` can be set to anything, even by the
+    // command being audited. The name is only there to make the log easier
+    // to read.
+    let user = shell.get_var("USER").unwrap_or("?");
+    // One record per line, whatever is in the text: a quoted newline in
+    // the command must not start what looks like another record. So
+    // control characters are escaped (`\n`), and backslashes and quotes
+    // too, so that the escapes can be told apart from typed text.
+    let line = format!(
+        "{} uid {} ({}) {} in {}: {} [{}]\n",
+        now(),
+        sys::user_id(),
+        user.escape_debug(),
+        event,
+        cwd.display().to_string().escape_debug(),
+        command.escape_debug(),
+        reason.escape_debug()
+    );
+    // Only the user can read it: commands can have secrets in them.
+    let mut file = OpenOptions::new()
+        .append(true)
+        .create(true)
+        .mode(0o600)
+        .open(path)?;
+    file.write_all(line.as_bytes())
+}
+
+/// The time now, in UTC: `2026-10-19 14:03:27 UTC`.
+fn now() -> String {
+    let seconds = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_secs());
+    let (days, time) = ((seconds / 86400) as i64, seconds % 86400);
+    // From days since 1970 to year, month and day, counting in eras of
+    // 400 years, which all have the same number of days. This is Howard
+    // Hinnant's `civil_from_days`.
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    // Months counted from March, so that February comes last.
+    let month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month + 2) / 5 + 1;
+    let month = if month < 10 { month + 3 } else { month - 9 };
+    let year = year_of_era + era * 400 + (month <= 2) as i64;
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC",
+        year,
+        month,
+        day,
+        time / 3600,
+        time / 60 % 60,
+        time % 60
+    )
+}
+
+fn parse_action(word: &str) -> Option<Action> {
+    match word {
+        "allow" => Some(Action::Allow),
+        "confirm" => Some(Action::Confirm),
+        "deny" => Some(Action::Deny),
+        _ => None,
+    }
+}
+
+/// `path` with a leading `~` replaced by `home`.
+fn tilde(path: &str, home: Option<&str>) -> String {
+    match (path.strip_prefix('~'), home) {
+        (Some(rest), Some(home)) if rest.is_empty() || rest.starts_with('/') => {
+            format!("{}{}", home, rest)
+        }
+        _ => path.to_string(),
+    }
+}
+
+/// `rm` for `/bin/rm`.
+fn base_name(command: &str) -> &str {
+    command.rsplit('/').next().unwrap_or(command)
+}
+
+fn outside(root: &Path, path: &str) -> String {
+    format!(
+        "writes outside the project root {}: {}",
+        root.display(),
+        path
+    )
+}
+
+/// `path` as an absolute path, with `.` and `..` worked out.
+fn absolute(path: &str, cwd: &Path) -> PathBuf {
+    normalize(&cwd.join(path))
+}
+
+/// `path` without `.` and `..`: `/a/./b/../c` is `/a/c`. This looks at
+/// the text only, without following symlinks.
+fn normalize(path: &Path) -> PathBuf {
+    let mut normal = PathBuf::from("/");
+    for component in path.components() {
+        match component {
+            Component::Normal(part) => normal.push(part),
+            Component::ParentDir => {
+                normal.pop();
+            }
+            _ => {}
+        }
+    }
+    normal
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(policy: &Policy, line: &str, cwd: &str) -> Action {
+        let words: Vec<String> = line.split_whitespace().map(String::from).collect();
+        policy.check(&words, &[], Path::new(cwd), true).action
+    }
+
+    #[test]
+    fn the_strictest_matching_rule_wins() {
+        let policy = Policy::parse(DEFAULT, "built-in", Some("/home/ana")).unwrap();
+        for line in [
+            "rm -rf /",
+            "rm -r -f /",
+            "rm --recursive /etc",
+            "sudo -u root rm -Rv /home/ana/",
+        ] {
+            assert_eq!(check(&policy, line, "/tmp"), Action::Deny, "{}", line);
+        }
+        assert_eq!(check(&policy, "/bin/rm -fr ..", "/home"), Action::Deny);
+        for line in [
+            "rm -rf build",
+            "rm -r /etc/x",
+            "dd if=a of=b",
+            "mkfs.ext4 /dev/sdb1",
+            "chmod -R 700 .",
+        ] {
+            assert_eq!(check(&policy, line, "/tmp"), Action::Confirm, "{}", line);
+        }
+        for line in [
+            "rm -f a",
+            "chmod 700 a",
+            "ls -R /",
+            "echo rm -rf /",
+            "rm -- -r",
+        ] {
+            assert_eq!(check(&policy, line, "/tmp"), Action::Allow, "{}", line);
+        }
+
+        let verdict = policy.check(&["dd".to_string()], &[], Path::new("/"), true);
+        assert_eq!(verdict.reason.as_deref(), Some("built-in:3: confirm dd"));
+    }
+
+    #[test]
+    fn otherwise_applies_to_programs_no_rule_matches() {
+        let text = "allow ls|git\ndeny git push --force\n\notherwise deny\n";
+        let policy = Policy::parse(text, "p", None).unwrap();
+        assert_eq!(check(&policy, "ls -l", "/"), Action::Allow);
+        assert_eq!(check(&policy, "git push", "/"), Action::Allow);
+        assert_eq!(check(&policy, "git push --force", "/"), Action::Deny);
+        let verdict = policy.check(&["cat".to_string()], &[], Path::new("/"), true);
+        assert_eq!(verdict.reason.as_deref(), Some("p:4: otherwise deny"));
+        // A builtin.
+        let verdict = policy.check(&["cd".to_string()], &[], Path::new("/"), false);
+        assert_eq!(verdict.action, Action::Allow);
+    }
+
+    #[test]
+    fn writing_outside_the_root_needs_confirmation() {
+        let policy = Policy::parse("# my project\nroot /nonexistent/site\n", "p", None).unwrap();
+        let site = "/nonexistent/site";
+        assert_eq!(check(&policy, "touch a b/c", site), Action::Allow);
+        assert_eq!(check(&policy, "touch ../a", site), Action::Confirm);
+        assert_eq!(check(&policy, "cp /etc/hosts .", site), Action::Allow);
+        assert_eq!(check(&policy, "cp hosts /etc", site), Action::Confirm);
+        assert_eq!(
+            check(&policy, "chmod 644 /nonexistent/site/x", "/"),
+            Action::Allow
+        );
+        assert_eq!(
+            check(&policy, "dd if=/dev/zero of=/tmp/x", site),
+            Action::Confirm
+        );
+
+        let echo = ["echo".to_string()];
+        let inside = policy.check(&echo, &[Some("out".to_string())], Path::new(site), false);
+        assert_eq!(inside.action, Action::Allow);
+        let outside = policy.check(
+            &echo,
+            &[Some("/etc/motd".to_string())],
+            Path::new(site),
+            false,
+        );
+        assert_eq!(
+            outside.reason.as_deref(),
+            Some("writes outside the project root /nonexistent/site: /etc/motd")
+        );
+        let unknown = policy.check(&[], &[None], Path::new(site), false);
+        assert_eq!(unknown.action, Action::Confirm);
+    }
+
+    #[test]
+    fn the_audit_log_has_the_real_uid_and_one_line_per_record() {
+        let path = env::temp_dir().join(format!("safe_shell_audit_{}", std::process::id()));
+        let mut shell = Shell {
+            policy: std::sync::Arc::new(Policy {
+                audit: Some(path.clone()),
+                ..Policy::default()
+            }),
+            ..Shell::default()
+        };
+        // Anyone can claim to be root in `$USER`.
+        shell.set_var("USER", "root".to_string());
+        audit(&shell, "override", "rm -rf /", "p:1: deny rm").unwrap();
+        let log = fs::read_to_string(&path).unwrap();
+        let user = format!(" uid {} (root) override in ", sys::user_id());
+        assert!(log.contains(&user), "{}", log);
+        assert!(log.ends_with(": rm -rf / [p:1: deny rm]\n"), "{}", log);
+
+        // A newline in the command can't forge a record of its own.
+        let forged = "echo \"x\n2026-01-01 00:00:00 UTC uid 0 (root) confirmed in /: ls\"";
+        audit(&shell, "override", forged, "p:1").unwrap();
+        let log = fs::read_to_string(&path).unwrap();
+        let _ = fs::remove_file(&path);
+        assert_eq!(log.lines().count(), 2, "{}", log);
+        assert!(log.contains(r#": echo \"x\n2026-01-01"#), "{}", log);
+    }
+
+    #[test]
+    fn mistakes_in_the_file_say_where_they_are() {
+        let parse = |text| Policy::parse(text, "p", None).unwrap_err();
+        assert_eq!(parse("allow ls\ndney rm"), "p:2: unknown rule 'dney'");
+        assert_eq!(parse("confirm"), "p:1: 'confirm' needs a command");
+        assert_eq!(parse("root site"), "p:1: site: not an absolute path");
+        assert_eq!(
+            parse("otherwise maybe"),
+            "p:1: 'maybe': not allow, confirm or deny"
+        );
+    }
+}
@@ -0,0 +1,202 @@
+// Redirections: sending a command's input and output somewhere else.
+//
+// A program doesn't know or care where its standard input, output and
+// error go. They are just file descriptors 0, 1 and 2, which it inherits
+// from the shell. To redirect them we open the file ourselves and hand the
+// child the open file in place of the terminal:
+//
+//     sort < names.txt > sorted.txt 2> errors.log
+//          \_________/ \__________/ \____________/
+//            fd 0         fd 1           fd 2
+//
+// Redirections happen left to right, after the pipes are connected. That
+// is why `cmd 2>&1 > file` sends errors to the terminal but output to the
+// file: at the time of `2>&1`, fd 1 was still the terminal.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::os::fd::{AsFd, OwnedFd};
+use std::thread;
+
+use crate::expand;
+use crate::parser::{Redirect, RedirectKind};
+use crate::shell::Shell;
+
+/// Where a command's standard input, output and error go. `None` means
+/// wherever the shell's own go (usually the terminal).
+pub type Fds = [Option<OwnedFd>; 3];
+
+/// Fills the gaps in `fds` with where input and output go for the
+/// commands the shell runs right now: inside `{ ...; } > file`, output
+/// goes to the file.
+pub fn inherit(fds: &mut Fds, shell: &Shell) -> Result<(), String> {
+    for (fd, io) in fds.iter_mut().zip(&shell.io) {
+        if let (None, Some(io)) = (&fd, io) {
+            *fd = Some(io.try_clone().map_err(|e| describe("dup", e))?);
+        }
+    }
+    Ok(())
+}
+
+/// Applies `redirects` to `fds`, in order. The error message names the
+/// file that could not be opened.
+pub fn apply(redirects: &[Redirect], fds: &mut Fds, shell: &Shell) -> Result<(), String> {
+    let options = &shell.options;
+    for redirect in redirects {
+        // `> $file` is one file even if the name has spaces in it.
+        let target = &expand::expand_word(&redirect.target, shell)?;
+        let fd = redirect.fd as usize;
+        match redirect.kind {
+            RedirectKind::Read => {
+                let file = File::open(target).map_err(|e| describe(target, e))?;
+                fds[fd] = Some(file.into());
+            }
+            RedirectKind::Write { append, force } => {
+                let file = open_for_writing(target, append, options.noclobber && !force)?;
+                fds[fd] = Some(file.into());
+            }
+            RedirectKind::WriteBoth => {
+                let file = open_for_writing(target, false, options.noclobber)?;
+                let copy = file.try_clone().map_err(|e| describe(target, e))?;
+                fds[1] = Some(file.into());
+                fds[2] = Some(copy.into());
+            }
+            RedirectKind::HereString => {
+                fds[fd] = Some(here_string(target).map_err(|e| describe("<<<", e))?);
+            }
+            RedirectKind::Duplicate(source) => {
+                let source = source as usize;
+                let copy = match &fds[source] {
+                    Some(open) => open.try_clone(),
+                    None => own_fd(source),
+                };
+                fds[fd] = Some(copy.map_err(|e| describe(&format!("{}>&{}", fd, source), e))?);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Opens `path` for `>` or `>>`. With `no_clobber`, an existing regular
+/// file is left alone and we return an error instead.
+fn open_for_writing(path: &str, append: bool, no_clobber: bool) -> Result<File, String> {
+    let mut open = OpenOptions::new();
+    open.write(true);
+    if append {
+        open.append(true).create(true);
+    } else if no_clobber {
+        // `create_new` checks and creates in one step, so no other process
+        // can sneak a file in between.
+        open.create_new(true);
+    } else {
+        open.create(true).truncate(true);
+    }
+    match open.open(path) {
+        Ok(file) => Ok(file),
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+            // noclobber protects files, not devices: `> /dev/null` is fine.
+            if fs::metadata(path).is_ok_and(|meta| meta.is_file()) {
+                return Err(format!(
+                    "{}: cannot overwrite existing file (noclobber is on; use '>|' to force)",
+                    path
+                ));
+            }
+            OpenOptions::new()
+                .write(true)
+                .open(path)
+                .map_err(|e| describe(path, e))
+        }
+        Err(e) => Err(describe(path, e)),
+    }
+}
+
+/// A pipe to read `text` and a newline from, for `<<< text`.
+fn here_string(text: &str) -> io::Result<OwnedFd> {
+    let (reader, mut writer) = io::pipe()?;
+    let text = format!("{}\n", text);
+    // A pipe only holds so much (64 KiB on Linux). Writing on a thread of
+    // its own means a long here-string can't block the shell: the writer
+    // simply waits until the command has read enough.
+    thread::spawn(move || {
+        let _ = writer.write_all(text.as_bytes());
+    });
+    Ok(reader.into())
+}
+
+/// A copy of the shell's own standard input, output or error.
+fn own_fd(fd: usize) -> io::Result<OwnedFd> {
+    match fd {
+        0 => io::stdin().as_fd().try_clone_to_owned(),
+        1 => io::stdout().as_fd().try_clone_to_owned(),
+        _ => io::stderr().as_fd().try_clone_to_owned(),
+    }
+}
+
+/// `notes.txt: no such file or directory`, without the `(os error 2)`
+/// that `io::Error` adds.
+fn describe(path: &str, e: io::Error) -> String {
+    let reason = match e.kind() {
+        io::ErrorKind::NotFound => "no such file or directory".to_string(),
+        io::ErrorKind::PermissionDenied => "permission denied".to_string(),
+        io::ErrorKind::IsADirectory => "is a directory".to_string(),
+        _ => e.to_string(),
+    };
+    format!("{}: {}", path, reason)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::tokenize;
+    use crate::parser::{Command, parse};
+    use std::io::Read;
+
+    fn redirects(line: &str) -> Vec<Redirect> {
+        let list = parse(tokenize(line).unwrap()).unwrap().unwrap();
+        match &list.items[0].first.commands[0] {
+            Command::Simple(command) => command.redirects.clone(),
+            other => panic!("not a simple command: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn noclobber_protects_existing_files() {
+        let path = std::env::temp_dir().join(format!("safe_shell_clobber_{}", std::process::id()));
+        let path = path.to_str().unwrap();
+        fs::write(path, "precious").unwrap();
+        let mut shell = Shell::default();
+        shell.options.noclobber = true;
+
+        let mut fds = Fds::default();
+        let err = apply(&redirects(&format!("x > {}", path)), &mut fds, &shell).unwrap_err();
+        assert!(err.contains("noclobber"), "{}", err);
+        assert_eq!(fs::read_to_string(path).unwrap(), "precious");
+
+        // `>>` adds to a file without destroying it, and `>|` insists.
+        apply(&redirects(&format!("x >> {}", path)), &mut fds, &shell).unwrap();
+        apply(&redirects(&format!("x >| {}", path)), &mut fds, &shell).unwrap();
+        assert_eq!(fs::read_to_string(path).unwrap(), "");
+        apply(&redirects("x > /dev/null"), &mut fds, &shell).unwrap();
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn here_strings_and_errors() {
+        let mut fds = Fds::default();
+        let mut shell = Shell::default();
+        shell.set_var("WORDS", "two  words".to_string());
+        apply(&redirects("cat <<< $WORDS"), &mut fds, &shell).unwrap();
+        let mut text = String::new();
+        File::from(fds[0].take().unwrap())
+            .read_to_string(&mut text)
+            .unwrap();
+        assert_eq!(text, "two  words\n");
+
+        let err = apply(
+            &redirects("cat < /no/such/file"),
+            &mut fds,
+            &Shell::default(),
+        );
+        assert_eq!(err.unwrap_err(), "/no/such/file: no such file or directory");
+    }
+}
@@ -0,0 +1,159 @@
+// Running commands that nobody types: scripts, `-c` and `source`.
+//
+//     safe_shell build.sh debug     runs build.sh, with `$1` set to debug
+//     safe_shell -c 'ls | wc -l'    runs the command and exits
+//     safe_shell < cmds.txt         runs the lines, without a prompt
+//     source env.sh                 runs env.sh in this very shell, so
+//                                   that its `cd` and variables stick
+//
+// A script is run one command at a time, exactly as if it was typed,
+// until it ends or runs `exit`. Most commands are one line, but an `if`
+// goes on to its `fi`, a quote to where it closes, and a line ending in
+// `|` or `&&` on the next line: lines are put together until the parser
+// has a whole command. With `set -e` the first command that fails ends
+// the script too (see `exec.rs`). A syntax error ends it as well: running
+// the lines after a broken one could do anything.
+//
+// A first line like `#!/usr/local/bin/safe_shell` is a comment to us. It
+// tells the system which program runs the script, so that after
+// `chmod +x build.sh` it can be started as `./build.sh`.
+
+use std::fs;
+use std::io;
+
+use crate::exec;
+use crate::lexer::{self, SyntaxError};
+use crate::parser;
+use crate::shell::Shell;
+
+/// Parses and runs `text`: one line, or the lines of one command. Returns
+/// its status, which is also kept as `$?`. An empty line changes nothing.
+/// If the command isn't finished yet, the error says it is `incomplete`,
+/// and nothing runs.
+pub fn run_line(shell: &mut Shell, text: &str) -> Result<i32, SyntaxError> {
+    if let Some(list) = lexer::tokenize(text).and_then(parser::parse)? {
+        shell.last_status = exec::run_list(shell, &list, text);
+    }
+    Ok(shell.last_status)
+}
+
+/// Runs `lines`, command by command. `name` is where they come from, for
+/// error messages: `build.sh: line 3: syntax error: ...`.
+pub fn run_lines<I>(shell: &mut Shell, lines: I, name: &str) -> i32
+where
+    I: IntoIterator<Item = io::Result<String>>,
+{
+    // The lines of the command read so far, and the number of the first.
+    let mut text = String::new();
+    let mut first = 0;
+    let mut result = Ok(0);
+    for (i, line) in lines.into_iter().enumerate() {
+        let line = match line {
+            Ok(line) => line,
+            Err(e) => {
+                eprintln!("{}: {}", name, e);
+                return 1;
+            }
+        };
+        if text.is_empty() {
+            first = i + 1;
+        } else {
+            text.push('\n');
+        }
+        text.push_str(&line);
+        result = run_line(shell, &text);
+        match &result {
+            Err(e) if e.incomplete => continue,
+            Err(_) => break,
+            Ok(_) => text.clear(),
+        }
+        if shell.exit.is_some() {
+            break;
+        }
+    }
+    // A syntax error, or the end came before the command was finished.
+    if let Err(e) = result {
+        let line = first + text[..e.span.start.min(text.len())].matches('\n').count();
+        eprintln!("{}: line {}: {}", name, line, e.render(&text));
+        // What other shells use for "you typed it wrong".
+        shell.last_status = 2;
+        return 2;
+    }
+    shell.last_status
+}
+
+/// Runs the script in file `path`.
+pub fn run_file(shell: &mut Shell, path: &str) -> io::Result<i32> {
+    let text = fs::read_to_string(path)?;
+    let lines = text.lines().map(|line| Ok(line.to_string()));
+    Ok(run_lines(shell, lines, path))
+}
+
+/// The status for a script that can't be read: 127 if it doesn't exist,
+/// 126 otherwise, as for commands.
+pub fn read_error_status(e: &io::Error) -> i32 {
+    match e.kind() {
+        io::ErrorKind::NotFound => 127,
+        _ => 126,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(shell: &mut Shell, text: &str) -> i32 {
+        run_lines(shell, text.lines().map(|line| Ok(line.to_string())), "test")
+    }
+
+    #[test]
+    fn runs_until_exit_or_a_syntax_error() {
+        let mut shell = Shell::default();
+        assert_eq!(run(&mut shell, "#!/bin/safe_shell\nA=1\n\nexit 3\nA=2"), 3);
+        assert_eq!(shell.get_var("A"), Some("1"));
+        assert_eq!(shell.exit, Some(3));
+
+        let mut shell = Shell::default();
+        assert_eq!(run(&mut shell, "A=1\nls | | wc\nA=2"), 2);
+        assert_eq!(shell.get_var("A"), Some("1"));
+    }
+
+    #[test]
+    fn commands_can_go_on_over_several_lines() {
+        let mut shell = Shell::default();
+        let script = "if true\nthen\n  A='one\ntwo'\nfi\ntrue &&\n  B=$A\nC=\\\nthree";
+        assert_eq!(run(&mut shell, script), 0);
+        assert_eq!(shell.get_var("B"), Some("one\ntwo"));
+        assert_eq!(shell.get_var("C"), Some("three"));
+
+        // The end of the script comes too early.
+        assert_eq!(run(&mut shell, "A=1\nwhile true; do\n  A=2"), 2);
+        assert_eq!(shell.get_var("A"), Some("1"));
+    }
+
+    #[test]
+    fn errexit_stops_at_the_first_unchecked_failure() {
+        let mut shell = Shell::default();
+        let script = "set -e\nfalse || true\nfalse && true\nA=1\nfalse\nA=2";
+        assert_eq!(run(&mut shell, script), 1);
+        assert_eq!(shell.get_var("A"), Some("1"));
+    }
+
+    #[test]
+    fn source_runs_in_this_shell_with_its_own_arguments() {
+        let path = std::env::temp_dir().join(format!("safe_shell_source_{}", std::process::id()));
+        let path = path.to_str().unwrap();
+        fs::write(path, "A=\"$# $1\"\nexport B=\"$A\"\n").unwrap();
+        let mut shell = Shell {
+            args: vec!["outer".to_string()],
+            ..Shell::default()
+        };
+        let script = format!("source {} x y\nC=$1", path);
+        assert_eq!(run(&mut shell, &script), 0);
+        assert_eq!(shell.get_var("B"), Some("2 x"));
+        assert_eq!(shell.get_var("C"), Some("outer"));
+        fs::remove_file(path).unwrap();
+
+        assert_eq!(run(&mut shell, "source /no/such/file"), 1);
+    }
+}
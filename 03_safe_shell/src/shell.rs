@@ -0,0 +1,225 @@
+// The shell's own state: everything a command can change that outlives
+// the command itself.
+//
+// Builtins get a `&mut Shell`, external programs never see it. When a
+// builtin runs as one stage of a pipeline it gets a copy instead, so that
+// `exit | cat` or `set -o pipefail | cat` change nothing, just like in a
+// real shell, where each stage runs in a subshell.
+//
+// Variables live here too. A shell variable is only seen by the shell
+// itself (`$NAME`); an exported one is also passed on to every program the
+// shell starts, as its environment. The shell starts out with the
+// environment it was given, all exported.
+//
+// The positional parameters are here too: `$0` is the name of the script
+// (or of the shell itself), and `$1`, `$2`, ... are its arguments. While a
+// function runs, they are the function's arguments instead.
+//
+// So are the functions the user defined, and what the commands running
+// right now need to know: which functions are being called and which
+// variables they made `local`, how many loops `break` can leave, and
+// where `{ ...; } > file` sends the output of the commands inside.
+//
+// And the command policy, which says what the shell won't run without
+// asking (see `policy.rs`).
+
+use std::cell::Cell;
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::os::fd::OwnedFd;
+use std::sync::Arc;
+
+use crate::history::History;
+use crate::jobs::{Jobs, Terminal};
+use crate::parser::CompoundCommand;
+use crate::policy::Policy;
+
+#[derive(Debug, Clone, Default)]
+pub struct Shell {
+    pub options: Options,
+    pub vars: HashMap<String, Var>,
+    /// `$0`.
+    pub name: String,
+    /// `$1`, `$2`, ...: the arguments of the script.
+    pub args: Vec<String>,
+    /// The exit status of the last pipeline. `exit` with no argument
+    /// exits with it.
+    pub last_status: i32,
+    /// Set by `exit`: the main loop stops and exits with this status.
+    pub exit: Option<i32>,
+    /// True in the copy a builtin gets as a pipeline stage.
+    pub in_pipeline: bool,
+    /// Background and stopped jobs.
+    pub jobs: Jobs,
+    /// The terminal, if the shell is interactive and controls it. Without
+    /// one there is no job control: everything stays in the shell's
+    /// process group.
+    pub terminal: Option<Terminal>,
+    /// The commands typed so far. Empty unless the shell is interactive.
+    pub history: History,
+    /// The functions defined with `name() { ...; }`.
+    pub functions: HashMap<String, Function>,
+    /// One frame for each function being called, innermost last: the
+    /// variables it made `local`, with what they were before.
+    pub frames: Vec<Vec<(String, Option<Var>)>>,
+    /// How many loops are running, around the current command.
+    pub loops: usize,
+    /// Set by `break`, `continue` and `return`: the commands after it are
+    /// skipped until a loop or function takes care of it.
+    pub control: Option<Control>,
+    /// Where standard input, output and error go for the commands run
+    /// right now, if not where the shell's own go: the file in
+    /// `while ...; done > file`, or the pipe a stage writes into.
+    pub io: [Option<Arc<OwnedFd>>; 3],
+    /// The exit status of the last `$(command)`, which is the status of
+    /// `A=$(command)`. Set while expanding, when the shell can't be
+    /// changed otherwise.
+    pub last_substitution: Cell<Option<i32>>,
+    /// What the shell refuses to run, or asks about first. Allows
+    /// everything unless `main` loads a policy.
+    pub policy: Arc<Policy>,
+}
+
+/// A function defined with `name() { ...; }`.
+#[derive(Debug, Clone)]
+pub struct Function {
+    pub body: Arc<CompoundCommand>,
+    /// The text it was defined in, which the spans in `body` point into.
+    pub text: Arc<str>,
+}
+
+/// Where `break`, `continue` and `return` go.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Control {
+    /// `break N`: leave this loop and the N - 1 around it.
+    Break(usize),
+    /// `continue N`: leave N - 1 loops, and go on with the next round of
+    /// the one around them.
+    Continue(usize),
+    /// `return`: leave the function.
+    Return,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Var {
+    pub value: String,
+    /// Passed on to programs the shell starts.
+    pub exported: bool,
+}
+
+impl Shell {
+    /// A shell with the variables of our own environment.
+    pub fn from_environment() -> Shell {
+        // Names or values that aren't UTF-8 are rare, and we couldn't show
+        // them with `$` or `env` anyway, so we leave them out.
+        let vars = env::vars_os()
+            .filter_map(|(name, value)| Some((name.into_string().ok()?, value.into_string().ok()?)))
+            .map(|(name, value)| {
+                let var = Var {
+                    value,
+                    exported: true,
+                };
+                (name, var)
+            })
+            .collect();
+        Shell {
+            vars,
+            name: "safe_shell".to_string(),
+            ..Shell::default()
+        }
+    }
+
+    pub fn get_var(&self, name: &str) -> Option<&str> {
+        self.vars.get(name).map(|var| var.value.as_str())
+    }
+
+    /// Sets a variable, keeping it exported if it already was.
+    pub fn set_var(&mut self, name: &str, value: String) {
+        match self.vars.get_mut(name) {
+            Some(var) => var.value = value,
+            None => {
+                let var = Var {
+                    value,
+                    exported: false,
+                };
+                self.vars.insert(name.to_string(), var);
+            }
+        }
+    }
+
+    /// The exported variables, sorted by name: the environment of the
+    /// programs we start.
+    pub fn environment(&self) -> BTreeMap<&str, &str> {
+        self.vars
+            .iter()
+            .filter(|(_, var)| var.exported)
+            .map(|(name, var)| (name.as_str(), var.value.as_str()))
+            .collect()
+    }
+}
+
+/// Whether `name` can be a variable name: letters, digits and `_`, not
+/// starting with a digit.
+pub fn is_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Options switched with `set -o NAME` and `set +o NAME`.
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    /// A command that fails ends the shell (`set -e`), unless `&&` or `||`
+    /// was there to deal with the failure.
+    pub errexit: bool,
+    /// A pattern that matches no files is an error, instead of being
+    /// passed on as it is.
+    pub failglob: bool,
+    /// A pipeline fails if any stage fails, not just the last one.
+    pub pipefail: bool,
+    /// `>` refuses to overwrite an existing file; `>|` still does.
+    pub noclobber: bool,
+    /// The prompt shows the status of the last command if it failed.
+    pub showstatus: bool,
+    /// Every command is printed, expanded, before it runs (`set -x`).
+    pub xtrace: bool,
+}
+
+impl Options {
+    /// The option names, in the order `set -o` lists them.
+    pub const NAMES: [&str; 6] = [
+        "errexit",
+        "failglob",
+        "noclobber",
+        "pipefail",
+        "showstatus",
+        "xtrace",
+    ];
+
+    pub fn get(&self, name: &str) -> Option<bool> {
+        match name {
+            "errexit" => Some(self.errexit),
+            "failglob" => Some(self.failglob),
+            "noclobber" => Some(self.noclobber),
+            "pipefail" => Some(self.pipefail),
+            "showstatus" => Some(self.showstatus),
+            "xtrace" => Some(self.xtrace),
+            _ => None,
+        }
+    }
+
+    /// The switch for option `name`, or `None` if there is no such option.
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut bool> {
+        match name {
+            "errexit" => Some(&mut self.errexit),
+            "failglob" => Some(&mut self.failglob),
+            "noclobber" => Some(&mut self.noclobber),
+            "pipefail" => Some(&mut self.pipefail),
+            "showstatus" => Some(&mut self.showstatus),
+            "xtrace" => Some(&mut self.xtrace),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,212 @@
+// Talking to the kernel about processes and terminals, for what the
+// standard library doesn't cover: process groups, who owns the terminal,
+// waiting for children that were stopped rather than finished, and
+// switching the terminal to raw mode.
+//
+// These are plain C library functions. Rust's standard library links the C
+// library anyway, so declaring them is all it takes (see
+// `04_advanced/src/bin/03_unsafe.rs` for FFI basics). Every `unsafe` block
+// below only passes numbers, or a pointer to a local variable.
+//
+// The signal and flag numbers, and the layout of `Termios`, are the ones
+// Linux uses. Elsewhere they are wrong, and a wrong `Termios` is worse
+// than wrong numbers: `tcgetattr` would write past the end of it. So
+// other systems are turned away when compiling instead.
+
+#[cfg(not(target_os = "linux"))]
+compile_error!("safe_shell uses Linux system call numbers and layouts (see src/sys.rs)");
+
+use std::io;
+use std::os::fd::RawFd;
+use std::os::unix::process::ExitStatusExt;
+use std::process::ExitStatus;
+
+pub const SIGHUP: i32 = 1;
+pub const SIGINT: i32 = 2;
+pub const SIGQUIT: i32 = 3;
+pub const SIGKILL: i32 = 9;
+pub const SIGTERM: i32 = 15;
+pub const SIGCONT: i32 = 18;
+pub const SIGTSTP: i32 = 20;
+const SIGTTIN: i32 = 21;
+const SIGTTOU: i32 = 22;
+
+const WNOHANG: i32 = 1;
+const WUNTRACED: i32 = 2;
+const WCONTINUED: i32 = 8;
+
+const SIG_DFL: usize = 0;
+const SIG_IGN: usize = 1;
+
+mod ffi {
+    unsafe extern "C" {
+        pub fn waitpid(pid: i32, status: *mut i32, options: i32) -> i32;
+        pub fn kill(pid: i32, signal: i32) -> i32;
+        pub fn getpgrp() -> i32;
+        pub fn getuid() -> u32;
+        pub fn setpgid(pid: i32, pgid: i32) -> i32;
+        pub fn tcsetpgrp(fd: i32, pgid: i32) -> i32;
+        /// Returns the old handler, which we never need.
+        pub fn signal(signal: i32, handler: usize) -> usize;
+        pub fn tcgetattr(fd: i32, termios: *mut super::Termios) -> i32;
+        pub fn tcsetattr(fd: i32, when: i32, termios: *const super::Termios) -> i32;
+        pub fn ioctl(fd: i32, request: u64, ...) -> i32;
+    }
+}
+
+/// A terminal's settings: `struct termios` from `<termios.h>`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct Termios {
+    input_flags: u32,
+    output_flags: u32,
+    control_flags: u32,
+    local_flags: u32,
+    line_discipline: u8,
+    control_chars: [u8; 32],
+    input_speed: u32,
+    output_speed: u32,
+}
+
+// Local flags.
+const ISIG: u32 = 0o1;
+const ICANON: u32 = 0o2;
+const ECHO: u32 = 0o10;
+const IEXTEN: u32 = 0o100000;
+// Input flags.
+const ICRNL: u32 = 0o400;
+const IXON: u32 = 0o2000;
+// Indexes into `control_chars`.
+const VTIME: usize = 5;
+const VMIN: usize = 6;
+/// `tcsetattr` once everything written so far has been sent.
+const TCSADRAIN: i32 = 1;
+
+impl Termios {
+    /// The settings for raw mode: every key press is passed on right away
+    /// and isn't shown. Normally the terminal collects a whole line,
+    /// handles Backspace itself and turns Ctrl-C into a signal.
+    pub fn raw(mut self) -> Termios {
+        self.local_flags &= !(ICANON | ECHO | ISIG | IEXTEN);
+        // Enter gives `\r` and Ctrl-S and Ctrl-Q are ordinary keys.
+        self.input_flags &= !(ICRNL | IXON);
+        // `read` waits for at least one byte, for as long as it takes.
+        self.control_chars[VMIN] = 1;
+        self.control_chars[VTIME] = 0;
+        self
+    }
+}
+
+/// The current settings of terminal `fd`.
+pub fn terminal_mode(fd: RawFd) -> io::Result<Termios> {
+    let mut termios = Termios {
+        input_flags: 0,
+        output_flags: 0,
+        control_flags: 0,
+        local_flags: 0,
+        line_discipline: 0,
+        control_chars: [0; 32],
+        input_speed: 0,
+        output_speed: 0,
+    };
+    check(unsafe { ffi::tcgetattr(fd, &mut termios) })?;
+    Ok(termios)
+}
+
+pub fn set_terminal_mode(fd: RawFd, termios: &Termios) -> io::Result<()> {
+    check(unsafe { ffi::tcsetattr(fd, TCSADRAIN, termios) })
+}
+
+/// `ioctl` request for the size of a terminal window.
+const TIOCGWINSZ: u64 = 0x5413;
+
+/// How many characters wide terminal `fd` is.
+pub fn terminal_width(fd: RawFd) -> io::Result<usize> {
+    // `struct winsize`: rows, columns, and the size in pixels.
+    let mut size = [0u16; 4];
+    check(unsafe { ffi::ioctl(fd, TIOCGWINSZ, size.as_mut_ptr()) })?;
+    Ok(size[1] as usize)
+}
+
+/// The signals the terminal sends when you press Ctrl-C, Ctrl-\ or Ctrl-Z,
+/// and the ones that stop a background process using the terminal.
+const JOB_SIGNALS: [i32; 5] = [SIGINT, SIGQUIT, SIGTSTP, SIGTTIN, SIGTTOU];
+
+/// Makes the shell immune to the job control signals: Ctrl-C is meant for
+/// the program running in the foreground, not for the shell.
+pub fn ignore_job_signals() {
+    for signal in JOB_SIGNALS {
+        unsafe { ffi::signal(signal, SIG_IGN) };
+    }
+}
+
+/// Undoes `ignore_job_signals`. Ignored signals stay ignored across
+/// `exec`, so every child calls this before it becomes the new program.
+/// It only calls `signal`, which is safe to use between `fork` and `exec`.
+pub fn default_job_signals() {
+    for signal in JOB_SIGNALS {
+        unsafe { ffi::signal(signal, SIG_DFL) };
+    }
+}
+
+/// Puts the shell in a process group of its own and makes that group the
+/// terminal's foreground group.
+pub fn take_terminal(terminal: RawFd) -> io::Result<i32> {
+    // This fails for a session leader (a shell started as the login
+    // shell), which is the leader of its group already.
+    unsafe { ffi::setpgid(0, 0) };
+    let pgid = process_group();
+    set_foreground(terminal, pgid)?;
+    Ok(pgid)
+}
+
+/// The process group of the calling process.
+pub fn process_group() -> i32 {
+    unsafe { ffi::getpgrp() }
+}
+
+/// The real user id of the calling process. Unlike `$USER`, which is just
+/// a variable anyone can set, this is what the kernel says.
+pub fn user_id() -> u32 {
+    unsafe { ffi::getuid() }
+}
+
+/// Gives the terminal to process group `pgid`: from now on it gets what is
+/// typed, and the signals for Ctrl-C and Ctrl-Z.
+pub fn set_foreground(terminal: RawFd, pgid: i32) -> io::Result<()> {
+    check(unsafe { ffi::tcsetpgrp(terminal, pgid) })
+}
+
+/// Sends `signal` to every process in group `pgid`.
+pub fn signal_group(pgid: i32, signal: i32) -> io::Result<()> {
+    check(unsafe { ffi::kill(-pgid, signal) })
+}
+
+/// Waits for child `pid` to finish, stop or continue. Without `block`,
+/// returns `None` right away if none of that has happened yet.
+pub fn wait(pid: i32, block: bool) -> io::Result<Option<ExitStatus>> {
+    let options = WUNTRACED | WCONTINUED | if block { 0 } else { WNOHANG };
+    let mut status = 0;
+    loop {
+        match unsafe { ffi::waitpid(pid, &mut status, options) } {
+            0 => return Ok(None),
+            -1 => {
+                let e = io::Error::last_os_error();
+                if e.kind() != io::ErrorKind::Interrupted {
+                    return Err(e);
+                }
+            }
+            // `ExitStatus` knows how to take the number apart: exit code,
+            // signal, stopped or continued.
+            _ => return Ok(Some(ExitStatus::from_raw(status))),
+        }
+    }
+}
+
+fn check(result: i32) -> io::Result<()> {
+    if result == -1 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}